@@ -9,6 +9,7 @@ local shaper = shaping:setup_with_automation {
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path '/tmp/acct.db'
+  kumo.configure_admin_db_path '/tmp/admin.db'
 
   -- Configure publishing of logs to automation daemon
   shaper.setup_publish()
@@ -127,25 +127,43 @@ local function construct_publisher(publish, domain, options)
     connection_verbose = options.publish_connection_verbose,
   }
   function connection:send(message)
-    local response = client
-      :post(string.format('%s/publish_log_v1', publish.endpoint))
-      :header('Content-Type', 'application/json')
-      :body(message:get_data())
-      :send()
-
-    local disposition = string.format(
-      '%d %s: %s',
-      response:status_code(),
-      response:status_reason(),
-      response:text()
-    )
-
-    if response:status_is_success() then
-      return disposition
+    local failures = {}
+    -- When publishing to a replicated TSA cluster, try each node
+    -- in turn until one of them accepts the record
+    for _, endpoint in ipairs(publish.endpoints) do
+      local status, response = pcall(function()
+        return client
+          :post(string.format('%s/publish_log_v1', endpoint))
+          :header('Content-Type', 'application/json')
+          :body(message:get_data())
+          :send()
+      end)
+
+      if status then
+        local disposition = string.format(
+          '%d %s: %s',
+          response:status_code(),
+          response:status_reason(),
+          response:text()
+        )
+
+        if response:status_is_success() then
+          return disposition
+        end
+        table.insert(
+          failures,
+          string.format('%s: %s', endpoint, disposition)
+        )
+      else
+        table.insert(
+          failures,
+          string.format('%s: %s', endpoint, tostring(response))
+        )
+      end
     end
 
     -- retry later
-    kumo.reject(400, disposition)
+    kumo.reject(400, table.concat(failures, '; '))
   end
   return connection
 end
@@ -340,14 +358,36 @@ local function process_tsa_events(url)
 end
 
 kumo.on('kumo.tsa.suspension.subscriber', function(args)
-  local url = args[1]
+  local urls = args[1]
+  if type(urls) ~= 'table' then
+    urls = { urls }
+  end
+  local idx = 1
 
   -- If we encounter an error (likely cause: tsa-daemon restarting),
-  -- then we'll try again after a short sleep
+  -- then we'll try again after a short sleep. When subscribing to
+  -- a replicated TSA cluster, the next node in the list is used.
   while true do
+    local url = urls[idx]
     local status, err = pcall(process_tsa_events, url)
-    kumo.log_error('TSA Error, will retry in 30 seconds', status, err)
-    kumo.sleep(30)
+    idx = (idx % #urls) + 1
+    if idx == 1 then
+      kumo.log_error(
+        'TSA Error, will retry in 30 seconds',
+        url,
+        status,
+        err
+      )
+      kumo.sleep(30)
+    else
+      kumo.log_error(
+        'TSA Error, failing over to',
+        urls[idx],
+        url,
+        status,
+        err
+      )
+    end
   end
 end)
 
@@ -355,6 +395,11 @@ end)
 local shaper = shaping:setup_with_automation {
   publish = {"http://10.0.0.1:8008"},
   subscribe = {"http://10.0.0.1:8008"},
+  -- To use a replicated TSA cluster, list its nodes together in a
+  -- nested table; records are published to the first reachable node
+  -- and the subscription fails over between them:
+  -- publish = {{"http://10.0.0.1:8008", "http://10.0.0.2:8008"}},
+  -- subscribe = {{"http://10.0.0.1:8008", "http://10.0.0.2:8008"}},
   -- this needs to list any files that hold your custom shaping rules; should match
   -- the additional files beyond /opt/kumomta/share/policy-extras/shaping.toml in your
   -- tsa config
@@ -434,23 +479,37 @@ function mod:setup_with_automation(options)
     end
   end
   if options.subscribe then
-    for _, url in ipairs(options.subscribe) do
-      table.insert(
-        file_names,
-        string.format('%s/get_config_v1/shaping.toml', url)
-      )
+    for _, subscription in ipairs(options.subscribe) do
+      -- A nested table lists the nodes of a replicated cluster;
+      -- their config is identical, so any reachable node will do
+      local urls = subscription
+      if type(urls) ~= 'table' then
+        urls = { urls }
+      end
+      for _, url in ipairs(urls) do
+        table.insert(
+          file_names,
+          string.format('%s/get_config_v1/shaping.toml', url)
+        )
+      end
     end
   end
 
   local publish = {}
   for _, destination in ipairs(options.publish or {}) do
+    local endpoints = destination
+    if type(endpoints) ~= 'table' then
+      endpoints = { endpoints }
+    end
+
     -- Generate the hook name and constructor name and
     -- keep that info in a more structured form
-    local hook_name = string.format('%s.tsa.kumomta', destination)
+    local hook_name = string.format('%s.tsa.kumomta', endpoints[1])
     local constructor = string.format('make.%s', hook_name)
 
     publish[hook_name] = {
-      endpoint = destination,
+      endpoint = endpoints[1],
+      endpoints = endpoints,
       hook_name = hook_name,
       constructor = constructor,
     }
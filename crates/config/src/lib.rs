@@ -255,6 +255,23 @@ impl LuaConfig {
         }
     }
 
+    /// Returns true if at least one handler has been registered
+    /// for the event described by sig
+    pub fn has_callback<A: IntoLuaMulti, R: FromLuaMulti>(
+        &self,
+        sig: &CallbackSignature<A, R>,
+    ) -> bool {
+        let lua = self.inner.as_ref().unwrap();
+        match lua
+            .lua
+            .named_registry_value::<mlua::Value>(&sig.decorated_name())
+        {
+            Ok(Value::Function(_)) => true,
+            Ok(Value::Table(tbl)) => tbl.raw_len() > 0,
+            _ => false,
+        }
+    }
+
     pub async fn call_callback<'a, A: IntoLuaMulti + Clone, R: FromLuaMulti>(
         &mut self,
         sig: &'a CallbackSignature<A, R>,
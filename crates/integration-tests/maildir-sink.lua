@@ -7,6 +7,7 @@ local TEST_DIR = os.getenv 'KUMOD_TEST_DIR'
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path ':memory:'
+  kumo.configure_admin_db_path ':memory:'
   kumo.aaa.configure_acct_log {
     log_dir = TEST_DIR .. '/acct',
     max_segment_duration = '1s',
@@ -38,6 +39,15 @@ kumo.on('init', function()
 
   kumo.start_esmtp_listener(smtp_params)
 
+  -- Coupled with lmtp.rs
+  local lmtp_socket = os.getenv 'KUMOD_LMTP_SOCKET'
+  if lmtp_socket then
+    kumo.start_lmtp_listener {
+      listen = lmtp_socket,
+      batch_handling = 'BatchByDomain',
+    }
+  end
+
   kumo.start_http_listener {
     listen = '127.0.0.1:0',
   }
@@ -50,6 +60,15 @@ kumo.on('init', function()
     },
   }
 
+  -- Coupled with trace_context.rs
+  local otel_endpoint = os.getenv 'KUMOD_OTEL_ENDPOINT'
+  if otel_endpoint then
+    kumo.configure_opentelemetry {
+      endpoint = otel_endpoint,
+      timeout = '1s',
+    }
+  end
+
   kumo.define_spool {
     name = 'data',
     path = TEST_DIR .. '/data-spool',
@@ -98,12 +117,41 @@ kumo.on('smtp_server_mail_from', function(sender)
   apply_rejections(sender)
 end)
 
+-- Coupled with lmtp.rs; rewriting the recipients and adding another
+-- here means that they no longer correspond to those of RCPT TO
+kumo.on('smtp_server_data', function(msg)
+  local extra = os.getenv 'KUMOD_ADD_RECIPIENT'
+  if extra then
+    local recipients = { extra }
+    for _, recip in ipairs(msg:recipient_list()) do
+      table.insert(
+        recipients,
+        string.format('rewritten-%s@%s', recip.user, recip.domain)
+      )
+    end
+    msg:set_recipient(recipients)
+  end
+end)
+
 kumo.on('smtp_server_message_received', function(msg)
   local sender = msg:sender().user
   if utils.starts_with(sender, 'disconnect-in-data-no-421') then
     kumo.disconnect(451, 'disconnecting ' .. sender, 'ForceDisconnect')
   end
 
+  -- Coupled with lmtp.rs; the per-recipient LMTP responses must
+  -- follow the RCPT TO addresses, not the rewritten ones
+  if os.getenv 'KUMOD_REWRITE_RECIPIENTS' then
+    local rewritten = {}
+    for _, recip in ipairs(msg:recipient_list()) do
+      table.insert(
+        rewritten,
+        string.format('rewritten-%s@%s', recip.user, recip.domain)
+      )
+    end
+    msg:set_recipient(rewritten)
+  end
+
   msg:set_meta('queue', 'maildir')
 end)
 
@@ -141,6 +189,18 @@ kumo.on('smtp_server_auth_plain', function(authz, authc, password)
   return simple_auth_check(authc, password)
 end)
 
+kumo.on('smtp_server_auth_cram_md5', function(authc)
+  local password_database = {
+    ['daniel'] = 'tiger',
+  }
+  return password_database[authc]
+end)
+
+kumo.on('smtp_server_auth_xoauth2', function(user, token)
+  -- Treat the token as the password for the purposes of testing
+  return simple_auth_check(user, token)
+end)
+
 kumo.on('smtp_server_ehlo', function(domain, conn_meta, extensions)
   local revised = {}
   for _, ext in ipairs(extensions) do
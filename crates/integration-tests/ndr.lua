@@ -52,6 +52,7 @@ log_hooks:new_disposition_hook {
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
 
   local relay_hosts = { '0.0.0.0/0' }
   kumo.start_esmtp_listener {
@@ -8,6 +8,7 @@ local SINK_PORT = tonumber(os.getenv 'KUMOD_SMTP_SINK_PORT')
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
 
   local relay_hosts = { '0.0.0.0/0' }
   kumo.start_esmtp_listener {
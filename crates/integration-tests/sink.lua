@@ -5,6 +5,7 @@ local TEST_DIR = os.getenv 'KUMOD_TEST_DIR'
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path ':memory:'
+  kumo.configure_admin_db_path ':memory:'
 
   kumo.start_esmtp_listener {
     listen = '127.0.0.1:0',
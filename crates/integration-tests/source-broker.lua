@@ -0,0 +1,95 @@
+local kumo = require 'kumo'
+package.path = '../../assets/?.lua;' .. package.path
+
+local TEST_DIR = os.getenv 'KUMOD_TEST_DIR'
+local SINK_PORT = tonumber(os.getenv 'KUMOD_SMTP_SINK_PORT')
+local KAFKA_BROKERS = os.getenv 'KUMOD_KAFKA_BROKERS'
+local AMQP_URI = os.getenv 'KUMOD_AMQP_URI'
+
+kumo.on('init', function()
+  kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
+  kumo.aaa.configure_acct_log {
+    log_dir = TEST_DIR .. '/acct',
+    max_segment_duration = '1s',
+  }
+
+  kumo.start_esmtp_listener {
+    listen = '127.0.0.1:0',
+    relay_hosts = { '0.0.0.0/0' },
+  }
+
+  kumo.start_http_listener {
+    listen = '127.0.0.1:0',
+  }
+
+  kumo.configure_local_logs {
+    log_dir = TEST_DIR .. '/logs',
+    max_segment_duration = '1s',
+  }
+
+  kumo.define_spool {
+    name = 'data',
+    path = TEST_DIR .. '/data-spool',
+  }
+
+  kumo.define_spool {
+    name = 'meta',
+    path = TEST_DIR .. '/meta-spool',
+  }
+end)
+
+kumo.on('get_listener_domain', function(domain, listener, conn_meta)
+  return kumo.make_listener_domain {
+    relay_to = true,
+  }
+end)
+
+kumo.on('get_queue_config', function(domain, tenant, campaign, routing_domain)
+  -- Coupled with kafka_native.rs
+  if domain == 'kafka' then
+    return kumo.make_queue_config {
+      protocol = {
+        kafka = {
+          config = { ['bootstrap.servers'] = KAFKA_BROKERS },
+          topic = 'kumo',
+          key_template = '{{ recipients[0] }}',
+          batch_size = 10,
+          max_batch_latency = '100ms',
+        },
+      },
+    }
+  end
+
+  -- Coupled with amqp_native.rs
+  if domain == 'amqp' or domain == 'unroutable.amqp' then
+    return kumo.make_queue_config {
+      protocol = {
+        amqp = {
+          uri = AMQP_URI,
+          routing_key = 'kumo-{{ queue }}',
+          payload_template = '{{ sender }} -> {{ recipients[0] }}',
+          mandatory = true,
+        },
+      },
+      retry_interval = '1m',
+    }
+  end
+
+  return kumo.make_queue_config {
+    protocol = {
+      -- Redirect traffic to the sink
+      smtp = {
+        mx_list = { 'localhost:' .. SINK_PORT },
+      },
+    },
+  }
+end)
+
+kumo.on('get_egress_path_config', function(domain, source_name, _site_name)
+  return kumo.make_egress_path {
+    enable_tls = 'OpportunisticInsecure',
+    prohibited_hosts = {},
+    skip_hosts = { '::/0' },
+  }
+end)
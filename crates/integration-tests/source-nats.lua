@@ -9,6 +9,7 @@ local NATS_ADDRESS = os.getenv 'NATS_ADDRESS'
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
   kumo.aaa.configure_acct_log {
     log_dir = TEST_DIR .. '/acct',
     max_segment_duration = '1s',
@@ -6,6 +6,7 @@ log_hooks = require 'policy-extras.log_hooks'
 local TEST_DIR = os.getenv 'KUMOD_TEST_DIR'
 local SINK_PORT = tonumber(os.getenv 'KUMOD_SMTP_SINK_PORT')
 local WEBHOOK_PORT = os.getenv 'KUMOD_WEBHOOK_PORT'
+local HTTP_DELIVER_URL = os.getenv 'KUMOD_HTTP_DELIVER_URL'
 local AMQPHOOK_URL = os.getenv 'KUMOD_AMQPHOOK_URL'
 local AMQP_HOST_PORT = os.getenv 'KUMOD_AMQP_HOST_PORT'
 local LISTENER_MAP = os.getenv 'KUMOD_LISTENER_DOMAIN_MAP'
@@ -18,6 +19,7 @@ local USE_SPLIT_TXN = os.getenv 'KUMOD_USE_SPLIT_TXN'
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
   kumo.aaa.configure_acct_log {
     log_dir = TEST_DIR .. '/acct',
     max_segment_duration = '1s',
@@ -57,6 +59,15 @@ kumo.on('init', function()
     meta = { 'xfer*' },
   }
 
+  -- Coupled with trace_context.rs
+  local otel_endpoint = os.getenv 'KUMOD_OTEL_ENDPOINT'
+  if otel_endpoint then
+    kumo.configure_opentelemetry {
+      endpoint = otel_endpoint,
+      timeout = '1s',
+    }
+  end
+
   if WEBHOOK_PORT then
     kumo.configure_log_hook {
       name = 'webhook',
@@ -277,6 +288,21 @@ kumo.on('get_queue_config', function(domain, tenant, campaign, routing_domain)
     }
   end
 
+  -- This domain is coupled with the http_deliver test
+  if domain == 'http-deliver.example.com' and HTTP_DELIVER_URL then
+    return kumo.make_queue_config {
+      protocol = {
+        http = {
+          url = HTTP_DELIVER_URL,
+          data_encoding = 'Base64',
+          signing = {
+            key = { key_data = 'http-deliver-secret' },
+          },
+        },
+      },
+    }
+  end
+
   local protocol = {
     -- Redirect traffic to the sink
     smtp = {
@@ -331,6 +357,18 @@ kumo.on('get_queue_config', function(domain, tenant, campaign, routing_domain)
     protocol = nil
   end
 
+  local lmtp_socket = os.getenv 'KUMOD_LMTP_SOCKET'
+  if lmtp_socket then
+    -- Coupled with lmtp.rs; deliver to the sink's LMTP listener
+    protocol = {
+      smtp = {
+        mx_list = {
+          { name = 'lmtp', addr = lmtp_socket },
+        },
+      },
+    }
+  end
+
   return kumo.make_queue_config {
     protocol = protocol,
     retry_interval = os.getenv 'KUMOD_RETRY_INTERVAL',
@@ -376,6 +414,7 @@ kumo.on('get_egress_path_config', function(domain, source_name, _site_name)
     ) or false,
     tls_prefer_openssl = ((os.getenv 'KUMOD_PREFER_OPENSSL') and true)
       or false,
+    enable_chunking = not os.getenv 'KUMOD_DISABLE_CHUNKING',
     max_recipients_per_batch = tonumber(MAX_RECIPIENTS_PER_BATCH),
 
     -- Skip IPv6 addresses that come back for eg: localhost.
@@ -417,6 +456,12 @@ kumo.on('get_egress_path_config', function(domain, source_name, _site_name)
     params.smtp_auth_plain_password = {
       key_data = password,
     }
+    params.smtp_auth_mechanism = os.getenv 'KUMOD_SMTP_AUTH_MECHANISM'
+  end
+
+  if os.getenv 'KUMOD_LMTP_SOCKET' then
+    params.use_lmtp = true
+    params.enable_tls = 'Disabled'
   end
 
   if domain == 'two-hosts.example.com' then
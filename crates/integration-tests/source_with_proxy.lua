@@ -10,6 +10,7 @@ local PROXY_PASSWORD = os.getenv 'KUMO_PROXY_PASSWORD'
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
 
   kumo.start_esmtp_listener {
     listen = '127.0.0.1:0',
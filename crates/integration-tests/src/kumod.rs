@@ -502,8 +502,10 @@ impl KumoDaemon {
                 }
                 let proto = fields[0];
                 let addr = fields[3];
-                let addr: SocketAddr = addr.parse()?;
-                listeners.insert(proto.to_string(), addr);
+                // Unix domain listeners don't have a SocketAddr
+                if let Ok(addr) = addr.parse::<SocketAddr>() {
+                    listeners.insert(proto.to_string(), addr);
+                }
             }
         }
 
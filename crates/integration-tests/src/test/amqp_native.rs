@@ -0,0 +1,111 @@
+use crate::kumod::{generate_message_text, DaemonWithMaildirOptions, MailGenParams};
+use anyhow::Context;
+use futures_lite::stream::StreamExt;
+use kumo_log_types::RecordType::{Delivery, TransientFailure};
+use lapin::options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions};
+use lapin::types::FieldTable;
+use lapin::{Connection, ConnectionProperties};
+use std::time::Duration;
+use testcontainers_modules::rabbitmq::RabbitMq;
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+
+#[tokio::test]
+async fn amqp_native() -> anyhow::Result<()> {
+    if std::env::var("KUMOD_TESTCONTAINERS").unwrap_or_else(|_| String::new()) != "1" {
+        return Ok(());
+    }
+
+    let rabbitmq_instance = RabbitMq::default().start().await?;
+    let amqp_url = format!(
+        "amqp://{}:{}",
+        rabbitmq_instance.get_host().await?,
+        rabbitmq_instance.get_host_port_ipv4(5672).await?
+    );
+
+    let options = ConnectionProperties::default()
+        .with_executor(tokio_executor_trait::Tokio::current())
+        .with_reactor(tokio_reactor_trait::Tokio);
+    let connection = Connection::connect(&amqp_url, options).await?;
+    let channel = connection.create_channel().await?;
+    // The routing key template in source-broker.lua produces
+    // this name for the `amqp` queue; there is deliberately no
+    // queue bound for the `unroutable.amqp` queue
+    channel
+        .queue_declare(
+            "kumo-amqp",
+            QueueDeclareOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_AMQP_URI", &amqp_url)
+        .policy_file("source-broker.lua")
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+    let body = generate_message_text(1024, 78);
+    for recip in ["rec@amqp", "rec@amqp", "rec@unroutable.amqp"] {
+        let response = MailGenParams {
+            body: Some(&body),
+            recip: Some(recip),
+            ..Default::default()
+        }
+        .send(&mut client)
+        .await
+        .context("send message")?;
+        anyhow::ensure!(response.code == 250);
+    }
+
+    daemon
+        .wait_for_source_summary(
+            |summary| {
+                summary.get(&Delivery).copied().unwrap_or(0) >= 2
+                    && summary.get(&TransientFailure).copied().unwrap_or(0) >= 1
+            },
+            Duration::from_secs(20),
+        )
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await.context("dump_logs")?;
+    k9::snapshot!(
+        delivery_summary,
+        "
+DeliverySummary {
+    source_counts: {
+        Reception: 3,
+        Delivery: 2,
+        TransientFailure: 1,
+    },
+    sink_counts: {},
+}
+"
+    );
+
+    let mut consumer = channel
+        .basic_consume(
+            "kumo-amqp",
+            "amqp_native",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    for _ in 0..2 {
+        let delivery = tokio::time::timeout(Duration::from_secs(20), consumer.next())
+            .await?
+            .unwrap()?;
+        assert_eq!(
+            String::from_utf8_lossy(&delivery.data),
+            "sender@example.com -> rec@amqp"
+        );
+        assert!(delivery.properties.message_id().is_some());
+        delivery.ack(BasicAckOptions::default()).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use crate::kumod::{generate_message_text, DaemonWithMaildir, MailGenParams};
+use std::time::Duration;
+
+/// Each SASL mechanism that the source can be configured to use
+/// when authenticating to the sink.  PLAIN is covered by the
+/// existing auth_deliver test.
+const MECHANISMS: &[&str] = &["Login", "CramMd5", "XOAuth2"];
+
+#[tokio::test]
+async fn auth_deliver_mechanisms() -> anyhow::Result<()> {
+    for mechanism in MECHANISMS {
+        let mut daemon = DaemonWithMaildir::start_with_env(vec![
+            ("KUMOD_SMTP_AUTH_USERNAME", "daniel"),
+            ("KUMOD_SMTP_AUTH_PASSWORD", "tiger"),
+            ("KUMOD_SMTP_AUTH_MECHANISM", mechanism),
+        ])
+        .await?;
+
+        let mut client = daemon.smtp_client().await?;
+
+        let body = generate_message_text(1024, 78);
+        let response = MailGenParams {
+            body: Some(&body),
+            ..Default::default()
+        }
+        .send(&mut client)
+        .await?;
+        anyhow::ensure!(response.code == 250, "{mechanism}: {response:?}");
+
+        daemon
+            .wait_for_maildir_count(1, Duration::from_secs(10))
+            .await;
+
+        daemon.stop_both().await?;
+
+        let delivery_summary = daemon.dump_logs().await?;
+        assert_eq!(
+            delivery_summary
+                .sink_counts
+                .get(&kumo_log_types::RecordType::Delivery),
+            Some(&1),
+            "{mechanism}: {delivery_summary:#?}"
+        );
+        daemon.assert_no_acct_deny().await?;
+    }
+    Ok(())
+}
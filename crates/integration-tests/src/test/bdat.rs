@@ -0,0 +1,317 @@
+use crate::kumod::{DaemonWithMaildir, DaemonWithMaildirOptions, MailGenParams};
+use anyhow::Context;
+use k9::assert_equal;
+use kumo_log_types::RecordType::{Bounce, Delivery, Reception};
+use mailparsing::DecodedBody;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Read a possibly multi-line SMTP response, returning the
+/// full text of the response
+async fn read_response(stream: &mut BufReader<TcpStream>) -> anyhow::Result<String> {
+    let mut response = String::new();
+    loop {
+        let mut line = String::new();
+        let size = tokio::time::timeout(Duration::from_secs(10), stream.read_line(&mut line))
+            .await
+            .context("timeout reading response")??;
+        anyhow::ensure!(size > 0, "peer disconnected. Response so far: {response}");
+        response.push_str(&line);
+        if line.as_bytes().get(3) == Some(&b' ') {
+            return Ok(response);
+        }
+    }
+}
+
+async fn connect(daemon: &DaemonWithMaildir) -> anyhow::Result<BufReader<TcpStream>> {
+    let stream = TcpStream::connect(daemon.source.listener("smtp")).await?;
+    let mut stream = BufReader::new(stream);
+    let banner = read_response(&mut stream).await?;
+    anyhow::ensure!(banner.starts_with("220 "), "unexpected banner {banner}");
+
+    stream.get_mut().write_all(b"EHLO there\r\n").await?;
+    let ehlo = read_response(&mut stream).await?;
+    anyhow::ensure!(
+        ehlo.contains("250-CHUNKING\r\n"),
+        "CHUNKING is not advertised: {ehlo}"
+    );
+    anyhow::ensure!(
+        ehlo.contains("250-BINARYMIME\r\n"),
+        "BINARYMIME is not advertised: {ehlo}"
+    );
+    Ok(stream)
+}
+
+/// Verify that a message submitted in pipelined BDAT chunks is
+/// received byte-for-byte, and relayed on to the sink
+#[tokio::test]
+async fn bdat_end_to_end() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut stream = connect(&daemon).await?;
+
+    let chunk1 = "From: <sender@example.com>\r\nTo: <recip@example.com>\r\n";
+    let chunk2 = "Subject: chunky\r\n\r\n.Stuffing is not required\r\nFor me\r\n";
+
+    let request = format!(
+        "MAIL FROM:<sender@example.com>\r\n\
+         RCPT TO:<recip@example.com>\r\n\
+         BDAT {}\r\n{chunk1}\
+         BDAT {} LAST\r\n{chunk2}",
+        chunk1.len(),
+        chunk2.len()
+    );
+    stream.get_mut().write_all(request.as_bytes()).await?;
+
+    let mail_from = read_response(&mut stream).await?;
+    assert!(mail_from.starts_with("250 "), "{mail_from}");
+    let rcpt_to = read_response(&mut stream).await?;
+    assert!(rcpt_to.starts_with("250 "), "{rcpt_to}");
+    let first_chunk = read_response(&mut stream).await?;
+    assert_equal!(
+        first_chunk,
+        format!("250 2.0.0 {} octets received\r\n", chunk1.len())
+    );
+    let last_chunk = read_response(&mut stream).await?;
+    assert!(last_chunk.starts_with("250 OK ids="), "{last_chunk}");
+
+    stream.get_mut().write_all(b"QUIT\r\n").await?;
+    read_response(&mut stream).await?;
+
+    daemon
+        .wait_for_maildir_count(1, Duration::from_secs(10))
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await.context("dump_logs")?;
+    k9::snapshot!(
+        delivery_summary,
+        "
+DeliverySummary {
+    source_counts: {
+        Reception: 1,
+        Delivery: 1,
+    },
+    sink_counts: {
+        Reception: 1,
+        Delivery: 1,
+    },
+}
+"
+    );
+
+    let mut messages = daemon.extract_maildir_messages()?;
+    assert_equal!(messages.len(), 1);
+    let body = String::from_utf8_lossy(messages[0].read_data()?).to_string();
+    assert!(
+        body.ends_with(chunk2),
+        "expected {body} to end with the unmodified {chunk2}"
+    );
+
+    Ok(())
+}
+
+/// Verify that BDAT chunks that are rejected are still consumed,
+/// keeping the session in sync with the client
+#[tokio::test]
+async fn bdat_out_of_sequence() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut stream = connect(&daemon).await?;
+
+    stream
+        .get_mut()
+        .write_all(b"BDAT 6 LAST\r\nNOOP\r\nNOOP\r\n")
+        .await?;
+    let bdat = read_response(&mut stream).await?;
+    assert_equal!(bdat, "503 5.5.0 MAIL FROM must be issued first\r\n");
+    let noop = read_response(&mut stream).await?;
+    assert!(noop.starts_with("250 "), "{noop}");
+
+    stream
+        .get_mut()
+        .write_all(
+            b"MAIL FROM:<sender@example.com> BODY=BINARYMIME\r\n\
+              RCPT TO:<recip@example.com>\r\n\
+              DATA\r\n",
+        )
+        .await?;
+    let mail_from = read_response(&mut stream).await?;
+    assert!(mail_from.starts_with("250 "), "{mail_from}");
+    let rcpt_to = read_response(&mut stream).await?;
+    assert!(rcpt_to.starts_with("250 "), "{rcpt_to}");
+    let data = read_response(&mut stream).await?;
+    assert_equal!(data, "503 5.5.1 BODY=BINARYMIME requires BDAT\r\n");
+
+    stream.get_mut().write_all(b"QUIT\r\n").await?;
+    read_response(&mut stream).await?;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    Ok(())
+}
+
+/// Relay a message that would require dot stuffing if sent via DATA,
+/// returning the command that the source used to complete the transaction
+/// with the sink
+async fn relay_stuffed(options: DaemonWithMaildirOptions) -> anyhow::Result<String> {
+    let mut daemon = options.start().await.context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+
+    let body = ".Stuffing required\r\nFor me\r\n";
+    let response = MailGenParams {
+        body: Some(body),
+        ..Default::default()
+    }
+    .send(&mut client)
+    .await
+    .context("send message")?;
+    anyhow::ensure!(response.code == 250);
+
+    daemon
+        .wait_for_maildir_count(1, Duration::from_secs(10))
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let mut messages = daemon.extract_maildir_messages()?;
+    assert_equal!(messages.len(), 1);
+    let parsed = messages[0].parsed()?;
+    assert_equal!(parsed.body().unwrap(), DecodedBody::Text(body.into()));
+
+    let logs = daemon.source.collect_logs().await?;
+    let delivered = logs
+        .iter()
+        .find(|record| record.kind == Delivery)
+        .context("no Delivery record")?;
+    delivered
+        .response
+        .command
+        .clone()
+        .context("Delivery record has no command")
+}
+
+/// Verify that the source uses BDAT to relay to the sink when
+/// it advertises CHUNKING
+#[tokio::test]
+async fn bdat_relay() -> anyhow::Result<()> {
+    let command = relay_stuffed(DaemonWithMaildirOptions::new()).await?;
+    assert!(command.starts_with("BDAT "), "{command}");
+    assert!(command.ends_with(" LAST\r\n"), "{command}");
+    Ok(())
+}
+
+/// Verify that the source falls back to DATA when chunking
+/// is disabled in the egress path config
+#[tokio::test]
+async fn bdat_relay_disabled() -> anyhow::Result<()> {
+    let command =
+        relay_stuffed(DaemonWithMaildirOptions::new().env("KUMOD_DISABLE_CHUNKING", "1")).await?;
+    assert_equal!(command, ".\r\n");
+    Ok(())
+}
+
+/// Submit a BODY=BINARYMIME message to the source via BDAT
+async fn submit_binary_mime(daemon: &DaemonWithMaildir) -> anyhow::Result<()> {
+    let mut stream = connect(daemon).await?;
+
+    let chunk = "From: <sender@example.com>\r\nTo: <recip@example.com>\r\n\
+                 Subject: binary\r\n\r\nNot line\roriented\n\0content\r\n";
+    let request = format!(
+        "MAIL FROM:<sender@example.com> BODY=BINARYMIME\r\n\
+         RCPT TO:<recip@example.com>\r\n\
+         BDAT {} LAST\r\n{chunk}",
+        chunk.len()
+    );
+    stream.get_mut().write_all(request.as_bytes()).await?;
+
+    let mail_from = read_response(&mut stream).await?;
+    assert!(mail_from.starts_with("250 "), "{mail_from}");
+    let rcpt_to = read_response(&mut stream).await?;
+    assert!(rcpt_to.starts_with("250 "), "{rcpt_to}");
+    let last_chunk = read_response(&mut stream).await?;
+    assert!(last_chunk.starts_with("250 OK ids="), "{last_chunk}");
+
+    stream.get_mut().write_all(b"QUIT\r\n").await?;
+    read_response(&mut stream).await?;
+    Ok(())
+}
+
+/// Verify that BINARYMIME content is relayed via BDAT with
+/// BODY=BINARYMIME, rather than being relabelled
+#[tokio::test]
+async fn binary_mime_relay() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    submit_binary_mime(&daemon).await?;
+
+    daemon
+        .wait_for_maildir_count(1, Duration::from_secs(10))
+        .await;
+    daemon.stop_both().await.context("stop_both")?;
+
+    let logs = daemon.source.collect_logs().await?;
+    let delivered = logs
+        .iter()
+        .find(|record| record.kind == Delivery)
+        .context("no Delivery record")?;
+    let command = delivered.response.command.clone().unwrap_or_default();
+    assert!(command.starts_with("BDAT "), "{command}");
+
+    let logs = daemon.sink.collect_logs().await?;
+    let received = logs
+        .iter()
+        .find(|record| record.kind == Reception)
+        .context("no Reception record in sink")?;
+    assert_equal!(
+        received.meta.get("binarymime"),
+        Some(&serde_json::Value::Bool(true))
+    );
+
+    Ok(())
+}
+
+/// Verify that BINARYMIME content is bounced rather than being
+/// sent via DATA when the source cannot use BDAT
+#[tokio::test]
+async fn binary_mime_relay_without_chunking() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_DISABLE_CHUNKING", "1")
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    submit_binary_mime(&daemon).await?;
+
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&Bounce).copied().unwrap_or(0) > 0,
+            Duration::from_secs(10),
+        )
+        .await;
+    daemon.stop_both().await.context("stop_both")?;
+
+    let logs = daemon.source.collect_logs().await?;
+    let bounced = logs
+        .iter()
+        .find(|record| record.kind == Bounce)
+        .context("no Bounce record")?;
+    assert_equal!(bounced.response.code, 554);
+    assert!(
+        bounced.response.content.contains("BINARYMIME"),
+        "{}",
+        bounced.response.content
+    );
+    assert_equal!(daemon.extract_maildir_messages()?.len(), 0);
+
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use crate::kumod::{DaemonWithMaildir, DaemonWithMaildirOptions, MailGenParams};
+use anyhow::Context;
+use k9::assert_equal;
+use kumo_log_types::RecordType::{Bounce, Reception};
+use rfc5321::{
+    ClientError, DsnNotify, DsnParams, DsnReturn, EsmtpParameter, ForwardPath, ReversePath,
+};
+use std::time::Duration;
+
+fn param(name: &str, value: &str) -> EsmtpParameter {
+    EsmtpParameter {
+        name: name.to_string(),
+        value: Some(value.to_string()),
+    }
+}
+
+/// Verify that the RFC 3461 parameters are accepted by the source,
+/// and relayed on to the sink, which records them in the message meta
+#[tokio::test]
+async fn dsn_relay() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+    assert!(client.capabilities().contains_key("DSN"));
+
+    let body = MailGenParams::default().generate()?;
+    let status = client
+        .send_mail_multi_recip_with_params(
+            ReversePath::try_from("sender@example.com").unwrap(),
+            vec![param("RET", "HDRS"), param("ENVID", "QQ314159")],
+            vec![(
+                ForwardPath::try_from("recip@example.com").unwrap(),
+                vec![
+                    param("NOTIFY", "SUCCESS,FAILURE"),
+                    param("ORCPT", "rfc822;orig+2Bx@example.com"),
+                ],
+            )],
+            &body,
+        )
+        .await?;
+    assert_equal!(status.response.code, 250);
+
+    daemon
+        .wait_for_maildir_count(1, Duration::from_secs(10))
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let sink_logs = daemon.sink.collect_logs().await?;
+    let reception = sink_logs
+        .iter()
+        .find(|record| record.kind == Reception)
+        .context("no Reception record in sink")?;
+    let dsn: DsnParams = serde_json::from_value(
+        reception
+            .meta
+            .get("dsn")
+            .cloned()
+            .context("dsn meta was not recorded by the sink")?,
+    )?;
+
+    assert_equal!(dsn.ret, Some(DsnReturn::Hdrs));
+    assert_equal!(dsn.envid.as_deref(), Some("QQ314159"));
+    let recip = dsn
+        .recipient("recip@example.com")
+        .context("no dsn params for recipient")?;
+    assert_equal!(
+        recip.notify,
+        Some(vec![DsnNotify::Success, DsnNotify::Failure])
+    );
+    assert_equal!(
+        recip.orcpt.as_ref().map(|orcpt| orcpt.to_string()),
+        Some("rfc822;orig+x@example.com".to_string())
+    );
+
+    Ok(())
+}
+
+/// Verify that invalid DSN parameters are rejected
+#[tokio::test]
+async fn dsn_invalid_notify() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+
+    let body = MailGenParams::default().generate()?;
+    let result = client
+        .send_mail_multi_recip_with_params(
+            ReversePath::try_from("sender@example.com").unwrap(),
+            vec![],
+            vec![(
+                ForwardPath::try_from("recip@example.com").unwrap(),
+                vec![param("NOTIFY", "NEVER,SUCCESS")],
+            )],
+            &body,
+        )
+        .await;
+
+    match result {
+        Err(ClientError::Rejected(response)) => {
+            assert_equal!(response.code, 501);
+            assert_equal!(
+                response.content,
+                "NOTIFY=NEVER cannot be combined with other values"
+            );
+        }
+        wat => anyhow::bail!("expected rejection, got {wat:?}"),
+    }
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    Ok(())
+}
+
+/// Verify that NOTIFY=NEVER suppresses the NDR that ndr.lua
+/// would otherwise generate for a bounce
+#[tokio::test]
+async fn dsn_notify_never_suppresses_ndr() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .policy_file("ndr.lua")
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+
+    let body = MailGenParams {
+        recip: Some("permfail@example.com"),
+        body: Some("woot"),
+        ..Default::default()
+    }
+    .generate()?;
+    let status = client
+        .send_mail_multi_recip_with_params(
+            ReversePath::try_from("sender@example.com").unwrap(),
+            vec![],
+            vec![(
+                ForwardPath::try_from("permfail@example.com").unwrap(),
+                vec![param("NOTIFY", "NEVER")],
+            )],
+            &body,
+        )
+        .await?;
+    assert_equal!(status.response.code, 250);
+
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&Bounce).copied().unwrap_or(0) > 0,
+            Duration::from_secs(10),
+        )
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await.context("dump_logs")?;
+    k9::snapshot!(
+        delivery_summary,
+        "
+DeliverySummary {
+    source_counts: {
+        Reception: 1,
+        Bounce: 1,
+    },
+    sink_counts: {
+        Rejection: 2,
+    },
+}
+"
+    );
+
+    assert_equal!(daemon.extract_maildir_messages()?.len(), 0);
+
+    Ok(())
+}
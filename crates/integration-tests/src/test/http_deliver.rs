@@ -0,0 +1,90 @@
+use crate::kumod::{generate_message_text, DaemonWithMaildirOptions, MailGenParams};
+use crate::webhook::WebHookServer;
+use kumo_log_types::RecordType;
+use std::time::Duration;
+
+/// Verify that the http delivery protocol POSTs the message to
+/// the configured endpoint, preserving binary-safe content via
+/// base64 encoding and signing the request body
+#[tokio::test]
+async fn http_deliver() -> anyhow::Result<()> {
+    let webhook = WebHookServer::start().await?;
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env(
+            "KUMOD_HTTP_DELIVER_URL",
+            format!("http://{}/http-deliver", webhook.addr),
+        )
+        .start()
+        .await?;
+
+    let mut client = daemon.smtp_client().await?;
+    let body = generate_message_text(1024, 78);
+    let response = MailGenParams {
+        recip: Some("user@http-deliver.example.com"),
+        body: Some(&body),
+        ..Default::default()
+    }
+    .send(&mut client)
+    .await?;
+    anyhow::ensure!(response.code == 250);
+
+    assert!(
+        webhook
+            .wait_for_http_delivery_count(1, Duration::from_secs(10))
+            .await
+    );
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&RecordType::Delivery).copied() == Some(1),
+            Duration::from_secs(10),
+        )
+        .await;
+
+    daemon.stop_both().await?;
+    webhook.shutdown();
+
+    let summary = daemon.dump_logs().await?;
+    assert_eq!(summary.source_counts.get(&RecordType::Reception), Some(&1));
+    assert_eq!(summary.source_counts.get(&RecordType::Delivery), Some(&1));
+    assert!(summary.sink_counts.is_empty());
+
+    let deliveries = webhook.http_deliveries();
+    assert_eq!(deliveries.len(), 1);
+    let delivery = &deliveries[0];
+
+    let key = aws_lc_rs::hmac::Key::new(aws_lc_rs::hmac::HMAC_SHA256, b"http-deliver-secret");
+    let expected_signature = format!(
+        "sha256={}",
+        data_encoding::HEXLOWER.encode(aws_lc_rs::hmac::sign(&key, &delivery.body).as_ref())
+    );
+    assert_eq!(
+        delivery
+            .headers
+            .get("x-signature")
+            .and_then(|v| v.to_str().ok()),
+        Some(expected_signature.as_str())
+    );
+    assert_eq!(
+        delivery
+            .headers
+            .get("content-type")
+            .and_then(|v| v.to_str().ok()),
+        Some("application/json")
+    );
+
+    let payload: serde_json::Value = serde_json::from_slice(&delivery.body)?;
+    assert_eq!(payload["sender"], "sender@example.com");
+    assert_eq!(
+        payload["recipients"],
+        serde_json::json!(["user@http-deliver.example.com"])
+    );
+    assert_eq!(payload["queue"], "http-deliver.example.com");
+    let data = data_encoding::BASE64.decode(payload["data"].as_str().unwrap_or("").as_bytes())?;
+    let data = String::from_utf8(data)?;
+    assert!(
+        data.contains("Subject: Hello! This is a test\r\n"),
+        "{data}"
+    );
+
+    Ok(())
+}
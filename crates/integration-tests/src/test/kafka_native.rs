@@ -0,0 +1,82 @@
+use crate::kumod::{generate_message_text, DaemonWithMaildirOptions, MailGenParams};
+use anyhow::Context;
+use kumo_log_types::RecordType::Delivery;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::{ClientConfig, Message};
+use std::time::Duration;
+use testcontainers_modules::kafka::{Kafka, KAFKA_PORT};
+use testcontainers_modules::testcontainers::runners::AsyncRunner;
+
+#[tokio::test]
+async fn kafka_native() -> anyhow::Result<()> {
+    if std::env::var("KUMOD_TESTCONTAINERS").unwrap_or_else(|_| String::new()) != "1" {
+        return Ok(());
+    }
+
+    let kafka_instance = Kafka::default().start().await?;
+    let brokers = format!(
+        "127.0.0.1:{}",
+        kafka_instance.get_host_port_ipv4(KAFKA_PORT).await?
+    );
+
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_KAFKA_BROKERS", &brokers)
+        .policy_file("source-broker.lua")
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let num_msgs = 3;
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+    let body = generate_message_text(1024, 78);
+    for _ in 0..num_msgs {
+        let response = MailGenParams {
+            body: Some(&body),
+            recip: Some("rec@kafka"),
+            ..Default::default()
+        }
+        .send(&mut client)
+        .await
+        .context("send message")?;
+        anyhow::ensure!(response.code == 250);
+    }
+
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&Delivery).copied().unwrap_or(0) >= num_msgs,
+            Duration::from_secs(30),
+        )
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await.context("dump_logs")?;
+    k9::snapshot!(
+        delivery_summary,
+        "
+DeliverySummary {
+    source_counts: {
+        Reception: 3,
+        Delivery: 3,
+    },
+    sink_counts: {},
+}
+"
+    );
+
+    let consumer: StreamConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &brokers)
+        .set("group.id", "kafka_native")
+        .set("auto.offset.reset", "earliest")
+        .create()?;
+    consumer.subscribe(&["kumo"])?;
+
+    for _ in 0..num_msgs {
+        let record = tokio::time::timeout(Duration::from_secs(30), consumer.recv()).await??;
+        assert_eq!(record.key(), Some("rec@kafka".as_bytes()));
+        let payload = String::from_utf8_lossy(record.payload().unwrap_or_default());
+        assert!(payload.contains("Hello! This is a test"), "{payload}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use crate::kumod::{DaemonWithMaildirOptions, MailGenParams};
+use anyhow::Context;
+use k9::assert_equal;
+use kumo_log_types::RecordType::{Delivery, Reception, TransientFailure};
+use std::time::Duration;
+
+/// Verify that the source can deliver via LMTP to an LMTP listener
+/// bound to a unix domain socket on the sink.  The two recipients
+/// are batched into a single message, so the sink must produce a
+/// response for each of them after the DATA phase.
+#[tokio::test]
+async fn lmtp_unix_socket() -> anyhow::Result<()> {
+    let socket_dir = tempfile::tempdir()?;
+    let socket_path = socket_dir.path().join("lmtp.sock");
+
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_BATCH_HANDLING", "BatchByDomain")
+        .env("KUMOD_LMTP_SOCKET", socket_path.display().to_string())
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+
+    let status = MailGenParams {
+        recip_list: Some(vec!["one@example.com", "two@example.com"]),
+        ..Default::default()
+    }
+    .send_batch(&mut client)
+    .await
+    .context("send message")?;
+    anyhow::ensure!(status.response.code == 250);
+
+    daemon
+        .wait_for_maildir_count(2, Duration::from_secs(10))
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await?;
+    assert_equal!(delivery_summary.source_counts.get(&Delivery), Some(&1));
+    assert_equal!(delivery_summary.sink_counts.get(&Reception), Some(&1));
+
+    let records = daemon.sink.collect_logs().await?;
+    let reception = records
+        .iter()
+        .find(|r| r.kind == Reception)
+        .expect("sink has a reception record");
+    assert_equal!(
+        reception
+            .meta
+            .get("reception_protocol")
+            .and_then(|v| v.as_str()),
+        Some("LMTP")
+    );
+    assert_equal!(
+        reception.meta.get("received_via").and_then(|v| v.as_str()),
+        Some(socket_path.display().to_string().as_str())
+    );
+
+    Ok(())
+}
+
+/// Verify that when the sink's policy rewrites the recipients of
+/// the message, the LMTP responses still correspond to the
+/// recipients given via RCPT TO, and each of them is accepted
+#[tokio::test]
+async fn lmtp_rewritten_recipients() -> anyhow::Result<()> {
+    let socket_dir = tempfile::tempdir()?;
+    let socket_path = socket_dir.path().join("lmtp.sock");
+
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_BATCH_HANDLING", "BatchByDomain")
+        .env("KUMOD_LMTP_SOCKET", socket_path.display().to_string())
+        .env("KUMOD_REWRITE_RECIPIENTS", "1")
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+
+    let status = MailGenParams {
+        recip_list: Some(vec!["one@example.com", "two@example.com"]),
+        ..Default::default()
+    }
+    .send_batch(&mut client)
+    .await
+    .context("send message")?;
+    anyhow::ensure!(status.response.code == 250);
+
+    daemon
+        .wait_for_maildir_count(2, Duration::from_secs(10))
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await?;
+    assert_equal!(delivery_summary.source_counts.get(&Delivery), Some(&1));
+    assert_equal!(delivery_summary.source_counts.get(&TransientFailure), None);
+    assert_equal!(delivery_summary.sink_counts.get(&Reception), Some(&1));
+
+    Ok(())
+}
+
+/// Verify that when the sink's policy rewrites the recipients in
+/// smtp_server_data and changes their number, so that they can no
+/// longer be matched up with RCPT TO, each recipient is still
+/// reported as accepted, rather than being temporarily failed and
+/// then delivered a second time
+#[tokio::test]
+async fn lmtp_added_recipient() -> anyhow::Result<()> {
+    let socket_dir = tempfile::tempdir()?;
+    let socket_path = socket_dir.path().join("lmtp.sock");
+
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_BATCH_HANDLING", "BatchByDomain")
+        .env("KUMOD_LMTP_SOCKET", socket_path.display().to_string())
+        .env("KUMOD_ADD_RECIPIENT", "three@example.com")
+        .start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+
+    let status = MailGenParams {
+        recip_list: Some(vec!["one@example.com", "two@example.com"]),
+        ..Default::default()
+    }
+    .send_batch(&mut client)
+    .await
+    .context("send message")?;
+    anyhow::ensure!(status.response.code == 250);
+
+    daemon
+        .wait_for_maildir_count(3, Duration::from_secs(10))
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let delivery_summary = daemon.dump_logs().await?;
+    assert_equal!(delivery_summary.source_counts.get(&Delivery), Some(&1));
+    assert_equal!(delivery_summary.source_counts.get(&TransientFailure), None);
+    assert_equal!(delivery_summary.sink_counts.get(&Reception), Some(&1));
+    assert_equal!(daemon.extract_maildir_messages()?.len(), 3);
+
+    Ok(())
+}
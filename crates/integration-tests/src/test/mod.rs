@@ -1,13 +1,17 @@
 #![cfg(test)]
+mod amqp_native;
 mod arc;
 mod auth_deliver;
 mod auth_deliver_invalid_password;
+mod auth_deliver_mechanisms;
+mod bdat;
 mod broken_first_choice_mx;
 mod disconnect_in_data;
 mod disconnect_in_mail_from;
 mod disconnect_peer_idle_out;
 mod disconnect_reconnect_same_host;
 mod disconnect_terminate_ok;
+mod dsn;
 mod eightbitmime;
 mod end_to_end;
 mod end_to_end_deferred_queue;
@@ -16,11 +20,14 @@ mod end_to_end_webhook;
 mod end_to_end_webhook_batch;
 mod expires;
 mod http_auth;
+mod http_deliver;
 mod http_inject_compression;
 mod http_inject_deferred;
 mod http_inject_size_limit;
 mod http_inject_template_syntax_error;
 mod http_liveness;
+mod kafka_native;
+mod lmtp;
 mod log_oob_arf;
 mod maildir_batch;
 mod maildir_batch_452;
@@ -38,6 +45,7 @@ mod rebind;
 mod rebind_event_defined;
 mod rebind_event_missing;
 mod rebind_port;
+mod requiretls;
 mod retry_schedule;
 mod rewrite_server_response;
 mod source_selection_rate;
@@ -52,6 +60,7 @@ mod tls_client_certificate;
 mod tls_info_log;
 mod tls_opportunistic_fail;
 mod tls_opportunistic_reconnect;
+mod trace_context;
 mod tsa_basic_automation;
 mod tsa_bounce_automation;
 mod tsa_bounce_campaign;
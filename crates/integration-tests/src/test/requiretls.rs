@@ -0,0 +1,148 @@
+use crate::kumod::{DaemonWithMaildir, MailGenParams};
+use anyhow::Context;
+use k9::assert_equal;
+use kumo_log_types::JsonLogRecord;
+use kumo_log_types::RecordType::Bounce;
+use rfc5321::{
+    ClientError, EnhancedStatusCode, EsmtpParameter, ForwardPath, ReversePath, TlsOptions,
+    TlsStatus,
+};
+use std::time::Duration;
+
+fn requiretls() -> EsmtpParameter {
+    EsmtpParameter {
+        name: "REQUIRETLS".to_string(),
+        value: None,
+    }
+}
+
+/// Verify that REQUIRETLS is refused outside of a TLS session
+#[tokio::test]
+async fn requiretls_needs_tls_session() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+    assert!(!client.capabilities().contains_key("REQUIRETLS"));
+
+    let body = MailGenParams::default().generate()?;
+    let result = client
+        .send_mail_multi_recip_with_params(
+            ReversePath::try_from("sender@example.com").unwrap(),
+            vec![requiretls()],
+            vec![(ForwardPath::try_from("recip@example.com").unwrap(), vec![])],
+            &body,
+        )
+        .await;
+
+    match result {
+        Err(ClientError::Rejected(response)) => {
+            assert_equal!(response.code, 530);
+        }
+        wat => anyhow::bail!("expected rejection, got {wat:?}"),
+    }
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    Ok(())
+}
+
+/// Verify that a REQUIRETLS message is accepted over TLS, but is
+/// bounced rather than relayed to a sink that has neither MTA-STS
+/// nor DANE
+#[tokio::test]
+async fn requiretls_bounces_without_validated_tls() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let bounce = send_requiretls_and_wait_for_bounce(&mut daemon, "recip@example.com").await?;
+    assert_equal!(
+        bounce.response.enhanced_code,
+        Some(EnhancedStatusCode {
+            class: 5,
+            subject: 7,
+            detail: 30,
+        })
+    );
+    assert_equal!(daemon.extract_maildir_messages()?.len(), 0);
+
+    Ok(())
+}
+
+/// Verify that a host that cannot carry a REQUIRETLS message causes
+/// the next host to be tried, and that the message is bounced only
+/// once every host has been tried
+#[tokio::test]
+async fn requiretls_tries_every_host() -> anyhow::Result<()> {
+    let mut daemon = DaemonWithMaildir::start()
+        .await
+        .context("DaemonWithMaildir::start")?;
+
+    let bounce =
+        send_requiretls_and_wait_for_bounce(&mut daemon, "recip@two-hosts.example.com").await?;
+    assert_equal!(
+        bounce.response.enhanced_code,
+        Some(EnhancedStatusCode {
+            class: 5,
+            subject: 7,
+            detail: 30,
+        })
+    );
+    for host in ["localhost-1", "localhost-2"] {
+        assert!(
+            bounce.response.content.contains(host),
+            "{host} not mentioned in {}",
+            bounce.response.content
+        );
+    }
+    assert_equal!(daemon.extract_maildir_messages()?.len(), 0);
+
+    Ok(())
+}
+
+async fn send_requiretls_and_wait_for_bounce(
+    daemon: &mut DaemonWithMaildir,
+    recipient: &str,
+) -> anyhow::Result<JsonLogRecord> {
+    let mut client = daemon.smtp_client().await.context("make smtp_client")?;
+    match client
+        .starttls(TlsOptions {
+            insecure: true,
+            ..Default::default()
+        })
+        .await?
+    {
+        TlsStatus::Info(_) => {}
+        TlsStatus::FailedHandshake(err) => anyhow::bail!("TLS handshake failed: {err}"),
+    }
+    client.ehlo("localhost").await?;
+    assert!(client.capabilities().contains_key("REQUIRETLS"));
+
+    let body = MailGenParams::default().generate()?;
+    let status = client
+        .send_mail_multi_recip_with_params(
+            ReversePath::try_from("sender@example.com").unwrap(),
+            vec![requiretls()],
+            vec![(ForwardPath::try_from(recipient).unwrap(), vec![])],
+            &body,
+        )
+        .await?;
+    assert_equal!(status.response.code, 250);
+
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&Bounce).copied().unwrap_or(0) > 0,
+            Duration::from_secs(10),
+        )
+        .await;
+
+    daemon.stop_both().await.context("stop_both")?;
+
+    let source_logs = daemon.source.collect_logs().await?;
+    source_logs
+        .into_iter()
+        .find(|record| record.kind == Bounce)
+        .context("no Bounce record in source")
+}
@@ -0,0 +1,115 @@
+use crate::kumod::DaemonWithMaildirOptions;
+use kumo_log_types::RecordType::{Reception, TransientFailure, XferIn, XferOut};
+use kumo_log_types::{JsonLogRecord, RecordType};
+use std::time::Duration;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+/// This test injects a message via HTTP with a traceparent header,
+/// then transfers it to the sink via xfer, and verifies that the
+/// log records on both nodes belong to the trace of the client
+#[tokio::test]
+async fn trace_context_across_xfer() -> anyhow::Result<()> {
+    // Nothing listens on this port, so exporting spans will fail,
+    // but that doesn't prevent the trace context from propagating
+    let mut daemon = DaemonWithMaildirOptions::new()
+        .env("KUMOD_OTEL_ENDPOINT", "http://127.0.0.1:1/v1/traces")
+        .start()
+        .await?;
+
+    daemon
+        .kcli([
+            "suspend",
+            "--domain",
+            "example.com",
+            "--reason",
+            "hold for xfer",
+        ])
+        .await?;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "http://{}/api/inject/v1",
+            daemon.source.listener("http")
+        ))
+        .header("traceparent", format!("00-{TRACE_ID}-{PARENT_SPAN_ID}-01"))
+        .json(&serde_json::json!({
+            "envelope_sender": "sender@example.com",
+            "recipients": [{"email": "user@example.com"}],
+            "content": {
+                "text_body": "Hello!",
+                "subject": "Trace me",
+            },
+        }))
+        .send()
+        .await?;
+    anyhow::ensure!(
+        response.status() == 200,
+        "Response status: {}",
+        response.status()
+    );
+
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&TransientFailure).copied().unwrap_or(0) > 0,
+            Duration::from_secs(50),
+        )
+        .await;
+
+    daemon
+        .kcli([
+            "xfer",
+            "--domain",
+            "example.com",
+            "--reason",
+            "testing",
+            "--target",
+            &format!("http://{}", daemon.sink.listener("http")),
+        ])
+        .await?;
+
+    daemon
+        .wait_for_source_summary(
+            |summary| summary.get(&XferOut).copied().unwrap_or(0) > 0,
+            Duration::from_secs(10),
+        )
+        .await;
+    daemon
+        .wait_for_sink_summary(
+            |summary| summary.get(&XferIn).copied().unwrap_or(0) > 0,
+            Duration::from_secs(5),
+        )
+        .await;
+
+    daemon.stop_both().await?;
+
+    let source_logs = daemon.source.collect_logs().await?;
+    let sink_logs = daemon.sink.collect_logs().await?;
+
+    let find = |logs: &[JsonLogRecord], kind: RecordType| {
+        logs.iter()
+            .find(|r| r.kind == kind)
+            .cloned()
+            .unwrap_or_else(|| panic!("no {kind:?} record in {logs:#?}"))
+    };
+    let reception = find(&source_logs, Reception);
+    let xfer_out = find(&source_logs, XferOut);
+    let xfer_in = find(&sink_logs, XferIn);
+
+    for record in [&reception, &xfer_out, &xfer_in] {
+        k9::assert_equal!(
+            record.trace_id.as_deref(),
+            Some(TRACE_ID),
+            "{:?} record continues the trace of the client",
+            record.kind
+        );
+    }
+
+    // The sink records its own reception span within that trace
+    assert!(xfer_in.span_id.is_some());
+    assert_ne!(xfer_in.span_id, reception.span_id);
+    assert_ne!(xfer_in.span_id.as_deref(), Some(PARENT_SPAN_ID));
+
+    Ok(())
+}
@@ -1,5 +1,7 @@
 #![cfg(test)]
+use axum::body::Bytes;
 use axum::extract::{Extension, Json};
+use axum::http::HeaderMap;
 use axum::routing::post;
 use axum::Router;
 use axum_server::Handle;
@@ -9,9 +11,17 @@ use std::net::{SocketAddr, TcpListener};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+/// A request received by the `/http-deliver` endpoint
+#[derive(Clone, Debug)]
+pub struct HttpDelivery {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
 #[derive(Default)]
 struct Shared {
     records: Vec<JsonLogRecord>,
+    http_deliveries: Vec<HttpDelivery>,
     request_counter: usize,
 }
 
@@ -28,6 +38,7 @@ impl WebHookServer {
         let app = Router::new()
             .route("/log", post(log_record))
             .route("/log-batch", post(log_batch))
+            .route("/http-deliver", post(http_deliver))
             .layer(Extension(Arc::clone(&shared)));
 
         let handle = Handle::new();
@@ -87,6 +98,23 @@ impl WebHookServer {
     pub fn return_logs(&self) -> Vec<JsonLogRecord> {
         self.shared.lock().unwrap().records.clone()
     }
+
+    pub async fn wait_for_http_delivery_count(&self, count: usize, timeout: Duration) -> bool {
+        eprintln!("waiting for http deliveries");
+
+        tokio::select! {
+            _ = async {
+                    while self.shared.lock().unwrap().http_deliveries.len() != count {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+            } => true,
+            _ = tokio::time::sleep(timeout) => false,
+        }
+    }
+
+    pub fn http_deliveries(&self) -> Vec<HttpDelivery> {
+        self.shared.lock().unwrap().http_deliveries.clone()
+    }
 }
 
 async fn log_batch(
@@ -106,3 +134,13 @@ async fn log_record(
     shared.records.push(record);
     shared.request_counter += 1;
 }
+
+async fn http_deliver(
+    Extension(shared): Extension<Arc<Mutex<Shared>>>,
+    headers: HeaderMap,
+    body: Bytes,
+) {
+    let mut shared = shared.lock().unwrap();
+    shared.http_deliveries.push(HttpDelivery { headers, body });
+    shared.request_counter += 1;
+}
@@ -20,6 +20,7 @@ local shaper = shaping:setup_with_automation {
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(TEST_DIR .. '/accounting.db')
+  kumo.configure_admin_db_path(TEST_DIR .. '/admin.db')
 
   kumo.start_esmtp_listener {
     listen = '127.0.0.1:0',
@@ -0,0 +1,111 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use clap::{ArgGroup, Parser};
+use kumo_api_client::KumoApiClient;
+use kumo_api_types::HoldV1Request;
+use reqwest::Url;
+use std::time::Duration;
+use throttle::ThrottleSpec;
+
+#[derive(Debug, Parser)]
+/// Administratively hold messages in matching queues until a release time.
+///
+/// While the hold is in effect, matching messages are not delivered.
+/// When the hold ends, the held messages are released gradually, according
+/// to the --release-rate, rather than all at once.
+///
+/// Holds are not retroactive with respect to messages that are already
+/// in the process of being delivered.
+#[clap(
+    group(ArgGroup::new("selection")
+        .multiple(true)
+        .required(true)
+        .args(&["domain", "campaign", "tenant", "everything", "queue"])),
+    group(ArgGroup::new("release")
+        .multiple(false)
+        .required(true)
+        .args(&["until", "duration"])),
+)]
+pub struct HoldCommand {
+    /// The domain name to match.
+    /// If omitted, any domains will match!
+    #[arg(long)]
+    domain: Option<String>,
+
+    /// The campaign name to match.
+    /// If omitted, any campaigns will match!
+    #[arg(long)]
+    campaign: Option<String>,
+
+    /// The tenant name to match.
+    /// If omitted, any tenant will match!
+    #[arg(long)]
+    tenant: Option<String>,
+
+    /// The reason for the hold
+    #[arg(long)]
+    reason: String,
+
+    /// Hold all queues.
+    #[arg(long, conflicts_with_all=&["domain", "campaign", "tenant", "queue"])]
+    everything: bool,
+
+    /// Hold specific scheduled queue names using their exact queue name(s).
+    /// Can be specified multiple times.
+    #[arg(long, conflicts_with_all=&["domain", "campaign", "tenant"])]
+    queue: Vec<String>,
+
+    /// The time at which the held messages will be released,
+    /// expressed as an RFC 3339 timestamp, such as
+    /// '2025-04-01T09:00:00-07:00'.
+    #[arg(long, value_parser=DateTime::<FixedOffset>::parse_from_rfc3339)]
+    until: Option<DateTime<FixedOffset>>,
+
+    /// Instead of --until, specify how long the hold should
+    /// remain in effect, such as '2h'.
+    #[arg(long, value_parser=humantime::parse_duration)]
+    duration: Option<Duration>,
+
+    /// The rate at which held messages will be released once
+    /// the hold ends, such as '100/min'. If omitted, all of the
+    /// held messages become due within a minute of the release time.
+    #[arg(long, value_parser=parse_throttle)]
+    release_rate: Option<ThrottleSpec>,
+}
+
+fn parse_throttle(s: &str) -> Result<ThrottleSpec, String> {
+    ThrottleSpec::try_from(s.to_string())
+}
+
+impl HoldCommand {
+    pub async fn run(&self, endpoint: &Url) -> anyhow::Result<()> {
+        if self.domain.is_none()
+            && self.campaign.is_none()
+            && self.tenant.is_none()
+            && self.queue.is_empty()
+            && !self.everything
+        {
+            anyhow::bail!(
+                "No domain, campaign or tenant was specified. \
+                 Use --everything if you intend to hold all queues"
+            );
+        }
+
+        let client = KumoApiClient::new(endpoint.clone());
+        let result = client
+            .admin_hold_v1(&HoldV1Request {
+                campaign: self.campaign.clone(),
+                domain: self.domain.clone(),
+                tenant: self.tenant.clone(),
+                reason: self.reason.clone(),
+                release_at: self.until.map(|until| until.with_timezone(&Utc)),
+                duration: self.duration,
+                release_rate: self.release_rate,
+                queue_names: self.queue.clone(),
+            })
+            .await?;
+
+        println!("{}", serde_json::to_string_pretty(&result)?);
+
+        Ok(())
+    }
+}
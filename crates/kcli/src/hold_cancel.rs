@@ -0,0 +1,34 @@
+use clap::Parser;
+use kumo_api_client::KumoApiClient;
+use kumo_api_types::HoldV1CancelRequest;
+use reqwest::Url;
+use uuid::Uuid;
+
+#[derive(Debug, Parser)]
+/// Cancels an admin hold entry.
+///
+/// Cancelling the entry prevents it from matching new messages.
+/// Messages that it already held remain scheduled for the
+/// release time that they were assigned.
+pub struct HoldCancelCommand {
+    /// The id field of the hold entry that you wish to cancel
+    #[arg(long, value_parser=Uuid::parse_str)]
+    pub id: Uuid,
+}
+
+impl HoldCancelCommand {
+    pub async fn run(&self, endpoint: &Url) -> anyhow::Result<()> {
+        let client = KumoApiClient::new(endpoint.clone());
+        let response = client
+            .admin_hold_cancel_v1(&HoldV1CancelRequest { id: self.id })
+            .await?;
+
+        if !response.is_empty() {
+            println!("{response}");
+        } else {
+            println!("OK");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use clap::Parser;
+use kumo_api_client::KumoApiClient;
+use reqwest::Url;
+
+#[derive(Debug, Parser)]
+/// Returns list of current administrative hold rules.
+///
+/// Returns the list of admin holds that have not yet reached
+/// their release time on the target instance.
+pub struct HoldListCommand {}
+
+impl HoldListCommand {
+    pub async fn run(&self, endpoint: &Url) -> anyhow::Result<()> {
+        let client = KumoApiClient::new(endpoint.clone());
+        let result = client.admin_hold_list_v1().await?;
+
+        println!("{}", serde_json::to_string_pretty(&result)?);
+
+        Ok(())
+    }
+}
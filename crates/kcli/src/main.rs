@@ -7,6 +7,9 @@ use std::time::Duration;
 mod bounce;
 mod bounce_cancel;
 mod bounce_list;
+mod hold;
+mod hold_cancel;
+mod hold_list;
 mod inspect_message;
 mod inspect_sched_q;
 mod logfilter;
@@ -60,6 +63,9 @@ enum SubCommand {
     BounceList(bounce_list::BounceListCommand),
     BounceCancel(bounce_cancel::BounceCancelCommand),
     Rebind(rebind::RebindCommand),
+    Hold(hold::HoldCommand),
+    HoldList(hold_list::HoldListCommand),
+    HoldCancel(hold_cancel::HoldCancelCommand),
     Suspend(suspend::SuspendCommand),
     SuspendList(suspend_list::SuspendListCommand),
     SuspendCancel(suspend_cancel::SuspendCancelCommand),
@@ -96,6 +102,9 @@ impl SubCommand {
                     ("bounce", &["bounce"]),
                     ("bounce-list", &["bounce"]),
                     ("bounce-cancel", &["bounce"]),
+                    ("hold", &["hold"]),
+                    ("hold-list", &["hold"]),
+                    ("hold-cancel", &["hold"]),
                     ("suspend", &["suspend"]),
                     ("suspend-list", &["suspend"]),
                     ("suspend-cancel", &["suspend"]),
@@ -157,6 +166,9 @@ impl SubCommand {
             Self::BounceCancel(cmd) => cmd.run(endpoint).await,
             Self::BounceList(cmd) => cmd.run(endpoint).await,
             Self::Rebind(cmd) => cmd.run(endpoint).await,
+            Self::Hold(cmd) => cmd.run(endpoint).await,
+            Self::HoldCancel(cmd) => cmd.run(endpoint).await,
+            Self::HoldList(cmd) => cmd.run(endpoint).await,
             Self::Suspend(cmd) => cmd.run(endpoint).await,
             Self::SuspendCancel(cmd) => cmd.run(endpoint).await,
             Self::SuspendList(cmd) => cmd.run(endpoint).await,
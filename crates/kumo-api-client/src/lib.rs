@@ -104,6 +104,29 @@ impl KumoApiClient {
         BounceV1CancelRequest
     );
 
+    method!(
+        admin_hold_v1,
+        POST,
+        "/api/admin/hold/v1",
+        HoldV1Request,
+        HoldV1Response
+    );
+
+    method!(
+        admin_hold_list_v1,
+        GET,
+        "/api/admin/hold/v1",
+        Vec<HoldV1ListEntry>
+    );
+
+    method!(
+        admin_hold_cancel_v1,
+        TEXT,
+        DELETE,
+        "/api/admin/hold/v1",
+        HoldV1CancelRequest
+    );
+
     method!(
         admin_inspect_sched_q_v1,
         GET,
@@ -54,6 +54,20 @@ impl Default for Tls {
     }
 }
 
+/// The SASL mechanism to use when authenticating to the next hop
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Copy, Default)]
+pub enum SmtpAuthMechanism {
+    /// RFC 4616 PLAIN
+    #[default]
+    Plain,
+    /// The legacy, non-standard LOGIN mechanism
+    Login,
+    /// RFC 2195 CRAM-MD5
+    CramMd5,
+    /// XOAUTH2, using an OAuth 2.0 access token as the password
+    XOAuth2,
+}
+
 pub fn parse_openssl_options(option_list: &str) -> anyhow::Result<SslOptions> {
     let mut result = SslOptions::empty();
 
@@ -195,6 +209,9 @@ pub struct EgressPathConfig {
     #[serde(default = "EgressPathConfig::default_enable_pipelining")]
     pub enable_pipelining: bool,
 
+    #[serde(default = "EgressPathConfig::default_enable_chunking")]
+    pub enable_chunking: bool,
+
     #[serde(default = "EgressPathConfig::default_enable_rset")]
     pub enable_rset: bool,
 
@@ -252,6 +269,9 @@ pub struct EgressPathConfig {
     #[serde(default)]
     pub allow_smtp_auth_plain_without_tls: bool,
 
+    #[serde(default)]
+    pub smtp_auth_mechanism: SmtpAuthMechanism,
+
     #[serde(default)]
     pub max_message_rate: Option<ThrottleSpec>,
 
@@ -376,6 +396,7 @@ impl Default for EgressPathConfig {
             enable_dane: Self::default_enable_dane(),
             enable_rset: Self::default_enable_rset(),
             enable_pipelining: Self::default_enable_pipelining(),
+            enable_chunking: Self::default_enable_chunking(),
             max_ready: Self::default_max_ready(),
             consecutive_connection_failures_before_delay:
                 Self::default_consecutive_connection_failures_before_delay(),
@@ -390,6 +411,7 @@ impl Default for EgressPathConfig {
             skip_hosts: CidrSet::default(),
             ehlo_domain: None,
             allow_smtp_auth_plain_without_tls: false,
+            smtp_auth_mechanism: SmtpAuthMechanism::default(),
             smtp_auth_plain_username: None,
             smtp_auth_plain_password: None,
             aggressive_connection_opening: false,
@@ -435,6 +457,10 @@ impl EgressPathConfig {
         true
     }
 
+    fn default_enable_chunking() -> bool {
+        true
+    }
+
     fn default_enable_rset() -> bool {
         true
     }
@@ -6,6 +6,7 @@ use serde_with::{serde_as, OneOrMany};
 use spool::SpoolId;
 use std::collections::HashMap;
 use std::time::Duration;
+use throttle::ThrottleSpec;
 use url::Url;
 use utoipa::{IntoParams, ToResponse, ToSchema};
 use uuid::Uuid;
@@ -279,6 +280,126 @@ pub struct SuspendV1ListEntry {
     pub duration: Duration,
 }
 
+/// Describes a hold that should be placed on matching scheduled queues.
+/// While the hold is in effect, matching messages are not delivered.
+/// At `release_at` the held messages are rescheduled so that they
+/// are released gradually, at the rate specified by `release_rate`.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HoldV1Request {
+    /// The campaign name to match. If omitted, any campaign will match.
+    #[serde(default)]
+    #[schema(example = "campaign_name")]
+    pub campaign: Option<String>,
+    /// The tenant name to match. If omitted, any tenant will match.
+    #[serde(default)]
+    #[schema(example = "tenant_name")]
+    pub tenant: Option<String>,
+    /// The domain name to match. If omitted, any domain will match.
+    #[serde(default)]
+    #[schema(example = "example.com")]
+    pub domain: Option<String>,
+
+    /// The reason for the hold
+    #[schema(example = "hold until the destination maintenance window has ended")]
+    pub reason: String,
+
+    /// The time at which the held messages will be released.
+    /// The timestamp may use any timezone offset, for example
+    /// `2025-04-01T09:00:00-07:00`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_at: Option<DateTime<Utc>>,
+
+    /// Instead of specifying release_at, you can specify how long
+    /// from now the hold should remain in effect.
+    #[serde(
+        default,
+        with = "duration_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(example = "2h")]
+    pub duration: Option<Duration>,
+
+    /// The rate at which held messages are released once the hold
+    /// ends. If omitted, all held messages become due at the release
+    /// time, with up to a minute of jitter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type=Option<String>, example = "100/min")]
+    pub release_rate: Option<ThrottleSpec>,
+
+    /// If present, queue_names takes precedence over `campaign`,
+    /// `tenant`, and `domain` and specifies the exact set of
+    /// scheduled queue names to which the hold applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(example=json!(["campaign_name:tenant_name@example.com"]))]
+    pub queue_names: Vec<String>,
+}
+
+impl HoldV1Request {
+    /// Returns the time at which the hold ends
+    pub fn release_at(&self) -> DateTime<Utc> {
+        if let Some(release_at) = self.release_at {
+            return release_at;
+        }
+        let duration = self.duration.unwrap_or_else(default_duration);
+        chrono::Duration::from_std(duration)
+            .ok()
+            .and_then(|duration| Utc::now().checked_add_signed(duration))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToResponse, ToSchema)]
+pub struct HoldV1Response {
+    /// The id of the hold. This can be used later to cancel
+    /// the hold.
+    pub id: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct HoldV1CancelRequest {
+    /// The id of the hold to cancel
+    pub id: Uuid,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct HoldV1ListEntry {
+    /// The id of the hold. This can be used later to cancel
+    /// the hold.
+    pub id: Uuid,
+
+    /// The campaign name to match. If omitted, any campaign will match.
+    #[serde(default)]
+    #[schema(example = "campaign_name")]
+    pub campaign: Option<String>,
+    /// The tenant name to match. If omitted, any tenant will match.
+    #[serde(default)]
+    #[schema(example = "tenant_name")]
+    pub tenant: Option<String>,
+    /// The domain name to match. If omitted, any domain will match.
+    #[serde(default)]
+    #[schema(example = "example.com")]
+    pub domain: Option<String>,
+    /// The exact set of scheduled queue names to which the hold applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub queue_names: Vec<String>,
+
+    /// The reason for the hold
+    #[schema(example = "hold until the destination maintenance window has ended")]
+    pub reason: String,
+
+    /// The time at which the held messages will be released
+    pub release_at: DateTime<Utc>,
+
+    /// The rate at which held messages will be released
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type=Option<String>, example = "100/min")]
+    pub release_rate: Option<ThrottleSpec>,
+
+    /// The number of messages that have been held so far
+    pub num_held: usize,
+}
+
 #[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct SuspendReadyQueueV1Request {
     /// The name of the ready queue that should be suspended
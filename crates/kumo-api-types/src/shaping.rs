@@ -1770,6 +1770,8 @@ match_internal = true
                 egress_source: None,
                 source_address: None,
                 feedback_report: None,
+                dmarc_aggregate_report: None,
+                tls_report: None,
                 meta: Default::default(),
                 headers: Default::default(),
                 delivery_protocol: None,
@@ -1780,6 +1782,8 @@ match_internal = true
                 tls_peer_subject_name: None,
                 provider_name: None,
                 session_id: None,
+                trace_id: None,
+                span_id: None,
             }
         }
 
@@ -1944,6 +1948,7 @@ MergedEntry {
         enable_mta_sts: true,
         enable_dane: false,
         enable_pipelining: true,
+        enable_chunking: true,
         enable_rset: true,
         tls_prefer_openssl: false,
         tls_certificate: None,
@@ -2098,6 +2103,7 @@ MergedEntry {
         enable_mta_sts: true,
         enable_dane: false,
         enable_pipelining: true,
+        enable_chunking: true,
         enable_rset: true,
         tls_prefer_openssl: false,
         tls_certificate: None,
@@ -2169,6 +2175,7 @@ MergedEntry {
             enable_mta_sts: true,
             enable_dane: false,
             enable_pipelining: true,
+            enable_chunking: true,
             enable_rset: true,
             tls_prefer_openssl: false,
             tls_certificate: None,
@@ -2325,6 +2332,7 @@ MergedEntry {
         enable_mta_sts: true,
         enable_dane: false,
         enable_pipelining: true,
+        enable_chunking: true,
         enable_rset: true,
         tls_prefer_openssl: false,
         tls_certificate: None,
@@ -3,12 +3,18 @@
 use crate::types::record::Record;
 pub use crate::types::results::{Disposition, DispositionWithContext};
 use dns_resolver::Resolver;
-use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::time::SystemTime;
 
-pub use types::results::DmarcResult;
+pub use kumo_spf::SpfDisposition;
+pub use report::{
+    is_authorized_destination, AggregateReport, AggregateReportStore, DkimAuth, PolicyEvaluation,
+    ReportMetadataParams, ReportedMessage, SpfAuth,
+};
+pub use types::feedback_address::FeedbackAddress;
+pub use types::results::{DkimResult, DmarcResult};
 
+mod report;
 mod types;
 
 #[cfg(test)]
@@ -23,25 +29,49 @@ pub struct CheckHostParams {
     /// The "MAIL FROM" email address if available.
     pub mail_from_domain: Option<String>,
 
-    /// The results of the DKIM part of the checks
-    pub dkim: Vec<BTreeMap<String, String>>,
+    /// The results of verifying the DKIM signatures of the message.
+    /// A signature without a `d=` tag should be represented with
+    /// an empty `domain`.
+    pub dkim: Vec<DkimAuth>,
+
+    /// The result of the SPF check of the "MAIL FROM" identity,
+    /// or of the HELO identity if "MAIL FROM" was empty
+    pub spf: Option<SpfAuth>,
 }
 
 impl CheckHostParams {
     pub async fn check(self, resolver: &dyn Resolver) -> DispositionWithContext {
+        self.check_with_evaluation(resolver).await.0
+    }
+
+    /// Like `check`, but also returns the information about the
+    /// evaluated policy that is needed to include this result in
+    /// an aggregate report.  The evaluation is `None` when no
+    /// DMARC record was found.
+    pub async fn check_with_evaluation(
+        self,
+        resolver: &dyn Resolver,
+    ) -> (DispositionWithContext, Option<PolicyEvaluation>) {
         let Self {
             from_domain,
             mail_from_domain,
             dkim,
+            spf,
         } = self;
 
         match DmarcContext::new(
             &from_domain,
             mail_from_domain.as_ref().map(|x| x.as_str()),
             &dkim[..],
+            spf.as_ref(),
         ) {
-            Ok(cx) => cx.check(resolver).await,
-            Err(result) => result,
+            Ok(cx) => {
+                let (result, record) = cx.check(resolver).await;
+                let evaluation =
+                    record.map(|(domain, record)| record.policy_evaluation(&cx, &domain, &result));
+                (result, evaluation)
+            }
+            Err(result) => (result, None),
         }
     }
 }
@@ -82,7 +112,8 @@ struct DmarcContext<'a> {
     pub(crate) from_domain: &'a str,
     pub(crate) mail_from_domain: Option<&'a str>,
     pub(crate) now: SystemTime,
-    pub(crate) dkim: &'a [BTreeMap<String, String>],
+    pub(crate) dkim: &'a [DkimAuth],
+    pub(crate) spf: Option<&'a SpfAuth>,
 }
 
 impl<'a> DmarcContext<'a> {
@@ -90,25 +121,35 @@ impl<'a> DmarcContext<'a> {
     ///
     /// - `from_domain` is the domain of the "From:" header
     /// - `mail_from_domain` is the domain portion of the "MAIL FROM" identity
-    /// - `client_ip` is the IP address of the SMTP client that is emitting the mail
+    /// - `dkim` holds the results of verifying the DKIM signatures
+    /// - `spf` is the result of the SPF check
     fn new(
         from_domain: &'a str,
         mail_from_domain: Option<&'a str>,
-        dkim: &'a [BTreeMap<String, String>],
+        dkim: &'a [DkimAuth],
+        spf: Option<&'a SpfAuth>,
     ) -> Result<Self, DispositionWithContext> {
         Ok(Self {
             from_domain,
             mail_from_domain,
             now: SystemTime::now(),
             dkim,
+            spf,
         })
     }
 
-    pub async fn check(&self, resolver: &dyn Resolver) -> DispositionWithContext {
+    /// Evaluate the DMARC policy for the message, returning the
+    /// disposition along with the record that was used and the
+    /// domain at which that record was published
+    pub async fn check(
+        &self,
+        resolver: &dyn Resolver,
+    ) -> (DispositionWithContext, Option<(String, Record)>) {
         match fetch_dmarc_records(&format!("_dmarc.{}", self.from_domain), resolver).await {
             DmarcRecordResolution::Records(records) => {
-                for record in records {
-                    return record.evaluate(self, SenderDomainAlignment::Exact).await;
+                if let Some(record) = records.into_iter().next() {
+                    let result = record.evaluate(self, SenderDomainAlignment::Exact).await;
+                    return (result, Some((self.from_domain.to_string(), record)));
                 }
             }
             x => {
@@ -117,42 +158,61 @@ impl<'a> DmarcContext<'a> {
                         let address = format!("_dmarc.{}", organizational_domain);
                         match fetch_dmarc_records(&address, resolver).await {
                             DmarcRecordResolution::TempError => {
-                                return DispositionWithContext {
-                                    result: Disposition::TempError,
-                                    context: format!(
-                                        "DNS records could not be resolved for {}",
-                                        address
-                                    ),
-                                }
+                                return (
+                                    DispositionWithContext {
+                                        result: Disposition::TempError,
+                                        context: format!(
+                                            "DNS records could not be resolved for {}",
+                                            address
+                                        ),
+                                    },
+                                    None,
+                                )
                             }
                             DmarcRecordResolution::PermError => {
-                                return DispositionWithContext {
-                                    result: Disposition::PermError,
-                                    context: format!("no DMARC records found for {}", address),
-                                }
+                                return (
+                                    DispositionWithContext {
+                                        result: Disposition::PermError,
+                                        context: format!("no DMARC records found for {}", address),
+                                    },
+                                    None,
+                                )
                             }
                             DmarcRecordResolution::Records(records) => {
-                                for record in records {
-                                    return record
+                                if let Some(record) = records.into_iter().next() {
+                                    let result = record
                                         .evaluate(self, SenderDomainAlignment::OrganizationalDomain)
                                         .await;
+                                    return (
+                                        result,
+                                        Some((organizational_domain.to_string(), record)),
+                                    );
                                 }
                             }
                         }
                     } else {
-                        return DispositionWithContext {
-                            result: x.into(),
-                            context: format!("no DMARC records found for {}", &self.from_domain),
-                        };
+                        return (
+                            DispositionWithContext {
+                                result: x.into(),
+                                context: format!(
+                                    "no DMARC records found for {}",
+                                    &self.from_domain
+                                ),
+                            },
+                            None,
+                        );
                     }
                 }
             }
         }
 
-        DispositionWithContext {
-            result: Disposition::None,
-            context: format!("no DMARC records found for {}", &self.from_domain),
-        }
+        (
+            DispositionWithContext {
+                result: Disposition::None,
+                context: format!("no DMARC records found for {}", &self.from_domain),
+            },
+            None,
+        )
     }
 }
 
@@ -0,0 +1,431 @@
+//! Aggregate (`rua`) reporting, as described by
+//! <https://datatracker.ietf.org/doc/html/rfc7489#section-7.2>
+
+use crate::types::date_range::DateRange;
+use crate::types::feedback::Feedback;
+use crate::types::feedback_address::FeedbackAddress;
+use crate::types::identifier::Identifier;
+use crate::types::policy_published::PolicyPublished;
+use crate::types::report_metadata::ReportMetadata;
+use crate::types::results::{
+    AuthResults, DkimAuthResult, DkimResult, PolicyEvaluated, Results, Row, SpfAuthResult, SpfScope,
+};
+use chrono::{DateTime, Utc};
+use dns_resolver::Resolver;
+use kumo_spf::SpfDisposition;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// The published policy, and the outcome of evaluating it, for a message.
+/// Produced by `CheckHostParams::check_with_evaluation`.
+#[derive(Debug, Clone)]
+pub struct PolicyEvaluation {
+    pub(crate) policy_published: PolicyPublished,
+    pub(crate) rua: Vec<FeedbackAddress>,
+    pub(crate) policy_evaluated: PolicyEvaluated,
+}
+
+impl PolicyEvaluation {
+    /// The domain at which the DMARC record was published
+    pub fn policy_domain(&self) -> &str {
+        &self.policy_published.domain
+    }
+
+    /// The aggregate report destinations listed in the `rua` tag
+    pub fn rua(&self) -> &[FeedbackAddress] {
+        &self.rua
+    }
+}
+
+/// A DKIM verification result to be included in a report
+#[derive(Debug, Clone)]
+pub struct DkimAuth {
+    pub domain: String,
+    pub selector: Option<String>,
+    pub result: DkimResult,
+}
+
+/// An SPF verification result to be included in a report
+#[derive(Debug, Clone)]
+pub struct SpfAuth {
+    pub domain: String,
+    pub result: SpfDisposition,
+}
+
+/// Information about an evaluated message that is included in a report
+#[derive(Debug, Clone)]
+pub struct ReportedMessage {
+    pub source_ip: IpAddr,
+    pub header_from: String,
+    pub envelope_from: Option<String>,
+    pub envelope_to: Option<String>,
+    pub dkim: Vec<DkimAuth>,
+    /// When `None`, the SPF result is reported as `none`
+    pub spf: Option<SpfAuth>,
+}
+
+/// The `report_metadata` section of a report
+#[derive(Debug, Clone)]
+pub struct ReportMetadataParams {
+    pub org_name: String,
+    pub email: String,
+    pub extra_contact_info: Option<String>,
+    pub report_id: String,
+    pub begin: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct PolicyDomainResults {
+    policy_published: PolicyPublished,
+    rua: Vec<FeedbackAddress>,
+    /// The results are keyed with a zero count; the value
+    /// is the number of messages with that result
+    records: HashMap<Results, u64>,
+}
+
+/// Accumulates DMARC evaluations for each policy domain
+#[derive(Debug, Default)]
+pub struct AggregateReportStore {
+    domains: HashMap<String, PolicyDomainResults>,
+}
+
+impl AggregateReportStore {
+    /// Record the outcome of evaluating the DMARC policy for a message
+    pub fn record(&mut self, evaluation: PolicyEvaluation, message: &ReportedMessage) {
+        let PolicyEvaluation {
+            policy_published,
+            rua,
+            policy_evaluated,
+        } = evaluation;
+
+        let results = Results {
+            row: Row {
+                source_ip: message.source_ip,
+                count: 0,
+                policy_evaluated,
+            },
+            identifiers: Identifier {
+                envelope_to: message.envelope_to.clone(),
+                envelope_from: message.envelope_from.clone().unwrap_or_default(),
+                header_from: message.header_from.clone(),
+            },
+            auth_results: AuthResults {
+                dkim: message
+                    .dkim
+                    .iter()
+                    .map(|dkim| DkimAuthResult {
+                        domain: dkim.domain.clone(),
+                        selector: dkim.selector.clone(),
+                        result: dkim.result,
+                        human_result: None,
+                    })
+                    .collect(),
+                spf: vec![match &message.spf {
+                    Some(spf) => SpfAuthResult {
+                        domain: spf.domain.clone(),
+                        scope: SpfScope::Mfrom,
+                        result: spf.result,
+                    },
+                    None => SpfAuthResult {
+                        domain: message
+                            .envelope_from
+                            .clone()
+                            .unwrap_or_else(|| message.header_from.clone()),
+                        scope: SpfScope::Mfrom,
+                        result: SpfDisposition::None,
+                    },
+                }],
+            },
+        };
+
+        let entry = self
+            .domains
+            .entry(policy_published.domain.clone())
+            .or_insert_with(|| PolicyDomainResults {
+                policy_published: policy_published.clone(),
+                rua: rua.clone(),
+                records: HashMap::new(),
+            });
+        // Report the most recently observed policy
+        entry.policy_published = policy_published;
+        entry.rua = rua;
+        *entry.records.entry(results).or_default() += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Take the accumulated results, leaving the store empty
+    pub fn take(&mut self) -> Vec<AggregateReport> {
+        let reports = self.reports();
+        self.domains.clear();
+        reports
+    }
+
+    /// Returns a copy of the accumulated results, leaving the store
+    /// unchanged. The results can be restored with `merge`.
+    pub fn reports(&self) -> Vec<AggregateReport> {
+        let mut reports: Vec<AggregateReport> = self
+            .domains
+            .values()
+            .map(|domain| {
+                let mut records: Vec<Results> = domain
+                    .records
+                    .iter()
+                    .map(|(results, count)| {
+                        let mut results = results.clone();
+                        results.row.count = *count;
+                        results
+                    })
+                    .collect();
+                records.sort_by(|a, b| {
+                    b.row
+                        .count
+                        .cmp(&a.row.count)
+                        .then_with(|| a.row.source_ip.cmp(&b.row.source_ip))
+                        .then_with(|| a.identifiers.header_from.cmp(&b.identifiers.header_from))
+                });
+                AggregateReport {
+                    policy_published: domain.policy_published.clone(),
+                    rua: domain.rua.clone(),
+                    records,
+                }
+            })
+            .collect();
+        reports.sort_by(|a, b| a.policy_domain().cmp(b.policy_domain()));
+        reports
+    }
+
+    /// Fold previously accumulated results back into the store,
+    /// summing the counts of matching results. The policy that has
+    /// been recorded since takes precedence over the merged policy.
+    pub fn merge(&mut self, reports: Vec<AggregateReport>) {
+        for report in reports {
+            let entry = self
+                .domains
+                .entry(report.policy_published.domain.clone())
+                .or_insert_with(|| PolicyDomainResults {
+                    policy_published: report.policy_published,
+                    rua: report.rua,
+                    records: HashMap::new(),
+                });
+            for mut results in report.records {
+                let count = std::mem::take(&mut results.row.count);
+                *entry.records.entry(results).or_default() += count;
+            }
+        }
+    }
+}
+
+/// The accumulated results for a single policy domain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregateReport {
+    policy_published: PolicyPublished,
+    rua: Vec<FeedbackAddress>,
+    records: Vec<Results>,
+}
+
+impl AggregateReport {
+    /// The domain at which the DMARC record was published
+    pub fn policy_domain(&self) -> &str {
+        &self.policy_published.domain
+    }
+
+    /// The destinations to which the report should be sent
+    pub fn rua(&self) -> &[FeedbackAddress] {
+        &self.rua
+    }
+
+    /// The total number of messages covered by this report
+    pub fn message_count(&self) -> u64 {
+        self.records.iter().map(|r| r.row.count).sum()
+    }
+
+    /// Serialize the report as RFC 7489 Appendix C aggregate XML
+    pub fn to_xml(self, metadata: ReportMetadataParams) -> Result<String, instant_xml::Error> {
+        let feedback = Feedback {
+            version: "1.0".to_string(),
+            metadata: ReportMetadata {
+                org_name: metadata.org_name,
+                email: metadata.email,
+                extra_contact_info: metadata.extra_contact_info,
+                report_id: metadata.report_id,
+                date_range: DateRange {
+                    begin: metadata.begin,
+                    end: metadata.end,
+                },
+                error: vec![],
+            },
+            policy: self.policy_published,
+            record: self.records,
+        };
+
+        Ok(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}",
+            instant_xml::to_string(&feedback)?
+        ))
+    }
+}
+
+/// Check whether `destination_domain` is permitted to receive reports
+/// about `policy_domain`.  When the destination is outside of the
+/// organizational domain of the policy domain, the destination must
+/// publish a `<policy_domain>._report._dmarc.<destination_domain>` record.
+/// <https://datatracker.ietf.org/doc/html/rfc7489#section-7.1>
+pub async fn is_authorized_destination(
+    policy_domain: &str,
+    destination_domain: &str,
+    resolver: &dyn Resolver,
+) -> bool {
+    fn organizational_domain(domain: &str) -> &str {
+        psl::domain_str(domain).unwrap_or(domain)
+    }
+
+    if organizational_domain(policy_domain) == organizational_domain(destination_domain) {
+        return true;
+    }
+
+    match resolver
+        .resolve_txt(&format!(
+            "{policy_domain}._report._dmarc.{destination_domain}"
+        ))
+        .await
+    {
+        Ok(answer) => answer
+            .as_txt()
+            .iter()
+            .any(|txt| txt.starts_with("v=DMARC1")),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CheckHostParams;
+    use chrono::TimeZone;
+    use dns_resolver::TestResolver;
+
+    async fn evaluate(
+        resolver: &TestResolver,
+        dkim_domain: &str,
+        spf: SpfDisposition,
+    ) -> PolicyEvaluation {
+        let message = message("10.0.0.1", dkim_domain, spf);
+        let (_result, evaluation) = CheckHostParams {
+            from_domain: "example.com".to_string(),
+            mail_from_domain: Some("example.com".to_string()),
+            dkim: message.dkim,
+            spf: message.spf,
+        }
+        .check_with_evaluation(resolver)
+        .await;
+        evaluation.expect("record was found")
+    }
+
+    fn message(source_ip: &str, dkim_domain: &str, spf: SpfDisposition) -> ReportedMessage {
+        ReportedMessage {
+            source_ip: source_ip.parse().unwrap(),
+            header_from: "example.com".to_string(),
+            envelope_from: Some("example.com".to_string()),
+            envelope_to: None,
+            dkim: vec![DkimAuth {
+                domain: dkim_domain.to_string(),
+                selector: Some("s1".to_string()),
+                result: DkimResult::Pass,
+            }],
+            spf: Some(SpfAuth {
+                domain: "example.com".to_string(),
+                result: spf,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate_xml() {
+        let resolver = TestResolver::default().with_txt(
+            "_dmarc.example.com",
+            "v=DMARC1; p=reject; rua=mailto:dmarc@example.com!10m".to_string(),
+        );
+
+        let mut store = AggregateReportStore::default();
+
+        let evaluation = evaluate(&resolver, "example.com", SpfDisposition::Pass).await;
+        assert_eq!(evaluation.policy_domain(), "example.com");
+        assert_eq!(evaluation.rua()[0].uri, "mailto:dmarc@example.com");
+        let passed = message("10.0.0.1", "example.com", SpfDisposition::Pass);
+        store.record(evaluation.clone(), &passed);
+        store.record(evaluation, &passed);
+
+        let evaluation = evaluate(&resolver, "other.example.net", SpfDisposition::Fail).await;
+        store.record(
+            evaluation,
+            &message("10.0.0.2", "other.example.net", SpfDisposition::Fail),
+        );
+
+        // A copy of the results can be merged into another store
+        let saved = serde_json::to_string(&store.reports()).unwrap();
+        let mut restored = AggregateReportStore::default();
+        restored.merge(serde_json::from_str(&saved).unwrap());
+        restored.merge(serde_json::from_str(&saved).unwrap());
+        let restored = restored.take();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].message_count(), 6);
+        assert_eq!(restored[0].records.len(), 2);
+
+        let reports = store.take();
+        assert!(store.is_empty());
+        assert_eq!(reports.len(), 1);
+
+        let report = reports.into_iter().next().unwrap();
+        assert_eq!(report.message_count(), 3);
+
+        let xml = report
+            .to_xml(ReportMetadataParams {
+                org_name: "Example Receiver".to_string(),
+                email: "postmaster@receiver.example".to_string(),
+                extra_contact_info: None,
+                report_id: "REPORTID".to_string(),
+                begin: Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap(),
+                end: Utc.with_ymd_and_hms(2024, 3, 5, 23, 59, 59).unwrap(),
+            })
+            .unwrap();
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feedback>"));
+        for expected in [
+            "<org_name>Example Receiver</org_name>",
+            "<report_id>REPORTID</report_id>",
+            "<date_range><begin>1709596800</begin><end>1709683199</end></date_range>",
+            "<domain>example.com</domain><adkim>r</adkim><aspf>r</aspf><p>reject</p><sp>reject</sp><pct>100</pct><fo>0</fo>",
+            "<row><source_ip>10.0.0.1</source_ip><count>2</count>\
+             <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>pass</spf></policy_evaluated></row>",
+            "<row><source_ip>10.0.0.2</source_ip><count>1</count>\
+             <policy_evaluated><disposition>reject</disposition><dkim>fail</dkim><spf>fail</spf></policy_evaluated></row>",
+            "<dkim><domain>other.example.net</domain><selector>s1</selector><result>pass</result></dkim>",
+            "<spf><domain>example.com</domain><scope>mfrom</scope><result>pass</result></spf>",
+            "<spf><domain>example.com</domain><scope>mfrom</scope><result>fail</result></spf>",
+        ] {
+            assert!(xml.contains(expected), "{expected} not found in {xml}");
+        }
+    }
+
+    #[tokio::test]
+    async fn external_destination() {
+        let resolver = TestResolver::default().with_txt(
+            "example.com._report._dmarc.thirdparty.example.net",
+            "v=DMARC1".to_string(),
+        );
+
+        assert!(is_authorized_destination("example.com", "example.com", &resolver).await);
+        assert!(is_authorized_destination("sub.example.com", "example.com", &resolver).await);
+        assert!(
+            is_authorized_destination("example.com", "thirdparty.example.net", &resolver).await
+        );
+        assert!(
+            !is_authorized_destination("example.org", "thirdparty.example.net", &resolver).await
+        );
+    }
+}
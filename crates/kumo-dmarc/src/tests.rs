@@ -1,12 +1,14 @@
 use crate::types::results::DispositionWithContext;
-use crate::{Disposition, DmarcContext};
+use crate::{Disposition, DkimAuth, DkimResult, DmarcContext, SpfAuth, SpfDisposition};
 use dns_resolver::{Resolver, TestResolver};
-use std::collections::BTreeMap;
 
 struct TestData<'a> {
     from_domain: &'a str,
     mail_from_domain: &'a str,
+    /// Passing DKIM signatures; None represents a signature without d=
     dkim_domains: &'a [Option<&'a str>],
+    /// The result of the SPF check of mail_from_domain
+    spf: Option<SpfDisposition>,
     resolver: &'a dyn Resolver,
 }
 
@@ -26,6 +28,7 @@ async fn dmarc_dkim_relaxed_subdomain() {
         from_domain: "sample.example.com",
         mail_from_domain: "sample.example.com",
         dkim_domains: &[Some("example.com")],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -49,6 +52,7 @@ async fn dmarc_dkim_relaxed_subdomain_deep() {
         from_domain: "a.b.c.sample.example.com",
         mail_from_domain: "a.b.c.sample.example.com",
         dkim_domains: &[Some("example.com")],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -72,6 +76,7 @@ async fn dmarc_dkim_relaxed_subdomain_fail() {
         from_domain: "sample.example.com",
         mail_from_domain: "sample.example.com",
         dkim_domains: &[Some("example.org")],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -95,6 +100,7 @@ async fn dmarc_dkim_relaxed_subdomain_sp_quarantine_fail() {
         from_domain: "sample.example.com",
         mail_from_domain: "sample.example.com",
         dkim_domains: &[Some("example.org")],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -118,6 +124,7 @@ async fn dmarc_dkim_strict_subdomain() {
         from_domain: "example.com",
         mail_from_domain: "example.com",
         dkim_domains: &[Some("example.com")],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -141,6 +148,7 @@ async fn dmarc_dkim_strict_subdomain_fail() {
         from_domain: "sample.example.com",
         mail_from_domain: "example.com",
         dkim_domains: &[Some("example.com")],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -164,6 +172,7 @@ async fn dmarc_dkim_relaxed_illformed() {
         from_domain: "example.com",
         mail_from_domain: "example.com",
         dkim_domains: &[None],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -188,6 +197,7 @@ async fn dmarc_dkim_strict_illformed() {
         from_domain: "example.com",
         mail_from_domain: "example.com",
         dkim_domains: &[None],
+        spf: None,
         resolver: &resolver,
     })
     .await;
@@ -212,6 +222,7 @@ async fn dmarc_spf_relaxed_subdomain() {
         from_domain: "example.com",
         mail_from_domain: "helper.example.com",
         dkim_domains: &[],
+        spf: Some(SpfDisposition::Pass),
         resolver: &resolver,
     })
     .await;
@@ -235,6 +246,7 @@ async fn dmarc_spf_relaxed_subdomain_deep() {
         from_domain: "example.com",
         mail_from_domain: "a.b.c.helper.example.com",
         dkim_domains: &[],
+        spf: Some(SpfDisposition::Pass),
         resolver: &resolver,
     })
     .await;
@@ -258,6 +270,7 @@ async fn dmarc_spf_relaxed_subdomain_fail() {
         from_domain: "example.com",
         mail_from_domain: "helper.example.org",
         dkim_domains: &[],
+        spf: Some(SpfDisposition::Pass),
         resolver: &resolver,
     })
     .await;
@@ -281,6 +294,7 @@ async fn dmarc_spf_strict_subdomain() {
         from_domain: "example.com",
         mail_from_domain: "helper.example.com",
         dkim_domains: &[],
+        spf: Some(SpfDisposition::Pass),
         resolver: &resolver,
     })
     .await;
@@ -310,6 +324,7 @@ async fn dmarc_pct_rate() {
             from_domain: "example.com",
             mail_from_domain: "helper.example.com",
             dkim_domains: &[],
+            spf: Some(SpfDisposition::Pass),
             resolver: &resolver,
         })
         .await;
@@ -327,29 +342,152 @@ async fn dmarc_pct_rate() {
     k9::assert_greater_than!(total_failures, lower_bound);
 }
 
+#[tokio::test]
+async fn dmarc_dkim_relaxed_signing_subdomain() {
+    let resolver = TestResolver::default()
+        .with_zone(EXAMPLE_COM)
+        .unwrap()
+        .with_txt(
+            "_dmarc.example.com",
+            "v=DMARC1; p=reject; adkim=r; \
+            rua=mailto:dmarc-feedback@example.com"
+                .to_string(),
+        );
+
+    let result = evaluate_ip(TestData {
+        from_domain: "example.com",
+        mail_from_domain: "example.com",
+        dkim_domains: &[Some("mail.example.com")],
+        spf: None,
+        resolver: &resolver,
+    })
+    .await;
+
+    k9::assert_equal!(result.result, Disposition::Pass);
+}
+
+#[tokio::test]
+async fn dmarc_no_authentication_results() {
+    let resolver = TestResolver::default()
+        .with_zone(EXAMPLE_COM)
+        .unwrap()
+        .with_txt(
+            "_dmarc.example.com",
+            "v=DMARC1; p=reject; \
+            rua=mailto:dmarc-feedback@example.com"
+                .to_string(),
+        );
+
+    let result = evaluate_ip(TestData {
+        from_domain: "example.com",
+        mail_from_domain: "example.com",
+        dkim_domains: &[],
+        spf: None,
+        resolver: &resolver,
+    })
+    .await;
+
+    k9::assert_equal!(result.result, Disposition::Reject);
+}
+
+#[tokio::test]
+async fn dmarc_dkim_not_passing() {
+    let resolver = TestResolver::default()
+        .with_zone(EXAMPLE_COM)
+        .unwrap()
+        .with_txt(
+            "_dmarc.example.com",
+            "v=DMARC1; p=reject; \
+            rua=mailto:dmarc-feedback@example.com"
+                .to_string(),
+        );
+
+    let dkim = [DkimAuth {
+        domain: "example.com".to_string(),
+        selector: None,
+        result: DkimResult::Fail,
+    }];
+    let (result, _) = DmarcContext::new("example.com", Some("example.com"), &dkim, None)
+        .unwrap()
+        .check(&resolver)
+        .await;
+
+    k9::assert_equal!(result.result, Disposition::Reject);
+    k9::assert_equal!(result.context.contains("no passing DKIM"), true);
+}
+
+#[tokio::test]
+async fn dmarc_spf_not_passing() {
+    let resolver = TestResolver::default()
+        .with_zone(EXAMPLE_COM)
+        .unwrap()
+        .with_txt(
+            "_dmarc.example.com",
+            "v=DMARC1; p=reject; \
+            rua=mailto:dmarc-feedback@example.com"
+                .to_string(),
+        );
+
+    let result = evaluate_ip(TestData {
+        from_domain: "example.com",
+        mail_from_domain: "example.com",
+        dkim_domains: &[],
+        spf: Some(SpfDisposition::SoftFail),
+        resolver: &resolver,
+    })
+    .await;
+
+    k9::assert_equal!(result.result, Disposition::Reject);
+}
+
+#[tokio::test]
+async fn dmarc_spf_aligned_dkim_unaligned() {
+    let resolver = TestResolver::default()
+        .with_zone(EXAMPLE_COM)
+        .unwrap()
+        .with_txt(
+            "_dmarc.example.com",
+            "v=DMARC1; p=reject; \
+            rua=mailto:dmarc-feedback@example.com"
+                .to_string(),
+        );
+
+    let result = evaluate_ip(TestData {
+        from_domain: "example.com",
+        mail_from_domain: "example.com",
+        dkim_domains: &[Some("example.org")],
+        spf: Some(SpfDisposition::Pass),
+        resolver: &resolver,
+    })
+    .await;
+
+    k9::assert_equal!(result.result, Disposition::Pass);
+}
+
 async fn evaluate_ip<'a>(
     TestData {
         from_domain,
         mail_from_domain,
         dkim_domains,
+        spf,
         resolver,
     }: TestData<'a>,
 ) -> DispositionWithContext {
-    let mut dkim_vec = vec![];
-
-    for dkim_domain in dkim_domains {
-        if let Some(dkim_domain) = dkim_domain {
-            let mut map = BTreeMap::new();
-            map.insert("header.d".to_string(), dkim_domain.to_string());
-
-            dkim_vec.push(map);
-        } else {
-            dkim_vec.push(BTreeMap::new());
-        }
-    }
-
-    match DmarcContext::new(from_domain, Some(mail_from_domain), &dkim_vec) {
-        Ok(cx) => cx.check(resolver).await,
+    let dkim_vec: Vec<DkimAuth> = dkim_domains
+        .iter()
+        .map(|dkim_domain| DkimAuth {
+            domain: dkim_domain.unwrap_or_default().to_string(),
+            selector: None,
+            result: DkimResult::Pass,
+        })
+        .collect();
+    let spf = spf.map(|result| SpfAuth {
+        domain: mail_from_domain.to_string(),
+        result,
+    });
+
+    match DmarcContext::new(from_domain, Some(mail_from_domain), &dkim_vec, spf.as_ref()) {
+        Ok(cx) => cx.check(resolver).await.0,
         Err(result) => result,
     }
 }
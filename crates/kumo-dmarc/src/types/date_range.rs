@@ -3,8 +3,8 @@ use instant_xml::ToXml;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct DateRange {
-    begin: DateTime<Utc>,
-    end: DateTime<Utc>,
+    pub(crate) begin: DateTime<Utc>,
+    pub(crate) end: DateTime<Utc>,
 }
 
 impl ToXml for DateRange {
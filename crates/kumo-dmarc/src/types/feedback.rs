@@ -4,9 +4,10 @@ use crate::types::results::Results;
 use instant_xml::ToXml;
 
 #[derive(Debug, Eq, PartialEq, ToXml)]
+#[xml(rename = "feedback")]
 pub struct Feedback {
-    version: String,
-    metadata: ReportMetadata,
-    policy: PolicyPublished,
-    record: Vec<Results>,
+    pub(crate) version: String,
+    pub(crate) metadata: ReportMetadata,
+    pub(crate) policy: PolicyPublished,
+    pub(crate) record: Vec<Results>,
 }
@@ -1,8 +1,10 @@
 use instant_xml::ToXml;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
+#[xml(rename = "identifiers")]
 pub struct Identifier {
-    envelope_to: Option<String>,
-    envelope_from: String,
-    header_from: String,
+    pub(crate) envelope_to: Option<String>,
+    pub(crate) envelope_from: String,
+    pub(crate) header_from: String,
 }
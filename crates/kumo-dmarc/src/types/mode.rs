@@ -1,13 +1,23 @@
 use instant_xml::ToXml;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, ToXml)]
-#[xml(scalar)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Mode {
     Relaxed,
     Strict,
 }
 
+impl ToXml for Mode {
+    fn serialize<W: std::fmt::Write + ?Sized>(
+        &self,
+        field: Option<instant_xml::Id<'_>>,
+        serializer: &mut instant_xml::Serializer<W>,
+    ) -> Result<(), instant_xml::Error> {
+        char::from(*self).to_string().serialize(field, serializer)
+    }
+}
+
 impl From<Mode> for char {
     fn from(value: Mode) -> Self {
         match value {
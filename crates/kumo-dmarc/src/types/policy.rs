@@ -1,8 +1,9 @@
 use instant_xml::ToXml;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, ToXml)]
-#[xml(scalar)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
+#[xml(scalar, rename_all = "lowercase")]
 pub enum Policy {
     None,
     Quarantine,
@@ -1,7 +1,8 @@
 use instant_xml::ToXml;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
-#[xml(scalar, rename_all = "lowercase")]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
+#[xml(scalar, rename_all = "snake_case")]
 pub enum PolicyOverride {
     Forwarded,
     SampledOut,
@@ -11,9 +12,9 @@ pub enum PolicyOverride {
     Other,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "reason")]
 pub struct PolicyOverrideReason {
-    r#type: PolicyOverride,
-    comment: Option<String>,
+    pub(crate) r#type: PolicyOverride,
+    pub(crate) comment: Option<String>,
 }
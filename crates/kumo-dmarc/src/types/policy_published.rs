@@ -2,21 +2,22 @@ use crate::types::mode::Mode;
 use crate::types::policy::Policy;
 use crate::types::report_failure::ReportFailure;
 use instant_xml::ToXml;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "policy_published")]
 pub struct PolicyPublished {
-    domain: String,
+    pub(crate) domain: String,
     #[xml(rename = "adkim")]
-    align_dkim: Option<Mode>,
+    pub(crate) align_dkim: Option<Mode>,
     #[xml(rename = "aspf")]
-    align_spf: Option<Mode>,
+    pub(crate) align_spf: Option<Mode>,
     #[xml(rename = "p")]
-    policy: Policy,
+    pub(crate) policy: Policy,
     #[xml(rename = "sp")]
-    subdomain_policy: Policy,
+    pub(crate) subdomain_policy: Policy,
     #[xml(rename = "pct")]
-    rate: u8,
+    pub(crate) rate: u8,
     #[xml(rename = "fo")]
-    report_failure: ReportFailure,
+    pub(crate) report_failure: ReportFailure,
 }
@@ -1,10 +1,16 @@
+use crate::report::PolicyEvaluation;
 use crate::types::feedback_address::FeedbackAddress;
 use crate::types::format::Format;
 use crate::types::mode::Mode;
 use crate::types::policy::Policy;
+use crate::types::policy_override::{PolicyOverride, PolicyOverrideReason};
+use crate::types::policy_published::PolicyPublished;
 use crate::types::report_failure::ReportFailure;
-use crate::types::results::{Disposition, DispositionWithContext};
+use crate::types::results::{
+    Disposition, DispositionWithContext, DkimResult, DmarcResult, PolicyEvaluated,
+};
 use crate::{DmarcContext, SenderDomainAlignment};
+use kumo_spf::SpfDisposition;
 use std::str::FromStr;
 
 #[derive(Debug)]
@@ -33,75 +39,125 @@ impl Record {
                 context: format!("sampled_out due to pct={}", self.rate),
             };
         }
-        match self.align_dkim {
-            Mode::Relaxed => {
-                for dkim in cx.dkim {
-                    if let Some(result) = dkim.get("header.d") {
-                        let organizational_domain = psl::domain_str(cx.from_domain);
-
-                        if cx.from_domain != result && organizational_domain != Some(result) {
-                            return DispositionWithContext {
-                                result: self.select_failure_mode(sender_location),
-                                context: "DMARC: DKIM relaxed check failed".into(),
-                            };
-                        }
-                    } else {
-                        return DispositionWithContext {
-                            result: self.select_failure_mode(sender_location),
-                            context: "DMARC: DKIM signature missing 'd=' tag".into(),
-                        };
-                    }
-                }
+        // The message passes if either of the DKIM or SPF
+        // authentication results pass and are aligned
+        match (self.check_dkim_alignment(cx), self.check_spf_alignment(cx)) {
+            (Ok(()), _) | (_, Ok(())) => DispositionWithContext {
+                result: Disposition::Pass,
+                context: "Success".into(),
+            },
+            (Err(dkim), Err(spf)) => DispositionWithContext {
+                result: self.select_failure_mode(sender_location),
+                context: format!("{dkim}; {spf}"),
+            },
+        }
+    }
+
+    fn is_aligned(mode: Mode, from_domain: &str, domain: &str) -> bool {
+        let from_domain = from_domain.to_ascii_lowercase();
+        let domain = domain.to_ascii_lowercase();
+        if from_domain == domain {
+            return true;
+        }
+        match mode {
+            // The organizational domains must match
+            Mode::Relaxed => psl::domain_str(&from_domain)
+                .is_some_and(|organizational| psl::domain_str(&domain) == Some(organizational)),
+            Mode::Strict => false,
+        }
+    }
+
+    /// Succeeds if at least one DKIM signature passed verification
+    /// and has a `d=` domain that is aligned with the From domain
+    fn check_dkim_alignment(&self, cx: &DmarcContext<'_>) -> Result<(), &'static str> {
+        let mut failure = "DMARC: no DKIM signature";
+        for dkim in cx.dkim {
+            if dkim.domain.is_empty() {
+                failure = "DMARC: DKIM signature missing 'd=' tag";
+                continue;
             }
-            Mode::Strict => {
-                for dkim in cx.dkim {
-                    if let Some(result) = dkim.get("header.d") {
-                        if cx.from_domain != result {
-                            return DispositionWithContext {
-                                result: self.select_failure_mode(sender_location),
-                                context: "DMARC: DKIM strict check failed".into(),
-                            };
-                        }
-                    } else {
-                        return DispositionWithContext {
-                            result: self.select_failure_mode(sender_location),
-                            context: "DMARC: DKIM signature missing 'd=' tag".into(),
-                        };
-                    }
-                }
+            if dkim.result != DkimResult::Pass {
+                failure = "DMARC: no passing DKIM signature";
+                continue;
             }
+            if Self::is_aligned(self.align_dkim, cx.from_domain, &dkim.domain) {
+                return Ok(());
+            }
+            failure = match self.align_dkim {
+                Mode::Relaxed => "DMARC: DKIM relaxed check failed",
+                Mode::Strict => "DMARC: DKIM strict check failed",
+            };
         }
+        Err(failure)
+    }
 
+    /// Succeeds if SPF passed for an identity that is aligned
+    /// with the From domain
+    fn check_spf_alignment(&self, cx: &DmarcContext<'_>) -> Result<(), &'static str> {
+        let Some(spf) = cx.spf else {
+            return Err("DMARC: no SPF result");
+        };
+        if spf.result != SpfDisposition::Pass {
+            return Err("DMARC: SPF did not pass");
+        }
+        if Self::is_aligned(self.align_spf, cx.from_domain, &spf.domain) {
+            return Ok(());
+        }
         match self.align_spf {
-            Mode::Relaxed => {
-                if let Some(mail_from_domain) = cx.mail_from_domain {
-                    let organizational_domain = psl::domain_str(mail_from_domain);
-
-                    if mail_from_domain != cx.from_domain
-                        && organizational_domain != Some(cx.from_domain)
-                    {
-                        return DispositionWithContext {
-                            result: self.select_failure_mode(sender_location),
-                            context: "DMARC: SPF relaxed check failed".into(),
-                        };
-                    }
-                }
-            }
-            Mode::Strict => {
-                if let Some(mail_from_domain) = cx.mail_from_domain {
-                    if mail_from_domain != cx.from_domain {
-                        return DispositionWithContext {
-                            result: self.select_failure_mode(sender_location),
-                            context: "DMARC: SPF strict check failed".into(),
-                        };
-                    }
-                }
-            }
+            Mode::Relaxed => Err("DMARC: SPF relaxed check failed"),
+            Mode::Strict => Err("DMARC: SPF strict check failed"),
         }
+    }
+
+    /// Produce the information about this record and the outcome of
+    /// evaluating it that is required for an aggregate report.
+    /// `domain` is the domain at which this record was published.
+    pub(crate) fn policy_evaluation(
+        &self,
+        cx: &DmarcContext<'_>,
+        domain: &str,
+        result: &DispositionWithContext,
+    ) -> PolicyEvaluation {
+        let alignment = |check: Result<(), &'static str>| match check {
+            Ok(()) => DmarcResult::Pass,
+            Err(_) => DmarcResult::Fail,
+        };
+        let dkim = alignment(self.check_dkim_alignment(cx));
+        let spf = alignment(self.check_spf_alignment(cx));
+
+        let (disposition, reason) = match result.result {
+            Disposition::Quarantine => (Policy::Quarantine, vec![]),
+            Disposition::Reject => (Policy::Reject, vec![]),
+            Disposition::Pass if dkim == DmarcResult::Fail && spf == DmarcResult::Fail => {
+                // The policy was not applied due to the pct tag
+                (
+                    Policy::None,
+                    vec![PolicyOverrideReason {
+                        r#type: PolicyOverride::SampledOut,
+                        comment: None,
+                    }],
+                )
+            }
+            _ => (Policy::None, vec![]),
+        };
 
-        DispositionWithContext {
-            result: Disposition::Pass,
-            context: "Success".into(),
+        PolicyEvaluation {
+            policy_published: PolicyPublished {
+                domain: domain.to_string(),
+                align_dkim: Some(self.align_dkim),
+                align_spf: Some(self.align_spf),
+                policy: self.policy,
+                subdomain_policy: self.subdomain_policy.unwrap_or(self.policy),
+                rate: self.rate,
+                report_failure: self.report_failure,
+            },
+            rua: self.aggregate_feedback.clone(),
+            policy_evaluated: PolicyEvaluated {
+                disposition,
+                dkim,
+                spf,
+                reason,
+            },
         }
     }
 
@@ -1,8 +1,9 @@
 use instant_xml::ToXml;
+use serde::{Deserialize, Serialize};
 use std::fmt::Write;
 use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ReportFailure {
     all_pass: bool,
     any_pass: bool,
@@ -13,10 +14,10 @@ pub struct ReportFailure {
 impl ToXml for ReportFailure {
     fn serialize<W: std::fmt::Write + ?Sized>(
         &self,
-        _: Option<instant_xml::Id<'_>>,
+        field: Option<instant_xml::Id<'_>>,
         serializer: &mut instant_xml::Serializer<W>,
     ) -> Result<(), instant_xml::Error> {
-        serializer.write_str(self)
+        self.to_string().serialize(field, serializer)
     }
 }
 
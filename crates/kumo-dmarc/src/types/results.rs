@@ -3,41 +3,42 @@ use crate::types::policy::Policy;
 use crate::types::policy_override::PolicyOverrideReason;
 use instant_xml::{FromXml, ToXml};
 use kumo_spf::SpfDisposition;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::net::IpAddr;
+use std::str::FromStr;
 
-#[derive(Debug, Eq, FromXml, PartialEq, ToXml)]
+#[derive(Debug, Clone, Copy, Eq, FromXml, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(scalar, rename_all = "lowercase")]
 pub enum SpfScope {
     Helo,
     Mfrom,
 }
 
-#[derive(Debug, Eq, FromXml, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, FromXml, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "spf")]
 pub struct SpfAuthResult {
-    domain: String,
-    scope: SpfScope,
-    result: SpfDisposition,
+    pub(crate) domain: String,
+    pub(crate) scope: SpfScope,
+    pub(crate) result: SpfDisposition,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "auth_results")]
 pub struct AuthResults {
-    dkim: Vec<DkimAuthResult>,
-    spf: Vec<SpfAuthResult>,
+    pub(crate) dkim: Vec<DkimAuthResult>,
+    pub(crate) spf: Vec<SpfAuthResult>,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "record")]
 pub struct Results {
-    row: Row,
-    identifiers: Identifier,
-    auth_results: AuthResults,
+    pub(crate) row: Row,
+    pub(crate) identifiers: Identifier,
+    pub(crate) auth_results: AuthResults,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(scalar, rename_all = "lowercase")]
 pub enum DkimResult {
     None,
@@ -49,15 +50,33 @@ pub enum DkimResult {
     PermError,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+impl FromStr for DkimResult {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "none" => Self::None,
+            "pass" => Self::Pass,
+            "fail" => Self::Fail,
+            "policy" => Self::Policy,
+            "neutral" => Self::Neutral,
+            "temperror" => Self::TempError,
+            "permerror" => Self::PermError,
+            _ => return Err(format!("invalid DKIM result {value:?}")),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
+#[xml(rename = "dkim")]
 pub struct DkimAuthResult {
-    domain: String,
-    selector: Option<String>,
-    result: DkimResult,
-    human_result: Option<String>,
+    pub(crate) domain: String,
+    pub(crate) selector: Option<String>,
+    pub(crate) result: DkimResult,
+    pub(crate) human_result: Option<String>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy, ToXml, Serialize)]
+#[derive(Debug, Eq, Hash, PartialEq, Clone, Copy, ToXml, Serialize, Deserialize)]
 #[xml(scalar, rename_all = "lowercase")]
 pub enum DmarcResult {
     Pass,
@@ -122,19 +141,19 @@ pub struct DispositionWithContext {
     pub context: String,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "policy_evaluated")]
 pub struct PolicyEvaluated {
-    disposition: Policy,
-    dkim: DmarcResult,
-    spf: DmarcResult,
-    reason: Vec<PolicyOverrideReason>,
+    pub(crate) disposition: Policy,
+    pub(crate) dkim: DmarcResult,
+    pub(crate) spf: DmarcResult,
+    pub(crate) reason: Vec<PolicyOverrideReason>,
 }
 
-#[derive(Debug, Eq, PartialEq, ToXml)]
+#[derive(Debug, Clone, Eq, Hash, PartialEq, ToXml, Serialize, Deserialize)]
 #[xml(rename = "row")]
 pub struct Row {
-    source_ip: IpAddr,
-    count: u64,
-    policy_evaluated: PolicyEvaluated,
+    pub(crate) source_ip: IpAddr,
+    pub(crate) count: u64,
+    pub(crate) policy_evaluated: PolicyEvaluated,
 }
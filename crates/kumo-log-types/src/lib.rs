@@ -1,4 +1,6 @@
 use crate::rfc5965::ARFReport;
+use crate::rfc7489::DmarcReportRecord;
+use crate::rfc8460::TlsReportRecord;
 use bounce_classify::BounceClass;
 use chrono::{DateTime, Utc};
 use kumo_address::host_or_socket::HostOrSocketAddress;
@@ -15,6 +17,8 @@ use uuid::Uuid;
 
 pub mod rfc3464;
 pub mod rfc5965;
+pub mod rfc7489;
+pub mod rfc8460;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct ResolvedAddress {
@@ -51,6 +55,10 @@ pub enum RecordType {
     OOB,
     /// Contains a feedback report
     Feedback,
+    /// Contains a row from a DMARC aggregate report
+    DmarcAggregate,
+    /// Contains a policy entry from a TLS aggregate report
+    TlsReport,
 
     /// SMTP Listener responded with a 4xx or 5xx
     Rejection,
@@ -92,6 +100,8 @@ impl RecordType {
             | Self::AdminRebind
             | Self::XferOut
             | Self::XferIn
+            | Self::DmarcAggregate
+            | Self::TlsReport
             | Self::Delayed => false,
             Self::Bounce
             | Self::TransientFailure
@@ -250,6 +260,14 @@ pub struct JsonLogRecord {
 
     pub feedback_report: Option<Box<ARFReport>>,
 
+    /// For DmarcAggregate records, the report row
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dmarc_aggregate_report: Option<Box<DmarcReportRecord>>,
+
+    /// For TlsReport records, the report policy entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_report: Option<Box<TlsReportRecord>>,
+
     pub meta: HashMap<String, Value>,
     pub headers: HashMap<String, Value>,
 
@@ -286,6 +304,14 @@ pub struct JsonLogRecord {
     /// the same connection for either ingress or egress
     #[serde(skip_serializing_if = "Option::is_none")]
     pub session_id: Option<Uuid>,
+
+    /// The OpenTelemetry trace id associated with the message, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+
+    /// The OpenTelemetry span id under which the message was received
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -300,5 +326,5 @@ pub struct MaybeProxiedSourceAddress {
 #[cfg(all(test, target_pointer_width = "64"))]
 #[test]
 fn sizes() {
-    assert_eq!(std::mem::size_of::<JsonLogRecord>(), 712);
+    assert_eq!(std::mem::size_of::<JsonLogRecord>(), 776);
 }
@@ -8,6 +8,7 @@ use crate::{JsonLogRecord, RecordType};
 use anyhow::{anyhow, Context};
 use chrono::{DateTime, Utc};
 use mailparsing::MimePart;
+use rfc5321::{DsnNotify, DsnParams, DsnReturn};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::str::FromStr;
@@ -434,31 +435,66 @@ impl Report {
             return Ok(None);
         }
 
-        let action = match &log.kind {
+        // The RFC 3461 parameters that were specified when the
+        // message was received, if any
+        let dsn: DsnParams = log
+            .meta
+            .get(DSN_META_KEY)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+            .unwrap_or_default();
+
+        let (action, condition) = match &log.kind {
             RecordType::Bounce
                 if params.enable_bounce && log.delivery_protocol.as_deref() == Some("ESMTP") =>
             {
-                ReportAction::Failed
+                (ReportAction::Failed, DsnNotify::Failure)
+            }
+            RecordType::Expiration if params.enable_expiration => {
+                (ReportAction::Failed, DsnNotify::Failure)
+            }
+            RecordType::Delivery if params.enable_success => {
+                if dsn.relayed {
+                    // The next hop accepted the DSN parameters, so it
+                    // is now responsible for reporting on success
+                    return Ok(None);
+                }
+                if log.delivery_protocol.as_deref() == Some("ESMTP") {
+                    (ReportAction::Relayed, DsnNotify::Success)
+                } else {
+                    (ReportAction::Delivered, DsnNotify::Success)
+                }
             }
-            RecordType::Expiration if params.enable_expiration => ReportAction::Failed,
             _ => return Ok(None),
         };
 
+        let recipients: Vec<&String> = log
+            .recipient
+            .iter()
+            .filter(|recip| dsn.should_notify(recip, condition))
+            .collect();
+        if recipients.is_empty() {
+            return Ok(None);
+        }
+
         let arrival_date = Some(log.created);
 
         let per_message = PerMessageReportEntry {
             arrival_date,
             dsn_gateway: None,
             extensions: Default::default(),
-            original_envelope_id: None,
+            original_envelope_id: dsn.envid.clone(),
             received_from_mta: None,
             reporting_mta: params.reporting_mta.clone(),
         };
 
         let mut per_recipient = vec![];
-        let recip_list = log.recipient.join(", ");
+        let recip_list = recipients
+            .iter()
+            .map(|recip| recip.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
 
-        for recip in &log.recipient {
+        for recip in &recipients {
             per_recipient.push(PerRecipientReportEntry {
                 action,
                 extensions: Default::default(),
@@ -468,7 +504,12 @@ impl Report {
                     diagnostic: log.response.to_single_line(),
                 }),
                 final_log_id: None,
-                original_recipient: None,
+                original_recipient: dsn.recipient(recip).and_then(|r| r.orcpt.as_ref()).map(
+                    |orcpt| Recipient {
+                        recipient_type: orcpt.addr_type.to_string(),
+                        recipient: orcpt.address.to_string(),
+                    },
+                ),
                 final_recipient: Recipient {
                     recipient_type: "rfc822".to_string(),
                     recipient: recip.to_string(),
@@ -525,6 +566,38 @@ impl Report {
                     status = log.response.to_single_line()
                 )
             }
+            RecordType::Delivery => {
+                let mut data = format!(
+                    "The message was received at {created}\r\n\
+                    from {sender} and addressed to {recip_list}.\r\n\
+                    ",
+                    created = log.created.to_rfc2822(),
+                    sender = log.sender,
+                );
+                if let Some(peer) = &log.peer_address {
+                    data.push_str(&format!(
+                        "It was successfully delivered to {host} ({ip}):\r\n\
+                        Response: {resp}\r\n",
+                        host = peer.name,
+                        ip = peer.addr,
+                        resp = log.response.to_single_line(),
+                    ));
+                } else {
+                    data.push_str(&format!(
+                        "It was successfully delivered.\r\n\
+                        Status: {}\r\n",
+                        log.response.to_single_line()
+                    ));
+                }
+                if action == ReportAction::Relayed {
+                    data.push_str(
+                        "\r\nThe destination does not support delivery status notifications.\r\n\
+                        No further notifications will be sent for this message.\r\n",
+                    );
+                }
+
+                data
+            }
             _ => unreachable!(),
         };
 
@@ -537,7 +610,19 @@ impl Report {
         parts
             .push(MimePart::new_text("message/delivery-status", &status_text).context("new_text")?);
 
-        match (params.include_original_message, msg) {
+        // RET=HDRS limits us to returning just the headers, and
+        // we never return the full content in a success report
+        let include_original_message = match (params.include_original_message, dsn.ret) {
+            (IncludeOriginalMessage::FullContent, Some(DsnReturn::Hdrs)) => {
+                IncludeOriginalMessage::HeadersOnly
+            }
+            (IncludeOriginalMessage::FullContent, _) if condition == DsnNotify::Success => {
+                IncludeOriginalMessage::HeadersOnly
+            }
+            (include, _) => include,
+        };
+
+        match (include_original_message, msg) {
             (IncludeOriginalMessage::No, _) | (_, None) => {}
             (IncludeOriginalMessage::HeadersOnly, Some(msg)) => {
                 let mut data = vec![];
@@ -581,7 +666,11 @@ impl Report {
             .context("set_content_type")?;
         report_msg
             .headers_mut()
-            .set_subject("Returned mail")
+            .set_subject(if condition == DsnNotify::Success {
+                "Successful Mail Delivery Report"
+            } else {
+                "Returned mail"
+            })
             .context("set_subject")?;
         report_msg
             .headers_mut()
@@ -615,6 +704,10 @@ impl Report {
     }
 }
 
+/// The message metadata key in which kumod records the RFC 3461
+/// parameters that were specified when the message was received
+pub const DSN_META_KEY: &str = "dsn";
+
 #[derive(Default, Debug, PartialEq, Clone, Copy, Deserialize)]
 pub enum IncludeOriginalMessage {
     #[default]
@@ -631,6 +724,10 @@ pub struct ReportGenerationParams {
     pub enable_expiration: bool,
     #[serde(default)]
     pub enable_bounce: bool,
+    /// Generate success reports for recipients that requested
+    /// them via `NOTIFY=SUCCESS`
+    #[serde(default)]
+    pub enable_success: bool,
     // If we decide to allow generating for delays in the future,
     // we'll probably add `enable_delay` here, but we'll also need
     // to have some kind of discriminating logic to decide when
@@ -673,6 +770,8 @@ mod test {
             egress_pool: None,
             egress_source: None,
             feedback_report: None,
+            dmarc_aggregate_report: None,
+            tls_report: None,
             headers: Default::default(),
             meta: Default::default(),
             num_attempts: 1,
@@ -686,6 +785,8 @@ mod test {
             recipient: vec!["recip@target.example.com".to_string()],
             sender: "sender@sender.example.com".to_string(),
             session_id: None,
+            trace_id: None,
+            span_id: None,
             response: Response {
                 code: 550,
                 command: None,
@@ -721,6 +822,8 @@ mod test {
             egress_pool: None,
             egress_source: None,
             feedback_report: None,
+            dmarc_aggregate_report: None,
+            tls_report: None,
             headers: Default::default(),
             meta: Default::default(),
             num_attempts: 3,
@@ -731,6 +834,8 @@ mod test {
             recipient: vec!["recip@target.example.com".to_string()],
             sender: "sender@sender.example.com".to_string(),
             session_id: None,
+            trace_id: None,
+            span_id: None,
             response: Response {
                 code: 551,
                 command: None,
@@ -759,6 +864,7 @@ mod test {
                 name: "mta1.example.com".to_string(),
             },
             enable_bounce: false,
+            enable_success: false,
             enable_expiration: true,
             include_original_message: IncludeOriginalMessage::HeadersOnly,
             stable_content: true,
@@ -890,6 +996,7 @@ Subject: Hello!
                 name: "mta1.example.com".to_string(),
             },
             enable_bounce: true,
+            enable_success: false,
             enable_expiration: true,
             include_original_message: IncludeOriginalMessage::HeadersOnly,
             stable_content: true,
@@ -1023,6 +1130,7 @@ Subject: Hello!
                 name: "mta1.example.com".to_string(),
             },
             enable_bounce: true,
+            enable_success: false,
             enable_expiration: true,
             include_original_message: IncludeOriginalMessage::FullContent,
             stable_content: true,
@@ -1161,6 +1269,7 @@ hello there
                 name: "mta1.example.com".to_string(),
             },
             enable_bounce: true,
+            enable_success: false,
             enable_expiration: true,
             include_original_message: IncludeOriginalMessage::No,
             stable_content: true,
@@ -1276,6 +1385,150 @@ Report {
         );
     }
 
+    #[test]
+    fn generate_bounce_notify_never() {
+        let params = ReportGenerationParams {
+            reporting_mta: RemoteMta {
+                mta_type: "dns".to_string(),
+                name: "mta1.example.com".to_string(),
+            },
+            enable_bounce: true,
+            enable_success: true,
+            enable_expiration: true,
+            include_original_message: IncludeOriginalMessage::No,
+            stable_content: true,
+        };
+
+        let mut log = make_bounce();
+        log.meta.insert(
+            DSN_META_KEY.to_string(),
+            serde_json::json!({
+                "recipients": {
+                    "recip@target.example.com": {"notify": ["NEVER"]}
+                }
+            }),
+        );
+
+        assert!(Report::generate(&params, None, &log).unwrap().is_none());
+    }
+
+    #[test]
+    fn generate_success_with_dsn() {
+        let params = ReportGenerationParams {
+            reporting_mta: RemoteMta {
+                mta_type: "dns".to_string(),
+                name: "mta1.example.com".to_string(),
+            },
+            enable_bounce: true,
+            enable_success: true,
+            enable_expiration: true,
+            include_original_message: IncludeOriginalMessage::FullContent,
+            stable_content: true,
+        };
+
+        let original_msg = make_message();
+
+        let mut log = make_bounce();
+        log.kind = RecordType::Delivery;
+        log.response = Response {
+            code: 250,
+            command: None,
+            content: "ok".to_string(),
+            enhanced_code: Some(EnhancedStatusCode {
+                class: 2,
+                subject: 0,
+                detail: 0,
+            }),
+        };
+
+        // Without NOTIFY=SUCCESS, no report is generated
+        assert!(Report::generate(&params, Some(&original_msg), &log)
+            .unwrap()
+            .is_none());
+
+        log.meta.insert(
+            DSN_META_KEY.to_string(),
+            serde_json::json!({
+                "envid": "ENV-123",
+                "recipients": {
+                    "recip@target.example.com": {
+                        "notify": ["SUCCESS", "FAILURE"],
+                        "orcpt": {"addr_type": "rfc822", "address": "orig@target.example.com"}
+                    }
+                }
+            }),
+        );
+
+        let report_msg = Report::generate(&params, Some(&original_msg), &log)
+            .unwrap()
+            .unwrap();
+        let report_eml = report_msg.to_message_string();
+        assert!(report_eml.contains("Subject: Successful Mail Delivery Report\r\n"));
+        // The full content is never returned in a success report
+        assert!(report_eml.contains("Content-Type: text/rfc822-headers\r\n"));
+        assert!(!report_eml.contains("hello there"));
+
+        let round_trip = Report::parse(report_eml.as_bytes()).unwrap().unwrap();
+        assert_eq!(
+            round_trip.per_message.original_envelope_id.as_deref(),
+            Some("ENV-123")
+        );
+        assert_eq!(round_trip.per_recipient.len(), 1);
+        let recip = &round_trip.per_recipient[0];
+        assert_eq!(recip.action, ReportAction::Relayed);
+        assert_eq!(
+            recip.original_recipient,
+            Some(Recipient {
+                recipient_type: "rfc822".to_string(),
+                recipient: "orig@target.example.com".to_string(),
+            })
+        );
+
+        // Once relayed to a DSN capable host, it is responsible
+        // for generating the success report
+        log.meta.insert(
+            DSN_META_KEY.to_string(),
+            serde_json::json!({
+                "recipients": {
+                    "recip@target.example.com": {"notify": ["SUCCESS"]}
+                },
+                "relayed": true,
+            }),
+        );
+        assert!(Report::generate(&params, Some(&original_msg), &log)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn generate_bounce_ret_hdrs() {
+        let params = ReportGenerationParams {
+            reporting_mta: RemoteMta {
+                mta_type: "dns".to_string(),
+                name: "mta1.example.com".to_string(),
+            },
+            enable_bounce: true,
+            enable_success: false,
+            enable_expiration: true,
+            include_original_message: IncludeOriginalMessage::FullContent,
+            stable_content: true,
+        };
+
+        let original_msg = make_message();
+
+        let mut log = make_bounce();
+        log.meta
+            .insert(DSN_META_KEY.to_string(), serde_json::json!({"ret": "HDRS"}));
+
+        let report_msg = Report::generate(&params, Some(&original_msg), &log)
+            .unwrap()
+            .unwrap();
+        let report_eml = report_msg.to_message_string();
+        assert!(report_eml.contains("Subject: Returned mail\r\n"));
+        assert!(report_eml.contains("Content-Type: text/rfc822-headers\r\n"));
+        assert!(!report_eml.contains("hello there"));
+    }
+
     #[test]
     fn rfc3464_1() {
         let result = Report::parse(include_bytes!("../data/rfc3464/1.eml")).unwrap();
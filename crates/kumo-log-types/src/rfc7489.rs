@@ -0,0 +1,525 @@
+//! DMARC aggregate reports
+//! <https://datatracker.ietf.org/doc/html/rfc7489#section-7.2>
+use crate::rfc3464::content_type;
+use crate::rfc8460::{body_bytes, gunzip};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use mailparsing::MimePart;
+use serde::{Deserialize, Serialize};
+
+/// An aggregate report, as sent to the `rua` address of a domain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AggregateReport {
+    pub org_name: String,
+    pub email: String,
+    #[serde(default)]
+    pub extra_contact_info: Option<String>,
+    pub report_id: String,
+    pub date_range: DateRange,
+    #[serde(default)]
+    pub errors: Vec<String>,
+    pub policy_published: PolicyPublished,
+    pub records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateRange {
+    pub begin: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// The DMARC policy that the reporter discovered for the domain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyPublished {
+    pub domain: String,
+    #[serde(default)]
+    pub adkim: Option<String>,
+    #[serde(default)]
+    pub aspf: Option<String>,
+    pub p: String,
+    #[serde(default)]
+    pub sp: Option<String>,
+    #[serde(default)]
+    pub pct: Option<u32>,
+    #[serde(default)]
+    pub fo: Option<String>,
+}
+
+/// A single row of an aggregate report, describing `count` messages
+/// that shared the same source ip and authentication results
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub source_ip: String,
+    pub count: u64,
+    pub disposition: String,
+    pub dkim: String,
+    pub spf: String,
+    #[serde(default)]
+    pub reasons: Vec<PolicyOverrideReason>,
+    #[serde(default)]
+    pub envelope_to: Option<String>,
+    #[serde(default)]
+    pub envelope_from: Option<String>,
+    pub header_from: String,
+    #[serde(default)]
+    pub dkim_auth_results: Vec<DkimAuthResult>,
+    #[serde(default)]
+    pub spf_auth_results: Vec<SpfAuthResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyOverrideReason {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DkimAuthResult {
+    pub domain: String,
+    #[serde(default)]
+    pub selector: Option<String>,
+    pub result: String,
+    #[serde(default)]
+    pub human_result: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpfAuthResult {
+    pub domain: String,
+    #[serde(default)]
+    pub scope: Option<String>,
+    pub result: String,
+}
+
+/// A single row of an aggregate report, along with the
+/// metadata from the report that contained it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DmarcReportRecord {
+    pub org_name: String,
+    pub email: String,
+    pub report_id: String,
+    pub date_range: DateRange,
+    pub policy_published: PolicyPublished,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+impl AggregateReport {
+    /// Parse a DMARC aggregate report email, returning the contained
+    /// report.  The report may be attached either as gzip compressed
+    /// or plain XML; zip compressed reports are not supported.
+    /// Returns `Ok(None)` if the message is not an aggregate report.
+    pub fn parse(input: &[u8]) -> anyhow::Result<Option<Self>> {
+        let mail = MimePart::parse(input)?;
+
+        let mut parts = vec![&mail];
+        parts.extend(mail.child_parts().iter());
+
+        for part in parts {
+            let data = match content_type(part).as_deref() {
+                Some("application/gzip" | "application/x-gzip") => gunzip(&body_bytes(part)?)?,
+                Some("text/xml" | "application/xml") => body_bytes(part)?,
+                Some("application/zip" | "application/x-zip-compressed") => {
+                    anyhow::bail!("zip compressed DMARC aggregate reports are not supported")
+                }
+                _ => continue,
+            };
+
+            let xml = String::from_utf8(data).context("DMARC aggregate report is not UTF-8")?;
+            if !xml.contains("<feedback") {
+                continue;
+            }
+            return Self::from_xml(&xml).map(Some);
+        }
+
+        Ok(None)
+    }
+
+    /// Parse the XML representation of an aggregate report
+    pub fn from_xml(xml: &str) -> anyhow::Result<Self> {
+        let xml = strip_prolog_and_namespace(xml);
+        let feedback: xml::Feedback =
+            instant_xml::from_str(&xml).context("parsing DMARC aggregate report XML")?;
+        feedback.try_into()
+    }
+
+    /// Flatten the report into one record per row
+    pub fn records(&self) -> Vec<DmarcReportRecord> {
+        self.records
+            .iter()
+            .map(|record| DmarcReportRecord {
+                org_name: self.org_name.clone(),
+                email: self.email.clone(),
+                report_id: self.report_id.clone(),
+                date_range: self.date_range.clone(),
+                policy_published: self.policy_published.clone(),
+                record: record.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Remove the `<?xml ...?>` declaration and the default DMARC
+/// namespace declaration (used by the DMARCbis schema), neither
+/// of which carry information that we need, so that the document
+/// can be matched against our unqualified element names.
+fn strip_prolog_and_namespace(xml: &str) -> String {
+    let mut xml = xml.trim_start().trim_start_matches('\u{feff}');
+    if xml.starts_with("<?xml") {
+        if let Some(end) = xml.find("?>") {
+            xml = xml[end + 2..].trim_start();
+        }
+    }
+
+    let Some(start) = xml.find("<feedback") else {
+        return xml.to_string();
+    };
+    let Some(end) = xml[start..].find('>').map(|idx| idx + start) else {
+        return xml.to_string();
+    };
+
+    format!("<feedback>{}", &xml[end + 1..])
+}
+
+fn timestamp(secs: &str) -> anyhow::Result<DateTime<Utc>> {
+    let secs: i64 = secs
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid date_range timestamp {secs}"))?;
+    DateTime::from_timestamp(secs, 0).ok_or_else(|| anyhow::anyhow!("invalid timestamp {secs}"))
+}
+
+impl TryFrom<xml::Feedback> for AggregateReport {
+    type Error = anyhow::Error;
+
+    fn try_from(feedback: xml::Feedback) -> anyhow::Result<Self> {
+        let meta = feedback.report_metadata;
+        let policy = feedback.policy_published;
+
+        let records = feedback
+            .records
+            .into_iter()
+            .map(|record| {
+                let evaluated = record.row.policy_evaluated;
+                Ok(Record {
+                    source_ip: record.row.source_ip,
+                    count: record
+                        .row
+                        .count
+                        .trim()
+                        .parse()
+                        .with_context(|| format!("invalid count {}", record.row.count))?,
+                    disposition: evaluated.disposition,
+                    dkim: evaluated.dkim,
+                    spf: evaluated.spf,
+                    reasons: evaluated
+                        .reasons
+                        .into_iter()
+                        .map(|reason| PolicyOverrideReason {
+                            kind: reason.kind,
+                            comment: reason.comment,
+                        })
+                        .collect(),
+                    envelope_to: record.identifiers.envelope_to,
+                    envelope_from: record.identifiers.envelope_from,
+                    header_from: record.identifiers.header_from,
+                    dkim_auth_results: record
+                        .auth_results
+                        .dkim
+                        .into_iter()
+                        .map(|dkim| DkimAuthResult {
+                            domain: dkim.domain,
+                            selector: dkim.selector,
+                            result: dkim.result,
+                            human_result: dkim.human_result,
+                        })
+                        .collect(),
+                    spf_auth_results: record
+                        .auth_results
+                        .spf
+                        .into_iter()
+                        .map(|spf| SpfAuthResult {
+                            domain: spf.domain,
+                            scope: spf.scope,
+                            result: spf.result,
+                        })
+                        .collect(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            org_name: meta.org_name,
+            email: meta.email,
+            extra_contact_info: meta.extra_contact_info,
+            report_id: meta.report_id,
+            date_range: DateRange {
+                begin: timestamp(&meta.date_range.begin)?,
+                end: timestamp(&meta.date_range.end)?,
+            },
+            errors: meta.errors,
+            policy_published: PolicyPublished {
+                domain: policy.domain,
+                adkim: policy.adkim,
+                aspf: policy.aspf,
+                p: policy.p,
+                sp: policy.sp,
+                pct: policy.pct.and_then(|pct| pct.trim().parse().ok()),
+                fo: policy.fo,
+            },
+            records,
+        })
+    }
+}
+
+/// The wire representation of the report.  Values are kept as strings
+/// so that minor deviations from the schema by reporters don't cause
+/// the entire report to be rejected.
+mod xml {
+    use instant_xml::FromXml;
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "feedback")]
+    pub struct Feedback {
+        pub report_metadata: ReportMetadata,
+        pub policy_published: PolicyPublished,
+        pub records: Vec<Record>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "report_metadata")]
+    pub struct ReportMetadata {
+        pub org_name: String,
+        pub email: String,
+        pub extra_contact_info: Option<String>,
+        pub report_id: String,
+        pub date_range: DateRange,
+        #[xml(rename = "error")]
+        pub errors: Vec<String>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "date_range")]
+    pub struct DateRange {
+        pub begin: String,
+        pub end: String,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "policy_published")]
+    pub struct PolicyPublished {
+        pub domain: String,
+        pub adkim: Option<String>,
+        pub aspf: Option<String>,
+        pub p: String,
+        pub sp: Option<String>,
+        pub pct: Option<String>,
+        pub fo: Option<String>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "record")]
+    pub struct Record {
+        pub row: Row,
+        pub identifiers: Identifiers,
+        pub auth_results: AuthResults,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "row")]
+    pub struct Row {
+        pub source_ip: String,
+        pub count: String,
+        pub policy_evaluated: PolicyEvaluated,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "policy_evaluated")]
+    pub struct PolicyEvaluated {
+        pub disposition: String,
+        pub dkim: String,
+        pub spf: String,
+        pub reasons: Vec<Reason>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "reason")]
+    pub struct Reason {
+        #[xml(rename = "type")]
+        pub kind: String,
+        pub comment: Option<String>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "identifiers")]
+    pub struct Identifiers {
+        pub envelope_to: Option<String>,
+        pub envelope_from: Option<String>,
+        pub header_from: String,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "auth_results")]
+    pub struct AuthResults {
+        pub dkim: Vec<DkimAuthResult>,
+        pub spf: Vec<SpfAuthResult>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "dkim")]
+    pub struct DkimAuthResult {
+        pub domain: String,
+        pub selector: Option<String>,
+        pub result: String,
+        pub human_result: Option<String>,
+    }
+
+    #[derive(Debug, FromXml)]
+    #[xml(rename = "spf")]
+    pub struct SpfAuthResult {
+        pub domain: String,
+        pub scope: Option<String>,
+        pub result: String,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    const SAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<feedback xmlns="urn:ietf:params:xml:ns:dmarc-2.0">
+  <version>1.0</version>
+  <report_metadata>
+    <org_name>google.com</org_name>
+    <email>noreply-dmarc-support@google.com</email>
+    <extra_contact_info>https://support.google.com/a/answer/2466580</extra_contact_info>
+    <report_id>11812836539931426433</report_id>
+    <date_range>
+      <begin>1700000000</begin>
+      <end>1700086399</end>
+    </date_range>
+  </report_metadata>
+  <policy_published>
+    <domain>example.com</domain>
+    <adkim>r</adkim>
+    <aspf>r</aspf>
+    <p>none</p>
+    <sp>none</sp>
+    <pct>100</pct>
+  </policy_published>
+  <record>
+    <row>
+      <source_ip>192.0.2.1</source_ip>
+      <count>2</count>
+      <policy_evaluated>
+        <disposition>none</disposition>
+        <dkim>pass</dkim>
+        <spf>fail</spf>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <dkim>
+        <domain>example.com</domain>
+        <selector>s1</selector>
+        <result>pass</result>
+      </dkim>
+      <spf>
+        <domain>bounce.example.net</domain>
+        <result>fail</result>
+      </spf>
+    </auth_results>
+  </record>
+  <record>
+    <row>
+      <source_ip>2001:db8::1</source_ip>
+      <count>1</count>
+      <policy_evaluated>
+        <disposition>none</disposition>
+        <dkim>fail</dkim>
+        <spf>fail</spf>
+        <reason>
+          <type>local_policy</type>
+          <comment>arc=pass</comment>
+        </reason>
+      </policy_evaluated>
+    </row>
+    <identifiers>
+      <envelope_from>example.com</envelope_from>
+      <header_from>example.com</header_from>
+    </identifiers>
+    <auth_results>
+      <spf>
+        <domain>example.com</domain>
+        <scope>mfrom</scope>
+        <result>softfail</result>
+      </spf>
+    </auth_results>
+  </record>
+</feedback>
+"#;
+
+    #[test]
+    fn parse_xml() {
+        let report = AggregateReport::from_xml(SAMPLE).unwrap();
+        assert_eq!(report.org_name, "google.com");
+        assert_eq!(report.report_id, "11812836539931426433");
+        assert_eq!(report.date_range.begin.timestamp(), 1700000000);
+        assert_eq!(report.policy_published.domain, "example.com");
+        assert_eq!(report.policy_published.pct, Some(100));
+
+        let records = report.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].record.source_ip, "192.0.2.1");
+        assert_eq!(records[0].record.count, 2);
+        assert_eq!(
+            records[0].record.dkim_auth_results[0].selector.as_deref(),
+            Some("s1")
+        );
+        assert_eq!(records[1].record.reasons[0].kind, "local_policy");
+        assert_eq!(records[1].record.spf_auth_results[0].result, "softfail");
+        assert_eq!(records[1].report_id, "11812836539931426433");
+    }
+
+    #[test]
+    fn parse_email() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(SAMPLE.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let encoded = data_encoding::BASE64_MIME.encode(&compressed);
+
+        let email = format!(
+            "Subject: Report domain: example.com Submitter: google.com\r\n\
+Mime-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"XX\"\r\n\
+\r\n\
+--XX\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+This is an aggregate report from google.com\r\n\
+--XX\r\n\
+Content-Type: application/gzip; name=\"google.com!example.com!1700000000!1700086399.xml.gz\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+{encoded}\r\n\
+--XX--\r\n"
+        );
+
+        let report = AggregateReport::parse(email.as_bytes()).unwrap().unwrap();
+        assert_eq!(report.records.len(), 2);
+
+        assert!(
+            AggregateReport::parse(b"Subject: hello\r\n\r\nnot a report\r\n")
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! SMTP TLS Reporting (TLS-RPT) aggregate reports
+//! <https://datatracker.ietf.org/doc/html/rfc8460>
+use crate::rfc3464::content_type;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use mailparsing::{DecodedBody, MimePart};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// An RFC 8460 aggregate report
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Report {
+    pub organization_name: String,
+    pub date_range: DateRange,
+    pub contact_info: String,
+    pub report_id: String,
+    pub policies: Vec<PolicyReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DateRange {
+    pub start_datetime: DateTime<Utc>,
+    pub end_datetime: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PolicyReport {
+    pub policy: Policy,
+    pub summary: Summary,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failure_details: Vec<FailureDetails>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Policy {
+    pub policy_type: PolicyType,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policy_string: Vec<String>,
+    pub policy_domain: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mx_host: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyType {
+    Tlsa,
+    Sts,
+    NoPolicyFound,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Summary {
+    pub total_successful_session_count: u64,
+    pub total_failure_session_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FailureDetails {
+    pub result_type: ResultType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sending_mta_ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiving_mx_hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiving_mx_helo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receiving_ip: Option<String>,
+    pub failed_session_count: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additional_information: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_reason_code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResultType {
+    // Negotiation failures
+    StarttlsNotSupported,
+    CertificateHostMismatch,
+    CertificateExpired,
+    CertificateNotTrusted,
+    ValidationFailure,
+    // Policy failures
+    TlsaInvalid,
+    DnssecInvalid,
+    DaneRequired,
+    StsPolicyFetchError,
+    StsPolicyInvalid,
+    StsWebpkiInvalid,
+    /// Result types defined by later specifications are
+    /// preserved verbatim
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl Report {
+    /// Parse an RFC 8460 report email, returning the contained
+    /// aggregate report.
+    /// Returns `Ok(None)` if the message is not a TLS report.
+    pub fn parse(input: &[u8]) -> anyhow::Result<Option<Self>> {
+        let mail = MimePart::parse(input)?;
+        let ct = match mail.headers().content_type()? {
+            None => return Ok(None),
+            Some(ct) => ct,
+        };
+
+        if ct.value != "multipart/report" {
+            return Ok(None);
+        }
+
+        if ct.get("report-type").as_deref() != Some("tlsrpt") {
+            return Ok(None);
+        }
+
+        for part in mail.child_parts() {
+            let ct = content_type(part);
+            let data = match ct.as_deref() {
+                Some("application/tlsrpt+gzip") => gunzip(&body_bytes(part)?)?,
+                Some("application/tlsrpt+json") => body_bytes(part)?,
+                _ => continue,
+            };
+
+            let report = serde_json::from_slice(&data).context("parsing TLS report JSON")?;
+            return Ok(Some(report));
+        }
+
+        anyhow::bail!("multipart/report with report-type=tlsrpt has no report attachment");
+    }
+
+    /// Flatten the report into one record per policy entry
+    pub fn records(&self) -> Vec<TlsReportRecord> {
+        self.policies
+            .iter()
+            .map(|entry| TlsReportRecord {
+                organization_name: self.organization_name.clone(),
+                contact_info: self.contact_info.clone(),
+                report_id: self.report_id.clone(),
+                date_range: self.date_range.clone(),
+                policy: entry.policy.clone(),
+                summary: entry.summary,
+                failure_details: entry.failure_details.clone(),
+            })
+            .collect()
+    }
+}
+
+/// A single policy entry from a TLS report, along with the
+/// metadata from the report that contained it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TlsReportRecord {
+    pub organization_name: String,
+    pub contact_info: String,
+    pub report_id: String,
+    pub date_range: DateRange,
+    pub policy: Policy,
+    pub summary: Summary,
+    #[serde(default)]
+    pub failure_details: Vec<FailureDetails>,
+}
+
+/// Returns the transfer-decoded bytes of a mime part
+pub(crate) fn body_bytes(part: &MimePart) -> anyhow::Result<Vec<u8>> {
+    Ok(match part.body()? {
+        DecodedBody::Text(text) => text.as_bytes().to_vec(),
+        DecodedBody::Binary(data) => data,
+    })
+}
+
+/// The largest decompressed report that we are willing to process.
+/// This guards against a small compressed attachment that expands
+/// to consume all available memory.
+pub(crate) const MAX_DECOMPRESSED_REPORT_SIZE: u64 = 32 * 1024 * 1024;
+
+pub(crate) fn gunzip(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    gunzip_with_limit(data, MAX_DECOMPRESSED_REPORT_SIZE)
+}
+
+fn gunzip_with_limit(data: &[u8], limit: u64) -> anyhow::Result<Vec<u8>> {
+    let mut result = vec![];
+    // Read one byte more than the limit, so that we can tell
+    // the difference between reaching it and exceeding it
+    GzDecoder::new(data)
+        .take(limit + 1)
+        .read_to_end(&mut result)
+        .context("decompressing gzip report")?;
+    anyhow::ensure!(
+        result.len() as u64 <= limit,
+        "decompressed gzip report exceeds the limit of {limit} bytes"
+    );
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn gunzip_limit() {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        encoder.write_all(&[0u8; 4096]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(gunzip_with_limit(&compressed, 4096).unwrap().len(), 4096);
+        let err = gunzip_with_limit(&compressed, 4095).unwrap_err();
+        assert!(err.to_string().contains("exceeds the limit"), "{err:#}");
+    }
+
+    #[test]
+    fn report_round_trip() {
+        // This is the example from RFC 8460 section 4.8,
+        // with the failure details trimmed
+        let sample = r#"{
+     "organization-name": "Company-X",
+     "date-range": {
+       "start-datetime": "2016-04-01T00:00:00Z",
+       "end-datetime": "2016-04-01T23:59:59Z"
+     },
+     "contact-info": "sts-reporting@company-x.example",
+     "report-id": "5065427c-23d3-47ca-b6e0-946ea0e8c4be",
+     "policies": [{
+       "policy": {
+         "policy-type": "sts",
+         "policy-string": ["version: STSv1","mode: testing",
+               "mx: *.mail.company-y.example","max_age: 86400"],
+         "policy-domain": "company-y.example",
+         "mx-host": ["*.mail.company-y.example"]
+       },
+       "summary": {
+         "total-successful-session-count": 5326,
+         "total-failure-session-count": 303
+       },
+       "failure-details": [{
+         "result-type": "certificate-expired",
+         "sending-mta-ip": "2001:db8:abcd:0012::1",
+         "receiving-mx-hostname": "mx1.mail.company-y.example",
+         "failed-session-count": 100
+       }, {
+         "result-type": "validation-failure",
+         "sending-mta-ip": "198.51.100.62",
+         "receiving-ip": "203.0.113.56",
+         "receiving-mx-hostname": "mx2.mail.company-y.example",
+         "failed-session-count": 200,
+         "additional-information": "https://reports.company-x.example/report_info?id=5065427c-23d3#StarttlsNotSupported"
+       }, {
+         "result-type": "something-new",
+         "failed-session-count": 3
+       }]
+     }]
+   }"#;
+
+        let report: Report = serde_json::from_str(sample).unwrap();
+        assert_eq!(report.policies[0].summary.total_failure_session_count, 303);
+        assert_eq!(
+            report.policies[0].failure_details[0].result_type,
+            ResultType::CertificateExpired
+        );
+        assert_eq!(
+            report.policies[0].failure_details[2].result_type,
+            ResultType::Other("something-new".to_string())
+        );
+
+        let json = serde_json::to_string(&report).unwrap();
+        let round_trip: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(report, round_trip);
+    }
+
+    #[test]
+    fn parse_report_email() {
+        let json = r#"{
+     "organization-name": "Company-X",
+     "date-range": {
+       "start-datetime": "2016-04-01T00:00:00Z",
+       "end-datetime": "2016-04-01T23:59:59Z"
+     },
+     "contact-info": "sts-reporting@company-x.example",
+     "report-id": "5065427c-23d3-47ca-b6e0-946ea0e8c4be",
+     "policies": [{
+       "policy": {
+         "policy-type": "no-policy-found",
+         "policy-domain": "company-y.example"
+       },
+       "summary": {
+         "total-successful-session-count": 10,
+         "total-failure-session-count": 1
+       },
+       "failure-details": [{
+         "result-type": "starttls-not-supported",
+         "receiving-mx-hostname": "mx1.company-y.example",
+         "failed-session-count": 1
+       }]
+     }]
+   }"#;
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let encoded = data_encoding::BASE64_MIME.encode(&compressed);
+
+        let email = format!(
+            "Subject: Report Domain: company-y.example\r\n\
+Mime-Version: 1.0\r\n\
+Content-Type: multipart/report; report-type=\"tlsrpt\"; boundary=\"XX\"\r\n\
+\r\n\
+--XX\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+This is an aggregate TLS report\r\n\
+--XX\r\n\
+Content-Type: application/tlsrpt+gzip\r\n\
+Content-Transfer-Encoding: base64\r\n\
+Content-Disposition: attachment; filename=\"company-x.example!company-y.example!1459468800!1459555199!5065427c.json.gz\"\r\n\
+\r\n\
+{encoded}\r\n\
+--XX--\r\n"
+        );
+
+        let report = Report::parse(email.as_bytes()).unwrap().unwrap();
+        assert_eq!(report.report_id, "5065427c-23d3-47ca-b6e0-946ea0e8c4be");
+
+        let records = report.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].organization_name, "Company-X");
+        assert_eq!(records[0].policy.policy_type, PolicyType::NoPolicyFound);
+        assert_eq!(records[0].summary.total_successful_session_count, 10);
+        assert_eq!(
+            records[0].failure_details[0].result_type,
+            ResultType::StarttlsNotSupported
+        );
+
+        assert!(Report::parse(b"Subject: hello\r\n\r\nnot a report\r\n")
+            .unwrap()
+            .is_none());
+    }
+}
@@ -96,6 +96,9 @@ pub struct Identity {
 pub enum IdentityContext {
     SmtpAuthPlainAuthentication,
     SmtpAuthPlainAuthorization,
+    SmtpAuthLoginAuthentication,
+    SmtpAuthCramMd5Authentication,
+    SmtpAuthXOAuth2Authentication,
     HttpBasicAuth,
     BearerToken,
     ProxyAuthRfc1929,
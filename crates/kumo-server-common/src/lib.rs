@@ -16,6 +16,7 @@ pub mod disk_space;
 pub mod http_server;
 pub mod log;
 pub mod nodeid;
+pub mod otel;
 pub mod panic;
 pub mod start;
 pub mod tls_helpers;
@@ -185,6 +186,19 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
         })?,
     )?;
 
+    kumo_mod.set(
+        "configure_opentelemetry",
+        lua.create_async_function(|lua, params: Value| async move {
+            let params: otel::OtlpTraceParams = from_lua_value(&lua, params)?;
+            // The exporter uses a blocking http client, which cannot
+            // be created from within an async context
+            tokio::task::spawn_blocking(move || params.init())
+                .await
+                .map_err(any_err)?
+                .map_err(any_err)
+        })?,
+    )?;
+
     fn variadic_to_string(args: Variadic<Value>) -> String {
         let mut output = String::new();
         for (idx, item) in args.into_iter().enumerate() {
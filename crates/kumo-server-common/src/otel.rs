@@ -0,0 +1,258 @@
+//! Distributed tracing using OpenTelemetry.
+//! Spans are exported over OTLP/HTTP to a collector, and their
+//! context is propagated between processes using the W3C
+//! `traceparent` representation.
+use anyhow::Context as _;
+use opentelemetry::global::{self, BoxedSpan};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue, Value};
+use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+pub use opentelemetry::trace::SpanKind;
+
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+const TRACEPARENT: &str = "traceparent";
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpTraceParams {
+    /// The OTLP/HTTP endpoint to which spans are exported
+    #[serde(default = "OtlpTraceParams::default_endpoint")]
+    pub endpoint: String,
+
+    /// The service.name resource attribute
+    #[serde(default = "OtlpTraceParams::default_service_name")]
+    pub service_name: String,
+
+    /// The fraction of new traces that are sampled, from 0.0 to 1.0.
+    /// Spans that continue an existing trace follow the sampling
+    /// decision of their parent.
+    #[serde(default = "OtlpTraceParams::default_sampling_ratio")]
+    pub sampling_ratio: f64,
+
+    /// Additional HTTP headers to send with each export request
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// How long to wait for an export request to complete
+    #[serde(default = "OtlpTraceParams::default_timeout", with = "duration_serde")]
+    pub timeout: Duration,
+}
+
+impl OtlpTraceParams {
+    fn default_endpoint() -> String {
+        "http://localhost:4318/v1/traces".to_string()
+    }
+
+    fn default_service_name() -> String {
+        "kumomta".to_string()
+    }
+
+    fn default_sampling_ratio() -> f64 {
+        1.0
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    /// Build the exporter and install the global tracer provider.
+    /// The exporter uses a blocking HTTP client, so this must
+    /// not be called directly from async code.
+    pub fn init(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.sampling_ratio),
+            "sampling_ratio must be between 0.0 and 1.0, got {}",
+            self.sampling_ratio
+        );
+        anyhow::ensure!(
+            PROVIDER.get().is_none(),
+            "configure_opentelemetry has already been called"
+        );
+
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&self.endpoint)
+            .with_timeout(self.timeout)
+            .with_headers(self.headers.clone())
+            .build()
+            .with_context(|| format!("building OTLP exporter for {}", self.endpoint))?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                self.sampling_ratio,
+            ))))
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(self.service_name.clone())
+                    .build(),
+            )
+            .build();
+
+        PROVIDER
+            .set(provider.clone())
+            .map_err(|_| anyhow::anyhow!("configure_opentelemetry has already been called"))?;
+        global::set_tracer_provider(provider);
+        Ok(())
+    }
+}
+
+/// Returns true if tracing has been configured
+pub fn is_enabled() -> bool {
+    PROVIDER.get().is_some()
+}
+
+/// Flush any buffered spans and stop the exporter
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            tracing::error!("error shutting down OpenTelemetry exporter: {err:#}");
+        }
+    }
+}
+
+fn parent_context(traceparent: Option<&str>) -> Context {
+    let mut carrier = HashMap::new();
+    if let Some(traceparent) = traceparent {
+        carrier.insert(TRACEPARENT.to_string(), traceparent.to_string());
+    }
+    TraceContextPropagator::new().extract_with_context(&Context::new(), &carrier)
+}
+
+/// Parses a W3C traceparent value, returning its trace id and
+/// span id in their hex form
+pub fn parse_traceparent(traceparent: &str) -> Option<(String, String)> {
+    let context = parent_context(Some(traceparent));
+    let span = context.span();
+    let span_context = span.span_context();
+    if span_context.is_valid() {
+        Some((
+            span_context.trace_id().to_string(),
+            span_context.span_id().to_string(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// A span that is ended when it is dropped.
+/// When tracing has not been configured, this is inert and
+/// none of its methods have any effect.
+pub struct TraceSpan {
+    span: Option<BoxedSpan>,
+}
+
+impl TraceSpan {
+    /// Start a span. If `parent` holds a valid traceparent,
+    /// the span is a child of that span, otherwise it begins
+    /// a new trace.
+    pub fn start(name: &'static str, kind: SpanKind, parent: Option<&str>) -> Self {
+        Self::start_impl(name, kind, parent, None)
+    }
+
+    /// Start a span whose start was at some point in the past
+    pub fn start_at(
+        name: &'static str,
+        kind: SpanKind,
+        parent: Option<&str>,
+        start: SystemTime,
+    ) -> Self {
+        Self::start_impl(name, kind, parent, Some(start))
+    }
+
+    /// Returns a span that records nothing
+    pub fn disabled() -> Self {
+        Self { span: None }
+    }
+
+    fn start_impl(
+        name: &'static str,
+        kind: SpanKind,
+        parent: Option<&str>,
+        start: Option<SystemTime>,
+    ) -> Self {
+        if !is_enabled() {
+            return Self::disabled();
+        }
+        let tracer = global::tracer("kumomta");
+        let mut builder = tracer.span_builder(name).with_kind(kind);
+        if let Some(start) = start {
+            builder = builder.with_start_time(start);
+        }
+        Self {
+            span: Some(tracer.build_with_context(builder, &parent_context(parent))),
+        }
+    }
+
+    pub fn set_attribute(&mut self, key: &'static str, value: impl Into<Value>) {
+        if let Some(span) = &mut self.span {
+            span.set_attribute(KeyValue::new(key, value));
+        }
+    }
+
+    /// Mark the span as having failed
+    pub fn set_error(&mut self, error: impl ToString) {
+        if let Some(span) = &mut self.span {
+            span.set_status(Status::error(error.to_string()));
+        }
+    }
+
+    /// Returns the W3C traceparent that identifies this span,
+    /// for use as the parent of subsequent spans
+    pub fn traceparent(&self) -> Option<String> {
+        let span = self.span.as_ref()?;
+        let context = Context::new().with_remote_span_context(span.span_context().clone());
+        let mut carrier = HashMap::new();
+        TraceContextPropagator::new().inject_context(&context, &mut carrier);
+        carrier.remove(TRACEPARENT)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn traceparent() {
+        assert_eq!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some((
+                "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+                "00f067aa0ba902b7".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(parse_traceparent("garbage"), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut span = TraceSpan::start("test", SpanKind::Internal, None);
+        span.set_attribute("key", "value");
+        assert_eq!(span.traceparent(), None);
+    }
+
+    #[test]
+    fn params() {
+        let params: OtlpTraceParams = serde_json::from_str("{}").unwrap();
+        assert_eq!(params.endpoint, "http://localhost:4318/v1/traces");
+        assert_eq!(params.sampling_ratio, 1.0);
+        assert_eq!(params.timeout, Duration::from_secs(10));
+
+        let params: OtlpTraceParams = serde_json::from_str(r#"{"sampling_ratio": 1.5}"#).unwrap();
+        assert!(params.init().is_err());
+    }
+}
@@ -84,6 +84,9 @@ impl StartConfig<'_> {
         // after waiting for those to idle out, shut down logging
         shutdown_future.await;
 
+        // and flush out any remaining trace spans
+        tokio::task::spawn_blocking(crate::otel::shutdown).await?;
+
         tracing::info!("Shutdown completed OK!");
 
         if let Some(error) = init_handle.await? {
@@ -4,7 +4,7 @@ use dns_resolver::{DnsError, Resolver};
 use hickory_resolver::proto::rr::RecordType;
 use hickory_resolver::Name;
 use instant_xml::{FromXml, ToXml};
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::net::IpAddr;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -17,7 +17,7 @@ use record::Qualifier;
 #[cfg(test)]
 mod tests;
 
-#[derive(Debug, Clone, Copy, Eq, FromXml, PartialEq, ToXml)]
+#[derive(Debug, Clone, Copy, Eq, FromXml, Hash, PartialEq, ToXml)]
 #[xml(scalar, rename_all = "lowercase")]
 pub enum SpfDisposition {
     /// A result of "none" means either (a) no syntactically valid DNS domain
@@ -68,12 +68,36 @@ impl SpfDisposition {
     }
 }
 
+impl std::str::FromStr for SpfDisposition {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "none" => Self::None,
+            "neutral" => Self::Neutral,
+            "pass" => Self::Pass,
+            "fail" => Self::Fail,
+            "softfail" => Self::SoftFail,
+            "temperror" => Self::TempError,
+            "permerror" => Self::PermError,
+            _ => return Err(format!("invalid SPF disposition {value:?}")),
+        })
+    }
+}
+
 impl Serialize for SpfDisposition {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         serializer.serialize_str(self.as_str())
     }
 }
 
+impl<'de> Deserialize<'de> for SpfDisposition {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 impl From<Qualifier> for SpfDisposition {
     fn from(qualifier: Qualifier) -> Self {
         match qualifier {
@@ -0,0 +1,562 @@
+//! The purpose of this module is to persist the administrative
+//! bounce, suspension and hold entries to local storage, so that they
+//! are not lost when kumod is restarted.
+
+use crate::http_server::admin_bounce_v1::AdminBounceEntry;
+use crate::http_server::admin_hold_v1::{AdminHoldEntry, PersistedHold};
+use crate::http_server::admin_suspend_ready_q_v1::AdminSuspendReadyQEntry;
+use crate::http_server::admin_suspend_v1::AdminSuspendEntry;
+use crate::http_server::queue_name_multi_index::Criteria;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use kumo_server_runtime::get_main_runtime;
+use parking_lot::FairMutex as Mutex;
+use sqlite::{Connection, ConnectionThreadSafe, State};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+pub static DB_PATH: LazyLock<Mutex<String>> =
+    LazyLock::new(|| Mutex::new("/var/spool/kumomta/admin.db".to_string()));
+
+/// The open connection to the admin db, along with the path
+/// from which it was opened
+static CONNECTION: LazyLock<Mutex<Option<(String, Arc<ConnectionThreadSafe>)>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+/// Changes to the admin db are applied, in order, by a dedicated
+/// writer thread, so that neither the http handlers nor lua
+/// callbacks that add or remove entries block on sqlite
+static WRITER: LazyLock<Option<flume::Sender<WriteOp>>> = LazyLock::new(|| {
+    let (tx, rx) = flume::unbounded();
+    match std::thread::Builder::new()
+        .name("admin-db-writer".to_string())
+        .spawn(move || writer_thread(rx))
+    {
+        Ok(_) => Some(tx),
+        Err(err) => {
+            tracing::error!("failed to spawn admin-db-writer thread: {err:#}");
+            None
+        }
+    }
+});
+
+/// Identifies which of the admin entry types a row holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminEntryKind {
+    Bounce,
+    Suspend,
+    SuspendReadyQ,
+    Hold,
+}
+
+impl AdminEntryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Bounce => "bounce",
+            Self::Suspend => "suspend",
+            Self::SuspendReadyQ => "suspend-ready-q",
+            Self::Hold => "hold",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        [Self::Bounce, Self::Suspend, Self::SuspendReadyQ, Self::Hold]
+            .into_iter()
+            .find(|k| k.as_str() == kind)
+    }
+}
+
+/// The persisted form of an admin entry.
+/// `criteria` is the JSON serialized form of the match criteria;
+/// a `Criteria` for bounces and scheduled queue suspensions,
+/// a `PersistedHold` for holds, or the queue name for ready
+/// queue suspensions.
+struct PersistedEntry {
+    id: Uuid,
+    kind: String,
+    criteria: String,
+    reason: String,
+    suppress_logging: bool,
+    expires: DateTime<Utc>,
+}
+
+/// Convert a monotonic expiration time into wall clock time
+fn instant_to_utc(expires: Instant) -> DateTime<Utc> {
+    let now = Utc::now();
+    chrono::Duration::from_std(expires.saturating_duration_since(Instant::now()))
+        .ok()
+        .and_then(|duration| now.checked_add_signed(duration))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+/// Convert a wall clock expiration time into monotonic time,
+/// returning None if it has already passed
+fn utc_to_instant(expires: DateTime<Utc>) -> Option<Instant> {
+    let duration = (expires - Utc::now()).to_std().ok()?;
+    if duration.is_zero() {
+        return None;
+    }
+    let now = Instant::now();
+    Some(
+        now.checked_add(duration)
+            // Effectively forever
+            .unwrap_or_else(|| now + Duration::from_secs(100 * 365 * 86400)),
+    )
+}
+
+enum WriteOp {
+    Save(PersistedEntry),
+    Delete { kind: AdminEntryKind, id: Uuid },
+    Flush(flume::Sender<()>),
+}
+
+fn writer_thread(rx: flume::Receiver<WriteOp>) {
+    while let Ok(op) = rx.recv() {
+        match op {
+            WriteOp::Save(entry) => {
+                let id = entry.id;
+                let kind = entry.kind.clone();
+                if let Err(err) = save_entry(entry) {
+                    tracing::error!(
+                        "Failed to persist admin {kind} entry {id}; \
+                         it will not survive a restart: {err:#}"
+                    );
+                }
+            }
+            WriteOp::Delete { kind, id } => {
+                if let Err(err) = delete_entry(&id) {
+                    tracing::error!(
+                        "Failed to remove admin {} entry {id} from the admin db: {err:#}",
+                        kind.as_str()
+                    );
+                }
+            }
+            WriteOp::Flush(done) => {
+                done.send(()).ok();
+            }
+        }
+    }
+}
+
+fn enqueue(op: WriteOp) {
+    match WRITER.as_ref() {
+        Some(tx) => {
+            if tx.send(op).is_err() {
+                tracing::error!("admin-db-writer thread has stopped; admin db not updated");
+            }
+        }
+        None => {
+            tracing::error!("admin-db-writer thread is not running; admin db not updated");
+        }
+    }
+}
+
+/// Returns the connection to the admin db, opening it if this
+/// is the first use, or if the configured path has changed
+fn admin_db() -> anyhow::Result<Arc<ConnectionThreadSafe>> {
+    let path = DB_PATH.lock().clone();
+    let mut connection = CONNECTION.lock();
+    if let Some((opened_path, db)) = connection.as_ref() {
+        if *opened_path == path {
+            return Ok(db.clone());
+        }
+    }
+    let db = Arc::new(open_admin_db(&path)?);
+    connection.replace((path, db.clone()));
+    Ok(db)
+}
+
+fn open_admin_db(path: &str) -> anyhow::Result<ConnectionThreadSafe> {
+    tracing::trace!("using path {path:?} for admin db");
+    let mut db = Connection::open_thread_safe(path)
+        .with_context(|| format!("opening admin database {path}"))?;
+    db.set_busy_timeout(30_000)?;
+
+    let query = r#"
+CREATE TABLE IF NOT EXISTS admin_entries (
+    id TEXT NOT NULL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    criteria TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    suppress_logging BOOLEAN NOT NULL,
+    expires INTEGER NOT NULL,
+    UNIQUE (kind, criteria)
+);
+    "#;
+
+    db.execute(query)?;
+
+    tracing::trace!("completed setup for {path:?}");
+
+    Ok(db)
+}
+
+fn save_entry(entry: PersistedEntry) -> anyhow::Result<()> {
+    let db = admin_db()?;
+
+    // An entry with the same criteria replaces any existing entry,
+    // which mirrors the behavior of QueueNameMultiIndexMap::insert
+    let mut insert = db
+        .prepare(
+            "INSERT OR REPLACE INTO admin_entries
+            (id, kind, criteria, reason, suppress_logging, expires)
+            values ($id, $kind, $criteria, $reason, $suppress_logging, $expires)",
+        )
+        .context("prepare")?;
+
+    insert
+        .bind(("$id", entry.id.to_string().as_str()))
+        .context("bind $id")?;
+    insert
+        .bind(("$kind", entry.kind.as_str()))
+        .context("bind $kind")?;
+    insert
+        .bind(("$criteria", entry.criteria.as_str()))
+        .context("bind $criteria")?;
+    insert
+        .bind(("$reason", entry.reason.as_str()))
+        .context("bind $reason")?;
+    insert
+        .bind(("$suppress_logging", entry.suppress_logging as i64))
+        .context("bind $suppress_logging")?;
+    insert
+        .bind(("$expires", entry.expires.timestamp()))
+        .context("bind $expires")?;
+
+    insert.next()?;
+    Ok(())
+}
+
+fn delete_entry(id: &Uuid) -> anyhow::Result<()> {
+    let db = admin_db()?;
+    let mut delete = db
+        .prepare("DELETE FROM admin_entries WHERE id=$id")
+        .context("prepare")?;
+    delete
+        .bind(("$id", id.to_string().as_str()))
+        .context("bind $id")?;
+    delete.next()?;
+    Ok(())
+}
+
+fn load_entries() -> anyhow::Result<Vec<PersistedEntry>> {
+    let path = DB_PATH.lock().clone();
+    if !Path::new(&path).exists() {
+        tracing::debug!("admin db {path} does not exist, nothing to restore");
+        return Ok(vec![]);
+    }
+
+    let db = admin_db()?;
+
+    let mut prune = db
+        .prepare("DELETE FROM admin_entries WHERE expires <= $now")
+        .context("prepare")?;
+    prune
+        .bind(("$now", Utc::now().timestamp()))
+        .context("bind $now")?;
+    prune.next()?;
+
+    let mut select = db
+        .prepare(
+            "SELECT id, kind, criteria, reason, suppress_logging, expires
+            FROM admin_entries",
+        )
+        .context("prepare")?;
+
+    let mut entries = vec![];
+    while let State::Row = select.next()? {
+        let id: String = select.read("id")?;
+        let expires: i64 = select.read("expires")?;
+        let suppress_logging: i64 = select.read("suppress_logging")?;
+        let Ok(parsed_id) = id.parse() else {
+            tracing::error!("ignoring admin entry with invalid id {id:?}");
+            continue;
+        };
+        let Some(expires) = DateTime::<Utc>::from_timestamp(expires, 0) else {
+            tracing::error!("ignoring admin entry {id} with invalid expires timestamp {expires}");
+            continue;
+        };
+        entries.push(PersistedEntry {
+            id: parsed_id,
+            kind: select.read("kind")?,
+            criteria: select.read("criteria")?,
+            reason: select.read("reason")?,
+            suppress_logging: suppress_logging != 0,
+            expires,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Record an admin entry in the admin db.
+/// The write happens asynchronously with respect to the caller.
+/// Failure to persist the entry does not prevent it from taking
+/// effect in this process, so errors are logged rather than returned.
+pub fn persist_entry(
+    kind: AdminEntryKind,
+    id: &Uuid,
+    criteria: &impl serde::Serialize,
+    reason: &str,
+    suppress_logging: bool,
+    expires: Instant,
+) {
+    if config::is_validating() {
+        return;
+    }
+    match serde_json::to_string(criteria) {
+        Ok(criteria) => enqueue(WriteOp::Save(PersistedEntry {
+            id: *id,
+            kind: kind.as_str().to_string(),
+            criteria,
+            reason: reason.to_string(),
+            suppress_logging,
+            expires: instant_to_utc(expires),
+        })),
+        Err(err) => {
+            tracing::error!(
+                "Failed to persist admin {} entry {id}; \
+                 it will not survive a restart: serializing criteria: {err:#}",
+                kind.as_str()
+            );
+        }
+    }
+}
+
+/// Remove an admin entry from the admin db.
+/// The write happens asynchronously with respect to the caller.
+pub fn forget_entry(kind: AdminEntryKind, id: &Uuid) {
+    if config::is_validating() {
+        return;
+    }
+    enqueue(WriteOp::Delete { kind, id: *id });
+}
+
+/// Wait for any pending changes to be written to the admin db
+pub async fn wait_for_shutdown() {
+    if config::is_validating() {
+        return;
+    }
+    let (tx, rx) = flume::bounded(1);
+    enqueue(WriteOp::Flush(tx));
+    rx.recv_async().await.ok();
+}
+
+/// Decode the criteria of a persisted entry.  An entry that cannot
+/// be decoded is logged and removed from the admin db, rather than
+/// preventing the remaining entries from being restored.
+fn decode<T: serde::de::DeserializeOwned>(
+    kind: AdminEntryKind,
+    entry: &PersistedEntry,
+) -> Option<T> {
+    match serde_json::from_str(&entry.criteria) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            tracing::error!(
+                "discarding admin {} entry {} with invalid criteria {:?}: {err:#}",
+                kind.as_str(),
+                entry.id,
+                entry.criteria
+            );
+            enqueue(WriteOp::Delete { kind, id: entry.id });
+            None
+        }
+    }
+}
+
+/// Load the persisted admin entries and make them active.
+/// This must be called prior to starting the spool, so that
+/// messages are subject to the entries as soon as they are
+/// loaded from the spool.
+pub async fn restore_admin_entries() -> anyhow::Result<()> {
+    let entries = get_main_runtime()
+        .spawn_blocking(load_entries)
+        .await?
+        .context("loading admin entries")?;
+
+    let mut num_restored = 0;
+    for entry in entries {
+        let Some(expires) = utc_to_instant(entry.expires) else {
+            continue;
+        };
+
+        match AdminEntryKind::parse(&entry.kind) {
+            Some(AdminEntryKind::Bounce) => {
+                let Some(criteria) = decode::<Criteria>(AdminEntryKind::Bounce, &entry) else {
+                    continue;
+                };
+                AdminBounceEntry::restore(AdminBounceEntry {
+                    id: entry.id,
+                    criteria,
+                    reason: entry.reason,
+                    suppress_logging: entry.suppress_logging,
+                    expires,
+                    bounced: Arc::new(Mutex::new(HashMap::new())),
+                });
+            }
+            Some(AdminEntryKind::Suspend) => {
+                let Some(criteria) = decode::<Criteria>(AdminEntryKind::Suspend, &entry) else {
+                    continue;
+                };
+                AdminSuspendEntry::restore(AdminSuspendEntry {
+                    id: entry.id,
+                    criteria,
+                    reason: entry.reason,
+                    expires,
+                });
+            }
+            Some(AdminEntryKind::SuspendReadyQ) => {
+                let Some(name) = decode::<String>(AdminEntryKind::SuspendReadyQ, &entry) else {
+                    continue;
+                };
+                AdminSuspendReadyQEntry::restore(AdminSuspendReadyQEntry {
+                    id: entry.id,
+                    name,
+                    reason: entry.reason,
+                    expires,
+                });
+            }
+            Some(AdminEntryKind::Hold) => {
+                let Some(hold) = decode::<PersistedHold>(AdminEntryKind::Hold, &entry) else {
+                    continue;
+                };
+                AdminHoldEntry::restore(AdminHoldEntry::new(
+                    entry.id,
+                    hold.criteria,
+                    entry.reason,
+                    entry.expires,
+                    hold.release_rate,
+                ));
+            }
+            None => {
+                tracing::error!(
+                    "ignoring admin entry {} with unknown kind {}",
+                    entry.id,
+                    entry.kind
+                );
+                continue;
+            }
+        }
+        num_restored += 1;
+    }
+
+    if num_restored > 0 {
+        tracing::info!("restored {num_restored} admin bounce/suspension/hold entries");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(kind: AdminEntryKind, criteria: &str, expires: DateTime<Utc>) -> PersistedEntry {
+        PersistedEntry {
+            id: Uuid::new_v4(),
+            kind: kind.as_str().to_string(),
+            criteria: criteria.to_string(),
+            reason: format!("reason for {criteria}"),
+            suppress_logging: false,
+            expires,
+        }
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("kumod-admin-{}.db", Uuid::new_v4()));
+        *DB_PATH.lock() = path.to_string_lossy().to_string();
+
+        let future = Utc::now() + chrono::Duration::hours(1);
+        let past = Utc::now() - chrono::Duration::hours(1);
+
+        let replaced = entry(AdminEntryKind::Bounce, "\"a\"", future);
+        let replaced_id = replaced.id;
+        save_entry(replaced).unwrap();
+
+        let replacement = entry(AdminEntryKind::Bounce, "\"a\"", future);
+        let replacement_id = replacement.id;
+        save_entry(replacement).unwrap();
+
+        // Same criteria but a different kind, so doesn't replace
+        let suspend = entry(AdminEntryKind::Suspend, "\"a\"", future);
+        let suspend_id = suspend.id;
+        save_entry(suspend).unwrap();
+
+        save_entry(entry(AdminEntryKind::SuspendReadyQ, "\"b\"", past)).unwrap();
+
+        let deleted = entry(AdminEntryKind::SuspendReadyQ, "\"c\"", future);
+        let deleted_id = deleted.id;
+        save_entry(deleted).unwrap();
+        delete_entry(&deleted_id).unwrap();
+
+        let mut ids: Vec<Uuid> = load_entries().unwrap().iter().map(|e| e.id).collect();
+        ids.sort();
+        let mut expected = vec![replacement_id, suspend_id];
+        expected.sort();
+        assert_eq!(ids, expected);
+        assert!(!ids.contains(&replaced_id));
+
+        // Changes made via the writer thread are visible once flushed
+        let persisted_id = Uuid::new_v4();
+        persist_entry(
+            AdminEntryKind::Hold,
+            &persisted_id,
+            &"d",
+            "held",
+            false,
+            Instant::now() + Duration::from_secs(3600),
+        );
+        forget_entry(AdminEntryKind::Suspend, &suspend_id);
+        wait_for_shutdown().await;
+
+        let mut ids: Vec<Uuid> = load_entries().unwrap().iter().map(|e| e.id).collect();
+        ids.sort();
+        let mut expected = vec![replacement_id, persisted_id];
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        // Rows that cannot be decoded are skipped rather than failing the load,
+        // and entries with undecodable criteria are removed from the db
+        admin_db()
+            .unwrap()
+            .execute(format!(
+                "INSERT INTO admin_entries (id, kind, criteria, reason, suppress_logging, expires)
+                VALUES ('not-a-uuid', 'bounce', '\"e\"', 'bad id', 0, {})",
+                future.timestamp()
+            ))
+            .unwrap();
+        let corrupt = entry(AdminEntryKind::Bounce, "{not json", future);
+        let corrupt_id = corrupt.id;
+        save_entry(corrupt).unwrap();
+
+        let loaded = load_entries().unwrap();
+        let corrupt = loaded.iter().find(|e| e.id == corrupt_id).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert!(decode::<Criteria>(AdminEntryKind::Bounce, corrupt).is_none());
+        wait_for_shutdown().await;
+
+        let mut ids: Vec<Uuid> = load_entries().unwrap().iter().map(|e| e.id).collect();
+        ids.sort();
+        assert_eq!(ids, expected);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn expiry_conversion() {
+        assert!(utc_to_instant(Utc::now() - chrono::Duration::seconds(10)).is_none());
+
+        let expires = utc_to_instant(Utc::now() + chrono::Duration::hours(1)).unwrap();
+        let remaining = expires.saturating_duration_since(Instant::now());
+        assert!(remaining > Duration::from_secs(3590), "{remaining:?}");
+        assert!(remaining <= Duration::from_secs(3600), "{remaining:?}");
+
+        let utc = instant_to_utc(Instant::now() + Duration::from_secs(3600));
+        let delta = (utc - Utc::now()).num_seconds();
+        assert!((3590..=3600).contains(&delta), "{delta}");
+    }
+}
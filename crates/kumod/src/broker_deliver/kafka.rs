@@ -0,0 +1,249 @@
+use crate::broker_deliver::{dispose_message, MessageTemplates, PublishOutcome};
+use crate::ready_queue::{AttemptConnectionDisposition, Dispatcher, QueueDispatcher};
+use async_trait::async_trait;
+use derive_where::derive_where;
+use kumo_log_types::ResolvedAddress;
+use kumo_template::TemplateDialect;
+use message::Message;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::future_producer::Delivery;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::util::Timeout;
+use rdkafka::ClientConfig;
+use rfc5321::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use tokio::time::Duration;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaDeliveryProtocol {
+    /// The librdkafka producer configuration, which must include
+    /// at least `bootstrap.servers`
+    pub config: BTreeMap<String, String>,
+
+    /// The destination topic
+    pub topic: String,
+
+    /// Optional destination partition. When not specified,
+    /// the partition is chosen based on the key
+    #[serde(default)]
+    partition: Option<i32>,
+
+    /// Template that produces the key for each message
+    #[serde(default)]
+    key_template: Option<String>,
+
+    /// Template that produces the payload for each message.
+    /// When not specified, the message data is used as the payload.
+    #[serde(default)]
+    payload_template: Option<String>,
+
+    #[serde(default)]
+    template_dialect: TemplateDialect,
+
+    /// Additional headers to include with each record
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+
+    #[serde(default = "KafkaDeliveryProtocol::default_batch_size")]
+    batch_size: usize,
+    #[serde(default = "KafkaDeliveryProtocol::default_batch_size")]
+    min_batch_size: usize,
+    #[serde(
+        default = "KafkaDeliveryProtocol::default_max_batch_latency",
+        with = "duration_serde"
+    )]
+    max_batch_latency: Duration,
+
+    /// How long to wait for the broker to acknowledge a record
+    /// before treating it as a transient failure
+    #[serde(
+        default = "KafkaDeliveryProtocol::default_timeout",
+        with = "duration_serde"
+    )]
+    timeout: Duration,
+}
+
+impl KafkaDeliveryProtocol {
+    fn default_batch_size() -> usize {
+        1
+    }
+
+    fn default_max_batch_latency() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    pub fn bootstrap_servers(&self) -> &str {
+        self.config
+            .get("bootstrap.servers")
+            .map(|s| s.as_str())
+            .unwrap_or("")
+    }
+}
+
+#[derive_where(Debug)]
+pub struct KafkaQueueDispatcher {
+    proto_config: KafkaDeliveryProtocol,
+    #[derive_where(skip)]
+    producer: Option<Arc<FutureProducer>>,
+    peer_address: ResolvedAddress,
+}
+
+impl KafkaQueueDispatcher {
+    pub fn new(proto_config: KafkaDeliveryProtocol) -> Self {
+        let peer_address = ResolvedAddress {
+            name: format!(
+                "Kafka via {}/{}",
+                proto_config.bootstrap_servers(),
+                proto_config.topic
+            ),
+            addr: Ipv4Addr::UNSPECIFIED.into(),
+        };
+
+        Self {
+            proto_config,
+            producer: None,
+            peer_address,
+        }
+    }
+}
+
+#[async_trait]
+impl QueueDispatcher for KafkaQueueDispatcher {
+    async fn close_connection(&mut self, _dispatcher: &mut Dispatcher) -> anyhow::Result<bool> {
+        Ok(self.producer.take().is_some())
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.proto_config.batch_size
+    }
+    fn min_batch_size(&self) -> usize {
+        self.proto_config.min_batch_size
+    }
+    fn max_batch_latency(&self) -> Duration {
+        self.proto_config.max_batch_latency
+    }
+
+    async fn attempt_connection(
+        &mut self,
+        dispatcher: &mut Dispatcher,
+    ) -> anyhow::Result<AttemptConnectionDisposition> {
+        if self.producer.is_some() {
+            return Ok(AttemptConnectionDisposition::ReusedExisting);
+        }
+
+        let mut builder = ClientConfig::new();
+        for (k, v) in &self.proto_config.config {
+            builder.set(k, v);
+        }
+        let producer: FutureProducer = builder.create()?;
+
+        self.producer.replace(Arc::new(producer));
+        dispatcher.delivered_this_connection = 0;
+        Ok(AttemptConnectionDisposition::ConnectedNew)
+    }
+
+    async fn have_more_connection_candidates(&mut self, _dispatcher: &mut Dispatcher) -> bool {
+        false
+    }
+
+    async fn deliver_message(
+        &mut self,
+        msgs: Vec<Message>,
+        dispatcher: &mut Dispatcher,
+    ) -> anyhow::Result<()> {
+        let Some(producer) = self.producer.clone() else {
+            anyhow::bail!("producer is not set in KafkaQueueDispatcher::deliver_message!?");
+        };
+
+        let templates = MessageTemplates::new(
+            self.proto_config.template_dialect,
+            self.proto_config.key_template.as_deref(),
+            self.proto_config.payload_template.as_deref(),
+        )?;
+
+        let headers = if self.proto_config.headers.is_empty() {
+            None
+        } else {
+            let mut headers = OwnedHeaders::new();
+            for (key, v) in &self.proto_config.headers {
+                headers = headers.insert(Header {
+                    key,
+                    value: Some(v),
+                });
+            }
+            Some(headers)
+        };
+
+        // Render everything up front, so that a template error
+        // cannot leave the batch partially published
+        let mut records = vec![];
+        for msg in &msgs {
+            records.push(templates.render(msg).await?);
+        }
+
+        // Submit the whole batch before waiting for any of the
+        // acknowledgements, so that librdkafka can batch them up
+        let mut pending = vec![];
+        for rendered in records {
+            let producer = producer.clone();
+            let topic = self.proto_config.topic.clone();
+            let partition = self.proto_config.partition;
+            let headers = headers.clone();
+            let timeout = self.proto_config.timeout;
+
+            pending.push(tokio::spawn(async move {
+                producer
+                    .send(
+                        FutureRecord {
+                            topic: &topic,
+                            partition,
+                            payload: Some(&rendered.payload),
+                            key: rendered.key.as_ref(),
+                            headers,
+                            timestamp: None,
+                        },
+                        Timeout::After(timeout),
+                    )
+                    .await
+                    .map_err(|(err, _msg)| err)
+            }));
+        }
+
+        let batch = dispatcher.msgs.drain(..).collect::<Vec<_>>();
+        for (msg, task) in batch.into_iter().zip(pending) {
+            let outcome = match task.await {
+                Ok(Ok(Delivery {
+                    partition, offset, ..
+                })) => PublishOutcome::Delivered(Response {
+                    code: 250,
+                    enhanced_code: None,
+                    content: format!("partition={partition} offset={offset}"),
+                    command: None,
+                }),
+                Ok(Err(err)) => PublishOutcome::TransientFailure(Response {
+                    code: 451,
+                    enhanced_code: None,
+                    content: format!("KumoMTA internal: kafka: {err:#}"),
+                    command: None,
+                }),
+                Err(err) => PublishOutcome::TransientFailure(Response {
+                    code: 451,
+                    enhanced_code: None,
+                    content: format!("KumoMTA internal: kafka send task failed: {err:#}"),
+                    command: None,
+                }),
+            };
+            dispose_message(dispatcher, &self.peer_address, "Kafka", msg, outcome).await;
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,5 @@
 use config::{any_err, get_or_create_sub_module, serialize_options};
-use kumo_dmarc::{CheckHostParams, Disposition};
+use kumo_dmarc::{CheckHostParams, Disposition, DkimAuth, DkimResult, SpfAuth};
 use mailparsing::AuthenticationResult;
 use message::Message;
 use mlua::{Lua, LuaSerdeExt, UserDataRef};
@@ -13,6 +13,38 @@ struct CheckHostOutput {
     result: AuthenticationResult,
 }
 
+/// Extract the DKIM signature results from a list of authentication results
+fn dkim_auth(results: &[AuthenticationResult]) -> Vec<DkimAuth> {
+    results
+        .iter()
+        .filter(|result| result.method == "dkim")
+        // An unsigned message is represented by a result without
+        // a domain, which doesn't correspond to any signature
+        .filter(|result| result.result != "none")
+        .map(|result| DkimAuth {
+            domain: result.props.get("header.d").cloned().unwrap_or_default(),
+            selector: result.props.get("header.s").cloned(),
+            result: result.result.parse().unwrap_or(DkimResult::PermError),
+        })
+        .collect()
+}
+
+/// Extract the checked identity and the result from an SPF result
+fn spf_auth(result: &AuthenticationResult) -> Option<SpfAuth> {
+    let domain = match result.props.get("smtp.mailfrom") {
+        Some(mail_from) if !mail_from.is_empty() => mail_from
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or(mail_from),
+        // The HELO identity is checked when MAIL FROM is null
+        _ => result.props.get("smtp.helo")?,
+    };
+    Some(SpfAuth {
+        domain: domain.to_string(),
+        result: result.result.parse().ok()?,
+    })
+}
+
 pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
     let dmarc_mod = get_or_create_sub_module(lua, "dmarc")?;
 
@@ -20,10 +52,11 @@ pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
         "check_msg",
         lua.create_async_function(
             |lua,
-             (msg, dkim_results, opt_resolver_name): (
+             (msg, dkim_results, opt_resolver_name, opt_spf_result): (
                 UserDataRef<Message>,
                 mlua::Value,
                 Option<String>,
+                mlua::Value,
             )| async move {
                 let resolver = get_resolver_instance(&opt_resolver_name).map_err(any_err)?;
 
@@ -72,14 +105,40 @@ pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
                 let dkim_results: Vec<AuthenticationResult> =
                     config::from_lua_value(&lua, dkim_results)?;
 
-                let result = CheckHostParams {
-                    from_domain,
-                    mail_from_domain,
-                    dkim: dkim_results.clone().into_iter().map(|x| x.props).collect(),
+                let spf_result: Option<AuthenticationResult> =
+                    config::from_lua_value(&lua, opt_spf_result)?;
+
+                let dkim = dkim_auth(&dkim_results);
+                let spf = spf_result.as_ref().and_then(spf_auth);
+
+                let (result, evaluation) = CheckHostParams {
+                    from_domain: from_domain.clone(),
+                    mail_from_domain: mail_from_domain.clone(),
+                    dkim: dkim.clone(),
+                    spf: spf.clone(),
                 }
-                .check(&**resolver)
+                .check_with_evaluation(&**resolver)
                 .await;
 
+                if let Some(evaluation) = evaluation {
+                    if crate::dmarc_reporting::is_enabled() {
+                        if let Err(err) = crate::dmarc_reporting::record_evaluation(
+                            &msg,
+                            evaluation,
+                            &from_domain,
+                            mail_from_domain.as_deref(),
+                            dkim,
+                            spf,
+                        )
+                        .await
+                        {
+                            tracing::debug!(
+                                "failed to record DMARC evaluation for report: {err:#}"
+                            );
+                        }
+                    }
+                }
+
                 match result.result {
                     Disposition::Pass
                     | Disposition::None
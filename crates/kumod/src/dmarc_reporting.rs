@@ -0,0 +1,562 @@
+//! This module implements DMARC aggregate reporting, RFC 7489 section 7.2.
+//! The results of `kumo.dmarc.check_msg` are accumulated per policy
+//! domain, and a report is periodically sent to each of the `rua`
+//! destinations published by that domain.
+//! The accumulated data is periodically saved to a state file, so
+//! that it is not lost when kumod is restarted.
+
+use crate::queue::{InsertReason, QueueManager};
+use crate::tls_reporting::period_start;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use config::{any_err, declare_event, get_or_create_sub_module, load_config};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use kumo_dmarc::{
+    is_authorized_destination, AggregateReport, AggregateReportStore, DkimAuth, PolicyEvaluation,
+    ReportMetadataParams, ReportedMessage, SpfAuth,
+};
+use kumo_server_lifecycle::ShutdownSubcription;
+use kumo_server_runtime::{get_main_runtime, rt_spawn};
+use mailparsing::{AttachmentOptions, MimePart};
+use message::{EnvelopeAddress, Message};
+use mlua::{Lua, LuaSerdeExt};
+use parking_lot::FairMutex as Mutex;
+use serde::{Deserialize, Serialize};
+use spool::SpoolId;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+declare_event! {
+static DMARC_REPORT_GENERATED: Single(
+    "dmarc_report_generated",
+    message: Message,
+) -> ();
+}
+
+static CONFIG: LazyLock<Mutex<Option<Arc<DmarcReportingParams>>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+static STORE: LazyLock<Mutex<AggregateReportStore>> =
+    LazyLock::new(|| Mutex::new(AggregateReportStore::default()));
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DmarcReportingParams {
+    /// Used as the `org_name` field of the report metadata
+    pub organization_name: String,
+    /// Used as the `email` field of the report metadata
+    pub email: String,
+    /// Used as the `extra_contact_info` field of the report metadata
+    #[serde(default)]
+    pub extra_contact_info: Option<String>,
+    /// The domain that is submitting the reports; it is used in the
+    /// report filename, subject and Message-ID
+    pub reporting_domain: String,
+    /// The envelope sender and `From` address of the reports
+    pub sender: String,
+    /// How often to generate reports. The reporting period is
+    /// aligned to a multiple of this interval since the unix
+    /// epoch, so the default of 1 day produces reports covering
+    /// each UTC day.
+    #[serde(
+        default = "DmarcReportingParams::default_report_interval",
+        with = "duration_serde"
+    )]
+    pub report_interval: Duration,
+    /// Where to save the data that has been accumulated for the
+    /// current reporting period
+    #[serde(default = "DmarcReportingParams::default_state_path")]
+    pub state_path: PathBuf,
+    /// How often to save the accumulated data to `state_path`
+    #[serde(
+        default = "DmarcReportingParams::default_save_interval",
+        with = "duration_serde"
+    )]
+    pub save_interval: Duration,
+}
+
+impl DmarcReportingParams {
+    fn default_report_interval() -> Duration {
+        Duration::from_secs(86400)
+    }
+
+    fn default_state_path() -> PathBuf {
+        "/var/spool/kumomta/dmarc-rua.json".into()
+    }
+
+    fn default_save_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+}
+
+/// The form in which the results for a reporting period are saved
+#[derive(Serialize, Deserialize, Debug)]
+struct PersistedStore {
+    period_start: DateTime<Utc>,
+    reports: Vec<AggregateReport>,
+}
+
+pub fn is_enabled() -> bool {
+    CONFIG.lock().is_some()
+}
+
+/// Record the outcome of a DMARC evaluation for inclusion
+/// in the next aggregate report
+pub async fn record_evaluation(
+    msg: &Message,
+    evaluation: PolicyEvaluation,
+    header_from: &str,
+    envelope_from: Option<&str>,
+    dkim: Vec<DkimAuth>,
+    spf: Option<SpfAuth>,
+) -> anyhow::Result<()> {
+    if evaluation.rua().is_empty() {
+        return Ok(());
+    }
+
+    let received_from = msg
+        .get_meta_string("received_from")
+        .await?
+        .context("received_from is not set")?;
+    let source_ip: IpAddr = match received_from.parse::<SocketAddr>() {
+        Ok(addr) => addr.ip(),
+        Err(_) => received_from
+            .parse()
+            .with_context(|| format!("failed to parse received_from {received_from}"))?,
+    };
+
+    let envelope_to = msg
+        .recipient()
+        .await
+        .ok()
+        .map(|recip| recip.domain().to_string());
+
+    STORE.lock().record(
+        evaluation,
+        &ReportedMessage {
+            source_ip,
+            header_from: header_from.to_string(),
+            envelope_from: envelope_from.map(|s| s.to_string()),
+            envelope_to,
+            // A signature without a domain cannot be reported
+            dkim: dkim
+                .into_iter()
+                .filter(|dkim| !dkim.domain.is_empty())
+                .collect(),
+            spf,
+        },
+    );
+
+    Ok(())
+}
+
+fn gzip(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Build the RFC 7489 section 7.2.1.1 email representation of a report
+fn build_report_message(
+    params: &DmarcReportingParams,
+    policy_domain: &str,
+    report_id: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    compressed: &[u8],
+    recipient: &str,
+) -> anyhow::Result<MimePart<'static>> {
+    let file_name = format!(
+        "{submitter}!{policy_domain}!{start}!{end}!{report_id}.xml.gz",
+        submitter = params.reporting_domain,
+        start = start.timestamp(),
+        end = end.timestamp(),
+    );
+
+    let exposition = format!(
+        "This is an aggregate DMARC report from {org} for {policy_domain}\r\n\
+         covering the period {start} to {end}.\r\n",
+        org = params.organization_name,
+        start = start.to_rfc3339(),
+        end = end.to_rfc3339(),
+    );
+
+    let parts = vec![
+        MimePart::new_text_plain(&exposition).context("new_text_plain")?,
+        MimePart::new_binary(
+            "application/gzip",
+            compressed,
+            Some(&AttachmentOptions {
+                file_name: Some(file_name),
+                inline: false,
+                content_id: None,
+            }),
+        )
+        .context("new_binary")?,
+    ];
+
+    let mut report_msg = MimePart::new_multipart("multipart/mixed", parts, None)?;
+
+    let headers = report_msg.headers_mut();
+    headers
+        .set_subject(
+            format!(
+                "Report Domain: {policy_domain} Submitter: {submitter} Report-ID: <{report_id}>",
+                submitter = params.reporting_domain,
+            )
+            .as_str(),
+        )
+        .context("set_subject")?;
+    headers
+        .set_mime_version("1.0")
+        .context("set_mime_version")?;
+    headers
+        .set_message_id(format!("<{report_id}@{}>", params.reporting_domain).as_str())
+        .context("set_message_id")?;
+    headers.set_to(recipient).context("set_to")?;
+    headers
+        .set_from(params.sender.as_str())
+        .context("set_from")?;
+    headers.set_date(Utc::now()).context("set_date")?;
+
+    Ok(report_msg)
+}
+
+async fn send_report(
+    params: &DmarcReportingParams,
+    report: AggregateReport,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let policy_domain = report.policy_domain().to_string();
+    let rua = report.rua().to_vec();
+    let report_id = Uuid::new_v4().to_string();
+
+    let xml = report
+        .to_xml(ReportMetadataParams {
+            org_name: params.organization_name.clone(),
+            email: params.email.clone(),
+            extra_contact_info: params.extra_contact_info.clone(),
+            report_id: report_id.clone(),
+            begin: start,
+            end,
+        })
+        .map_err(|err| anyhow::anyhow!("failed to serialize report: {err:?}"))?;
+    let compressed = gzip(xml.as_bytes())?;
+
+    let resolver = dns_resolver::get_resolver();
+
+    for destination in rua {
+        let Some(recipient) = destination.uri.strip_prefix("mailto:") else {
+            tracing::debug!(
+                "DMARC report for {policy_domain}: unsupported rua {}",
+                destination.uri
+            );
+            continue;
+        };
+
+        let address = match EnvelopeAddress::parse(recipient) {
+            Ok(address) => address,
+            Err(err) => {
+                tracing::error!(
+                    "DMARC report for {policy_domain}: invalid rua {}: {err:#}",
+                    destination.uri
+                );
+                continue;
+            }
+        };
+        if !is_authorized_destination(&policy_domain, address.domain(), &**resolver).await {
+            tracing::debug!(
+                "DMARC report for {policy_domain}: {recipient} has not \
+                 authorized receiving reports for this domain"
+            );
+            continue;
+        }
+
+        if let Some(size) = destination.size {
+            if compressed.len() as u64 > size {
+                tracing::debug!(
+                    "DMARC report for {policy_domain}: report size {} \
+                     exceeds the {size} byte limit for {recipient}",
+                    compressed.len()
+                );
+                continue;
+            }
+        }
+
+        let report_msg = build_report_message(
+            params,
+            &policy_domain,
+            &report_id,
+            start,
+            end,
+            &compressed,
+            recipient,
+        )?;
+
+        let msg = Message::new_dirty(
+            SpoolId::new(),
+            EnvelopeAddress::parse(&params.sender)?,
+            vec![address],
+            serde_json::json!({}),
+            Arc::new(
+                report_msg
+                    .to_message_string()
+                    .into_bytes()
+                    .into_boxed_slice(),
+            ),
+        )?;
+
+        let mut config = load_config().await?;
+        config
+            .async_call_callback(&DMARC_REPORT_GENERATED, msg.clone())
+            .await?;
+        config.put();
+
+        let queue_name = msg.get_queue_name().await?;
+        if queue_name != "null" {
+            msg.save(None).await?;
+            QueueManager::insert(&queue_name, msg, InsertReason::Received.into()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Send out the reports for the data accumulated during a reporting period
+async fn send_reports(
+    params: &DmarcReportingParams,
+    reports: Vec<AggregateReport>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) {
+    for report in reports {
+        let policy_domain = report.policy_domain().to_string();
+        if let Err(err) = send_report(params, report, start, end).await {
+            tracing::error!("failed to send DMARC report for {policy_domain}: {err:#}");
+        }
+    }
+}
+
+fn load_state(path: &Path) -> anyhow::Result<Option<PersistedStore>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data).with_context(|| {
+            format!("parsing DMARC reporting state {}", path.display())
+        })?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => {
+            Err(err).with_context(|| format!("reading DMARC reporting state {}", path.display()))
+        }
+    }
+}
+
+fn save_state(path: &Path, state: &PersistedStore) -> anyhow::Result<()> {
+    let data = serde_json::to_vec(state)?;
+    // Write to a temporary file and rename it into place, so that
+    // a crash part way through doesn't leave a truncated file
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, data)
+        .with_context(|| format!("writing DMARC reporting state {}", temp_path.display()))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("renaming DMARC reporting state to {}", path.display()))?;
+    Ok(())
+}
+
+/// Save the data accumulated so far for the period beginning at `start`
+async fn persist_store(params: &DmarcReportingParams, start: DateTime<Utc>) {
+    let state = PersistedStore {
+        period_start: start,
+        reports: STORE.lock().reports(),
+    };
+    let path = params.state_path.clone();
+    let result = match get_main_runtime()
+        .spawn_blocking(move || save_state(&path, &state))
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => Err(err.into()),
+    };
+    if let Err(err) = result {
+        tracing::error!("failed to save DMARC reporting state: {err:#}");
+    }
+}
+
+/// Load the data saved by a previous run. Data for the current
+/// reporting period is restored into the store, while the
+/// data for an earlier period is returned so that it can be reported.
+async fn restore_store(
+    params: &DmarcReportingParams,
+    current_start: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, Vec<AggregateReport>)> {
+    let path = params.state_path.clone();
+    let state = match get_main_runtime()
+        .spawn_blocking(move || load_state(&path))
+        .await
+    {
+        Ok(Ok(Some(state))) => state,
+        Ok(Ok(None)) => return None,
+        Ok(Err(err)) => {
+            tracing::error!("failed to restore DMARC reporting state: {err:#}");
+            return None;
+        }
+        Err(err) => {
+            tracing::error!("failed to restore DMARC reporting state: {err:#}");
+            return None;
+        }
+    };
+
+    if state.period_start == current_start {
+        STORE.lock().merge(state.reports);
+        return None;
+    }
+
+    if state.reports.is_empty() {
+        None
+    } else {
+        Some((state.period_start, state.reports))
+    }
+}
+
+async fn report_generator(params: Arc<DmarcReportingParams>) {
+    let mut shutdown = ShutdownSubcription::get();
+    let interval =
+        chrono::Duration::from_std(params.report_interval).unwrap_or(chrono::Duration::days(1));
+
+    let mut start = period_start(Utc::now(), params.report_interval);
+    if let Some((prior_start, prior)) = restore_store(&params, start).await {
+        // The data is from a period that ended while we were not running
+        let prior_end = (prior_start + interval).min(start);
+        send_reports(
+            &params,
+            prior,
+            prior_start,
+            prior_end - chrono::Duration::seconds(1),
+        )
+        .await;
+        persist_store(&params, start).await;
+    }
+
+    let mut save_interval = tokio::time::interval(params.save_interval);
+    loop {
+        let end = start + interval;
+        let wait = (end - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+
+        tokio::select! {
+            _ = shutdown.shutting_down() => {
+                tracing::debug!("DMARC report generator shutting down");
+                persist_store(&params, start).await;
+                return;
+            }
+            _ = save_interval.tick() => {
+                persist_store(&params, start).await;
+                continue;
+            }
+            _ = tokio::time::sleep(wait) => {}
+        };
+
+        // The report covers the period up to, but not including, `end`
+        let reports = STORE.lock().take();
+        send_reports(&params, reports, start, end - chrono::Duration::seconds(1)).await;
+        start = end;
+        persist_store(&params, start).await;
+    }
+}
+
+pub fn register(lua: &Lua) -> anyhow::Result<()> {
+    let dmarc_mod = get_or_create_sub_module(lua, "dmarc")?;
+
+    dmarc_mod.set(
+        "configure_aggregate_reporting",
+        lua.create_function(|lua, params: mlua::Value| {
+            let params: DmarcReportingParams = lua.from_value(params)?;
+            if config::is_validating() {
+                return Ok(());
+            }
+
+            let params = Arc::new(params);
+            let mut config = CONFIG.lock();
+            let already_started = config.is_some();
+            config.replace(params.clone());
+            if !already_started {
+                rt_spawn("dmarc_report_generator", report_generator(params)).map_err(any_err)?;
+            }
+            Ok(())
+        })?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn report_message() {
+        let params = DmarcReportingParams {
+            organization_name: "Example Org".to_string(),
+            email: "postmaster@example.org".to_string(),
+            extra_contact_info: None,
+            reporting_domain: "example.org".to_string(),
+            sender: "dmarc@example.org".to_string(),
+            report_interval: DmarcReportingParams::default_report_interval(),
+            state_path: DmarcReportingParams::default_state_path(),
+            save_interval: DmarcReportingParams::default_save_interval(),
+        };
+        let start = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 3, 5, 23, 59, 59).unwrap();
+        let compressed = gzip(b"<feedback/>").unwrap();
+
+        let msg = build_report_message(
+            &params,
+            "example.com",
+            "REPORTID",
+            start,
+            end,
+            &compressed,
+            "rua@example.com",
+        )
+        .unwrap();
+
+        assert_eq!(
+            msg.headers().subject().unwrap().unwrap(),
+            "Report Domain: example.com Submitter: example.org Report-ID: <REPORTID>"
+        );
+
+        let attachment = &msg.child_parts()[1];
+        let ct = attachment.headers().content_type().unwrap().unwrap();
+        assert_eq!(ct.value, "application/gzip");
+        assert_eq!(
+            ct.get("name").unwrap(),
+            "example.org!example.com!1709596800!1709683199!REPORTID.xml.gz"
+        );
+    }
+    #[test]
+    fn persistence() {
+        let path = std::env::temp_dir().join(format!("kumod-dmarc-rua-{}.json", Uuid::new_v4()));
+        assert!(load_state(&path).unwrap().is_none());
+
+        let start = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        save_state(
+            &path,
+            &PersistedStore {
+                period_start: start,
+                reports: vec![],
+            },
+        )
+        .unwrap();
+        let state = load_state(&path).unwrap().unwrap();
+        assert_eq!(state.period_start, start);
+        assert!(state.reports.is_empty());
+
+        std::fs::write(&path, b"not json").unwrap();
+        assert!(load_state(&path).is_err());
+        std::fs::remove_file(&path).ok();
+    }
+}
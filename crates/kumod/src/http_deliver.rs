@@ -0,0 +1,580 @@
+use crate::logging::disposition::{log_disposition, LogDisposition};
+use crate::queue::config::ResponseCodePattern;
+use crate::queue::{IncrementAttempts, InsertReason, QueueManager};
+use crate::ready_queue::{AttemptConnectionDisposition, Dispatcher, QueueDispatcher};
+use crate::spool::SpoolManager;
+use anyhow::Context;
+use async_trait::async_trait;
+use data_encoding::HEXLOWER;
+use data_loader::KeySource;
+use kumo_log_types::{RecordType, ResolvedAddress};
+use kumo_server_runtime::spawn;
+use kumo_template::{TemplateDialect, TemplateEngine};
+use message::Message;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, Url};
+use rfc5321::Response;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use tokio::time::Duration;
+
+/// Response bodies are included in the logged response; limit
+/// how much of a potentially large body we retain
+const MAX_RESPONSE_CONTENT: usize = 1024;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpDeliveryProtocol {
+    /// The URL to which each batch of messages will be POSTed
+    pub url: String,
+
+    /// Additional headers to include in each request
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+
+    #[serde(default)]
+    auth: Option<HttpAuth>,
+
+    #[serde(default)]
+    signing: Option<HttpSigning>,
+
+    /// Template that produces the request body from the batch.
+    /// When not specified, the batch is serialized as JSON.
+    #[serde(default)]
+    payload_template: Option<String>,
+    #[serde(default)]
+    template_dialect: TemplateDialect,
+    #[serde(default = "HttpDeliveryProtocol::default_content_type")]
+    content_type: String,
+    /// How the message content is represented in the payload
+    #[serde(default)]
+    data_encoding: DataEncoding,
+
+    #[serde(default = "HttpDeliveryProtocol::default_batch_size")]
+    batch_size: usize,
+    #[serde(default = "HttpDeliveryProtocol::default_batch_size")]
+    min_batch_size: usize,
+    #[serde(
+        default = "HttpDeliveryProtocol::default_max_batch_latency",
+        with = "duration_serde"
+    )]
+    max_batch_latency: Duration,
+
+    #[serde(
+        default = "HttpDeliveryProtocol::default_timeout",
+        with = "duration_serde"
+    )]
+    timeout: Duration,
+
+    /// Rules that are consulted, in order, to decide how to
+    /// treat the status code returned by the server
+    #[serde(default)]
+    status_rules: Vec<HttpStatusRule>,
+}
+
+impl HttpDeliveryProtocol {
+    fn default_content_type() -> String {
+        "application/json".to_string()
+    }
+
+    fn default_batch_size() -> usize {
+        1
+    }
+
+    fn default_max_batch_latency() -> Duration {
+        Duration::from_secs(0)
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    /// Determine how to treat an HTTP status code.
+    /// The first matching status rule wins; if none match,
+    /// 2xx is success, 408, 429 and 5xx are transient and
+    /// anything else is a permanent failure.
+    pub fn classify_status(&self, status: u16) -> HttpStatusDisposition {
+        if let Some(rule) = self
+            .status_rules
+            .iter()
+            .find(|rule| rule.status.iter().any(|p| p.matches_code(status)))
+        {
+            return rule.disposition;
+        }
+        match status {
+            200..=299 => HttpStatusDisposition::Success,
+            408 | 429 | 500..=599 => HttpStatusDisposition::Transient,
+            _ => HttpStatusDisposition::Permanent,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HttpAuth {
+    Bearer {
+        bearer_token: KeySource,
+    },
+    Basic {
+        username: String,
+        #[serde(default)]
+        password: Option<KeySource>,
+    },
+}
+
+/// Signs the request body using HMAC-SHA256.
+/// The signature is sent as `sha256=<hex digest>` in the
+/// specified header.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpSigning {
+    key: KeySource,
+    #[serde(default = "HttpSigning::default_header")]
+    header: String,
+}
+
+impl HttpSigning {
+    fn default_header() -> String {
+        "X-Signature".to_string()
+    }
+}
+
+fn sign_payload(key: &[u8], payload: &[u8]) -> String {
+    let key = aws_lc_rs::hmac::Key::new(aws_lc_rs::hmac::HMAC_SHA256, key);
+    let tag = aws_lc_rs::hmac::sign(&key, payload);
+    format!("sha256={}", HEXLOWER.encode(tag.as_ref()))
+}
+
+/// How the message content is represented in the `data`
+/// field of the message context
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataEncoding {
+    /// The content as a string. Any bytes that are not valid UTF-8
+    /// are replaced by U+FFFD, so binary content is not preserved.
+    #[default]
+    Utf8,
+    /// The content encoded as base64, which preserves it exactly
+    Base64,
+}
+
+impl DataEncoding {
+    fn encode(&self, data: &[u8]) -> String {
+        match self {
+            Self::Utf8 => String::from_utf8_lossy(data).into_owned(),
+            Self::Base64 => data_encoding::BASE64.encode(data),
+        }
+    }
+}
+
+/// Produces the representation of a message that is used in
+/// payloads and passed to payload templates
+pub async fn message_context(
+    msg: &Message,
+    data_encoding: DataEncoding,
+) -> anyhow::Result<serde_json::Value> {
+    let data = msg.data().await?;
+    Ok(json!({
+        "id": msg.id().to_string(),
+        "sender": msg.sender().await?.to_string(),
+        "recipients": msg.recipient_list_string().await?,
+        "queue": msg.get_queue_name().await?,
+        "num_attempts": msg.get_num_attempts(),
+        "meta": msg.get_meta_obj().await?,
+        "data": data_encoding.encode(&data),
+    }))
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpStatusDisposition {
+    Success,
+    Transient,
+    Permanent,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpStatusRule {
+    /// Status code patterns, such as `429` or `5xx`
+    #[serde(deserialize_with = "deserialize_status_patterns")]
+    pub status: Vec<ResponseCodePattern>,
+    pub disposition: HttpStatusDisposition,
+}
+
+/// HTTP responses have no enhanced status code, so a rule using
+/// such a pattern could never match; reject it rather than
+/// silently ignoring it
+fn deserialize_status_patterns<'de, D>(d: D) -> Result<Vec<ResponseCodePattern>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let patterns = Vec::<ResponseCodePattern>::deserialize(d)?;
+    if let Some(pattern) = patterns.iter().find(|p| p.is_enhanced()) {
+        return Err(serde::de::Error::custom(format!(
+            "status pattern '{}' is an enhanced status code pattern, \
+             but HTTP responses only have a three digit status code; \
+             use a pattern like '429' or '5xx' instead",
+            pattern.as_str()
+        )));
+    }
+    Ok(patterns)
+}
+
+/// The state that we establish once per session
+#[derive(Debug)]
+struct HttpSession {
+    client: Client,
+    url: Url,
+    signing_key: Option<(HeaderName, Vec<u8>)>,
+}
+
+#[derive(Debug)]
+pub struct HttpQueueDispatcher {
+    proto_config: HttpDeliveryProtocol,
+    session: Option<HttpSession>,
+    peer_address: ResolvedAddress,
+}
+
+impl HttpQueueDispatcher {
+    pub fn new(proto_config: HttpDeliveryProtocol) -> Self {
+        let peer_address = ResolvedAddress {
+            name: format!("HTTP via {}", proto_config.url),
+            addr: Ipv4Addr::UNSPECIFIED.into(),
+        };
+
+        Self {
+            proto_config,
+            session: None,
+            peer_address,
+        }
+    }
+
+    async fn build_session(&self) -> anyhow::Result<HttpSession> {
+        async fn load_string(source: &KeySource, what: &str) -> anyhow::Result<String> {
+            let data = source
+                .get()
+                .await
+                .with_context(|| format!("loading http delivery {what}"))?;
+            Ok(String::from_utf8(data)
+                .with_context(|| format!("http delivery {what} is not UTF-8"))?
+                .trim()
+                .to_string())
+        }
+
+        let config = &self.proto_config;
+        let url = Url::parse(&config.url).with_context(|| format!("parsing url {}", config.url))?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(&config.content_type)?);
+        for (name, value) in &config.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+
+        match &config.auth {
+            Some(HttpAuth::Bearer { bearer_token }) => {
+                let token = load_string(bearer_token, "bearer_token").await?;
+                let mut value = HeaderValue::from_str(&format!("Bearer {token}"))?;
+                value.set_sensitive(true);
+                headers.insert(AUTHORIZATION, value);
+            }
+            Some(HttpAuth::Basic { username, password }) => {
+                let password = match password {
+                    Some(password) => load_string(password, "password").await?,
+                    None => String::new(),
+                };
+                let credentials =
+                    data_encoding::BASE64.encode(format!("{username}:{password}").as_bytes());
+                let mut value = HeaderValue::from_str(&format!("Basic {credentials}"))?;
+                value.set_sensitive(true);
+                headers.insert(AUTHORIZATION, value);
+            }
+            None => {}
+        }
+
+        let signing_key = match &config.signing {
+            Some(signing) => Some((
+                HeaderName::from_bytes(signing.header.as_bytes())?,
+                signing
+                    .key
+                    .get()
+                    .await
+                    .context("loading http delivery signing key")?,
+            )),
+            None => None,
+        };
+
+        let client = Client::builder()
+            .timeout(config.timeout)
+            .default_headers(headers)
+            .build()?;
+
+        Ok(HttpSession {
+            client,
+            url,
+            signing_key,
+        })
+    }
+
+    async fn build_payload(&self, msgs: &[Message]) -> anyhow::Result<String> {
+        let mut messages = vec![];
+        for msg in msgs {
+            messages.push(message_context(msg, self.proto_config.data_encoding).await?);
+        }
+
+        // With a batch size of 1 we produce a single object,
+        // rather than an array, for the convenience of the receiver
+        let single = self.proto_config.batch_size == 1 && messages.len() == 1;
+
+        match &self.proto_config.payload_template {
+            Some(template) => {
+                let mut context = json!({
+                    "messages": messages,
+                });
+                if single {
+                    context["message"] = context["messages"][0].clone();
+                }
+                TemplateEngine::with_dialect(self.proto_config.template_dialect).render(
+                    "payload_template",
+                    template,
+                    context,
+                )
+            }
+            None if single => Ok(serde_json::to_string(&messages[0])?),
+            None => Ok(serde_json::to_string(&messages)?),
+        }
+    }
+}
+
+#[async_trait]
+impl QueueDispatcher for HttpQueueDispatcher {
+    async fn close_connection(&mut self, _dispatcher: &mut Dispatcher) -> anyhow::Result<bool> {
+        Ok(self.session.take().is_some())
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.proto_config.batch_size
+    }
+    fn min_batch_size(&self) -> usize {
+        self.proto_config.min_batch_size
+    }
+    fn max_batch_latency(&self) -> Duration {
+        self.proto_config.max_batch_latency
+    }
+
+    async fn attempt_connection(
+        &mut self,
+        dispatcher: &mut Dispatcher,
+    ) -> anyhow::Result<AttemptConnectionDisposition> {
+        if self.session.is_some() {
+            return Ok(AttemptConnectionDisposition::ReusedExisting);
+        }
+        self.session.replace(self.build_session().await?);
+        dispatcher.delivered_this_connection = 0;
+        Ok(AttemptConnectionDisposition::ConnectedNew)
+    }
+
+    async fn have_more_connection_candidates(&mut self, _dispatcher: &mut Dispatcher) -> bool {
+        false
+    }
+
+    async fn deliver_message(
+        &mut self,
+        msgs: Vec<Message>,
+        dispatcher: &mut Dispatcher,
+    ) -> anyhow::Result<()> {
+        let Some(session) = &self.session else {
+            anyhow::bail!("session is not set in HttpQueueDispatcher::deliver_message!?");
+        };
+
+        let payload = self.build_payload(&msgs).await?;
+
+        let mut request = session.client.post(session.url.clone());
+        if let Some((header, key)) = &session.signing_key {
+            request = request.header(header, sign_payload(key, payload.as_bytes()));
+        }
+
+        let result = request.body(payload).send().await;
+        let result = match result {
+            Ok(result) => result,
+            Err(err) => {
+                // Something is wrong with the connection; make a
+                // fresh start for the next attempt
+                self.session.take();
+                return Err(err).with_context(|| format!("POST to {}", self.proto_config.url));
+            }
+        };
+
+        let status = result.status();
+        let body = result.text().await.unwrap_or_default();
+        let response = Response {
+            code: status.as_u16(),
+            enhanced_code: None,
+            content: format!(
+                "{} {}",
+                status.canonical_reason().unwrap_or(""),
+                &body[..body.ceil_char_boundary(MAX_RESPONSE_CONTENT)]
+            )
+            .trim()
+            .to_string(),
+            command: None,
+        };
+
+        let disposition = self.proto_config.classify_status(status.as_u16());
+        if disposition == HttpStatusDisposition::Success {
+            tracing::debug!("Delivered OK! {response:?}");
+        } else {
+            tracing::debug!(
+                "failed to send message to {}: {response:?}",
+                dispatcher.name,
+            );
+        }
+
+        for msg in dispatcher.msgs.drain(..) {
+            log_disposition(LogDisposition {
+                kind: match disposition {
+                    HttpStatusDisposition::Success => RecordType::Delivery,
+                    HttpStatusDisposition::Transient => RecordType::TransientFailure,
+                    HttpStatusDisposition::Permanent => RecordType::Bounce,
+                },
+                msg: msg.clone(),
+                site: &dispatcher.name,
+                peer_address: Some(&self.peer_address),
+                response: response.clone(),
+                egress_pool: Some(&dispatcher.egress_pool),
+                egress_source: Some(&dispatcher.egress_source.name),
+                received_report: None,
+                delivery_protocol: Some("Http"),
+                tls_info: None,
+                source_address: None,
+                provider: dispatcher.path_config.borrow().provider_name.as_deref(),
+                session_id: Some(dispatcher.session_id),
+                // The whole message, with all of its recipients, is
+                // delivered in a single request, so the disposition
+                // applies to all of them
+                recipient_list: None,
+            })
+            .await;
+
+            match disposition {
+                HttpStatusDisposition::Success => {
+                    SpoolManager::remove_from_spool(*msg.id()).await?;
+                    dispatcher.metrics.inc_delivered();
+                }
+                HttpStatusDisposition::Transient => {
+                    spawn(
+                        "requeue message".to_string(),
+                        QueueManager::requeue_message(
+                            msg,
+                            IncrementAttempts::Yes,
+                            None,
+                            response.clone(),
+                            InsertReason::LoggedTransientFailure.into(),
+                        ),
+                    )?;
+                    dispatcher.metrics.inc_transfail();
+                }
+                HttpStatusDisposition::Permanent => {
+                    dispatcher.metrics.inc_fail();
+                    SpoolManager::remove_from_spool(*msg.id()).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_proto(rules: serde_json::Value) -> HttpDeliveryProtocol {
+        serde_json::from_value(json!({
+            "url": "https://example.com/hook",
+            "status_rules": rules,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn status_rules() {
+        let proto = make_proto(json!([]));
+        assert_eq!(proto.classify_status(200), HttpStatusDisposition::Success);
+        assert_eq!(proto.classify_status(204), HttpStatusDisposition::Success);
+        assert_eq!(proto.classify_status(429), HttpStatusDisposition::Transient);
+        assert_eq!(proto.classify_status(503), HttpStatusDisposition::Transient);
+        assert_eq!(proto.classify_status(400), HttpStatusDisposition::Permanent);
+        assert_eq!(proto.classify_status(301), HttpStatusDisposition::Permanent);
+
+        let proto = make_proto(json!([
+            {"status": ["409"], "disposition": "Success"},
+            {"status": ["501", "4xx"], "disposition": "Transient"},
+            {"status": ["5xx"], "disposition": "Permanent"},
+        ]));
+        assert_eq!(proto.classify_status(409), HttpStatusDisposition::Success);
+        assert_eq!(proto.classify_status(404), HttpStatusDisposition::Transient);
+        assert_eq!(proto.classify_status(501), HttpStatusDisposition::Transient);
+        assert_eq!(proto.classify_status(502), HttpStatusDisposition::Permanent);
+        assert_eq!(proto.classify_status(200), HttpStatusDisposition::Success);
+
+        let err = serde_json::from_value::<HttpDeliveryProtocol>(json!({
+            "url": "https://example.com/hook",
+            "status_rules": [{"status": ["4xx", "4.7.1"], "disposition": "Permanent"}],
+        }))
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("status pattern '4.7.1' is an enhanced status code pattern"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn data_encoding() {
+        let data = b"Subject: hi\r\n\r\n\xff\xfe\x00binary";
+        assert_eq!(
+            DataEncoding::Base64.encode(data),
+            "U3ViamVjdDogaGkNCg0K//4AYmluYXJ5"
+        );
+        assert_eq!(
+            DataEncoding::Utf8.encode(data),
+            "Subject: hi\r\n\r\n\u{fffd}\u{fffd}\0binary"
+        );
+    }
+
+    #[test]
+    fn auth_config() {
+        let proto: HttpDeliveryProtocol = serde_json::from_value(json!({
+            "url": "https://example.com/hook",
+            "auth": {"bearer_token": {"key_data": "secret"}},
+        }))
+        .unwrap();
+        assert!(matches!(proto.auth, Some(HttpAuth::Bearer { .. })));
+
+        let proto: HttpDeliveryProtocol = serde_json::from_value(json!({
+            "url": "https://example.com/hook",
+            "auth": {"username": "user", "password": "/path/to/password"},
+        }))
+        .unwrap();
+        assert_eq!(
+            proto.auth,
+            Some(HttpAuth::Basic {
+                username: "user".to_string(),
+                password: Some(KeySource::File("/path/to/password".to_string())),
+            })
+        );
+    }
+
+    #[test]
+    fn signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign_payload(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
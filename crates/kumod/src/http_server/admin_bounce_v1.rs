@@ -1,3 +1,4 @@
+use crate::admin_db::{forget_entry, persist_entry, AdminEntryKind};
 use crate::http_server::queue_name_multi_index::{
     CachedEntry, Criteria, GetCriteria, QueueNameMultiIndexMap,
 };
@@ -80,11 +81,26 @@ impl AdminBounceEntry {
     }
 
     pub fn remove_by_id(id: &Uuid) -> bool {
-        let mut entries = ENTRIES.lock();
-        entries.remove_by_id(id).is_some()
+        let removed = ENTRIES.lock().remove_by_id(id).is_some();
+        forget_entry(AdminEntryKind::Bounce, id);
+        removed
     }
 
     pub fn add(entry: Self) {
+        persist_entry(
+            AdminEntryKind::Bounce,
+            &entry.id,
+            &entry.criteria,
+            &entry.reason,
+            entry.suppress_logging,
+            entry.expires,
+        );
+        Self::restore(entry);
+    }
+
+    /// Activate an entry without persisting it; used when
+    /// loading the entries from the admin db at startup
+    pub fn restore(entry: Self) {
         let mut entries = ENTRIES.lock();
         // Age out expired entries, and replace any entries with the
         // same criteria; this allows updating the reason with a newer
@@ -169,7 +185,7 @@ impl AdminBounceEntry {
                 },
                 egress_source: None,
                 egress_pool: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: None,
                 provider: None,
                 tls_info: None,
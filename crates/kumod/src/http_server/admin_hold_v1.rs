@@ -0,0 +1,373 @@
+use crate::admin_db::{forget_entry, persist_entry, AdminEntryKind};
+use crate::http_server::queue_name_multi_index::{Criteria, GetCriteria, QueueNameMultiIndexMap};
+use crate::queue::{IncrementAttempts, InsertReason, QueueManager};
+use axum::extract::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use config::get_or_create_sub_module;
+use kumo_api_types::{HoldV1CancelRequest, HoldV1ListEntry, HoldV1Request, HoldV1Response};
+use kumo_server_common::http_server::AppError;
+use message::message::QueueNameComponents;
+use message::Message;
+use mlua::{Lua, LuaSerdeExt, Value};
+use parking_lot::FairMutex as Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use throttle::ThrottleSpec;
+use uuid::Uuid;
+
+static ENTRIES: LazyLock<Mutex<QueueNameMultiIndexMap<AdminHoldEntry>>> =
+    LazyLock::new(|| Mutex::new(QueueNameMultiIndexMap::new()));
+
+/// When no release_rate is specified, held messages are spread
+/// over this period following the release time
+const DEFAULT_RELEASE_JITTER: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub struct AdminHoldEntry {
+    pub id: Uuid,
+    pub criteria: Criteria,
+    pub reason: String,
+    pub release_at: DateTime<Utc>,
+    pub release_rate: Option<ThrottleSpec>,
+    pub expires: Instant,
+    /// The number of messages that have been assigned a release time
+    num_held: Arc<AtomicUsize>,
+}
+
+/// The form in which a hold is recorded in the admin db
+#[derive(Serialize, Deserialize)]
+pub struct PersistedHold {
+    pub criteria: Criteria,
+    pub release_rate: Option<ThrottleSpec>,
+}
+
+impl GetCriteria for AdminHoldEntry {
+    fn get_id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn get_criteria(&self) -> &Criteria {
+        &self.criteria
+    }
+
+    fn get_expires(&self) -> Instant {
+        self.expires
+    }
+}
+
+impl AdminHoldEntry {
+    pub fn new(
+        id: Uuid,
+        criteria: Criteria,
+        reason: String,
+        release_at: DateTime<Utc>,
+        release_rate: Option<ThrottleSpec>,
+    ) -> Self {
+        let now = Instant::now();
+        let remaining = (release_at - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+        let expires = now
+            .checked_add(remaining)
+            // Effectively forever
+            .unwrap_or_else(|| now + Duration::from_secs(100 * 365 * 86400));
+        Self {
+            id,
+            criteria,
+            reason,
+            release_at,
+            release_rate,
+            expires,
+            num_held: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn from_request(request: HoldV1Request) -> Self {
+        let release_at = request.release_at();
+        Self::new(
+            Uuid::new_v4(),
+            Criteria {
+                campaign: request.campaign,
+                tenant: request.tenant,
+                domain: request.domain,
+                routing_domain: None,
+                queue_names: request.queue_names.into_iter().collect(),
+            },
+            request.reason,
+            release_at,
+            request.release_rate,
+        )
+    }
+
+    /// Allocate the time at which the next held message should be
+    /// released. Successive messages are assigned successive slots
+    /// according to the release_rate, so that the held messages
+    /// ramp up smoothly rather than all becoming due at once.
+    pub fn next_release_time(&self) -> DateTime<Utc> {
+        let offset = match &self.release_rate {
+            Some(rate) => {
+                let slot = self.num_held.fetch_add(1, Ordering::Relaxed);
+                rate.interval()
+                    .saturating_mul(slot.try_into().unwrap_or(u32::MAX))
+            }
+            None => {
+                self.num_held.fetch_add(1, Ordering::Relaxed);
+                DEFAULT_RELEASE_JITTER.mul_f64(rand::random::<f64>())
+            }
+        };
+        chrono::Duration::from_std(offset)
+            .ok()
+            .and_then(|offset| self.release_at.checked_add_signed(offset))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// Reschedule msg, which matched this hold, so that it
+    /// becomes due at its allotted release time
+    pub async fn hold_message(&self, msg: Message) -> anyhow::Result<()> {
+        let release_time = self.next_release_time();
+        let delay = (release_time - Utc::now()).max(chrono::Duration::zero());
+        tracing::trace!("{} is held until {release_time}", msg.id());
+
+        Box::pin(QueueManager::requeue_message(
+            msg,
+            IncrementAttempts::No,
+            Some(delay),
+            rfc5321::Response {
+                code: 451,
+                enhanced_code: Some(rfc5321::EnhancedStatusCode {
+                    class: 4,
+                    subject: 4,
+                    detail: 4,
+                }),
+                content: format!(
+                    "KumoMTA internal: scheduled queue is held until {release_time}: {}",
+                    self.reason
+                ),
+                command: None,
+            },
+            InsertReason::AdminHold.into(),
+        ))
+        .await
+    }
+
+    pub fn get_all() -> Vec<Self> {
+        let mut entries = ENTRIES.lock();
+        entries.prune_expired();
+        entries.get_all()
+    }
+
+    pub fn get_all_v1() -> Vec<HoldV1ListEntry> {
+        Self::get_all()
+            .into_iter()
+            .map(|entry| HoldV1ListEntry {
+                id: entry.id,
+                campaign: entry.criteria.campaign,
+                tenant: entry.criteria.tenant,
+                domain: entry.criteria.domain,
+                queue_names: entry.criteria.queue_names.into_iter().collect(),
+                reason: entry.reason,
+                release_at: entry.release_at,
+                release_rate: entry.release_rate,
+                num_held: entry.num_held.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    pub fn remove_by_id(id: &Uuid) -> bool {
+        let removed = ENTRIES.lock().remove_by_id(id).is_some();
+        forget_entry(AdminEntryKind::Hold, id);
+        removed
+    }
+
+    /// Activate an entry and persist it to the admin db.
+    /// Any existing entry with the same criteria is replaced.
+    pub fn add(entry: Self) {
+        // Hold the lock while queueing the admin db changes, so that
+        // they are applied in the same order as the changes to the map.
+        // persist_entry and forget_entry only queue the change for
+        // the admin db writer thread, so they don't block on sqlite.
+        let mut entries = ENTRIES.lock();
+        persist_entry(
+            AdminEntryKind::Hold,
+            &entry.id,
+            &PersistedHold {
+                criteria: entry.criteria.clone(),
+                release_rate: entry.release_rate,
+            },
+            &entry.reason,
+            false,
+            entry.expires,
+        );
+        Self::insert(&mut entries, entry);
+    }
+
+    /// Activate an entry without persisting it; used when
+    /// loading the entries from the admin db at startup
+    pub fn restore(entry: Self) {
+        let mut entries = ENTRIES.lock();
+        Self::insert(&mut entries, entry);
+    }
+
+    fn insert(entries: &mut QueueNameMultiIndexMap<Self>, entry: Self) {
+        entries.maybe_prune();
+        // The release_rate is part of the persisted criteria, so the
+        // admin db won't necessarily replace the row of the entry that
+        // the map just evicted; forget it explicitly so that the db
+        // holds the same set of entries as the map
+        if let Some(replaced) = entries.insert(entry) {
+            forget_entry(AdminEntryKind::Hold, &replaced.id);
+        }
+    }
+
+    pub fn get_for_queue_name(queue_name: &str) -> Option<Self> {
+        let components = QueueNameComponents::parse(queue_name);
+        let mut entries = ENTRIES.lock();
+        entries.maybe_prune();
+        entries.get_matching(
+            components.campaign,
+            components.tenant,
+            Some(components.domain),
+            None,
+            Some(queue_name),
+        )
+    }
+}
+
+/// Place a time-windowed hold on matching scheduled queues.
+/// At the release time, the held messages are released gradually
+/// according to the release rate.
+#[utoipa::path(
+    post,
+    tags=["hold", "kcli:hold"],
+    path="/api/admin/hold/v1",
+    request_body=HoldV1Request,
+    responses(
+        (status = 200, description = "Held", body=HoldV1Response),
+    ),
+)]
+pub async fn hold(
+    // Note: Json<> must be last in the param list
+    Json(request): Json<HoldV1Request>,
+) -> Result<Json<HoldV1Response>, AppError> {
+    let entry = AdminHoldEntry::from_request(request);
+    let id = entry.id;
+    AdminHoldEntry::add(entry);
+    Ok(Json(HoldV1Response { id }))
+}
+
+/// List the active scheduled-queue holds
+#[utoipa::path(
+    get,
+    tags=["hold", "kcli:hold-list"],
+    path="/api/admin/hold/v1",
+    responses(
+        (status = 200, description = "Held", body=HoldV1ListEntry),
+    ),
+)]
+pub async fn list() -> Result<Json<Vec<HoldV1ListEntry>>, AppError> {
+    Ok(Json(AdminHoldEntry::get_all_v1()))
+}
+
+/// Remove a scheduled-queue hold.
+/// Messages that have already been assigned a release time
+/// remain scheduled for that time.
+#[utoipa::path(
+    delete,
+    tags=["hold", "kcli:hold-cancel"],
+    path="/api/admin/hold/v1",
+    request_body=HoldV1CancelRequest,
+    responses(
+        (status = 200, description = "Removed the hold"),
+        (status = 404, description = "Hold either expired or was never valid"),
+    ),
+)]
+pub async fn delete(Json(request): Json<HoldV1CancelRequest>) -> Response {
+    let removed = AdminHoldEntry::remove_by_id(&request.id);
+    if removed {
+        (StatusCode::OK, format!("removed {}", request.id))
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            format!("hold entry {} not found", request.id),
+        )
+    }
+    .into_response()
+}
+
+pub fn register(lua: &Lua) -> anyhow::Result<()> {
+    let module = get_or_create_sub_module(lua, "api.admin.hold")?;
+
+    module.set(
+        "list",
+        lua.create_function(move |lua, ()| {
+            let result = AdminHoldEntry::get_all_v1();
+            lua.to_value(&result)
+        })?,
+    )?;
+
+    module.set(
+        "hold",
+        lua.create_function(move |lua, request: Value| {
+            let request: HoldV1Request = lua.from_value(request)?;
+            let entry = AdminHoldEntry::from_request(request);
+            let id = entry.id;
+            AdminHoldEntry::add(entry);
+            lua.to_value(&id)
+        })?,
+    )?;
+
+    module.set(
+        "delete",
+        lua.create_function(move |lua, id: Value| {
+            let id: Uuid = lua.from_value(id)?;
+            let removed = AdminHoldEntry::remove_by_id(&id);
+            Ok(removed)
+        })?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn release_ramp() {
+        let release_at = Utc::now() + chrono::Duration::hours(1);
+        let entry = AdminHoldEntry::new(
+            Uuid::new_v4(),
+            Criteria {
+                campaign: None,
+                tenant: None,
+                domain: Some("example.com".to_string()),
+                routing_domain: None,
+                queue_names: Default::default(),
+            },
+            "maintenance".to_string(),
+            release_at,
+            Some(ThrottleSpec::try_from("60/min".to_string()).unwrap()),
+        );
+
+        let times: Vec<_> = (0..3).map(|_| entry.next_release_time()).collect();
+        assert_eq!(
+            times,
+            vec![
+                release_at,
+                release_at + chrono::Duration::seconds(1),
+                release_at + chrono::Duration::seconds(2)
+            ]
+        );
+        assert_eq!(entry.clone().num_held.load(Ordering::Relaxed), 3);
+
+        let unrated = AdminHoldEntry {
+            release_rate: None,
+            ..entry
+        };
+        let time = unrated.next_release_time();
+        assert!(time >= release_at);
+        assert!(time <= release_at + chrono::Duration::seconds(60));
+    }
+}
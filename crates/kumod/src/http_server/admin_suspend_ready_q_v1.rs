@@ -1,3 +1,4 @@
+use crate::admin_db::{forget_entry, persist_entry, AdminEntryKind};
 use axum::extract::Json;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
@@ -147,11 +148,26 @@ impl AdminSuspendReadyQEntry {
     }
 
     pub fn remove_by_id(id: &Uuid) -> bool {
-        let mut entries = ENTRIES.lock();
-        entries.remove_by_id(id)
+        let removed = ENTRIES.lock().remove_by_id(id);
+        forget_entry(AdminEntryKind::SuspendReadyQ, id);
+        removed
     }
 
     pub fn add(entry: Self) {
+        persist_entry(
+            AdminEntryKind::SuspendReadyQ,
+            &entry.id,
+            &entry.name,
+            &entry.reason,
+            false,
+            entry.expires,
+        );
+        Self::restore(entry);
+    }
+
+    /// Activate an entry without persisting it; used when
+    /// loading the entries from the admin db at startup
+    pub fn restore(entry: Self) {
         let mut entries = ENTRIES.lock();
         entries.insert(entry);
     }
@@ -1,3 +1,4 @@
+use crate::admin_db::{forget_entry, persist_entry, AdminEntryKind};
 use crate::http_server::queue_name_multi_index::{Criteria, GetCriteria, QueueNameMultiIndexMap};
 use axum::extract::Json;
 use axum::http::StatusCode;
@@ -74,11 +75,26 @@ impl AdminSuspendEntry {
     }
 
     pub fn remove_by_id(id: &Uuid) -> bool {
-        let mut entries = ENTRIES.lock();
-        entries.remove_by_id(id).is_some()
+        let removed = ENTRIES.lock().remove_by_id(id).is_some();
+        forget_entry(AdminEntryKind::Suspend, id);
+        removed
     }
 
     pub fn add(entry: Self) {
+        persist_entry(
+            AdminEntryKind::Suspend,
+            &entry.id,
+            &entry.criteria,
+            &entry.reason,
+            false,
+            entry.expires,
+        );
+        Self::restore(entry);
+    }
+
+    /// Activate an entry without persisting it; used when
+    /// loading the entries from the admin db at startup
+    pub fn restore(entry: Self) {
         let mut entries = ENTRIES.lock();
         // Age out expired entries, and replace any entries with the
         // same criteria; this allows updating the reason with a newer
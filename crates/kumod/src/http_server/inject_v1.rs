@@ -1,5 +1,6 @@
 use crate::delivery_metrics::MetricsWrappedConnection;
 use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
+use crate::message_trace::{set_trace_context, TRACEPARENT_META};
 use crate::queue::{DeliveryProto, QueueConfig, QueueManager};
 use crate::ready_queue::{AttemptConnectionDisposition, Dispatcher, QueueDispatcher};
 use crate::smtp_server::{default_hostname, TraceHeaders};
@@ -8,6 +9,7 @@ use anyhow::Context;
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use axum::extract::{Json, State};
+use axum::http::HeaderMap;
 use axum_client_ip::ClientIp;
 use config::{any_err, get_or_create_sub_module, load_config, LuaConfig, SerdeWrappedValue};
 use kumo_chrono_helper::Utc;
@@ -15,6 +17,7 @@ use kumo_log_types::ResolvedAddress;
 use kumo_prometheus::AtomicCounter;
 use kumo_server_common::authn_authz::AuthInfo;
 use kumo_server_common::http_server::{AppError, AppState};
+use kumo_server_common::otel::{SpanKind, TraceSpan};
 use kumo_server_lifecycle::Activity;
 use kumo_server_runtime::{Runtime, RUNTIME};
 use kumo_template::{CompiledTemplates, TemplateDialect, TemplateEngine, TemplateList};
@@ -812,6 +815,7 @@ async fn process_recipient<'a>(
     auth: &AuthInfo,
     via_address: &Option<IpAddr>,
     hostname: &Option<String>,
+    traceparent: &Option<String>,
 ) -> anyhow::Result<()> {
     MSGS_RECVD.inc();
 
@@ -826,6 +830,7 @@ async fn process_recipient<'a>(
         hostname,
     )
     .await?;
+    set_trace_context(&message, traceparent.clone()).await?;
 
     // call callback to assign to queue
     config
@@ -860,7 +865,7 @@ async fn process_recipient<'a>(
             },
             egress_source: None,
             egress_pool: None,
-            relay_disposition: None,
+            received_report: None,
             delivery_protocol: None,
             tls_info: None,
             source_address: None,
@@ -881,6 +886,7 @@ async fn queue_deferred(
     sender: EnvelopeAddress,
     peer_address: IpAddr,
     mut request: InjectV1Request,
+    traceparent: Option<String>,
 ) -> anyhow::Result<InjectV1Response> {
     request.deferred_generation = false;
     // build into a Message
@@ -905,6 +911,7 @@ async fn queue_deferred(
         .set_meta("received_from", peer_address.to_string())
         .await?;
     message.set_meta("queue", GENERATOR_QUEUE_NAME).await?;
+    set_trace_context(&message, traceparent).await?;
     if !request.deferred_spool {
         message.save(None).await?;
     }
@@ -924,7 +931,7 @@ async fn queue_deferred(
         },
         egress_source: None,
         egress_pool: None,
-        relay_disposition: None,
+        received_report: None,
         delivery_protocol: None,
         tls_info: None,
         source_address: None,
@@ -959,14 +966,24 @@ async fn inject_v1_impl(
     mut request: InjectV1Request,
     via_address: Option<IpAddr>,
     hostname: Option<String>,
+    parent_traceparent: Option<String>,
 ) -> Result<Json<InjectV1Response>, AppError> {
+    let mut span = TraceSpan::start(
+        "http_inject",
+        SpanKind::Server,
+        parent_traceparent.as_deref(),
+    );
+    span.set_attribute("client.address", peer_address.to_string());
+    span.set_attribute("kumo.recipient_count", request.recipients.len() as i64);
+    let traceparent = span.traceparent();
+
     request.normalize().map_err(content_syntax_err)?;
 
     let compiled = request.compile().map_err(content_syntax_err)?;
 
     if request.deferred_generation {
         return Ok(Json(
-            queue_deferred(auth, sender, peer_address, request).await?,
+            queue_deferred(auth, sender, peer_address, request, traceparent).await?,
         ));
     }
 
@@ -986,6 +1003,7 @@ async fn inject_v1_impl(
             &auth,
             &via_address,
             &hostname,
+            &traceparent,
         )
         .await
         {
@@ -1001,6 +1019,10 @@ async fn inject_v1_impl(
     }
     config.put();
 
+    if fail_count > 0 {
+        span.set_error(format!("{fail_count} recipient(s) failed"));
+    }
+
     Ok(Json(InjectV1Response {
         success_count,
         fail_count,
@@ -1062,6 +1084,7 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
                 request,
                 None,
                 None,
+                None,
             )
             .await
             .map_err(|err| any_err(err.err))?;
@@ -1219,6 +1242,7 @@ pub async fn inject_v1(
     auth: AuthInfo,
     ClientIp(peer_address): ClientIp,
     State(app_state): State<AppState>,
+    headers: HeaderMap,
     // Note: Json<> must be last in the param list
     Json(request): Json<InjectV1Request>,
 ) -> Result<Json<InjectV1Response>, AppError> {
@@ -1253,10 +1277,23 @@ pub async fn inject_v1(
 
     let via_address = Some(app_state.local_addr().ip().clone());
     let hostname = Some(app_state.params().hostname.to_string());
+    // Continue the trace of the client, if it supplied one
+    let traceparent = headers
+        .get("traceparent")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
 
     pool.spawn(format!("http inject_v1 for {peer_address:?}"), async move {
-        let result =
-            inject_v1_impl(auth, sender, peer_address, request, via_address, hostname).await;
+        let result = inject_v1_impl(
+            auth,
+            sender,
+            peer_address,
+            request,
+            via_address,
+            hostname,
+            traceparent,
+        )
+        .await;
         drop(activity);
         result
     })?
@@ -1311,6 +1348,7 @@ impl HttpInjectionGeneratorDispatcher {
                 };
 
                 let sender = msg.sender().await?;
+                let traceparent = msg.get_meta_string(TRACEPARENT_META).await.ok().flatten();
 
                 let _ = inject_v1_impl(
                     auth_info,
@@ -1319,6 +1357,7 @@ impl HttpInjectionGeneratorDispatcher {
                     request,
                     via_address,
                     hostname,
+                    traceparent,
                 )
                 .await
                 .map_err(|err| err.err)?;
@@ -1395,7 +1434,7 @@ impl QueueDispatcher for HttpInjectionGeneratorDispatcher {
                 response,
                 egress_pool: None,
                 egress_source: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: Some("HttpInjectionGenerator"),
                 tls_info: None,
                 source_address: None,
@@ -3,6 +3,7 @@ use kumo_server_common::router_with_docs;
 use utoipa::OpenApi;
 
 pub mod admin_bounce_v1;
+pub mod admin_hold_v1;
 pub mod admin_inspect_message;
 pub mod admin_inspect_scheduled_queue;
 pub mod admin_ready_queue_states;
@@ -22,6 +23,9 @@ pub fn make_router() -> RouterAndDocs {
             admin_bounce_v1::bounce_v1,
             admin_bounce_v1::bounce_v1_delete,
             admin_bounce_v1::bounce_v1_list,
+            admin_hold_v1::delete,
+            admin_hold_v1::hold,
+            admin_hold_v1::list,
             admin_inspect_message::inspect_v1,
             admin_inspect_scheduled_queue::inspect_v1,
             admin_ready_queue_states::readyq_states,
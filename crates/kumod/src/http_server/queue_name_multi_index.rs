@@ -1,4 +1,5 @@
 use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
 use std::time::Instant;
@@ -68,7 +69,7 @@ enum KeyType {
 /// so we use a map with () as the value type.
 type UuidHashSet = HashMap<Uuid, ()>;
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Criteria {
     pub campaign: Option<String>,
     pub tenant: Option<String>,
@@ -187,18 +188,18 @@ impl<T: GetCriteria> QueueNameMultiIndexMap<T> {
         self.by_id.values().cloned().collect()
     }
 
-    fn remove_existing_entry_with_same_critiera(&mut self, entry: &T) {
+    fn remove_existing_entry_with_same_critiera(&mut self, entry: &T) -> Option<T> {
         let criteria = entry.get_criteria();
-        if let Some(existing_id) = self.by_criteria.get(criteria).cloned() {
-            self.remove_by_id(&existing_id);
-        }
+        let existing_id = self.by_criteria.get(criteria).cloned()?;
+        self.remove_by_id(&existing_id)
     }
 
-    /// Remove any entry with the same criteria, then insert `entry`
-    pub fn insert(&mut self, entry: T) {
+    /// Remove any entry with the same criteria, then insert `entry`.
+    /// Returns the entry that was replaced, if any.
+    pub fn insert(&mut self, entry: T) -> Option<T> {
         let id = entry.get_id();
 
-        self.remove_existing_entry_with_same_critiera(&entry);
+        let replaced = self.remove_existing_entry_with_same_critiera(&entry);
 
         let criteria = entry.get_criteria();
         match criteria.key() {
@@ -234,6 +235,7 @@ impl<T: GetCriteria> QueueNameMultiIndexMap<T> {
         self.by_criteria.insert(criteria.clone(), *id);
         self.by_id.insert(*id, entry);
         self.generation_count += 1;
+        replaced
     }
 
     /// Remove the entry with the specified id
@@ -556,12 +558,13 @@ mod test {
             "any"
         );
 
-        map.insert(Entry {
+        let replaced = map.insert(Entry {
             id: Uuid::new_v4(),
             criteria: Criteria::new_match_all(),
             expires: Instant::now() + Duration::from_secs(60),
             reason: "a different any".to_string(),
         });
+        assert_eq!(replaced.unwrap().reason, "any");
 
         assert_eq!(
             map.get_matching(None, None, None, None, None)
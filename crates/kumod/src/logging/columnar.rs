@@ -0,0 +1,502 @@
+//! Writes log segments in the Apache Parquet format.
+//! The schema is derived from JsonLogRecord, with a fixed set of
+//! columns for the record fields, plus optional `meta` and `headers`
+//! struct columns that have a field for each of the meta and header
+//! names that are listed in the LogFileParams.
+use crate::logging::value_text;
+use anyhow::Context;
+use arrow::array::{
+    ArrayRef, ListBuilder, StringArray, StringBuilder, StructArray, TimestampMicrosecondArray,
+    UInt16Array, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use kumo_log_types::JsonLogRecord;
+use kumo_server_common::log::mark_path_as_done;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// How many records to accumulate before encoding them
+/// into the current row group
+const BATCH_SIZE: usize = 1024;
+
+/// The arrow schema for a Parquet log, along with the names
+/// of the meta and header fields that it holds
+#[derive(Debug)]
+pub struct LogSchema {
+    schema: SchemaRef,
+    meta: Vec<String>,
+    headers: Vec<String>,
+}
+
+fn string_list() -> DataType {
+    DataType::List(Arc::new(Field::new("item", DataType::Utf8, true)))
+}
+
+fn timestamp() -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+}
+
+fn struct_fields(names: &[String]) -> Fields {
+    names
+        .iter()
+        .map(|name| Field::new(name, DataType::Utf8, true))
+        .collect()
+}
+
+impl LogSchema {
+    pub fn new(meta: &[String], headers: &[String]) -> anyhow::Result<Self> {
+        for name in meta.iter().chain(headers.iter()) {
+            anyhow::ensure!(
+                !name.ends_with('*'),
+                "the Parquet log format uses a fixed schema, \
+                 so it cannot capture meta or headers by wildcard `{name}`"
+            );
+        }
+
+        let mut fields = vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("id", DataType::Utf8, false),
+            Field::new("sender", DataType::Utf8, false),
+            Field::new("recipient", string_list(), false),
+            Field::new("queue", DataType::Utf8, false),
+            Field::new("site", DataType::Utf8, false),
+            Field::new("size", DataType::UInt64, false),
+            Field::new("response_code", DataType::UInt16, false),
+            Field::new("response_enhanced_code", DataType::Utf8, true),
+            Field::new("response_content", DataType::Utf8, false),
+            Field::new("response_command", DataType::Utf8, true),
+            Field::new("peer_address_name", DataType::Utf8, true),
+            Field::new("peer_address_addr", DataType::Utf8, true),
+            Field::new("timestamp", timestamp(), false),
+            Field::new("created", timestamp(), false),
+            Field::new("num_attempts", DataType::UInt16, false),
+            Field::new("bounce_classification", DataType::Utf8, false),
+            Field::new("egress_pool", DataType::Utf8, true),
+            Field::new("egress_source", DataType::Utf8, true),
+            Field::new("source_address", DataType::Utf8, true),
+            Field::new("delivery_protocol", DataType::Utf8, true),
+            Field::new("reception_protocol", DataType::Utf8, true),
+            Field::new("nodeid", DataType::Utf8, false),
+            Field::new("tls_cipher", DataType::Utf8, true),
+            Field::new("tls_protocol_version", DataType::Utf8, true),
+            Field::new("tls_peer_subject_name", string_list(), true),
+            Field::new("provider_name", DataType::Utf8, true),
+            Field::new("session_id", DataType::Utf8, true),
+            Field::new("trace_id", DataType::Utf8, true),
+            Field::new("span_id", DataType::Utf8, true),
+            Field::new("feedback_report", DataType::Utf8, true),
+            Field::new("dmarc_aggregate_report", DataType::Utf8, true),
+            Field::new("tls_report", DataType::Utf8, true),
+        ];
+        if !meta.is_empty() {
+            fields.push(Field::new(
+                "meta",
+                DataType::Struct(struct_fields(meta)),
+                false,
+            ));
+        }
+        if !headers.is_empty() {
+            fields.push(Field::new(
+                "headers",
+                DataType::Struct(struct_fields(headers)),
+                false,
+            ));
+        }
+
+        Ok(Self {
+            schema: Arc::new(Schema::new(fields)),
+            meta: meta.to_vec(),
+            headers: headers.to_vec(),
+        })
+    }
+
+    /// Convert a batch of records into an arrow RecordBatch
+    pub fn make_batch(&self, records: &[JsonLogRecord]) -> anyhow::Result<RecordBatch> {
+        fn strings<'a>(
+            records: &'a [JsonLogRecord],
+            f: impl Fn(&'a JsonLogRecord) -> Option<String>,
+        ) -> ArrayRef {
+            Arc::new(records.iter().map(f).collect::<StringArray>())
+        }
+
+        fn string_lists<'a>(
+            records: &'a [JsonLogRecord],
+            f: impl Fn(&'a JsonLogRecord) -> Option<&'a Vec<String>>,
+        ) -> ArrayRef {
+            let mut builder = ListBuilder::new(StringBuilder::new());
+            for record in records {
+                match f(record) {
+                    Some(items) => {
+                        for item in items {
+                            builder.values().append_value(item);
+                        }
+                        builder.append(true);
+                    }
+                    None => builder.append(false),
+                }
+            }
+            Arc::new(builder.finish())
+        }
+
+        fn timestamps(records: &[JsonLogRecord], f: impl Fn(&JsonLogRecord) -> i64) -> ArrayRef {
+            Arc::new(
+                TimestampMicrosecondArray::from_iter_values(records.iter().map(f))
+                    .with_timezone("UTC"),
+            )
+        }
+
+        fn captured(
+            records: &[JsonLogRecord],
+            names: &[String],
+            f: impl Fn(&JsonLogRecord, &str) -> Option<&Value>,
+        ) -> ArrayRef {
+            let columns: Vec<ArrayRef> = names
+                .iter()
+                .map(|name| strings(records, |r| f(r, name).map(value_text)))
+                .collect();
+            Arc::new(StructArray::new(struct_fields(names), columns, None))
+        }
+
+        let mut columns: Vec<ArrayRef> = vec![
+            strings(records, |r| serialized_text(&r.kind)),
+            strings(records, |r| Some(r.id.clone())),
+            strings(records, |r| Some(r.sender.clone())),
+            string_lists(records, |r| Some(&r.recipient)),
+            strings(records, |r| Some(r.queue.clone())),
+            strings(records, |r| Some(r.site.clone())),
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|r| r.size),
+            )),
+            Arc::new(UInt16Array::from_iter_values(
+                records.iter().map(|r| r.response.code),
+            )),
+            strings(records, |r| {
+                r.response
+                    .enhanced_code
+                    .as_ref()
+                    .map(|c| format!("{}.{}.{}", c.class, c.subject, c.detail))
+            }),
+            strings(records, |r| Some(r.response.content.clone())),
+            strings(records, |r| {
+                r.response
+                    .command
+                    .as_ref()
+                    .map(|c| c.trim_end().to_string())
+            }),
+            strings(records, |r| r.peer_address.as_ref().map(|a| a.name.clone())),
+            strings(records, |r| {
+                r.peer_address
+                    .as_ref()
+                    .and_then(|a| serialized_text(&a.addr))
+            }),
+            timestamps(records, |r| r.timestamp.timestamp_micros()),
+            timestamps(records, |r| r.created.timestamp_micros()),
+            Arc::new(UInt16Array::from_iter_values(
+                records.iter().map(|r| r.num_attempts),
+            )),
+            strings(records, |r| serialized_text(&r.bounce_classification)),
+            strings(records, |r| r.egress_pool.clone()),
+            strings(records, |r| r.egress_source.clone()),
+            strings(records, |r| {
+                r.source_address
+                    .as_ref()
+                    .and_then(|a| serialized_text(&a.address))
+            }),
+            strings(records, |r| r.delivery_protocol.clone()),
+            strings(records, |r| r.reception_protocol.clone()),
+            strings(records, |r| Some(r.nodeid.to_string())),
+            strings(records, |r| r.tls_cipher.clone()),
+            strings(records, |r| r.tls_protocol_version.clone()),
+            string_lists(records, |r| r.tls_peer_subject_name.as_ref()),
+            strings(records, |r| r.provider_name.clone()),
+            strings(records, |r| r.session_id.map(|id| id.to_string())),
+            strings(records, |r| r.trace_id.clone()),
+            strings(records, |r| r.span_id.clone()),
+            strings(records, |r| r.feedback_report.as_ref().and_then(json_text)),
+            strings(records, |r| {
+                r.dmarc_aggregate_report.as_ref().and_then(json_text)
+            }),
+            strings(records, |r| r.tls_report.as_ref().and_then(json_text)),
+        ];
+        if !self.meta.is_empty() {
+            columns.push(captured(records, &self.meta, |r, name| r.meta.get(name)));
+        }
+        if !self.headers.is_empty() {
+            // Captured headers are keyed by the name used in the
+            // message, which may differ in case from the configuration
+            columns.push(captured(records, &self.headers, |r, name| {
+                r.headers
+                    .iter()
+                    .find(|(candidate, _)| candidate.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value)
+            }));
+        }
+
+        RecordBatch::try_new(self.schema.clone(), columns).context("building record batch")
+    }
+}
+
+fn json_text<T: Serialize>(value: T) -> Option<String> {
+    serde_json::to_string(&value).ok()
+}
+
+/// Produce the same text that would be used in the json
+/// representation of a record, minus the quotes for strings
+fn serialized_text<T: Serialize>(value: T) -> Option<String> {
+    serde_json::to_value(value).ok().map(|v| value_text(&v))
+}
+
+/// Represents an opened Parquet log segment.
+/// Records are buffered and encoded in batches; dropping the
+/// segment encodes any remaining records, writes the Parquet
+/// footer and marks the file as done.
+pub struct ParquetSegment {
+    writer: Option<ArrowWriter<File>>,
+    schema: Arc<LogSchema>,
+    pending: Vec<JsonLogRecord>,
+    pub name: PathBuf,
+    pub expires: Option<Instant>,
+}
+
+impl ParquetSegment {
+    pub fn new(
+        file: File,
+        name: PathBuf,
+        schema: Arc<LogSchema>,
+        compression_level: i32,
+        expires: Option<Instant>,
+    ) -> anyhow::Result<Self> {
+        let level = if compression_level == 0 {
+            zstd::DEFAULT_COMPRESSION_LEVEL
+        } else {
+            compression_level
+        };
+        let props = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::try_new(level)?))
+            .build();
+        let writer = ArrowWriter::try_new(file, schema.schema.clone(), Some(props))
+            .with_context(|| format!("creating parquet writer for {}", name.display()))?;
+
+        Ok(Self {
+            writer: Some(writer),
+            schema,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            name,
+            expires,
+        })
+    }
+
+    pub fn write_record(&mut self, record: JsonLogRecord) -> anyhow::Result<()> {
+        self.pending.push(record);
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// The number of bytes that have been written to the file,
+    /// plus the estimated encoded size of the buffered data
+    pub fn written(&self) -> u64 {
+        self.writer
+            .as_ref()
+            .map(|w| (w.bytes_written() + w.in_progress_size()) as u64)
+            .unwrap_or(0)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let batch = self.schema.make_batch(&self.pending)?;
+        let Some(writer) = self.writer.as_mut() else {
+            anyhow::bail!("parquet writer for {} is closed", self.name.display());
+        };
+        writer
+            .write(&batch)
+            .with_context(|| format!("writing records to {}", self.name.display()))?;
+        // Only discard the records once they have been written,
+        // so that a failed write can be retried by the next flush
+        self.pending.clear();
+        Ok(())
+    }
+
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer
+                .close()
+                .with_context(|| format!("closing {}", self.name.display()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ParquetSegment {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            tracing::error!("{err:#}");
+        }
+        mark_path_as_done(&self.name).ok();
+        tracing::debug!("Flushed {:?}", self.name);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use arrow::array::Array;
+    use kumo_log_types::RecordType;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn make_record(id: &str, tenant: Option<&str>) -> JsonLogRecord {
+        JsonLogRecord {
+            kind: RecordType::Delivery,
+            id: id.to_string(),
+            sender: "sender@example.com".to_string(),
+            recipient: vec!["recip@example.com".to_string()],
+            queue: "example.com".to_string(),
+            site: "mx.example.com".to_string(),
+            size: 1024,
+            response: rfc5321::Response {
+                code: 250,
+                enhanced_code: None,
+                content: "OK".to_string(),
+                command: None,
+            },
+            peer_address: None,
+            timestamp: chrono::Utc::now(),
+            created: chrono::Utc::now(),
+            num_attempts: 0,
+            bounce_classification: Default::default(),
+            egress_pool: Some("pool".to_string()),
+            egress_source: None,
+            source_address: None,
+            feedback_report: None,
+            dmarc_aggregate_report: None,
+            tls_report: None,
+            meta: tenant
+                .map(|t| ("tenant".to_string(), Value::String(t.to_string())))
+                .into_iter()
+                .collect(),
+            headers: Default::default(),
+            delivery_protocol: Some("ESMTP".to_string()),
+            reception_protocol: None,
+            nodeid: Default::default(),
+            tls_cipher: None,
+            tls_protocol_version: None,
+            tls_peer_subject_name: None,
+            provider_name: None,
+            session_id: None,
+            trace_id: None,
+            span_id: None,
+        }
+    }
+
+    #[test]
+    fn rejects_wildcards() {
+        assert!(LogSchema::new(&["tenant*".to_string()], &[]).is_err());
+        assert!(LogSchema::new(&[], &["X-*".to_string()]).is_err());
+    }
+
+    #[test]
+    fn batch() {
+        let schema = LogSchema::new(&["tenant".to_string()], &["Subject".to_string()]).unwrap();
+        let mut one = make_record("one", Some("a"));
+        one.headers
+            .insert("subject".to_string(), Value::String("hello".to_string()));
+        let batch = schema.make_batch(&[one, make_record("two", None)]).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+
+        let meta = batch
+            .column_by_name("meta")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .unwrap()
+            .column_by_name("tenant")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .clone();
+        assert_eq!(meta.iter().collect::<Vec<_>>(), vec![Some("a"), None]);
+
+        let subject = batch
+            .column_by_name("headers")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StructArray>()
+            .unwrap()
+            .column_by_name("Subject")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .clone();
+        assert_eq!(
+            subject.iter().collect::<Vec<_>>(),
+            vec![Some("hello"), None]
+        );
+
+        let kind = batch
+            .column_by_name("type")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .clone();
+        assert_eq!(kind.value(0), "Delivery");
+    }
+
+    #[test]
+    fn segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = dir.path().join("segment.parquet");
+        let schema = Arc::new(LogSchema::new(&[], &[]).unwrap());
+
+        let mut segment =
+            ParquetSegment::new(File::create(&name).unwrap(), name.clone(), schema, 0, None)
+                .unwrap();
+        for i in 0..BATCH_SIZE + 10 {
+            segment
+                .write_record(make_record(&i.to_string(), None))
+                .unwrap();
+        }
+        assert!(segment.written() > 0);
+        drop(segment);
+
+        let reader = SerializedFileReader::new(File::open(&name).unwrap()).unwrap();
+        assert_eq!(
+            reader.metadata().file_metadata().num_rows(),
+            (BATCH_SIZE + 10) as i64
+        );
+        assert!(name.metadata().unwrap().permissions().readonly());
+    }
+
+    #[test]
+    fn failed_flush_retains_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = dir.path().join("segment.parquet");
+        let schema = Arc::new(LogSchema::new(&[], &[]).unwrap());
+
+        let mut segment =
+            ParquetSegment::new(File::create(&name).unwrap(), name.clone(), schema, 0, None)
+                .unwrap();
+        segment.write_record(make_record("one", None)).unwrap();
+        let writer = segment.writer.take();
+        assert!(segment.flush().is_err());
+        assert_eq!(segment.pending.len(), 1);
+
+        segment.writer = writer;
+        segment.flush().unwrap();
+        assert!(segment.pending.is_empty());
+    }
+}
@@ -1,9 +1,12 @@
 use crate::logging::Logger;
-use crate::smtp_server::RelayDisposition;
+use crate::message_trace::get_trace_ids;
 use bounce_classify::BounceClass;
 use chrono::Utc;
 use config::{load_config, CallbackSignature};
-use kumo_log_types::rfc3464::ReportAction;
+use kumo_log_types::rfc3464::{Report as OobReport, ReportAction};
+use kumo_log_types::rfc5965::ARFReport;
+use kumo_log_types::rfc7489::AggregateReport as DmarcAggregateReport;
+use kumo_log_types::rfc8460::Report as TlsReport;
 use kumo_log_types::MaybeProxiedSourceAddress;
 pub use kumo_log_types::*;
 use message::Message;
@@ -11,6 +14,18 @@ use rfc5321::{EnhancedStatusCode, Response, TlsInformation};
 use std::net::Ipv4Addr;
 use uuid::Uuid;
 
+/// A report that was found in a received message, and which the
+/// listener domain has configured to be logged.
+/// The report is parsed once when the message is received, and
+/// the parsed form is passed along to `log_disposition`.
+#[derive(Debug, Clone)]
+pub enum ReceivedReport {
+    Feedback(Box<ARFReport>),
+    Oob(Box<OobReport>),
+    DmarcAggregate(Box<DmarcAggregateReport>),
+    Tls(Box<TlsReport>),
+}
+
 pub struct LogDisposition<'a> {
     pub kind: RecordType,
     pub msg: Message,
@@ -19,7 +34,7 @@ pub struct LogDisposition<'a> {
     pub response: Response,
     pub egress_pool: Option<&'a str>,
     pub egress_source: Option<&'a str>,
-    pub relay_disposition: Option<RelayDisposition>,
+    pub received_report: Option<ReceivedReport>,
     pub delivery_protocol: Option<&'a str>,
     pub tls_info: Option<&'a TlsInformation>,
     pub source_address: Option<MaybeProxiedSourceAddress>,
@@ -40,7 +55,7 @@ pub async fn log_disposition(args: LogDisposition<'_>) {
         response,
         egress_pool,
         egress_source,
-        relay_disposition,
+        received_report,
         delivery_protocol,
         tls_info,
         source_address,
@@ -71,16 +86,25 @@ pub async fn log_disposition(args: LogDisposition<'_>) {
         .await
         .unwrap_or(None);
 
+    let mut dmarc_rows = vec![];
+    let mut tls_policies = vec![];
+    let mut oob_report = None;
     if kind == RecordType::Reception {
-        if relay_disposition
-            .as_ref()
-            .map(|disp| disp.log_arf.should_log())
-            .unwrap_or(false)
-        {
-            if let Ok(Some(report)) = msg.parse_rfc5965().await {
-                feedback_report.replace(Box::new(report));
+        match received_report {
+            Some(ReceivedReport::Feedback(report)) => {
+                feedback_report.replace(report);
                 kind = RecordType::Feedback;
             }
+            Some(ReceivedReport::Oob(report)) => {
+                oob_report.replace(report);
+            }
+            Some(ReceivedReport::DmarcAggregate(report)) => {
+                dmarc_rows = report.records();
+            }
+            Some(ReceivedReport::Tls(report)) => {
+                tls_policies = report.records();
+            }
+            None => {}
         }
     }
 
@@ -140,6 +164,7 @@ pub async fn log_disposition(args: LogDisposition<'_>) {
         }
 
         let (headers, meta) = logger.extract_fields(&msg).await;
+        let (trace_id, span_id) = get_trace_ids(&msg).await;
 
         let mut tls_cipher = None;
         let mut tls_protocol_version = None;
@@ -174,6 +199,8 @@ pub async fn log_disposition(args: LogDisposition<'_>) {
             egress_source: egress_source.map(|s| s.to_string()),
             bounce_classification: BounceClass::default(),
             feedback_report: feedback_report.clone(),
+            dmarc_aggregate_report: None,
+            tls_report: None,
             headers: headers.clone(),
             meta: meta.clone(),
             delivery_protocol: delivery_protocol.map(|s| s.to_string()),
@@ -185,110 +212,132 @@ pub async fn log_disposition(args: LogDisposition<'_>) {
             source_address: source_address.clone(),
             provider_name: provider.map(|s| s.to_string()),
             session_id,
+            trace_id: trace_id.clone(),
+            span_id: span_id.clone(),
         };
+
+        // Each row of an incoming aggregate report is logged as its
+        // own record, alongside the Reception record for the report
+        let report_records: Vec<JsonLogRecord> = dmarc_rows
+            .iter()
+            .map(|row| JsonLogRecord {
+                kind: RecordType::DmarcAggregate,
+                dmarc_aggregate_report: Some(Box::new(row.clone())),
+                ..record.clone()
+            })
+            .chain(tls_policies.iter().map(|policy| JsonLogRecord {
+                kind: RecordType::TlsReport,
+                tls_report: Some(Box::new(policy.clone())),
+                ..record.clone()
+            }))
+            .collect();
+
         if let Err(err) = logger.log(record, Some(msg.clone())).await {
             tracing::error!("failed to log: {err:#}");
         }
 
-        if kind == RecordType::Reception {
-            if relay_disposition
-                .as_ref()
-                .map(|disp| disp.log_oob.should_log())
-                .unwrap_or(false)
-            {
-                if let Ok(Some(report)) = msg.parse_rfc3464().await {
-                    // This incoming bounce report is addressed to
-                    // the envelope from of the original message
-                    let sender = msg
-                        .first_recipient()
-                        .await
-                        .map(|addr| addr.to_string())
-                        .unwrap_or_else(|err| format!("{err:#}"));
-                    let queue = msg
-                        .get_queue_name()
-                        .await
-                        .unwrap_or_else(|err| format!("{err:#}"));
-
-                    let reconstructed_original_msg = None; // FIXME: try to build this from the
-                                                           // parsed rfc3464 report?
+        for record in report_records {
+            if !logger.record_is_enabled(record.kind) {
+                continue;
+            }
+            if let Err(err) = logger.log(record, Some(msg.clone())).await {
+                tracing::error!("failed to log: {err:#}");
+            }
+        }
 
-                    for recip in &report.per_recipient {
-                        if recip.action != ReportAction::Failed {
-                            continue;
-                        }
+        if let Some(report) = &oob_report {
+            // This incoming bounce report is addressed to
+            // the envelope from of the original message
+            let sender = msg
+                .first_recipient()
+                .await
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|err| format!("{err:#}"));
+            let queue = msg
+                .get_queue_name()
+                .await
+                .unwrap_or_else(|err| format!("{err:#}"));
 
-                        let enhanced_code = EnhancedStatusCode {
-                            class: recip.status.class,
-                            subject: recip.status.subject,
-                            detail: recip.status.detail,
-                        };
+            let reconstructed_original_msg = None; // FIXME: try to build this from the
+                                                   // parsed rfc3464 report?
 
-                        let (code, content) = match &recip.diagnostic_code {
-                            Some(diag) if diag.diagnostic_type == "smtp" => {
-                                if let Some((code, content)) = diag.diagnostic.split_once(' ') {
-                                    if let Ok(code) = code.parse() {
-                                        (code, content.to_string())
-                                    } else {
-                                        (550, diag.diagnostic.to_string())
-                                    }
-                                } else {
-                                    (550, diag.diagnostic.to_string())
-                                }
-                            }
-                            _ => (550, "".to_string()),
-                        };
+            for recip in &report.per_recipient {
+                if recip.action != ReportAction::Failed {
+                    continue;
+                }
 
-                        let record = JsonLogRecord {
-                            kind: RecordType::OOB,
-                            id: msg.id().to_string(),
-                            size: 0,
-                            sender: sender.clone(),
-                            recipient: vec![recip
-                                .original_recipient
-                                .as_ref()
-                                .unwrap_or(&recip.final_recipient)
-                                .recipient
-                                .to_string()],
-                            queue: queue.to_string(),
-                            site: site.to_string(),
-                            peer_address: Some(ResolvedAddress {
-                                name: report.per_message.reporting_mta.name.to_string(),
-                                addr: peer_address
-                                    .map(|a| a.addr.clone())
-                                    .unwrap_or_else(|| Ipv4Addr::UNSPECIFIED.into()),
-                            }),
-                            response: Response {
-                                code,
-                                enhanced_code: Some(enhanced_code),
-                                content,
-                                command: None,
-                            },
-                            timestamp: recip.last_attempt_date.unwrap_or_else(|| Utc::now()),
-                            created: msg.id().created(),
-                            num_attempts: 0,
-                            egress_pool: None,
-                            egress_source: None,
-                            bounce_classification: BounceClass::default(),
-                            feedback_report: None,
-                            headers: headers.clone(),
-                            meta: meta.clone(),
-                            delivery_protocol: None,
-                            reception_protocol: reception_protocol.clone(),
-                            nodeid,
-                            tls_cipher: None,
-                            tls_protocol_version: None,
-                            tls_peer_subject_name: None,
-                            source_address: None,
-                            provider_name: provider.map(|s| s.to_string()),
-                            session_id,
-                        };
+                let enhanced_code = EnhancedStatusCode {
+                    class: recip.status.class,
+                    subject: recip.status.subject,
+                    detail: recip.status.detail,
+                };
 
-                        if let Err(err) =
-                            logger.log(record, reconstructed_original_msg.clone()).await
-                        {
-                            tracing::error!("failed to log: {err:#}");
+                let (code, content) = match &recip.diagnostic_code {
+                    Some(diag) if diag.diagnostic_type == "smtp" => {
+                        if let Some((code, content)) = diag.diagnostic.split_once(' ') {
+                            if let Ok(code) = code.parse() {
+                                (code, content.to_string())
+                            } else {
+                                (550, diag.diagnostic.to_string())
+                            }
+                        } else {
+                            (550, diag.diagnostic.to_string())
                         }
                     }
+                    _ => (550, "".to_string()),
+                };
+
+                let record = JsonLogRecord {
+                    kind: RecordType::OOB,
+                    id: msg.id().to_string(),
+                    size: 0,
+                    sender: sender.clone(),
+                    recipient: vec![recip
+                        .original_recipient
+                        .as_ref()
+                        .unwrap_or(&recip.final_recipient)
+                        .recipient
+                        .to_string()],
+                    queue: queue.to_string(),
+                    site: site.to_string(),
+                    peer_address: Some(ResolvedAddress {
+                        name: report.per_message.reporting_mta.name.to_string(),
+                        addr: peer_address
+                            .map(|a| a.addr.clone())
+                            .unwrap_or_else(|| Ipv4Addr::UNSPECIFIED.into()),
+                    }),
+                    response: Response {
+                        code,
+                        enhanced_code: Some(enhanced_code),
+                        content,
+                        command: None,
+                    },
+                    timestamp: recip.last_attempt_date.unwrap_or_else(|| Utc::now()),
+                    created: msg.id().created(),
+                    num_attempts: 0,
+                    egress_pool: None,
+                    egress_source: None,
+                    bounce_classification: BounceClass::default(),
+                    feedback_report: None,
+                    dmarc_aggregate_report: None,
+                    tls_report: None,
+                    headers: headers.clone(),
+                    meta: meta.clone(),
+                    delivery_protocol: None,
+                    reception_protocol: reception_protocol.clone(),
+                    nodeid,
+                    tls_cipher: None,
+                    tls_protocol_version: None,
+                    tls_peer_subject_name: None,
+                    source_address: None,
+                    provider_name: provider.map(|s| s.to_string()),
+                    session_id,
+                    trace_id: trace_id.clone(),
+                    span_id: span_id.clone(),
+                };
+
+                if let Err(err) = logger.log(record, reconstructed_original_msg.clone()).await {
+                    tracing::error!("failed to log: {err:#}");
                 }
             }
         }
@@ -1,3 +1,4 @@
+use crate::logging::columnar::{LogSchema, ParquetSegment};
 use crate::logging::{LogCommand, LogRecordParams};
 use anyhow::Context;
 use chrono::Utc;
@@ -11,9 +12,19 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use zstd::stream::write::Encoder;
 
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFileFormat {
+    /// zstd compressed segments holding a json object per line
+    #[default]
+    Json,
+    /// Apache Parquet segments with a fixed schema
+    Parquet,
+}
+
 #[derive(Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct LogFileParams {
@@ -37,6 +48,10 @@ pub struct LogFileParams {
     #[serde(default, with = "duration_serde")]
     pub max_segment_duration: Option<Duration>,
 
+    /// The format of the log file segments
+    #[serde(default)]
+    pub format: LogFileFormat,
+
     /// List of meta fields to capture in the log
     #[serde(default)]
     pub meta: Vec<String>,
@@ -70,6 +85,44 @@ impl LogFileParams {
     pub fn default_compression_level() -> i32 {
         0 // use the zstd default
     }
+
+    /// Produce the schema used for Parquet segments, or None
+    /// if this log uses the json format
+    pub fn parquet_schema(&self) -> anyhow::Result<Option<Arc<LogSchema>>> {
+        if self.format != LogFileFormat::Parquet {
+            return Ok(None);
+        }
+        for (kind, per_rec) in &self.per_record {
+            anyhow::ensure!(
+                per_rec.template.is_none() && per_rec.segment_header.is_empty(),
+                "template and segment_header cannot be used with \
+                 the Parquet log format (per_record {kind:?})"
+            );
+        }
+        Ok(Some(Arc::new(LogSchema::new(&self.meta, &self.headers)?)))
+    }
+}
+
+/// An open log segment in one of the supported formats
+pub enum SegmentFile {
+    Json(OpenedFile),
+    Parquet(ParquetSegment),
+}
+
+impl SegmentFile {
+    fn expires(&self) -> Option<Instant> {
+        match self {
+            Self::Json(f) => f.expires,
+            Self::Parquet(f) => f.expires,
+        }
+    }
+
+    fn written(&self) -> u64 {
+        match self {
+            Self::Json(f) => f.written,
+            Self::Parquet(f) => f.written(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -82,7 +135,8 @@ pub struct LogThreadState {
     pub params: LogFileParams,
     pub receiver: Receiver<LogCommand>,
     pub template_engine: TemplateEngine,
-    pub file_map: HashMap<FileNameKey, OpenedFile>,
+    pub parquet_schema: Option<Arc<LogSchema>>,
+    pub file_map: HashMap<FileNameKey, SegmentFile>,
 }
 
 impl LogThreadState {
@@ -172,7 +226,7 @@ impl LogThreadState {
 
     fn expire(&mut self) {
         let now = Instant::now();
-        self.file_map.retain(|_, of| match of.expires {
+        self.file_map.retain(|_, of| match of.expires() {
             Some(exp) => {
                 tracing::trace!("check {exp:?} vs {now:?} -> {}", exp > now);
                 exp > now
@@ -182,7 +236,7 @@ impl LogThreadState {
     }
 
     fn get_deadline(&self) -> Option<Instant> {
-        self.file_map.values().filter_map(|of| of.expires).min()
+        self.file_map.values().filter_map(|of| of.expires()).min()
     }
 
     fn per_record(&self, kind: RecordType) -> Option<&LogRecordParams> {
@@ -238,6 +292,9 @@ impl LogThreadState {
             if let Some(suffix) = &file_key.suffix {
                 base_name.push_str(suffix);
             }
+            if self.parquet_schema.is_some() {
+                base_name.push_str(".parquet");
+            }
 
             let name = file_key.log_dir.join(base_name);
             // They might be trying to use multiple directories below
@@ -278,57 +335,79 @@ impl LogThreadState {
                 }
             };
 
-            let mut file = OpenedFile {
-                file: Encoder::new(f, self.params.compression_level)
-                    .context("set up zstd encoder")?,
-                name,
-                written: 0,
-                expires: self
-                    .params
-                    .max_segment_duration
-                    .map(|duration| Instant::now() + duration),
-            };
-
-            if let Some(per_rec) = self.per_record(record.kind) {
-                if !per_rec.segment_header.is_empty() {
-                    file.file
-                        .write_all(per_rec.segment_header.as_bytes())
-                        .with_context(|| {
-                            format!(
-                                "writing segment header to newly opened segment file {}",
-                                file.name.display()
-                            )
-                        })?;
+            let expires = self
+                .params
+                .max_segment_duration
+                .map(|duration| Instant::now() + duration);
+
+            let segment = if let Some(schema) = &self.parquet_schema {
+                SegmentFile::Parquet(ParquetSegment::new(
+                    f,
+                    name,
+                    schema.clone(),
+                    self.params.compression_level,
+                    expires,
+                )?)
+            } else {
+                let mut file = OpenedFile {
+                    file: Encoder::new(f, self.params.compression_level)
+                        .context("set up zstd encoder")?,
+                    name,
+                    written: 0,
+                    expires,
+                };
+
+                if let Some(per_rec) = self.per_record(record.kind) {
+                    if !per_rec.segment_header.is_empty() {
+                        file.file
+                            .write_all(per_rec.segment_header.as_bytes())
+                            .with_context(|| {
+                                format!(
+                                    "writing segment header to newly opened segment file {}",
+                                    file.name.display()
+                                )
+                            })?;
+                    }
                 }
-            }
+                SegmentFile::Json(file)
+            };
 
-            self.file_map.insert(file_key.clone(), file);
+            self.file_map.insert(file_key.clone(), segment);
         }
 
         let mut need_rotate = false;
 
-        if let Some(file) = self.file_map.get_mut(&file_key) {
-            let mut record_text = Vec::new();
-            self.template_engine.add_global("log_record", &record)?;
-
-            if let Some(template) =
-                Self::resolve_template(&self.params, &self.template_engine, record.kind)
-            {
-                template.render_to_write(&record, &mut record_text)?;
-            } else {
-                serde_json::to_writer(&mut record_text, &record).context("serializing record")?;
+        match self.file_map.get_mut(&file_key) {
+            Some(SegmentFile::Parquet(segment)) => {
+                segment.write_record(record)?;
             }
-            if record_text.last() != Some(&b'\n') {
-                record_text.push(b'\n');
+            Some(SegmentFile::Json(file)) => {
+                let mut record_text = Vec::new();
+                self.template_engine.add_global("log_record", &record)?;
+
+                if let Some(template) =
+                    Self::resolve_template(&self.params, &self.template_engine, record.kind)
+                {
+                    template.render_to_write(&record, &mut record_text)?;
+                } else {
+                    serde_json::to_writer(&mut record_text, &record)
+                        .context("serializing record")?;
+                }
+                if record_text.last() != Some(&b'\n') {
+                    record_text.push(b'\n');
+                }
+                file.file
+                    .write_all(&record_text)
+                    .with_context(|| format!("writing record to {}", file.name.display()))?;
+                file.written += record_text.len() as u64;
             }
-            file.file
-                .write_all(&record_text)
-                .with_context(|| format!("writing record to {}", file.name.display()))?;
-            file.written += record_text.len() as u64;
+            None => {}
+        }
 
-            need_rotate = file.written >= self.params.max_file_size
+        if let Some(file) = self.file_map.get(&file_key) {
+            need_rotate = file.written() >= self.params.max_file_size
                 || file
-                    .expires
+                    .expires()
                     .map(|exp| exp <= Instant::now())
                     .unwrap_or(false);
         }
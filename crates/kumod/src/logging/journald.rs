@@ -0,0 +1,369 @@
+//! Sends log records to the systemd journal using its native
+//! protocol. The record text is sent as the MESSAGE field, and
+//! the key fields of the record, along with any captured meta
+//! and headers, are sent as additional journal fields so that
+//! they can be used to filter the journal.
+use crate::logging::syslog::severity_for_record;
+use crate::logging::{render_record, value_text, LogCommand, LogRecordParams, LogSink};
+use anyhow::Context;
+use flume::Receiver;
+pub use kumo_log_types::*;
+use kumo_template::TemplateEngine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use tokio::io::Interest;
+use tokio::net::UnixDatagram;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct JournaldParams {
+    /// The value of the SYSLOG_IDENTIFIER field
+    #[serde(default = "JournaldParams::default_syslog_identifier")]
+    pub syslog_identifier: String,
+
+    /// The path to the journald native protocol socket
+    #[serde(default = "JournaldParams::default_socket_path")]
+    pub socket_path: PathBuf,
+
+    /// Maximum number of outstanding items to be logged before
+    /// the submission will block; helps to avoid runaway issues
+    /// spiralling out of control.
+    #[serde(default = "crate::logging::files::LogFileParams::default_back_pressure")]
+    pub back_pressure: usize,
+
+    /// List of meta fields to capture in the log
+    #[serde(default)]
+    pub meta: Vec<String>,
+
+    /// List of message headers to capture in the log
+    #[serde(default)]
+    pub headers: Vec<String>,
+
+    #[serde(default)]
+    pub per_record: HashMap<RecordType, LogRecordParams>,
+
+    /// The name of an event which can be used to filter
+    /// out log records which should not be sent to the journal
+    #[serde(default)]
+    pub filter_event: Option<String>,
+}
+
+impl JournaldParams {
+    fn default_syslog_identifier() -> String {
+        "kumod".to_string()
+    }
+
+    fn default_socket_path() -> PathBuf {
+        "/run/systemd/journal/socket".into()
+    }
+}
+
+impl LogSink for JournaldParams {
+    fn logger_name(&self) -> String {
+        format!("journald-{}", self.socket_path.display())
+    }
+    fn back_pressure(&self) -> usize {
+        self.back_pressure
+    }
+    fn meta(&self) -> &[String] {
+        &self.meta
+    }
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    fn per_record(&self) -> &HashMap<RecordType, LogRecordParams> {
+        &self.per_record
+    }
+    fn filter_event(&self) -> Option<&str> {
+        self.filter_event.as_deref()
+    }
+
+    fn run(
+        self,
+        receiver: Receiver<LogCommand>,
+        mut template_engine: TemplateEngine,
+    ) -> impl Future<Output = ()> + Send {
+        async move {
+            tracing::debug!("JournaldParams: {self:#?}");
+
+            let socket = match UnixDatagram::unbound() {
+                Ok(socket) => socket,
+                Err(err) => {
+                    tracing::error!("failed to create journald socket: {err:#}");
+                    return;
+                }
+            };
+
+            loop {
+                let cmd = match receiver.recv_async().await {
+                    Ok(cmd) => cmd,
+                    other => {
+                        tracing::debug!("logging channel closed {other:?}");
+                        return;
+                    }
+                };
+                match cmd {
+                    LogCommand::Terminate => {
+                        tracing::debug!("LogCommand::Terminate received. Stopping writing logs");
+                        break;
+                    }
+                    LogCommand::Record(record, _msg) => {
+                        let result = async {
+                            let text =
+                                render_record(&self.per_record, &mut template_engine, &record)?;
+                            let datagram = encode_record(&self, &record, &text);
+                            send_datagram(&socket, &self.socket_path, &datagram)
+                                .await
+                                .with_context(|| {
+                                    format!("sending to {}", self.socket_path.display())
+                                })
+                        }
+                        .await;
+                        if let Err(err) = result {
+                            tracing::error!("failed to log: {err:#}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Send datagram to journald. Entries that are too large to be
+/// sent as a single datagram are written to a sealed memfd whose
+/// descriptor is passed to journald instead, in the same way as
+/// sd_journal_send.
+async fn send_datagram(socket: &UnixDatagram, path: &Path, datagram: &[u8]) -> std::io::Result<()> {
+    match socket.send_to(datagram, path).await {
+        Ok(_) => Ok(()),
+        Err(err) if matches!(err.raw_os_error(), Some(libc::EMSGSIZE | libc::ENOBUFS)) => {
+            send_via_memfd(path, datagram).await
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn send_via_memfd(path: &Path, datagram: &[u8]) -> std::io::Result<()> {
+    let fd = unsafe {
+        libc::memfd_create(
+            c"kumod-journal".as_ptr(),
+            libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    file.write_all(datagram)?;
+    // journald refuses descriptors that are not sealed
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    socket
+        .async_io(Interest::WRITABLE, || {
+            send_fd(socket.as_raw_fd(), file.as_raw_fd())
+        })
+        .await
+}
+
+/// Send an empty datagram carrying fd as SCM_RIGHTS ancillary data
+fn send_fd(socket: RawFd, fd: RawFd) -> std::io::Result<()> {
+    let fd_len = std::mem::size_of::<RawFd>() as libc::c_uint;
+    let space = unsafe { libc::CMSG_SPACE(fd_len) } as usize;
+    // Use u64 storage so that the control buffer is suitably
+    // aligned for cmsghdr
+    let mut control = vec![0u64; space.div_ceil(8)];
+
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+    }
+
+    if unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Produce a journal field name, which may consist only of
+/// upper case letters, digits and underscores, must not begin
+/// with an underscore or a digit and is limited to 64 characters
+fn field_name(prefix: &str, name: &str) -> String {
+    let mut field: String = format!("{prefix}{name}")
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .collect();
+    if !field.starts_with(|c: char| c.is_ascii_uppercase()) {
+        field.insert(0, 'X');
+    }
+    field.truncate(64);
+    field
+}
+
+/// Append a field using the journal native protocol encoding.
+/// Values that contain newlines must use the length-prefixed
+/// binary form.
+fn push_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+/// Encode record as a journal entry with text as its MESSAGE
+pub fn encode_record(params: &JournaldParams, record: &JsonLogRecord, text: &str) -> Vec<u8> {
+    let mut datagram = vec![];
+    push_field(&mut datagram, "MESSAGE", text);
+    push_field(
+        &mut datagram,
+        "PRIORITY",
+        &severity_for_record(record.kind).to_string(),
+    );
+    push_field(
+        &mut datagram,
+        "SYSLOG_IDENTIFIER",
+        &params.syslog_identifier,
+    );
+    push_field(&mut datagram, "KUMO_TYPE", &format!("{:?}", record.kind));
+    push_field(&mut datagram, "KUMO_ID", &record.id);
+    push_field(&mut datagram, "KUMO_QUEUE", &record.queue);
+    if !record.site.is_empty() {
+        push_field(&mut datagram, "KUMO_SITE", &record.site);
+    }
+    push_field(&mut datagram, "KUMO_SENDER", &record.sender);
+    for recip in &record.recipient {
+        push_field(&mut datagram, "KUMO_RECIPIENT", recip);
+    }
+    push_field(
+        &mut datagram,
+        "KUMO_RESPONSE_CODE",
+        &record.response.code.to_string(),
+    );
+    push_field(
+        &mut datagram,
+        "KUMO_NUM_ATTEMPTS",
+        &record.num_attempts.to_string(),
+    );
+    if let Some(trace_id) = &record.trace_id {
+        push_field(&mut datagram, "KUMO_TRACE_ID", trace_id);
+    }
+    if let Some(span_id) = &record.span_id {
+        push_field(&mut datagram, "KUMO_SPAN_ID", span_id);
+    }
+
+    let mut meta: Vec<_> = record.meta.iter().collect();
+    meta.sort_by_key(|(name, _)| *name);
+    for (name, value) in meta {
+        push_field(
+            &mut datagram,
+            &field_name("KUMO_META_", name),
+            &value_text(value),
+        );
+    }
+
+    let mut headers: Vec<_> = record.headers.iter().collect();
+    headers.sort_by_key(|(name, _)| *name);
+    for (name, value) in headers {
+        push_field(
+            &mut datagram,
+            &field_name("KUMO_HEADER_", name),
+            &value_text(value),
+        );
+    }
+
+    datagram
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn field_names() {
+        assert_eq!(
+            field_name("KUMO_META_", "x-tenant.id"),
+            "KUMO_META_X_TENANT_ID"
+        );
+        assert_eq!(field_name("", "_private"), "X_PRIVATE");
+        assert_eq!(field_name("", "1st"), "X1ST");
+        assert_eq!(field_name("KUMO_HEADER_", &"a".repeat(100)).len(), 64);
+    }
+
+    #[test]
+    fn encoding() {
+        let mut datagram = vec![];
+        push_field(&mut datagram, "MESSAGE", "hello");
+        push_field(&mut datagram, "KUMO_HEADER_SUBJECT", "one\ntwo");
+        assert_eq!(
+            datagram,
+            b"MESSAGE=hello\nKUMO_HEADER_SUBJECT\n\x07\0\0\0\0\0\0\0one\ntwo\n".to_vec()
+        );
+    }
+
+    /// Receive a datagram sent by send_fd and return the passed file
+    fn recv_fd(socket: RawFd) -> std::io::Result<File> {
+        let fd_len = std::mem::size_of::<RawFd>() as libc::c_uint;
+        let space = unsafe { libc::CMSG_SPACE(fd_len) } as usize;
+        let mut control = vec![0u64; space.div_ceil(8)];
+
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = space as _;
+        if unsafe { libc::recvmsg(socket, &mut msg, 0) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let fd = unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            assert!(!cmsg.is_null(), "no fd was passed");
+            assert_eq!((*cmsg).cmsg_type, libc::SCM_RIGHTS);
+            std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>())
+        };
+        Ok(File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    #[tokio::test]
+    async fn oversize_uses_memfd() {
+        use std::io::{Read, Seek};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("socket");
+        let journal = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        // Larger than any socket buffer will accept as a datagram
+        let mut datagram = vec![];
+        push_field(&mut datagram, "MESSAGE", &"x".repeat(8 * 1024 * 1024));
+
+        let socket = UnixDatagram::unbound().unwrap();
+        send_datagram(&socket, &path, &datagram).await.unwrap();
+
+        let mut file = recv_fd(journal.as_raw_fd()).unwrap();
+        file.rewind().unwrap();
+        let mut received = vec![];
+        file.read_to_end(&mut received).unwrap();
+        assert!(received == datagram, "memfd content doesn't match");
+    }
+}
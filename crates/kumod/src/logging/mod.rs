@@ -2,9 +2,11 @@ use crate::logging::classify::{apply_classification, ClassifierParams};
 use crate::logging::disposition_hooks::{DispHookParams, RecordWrapper};
 use crate::logging::files::{LogFileParams, LogThreadState};
 use crate::logging::hooks::{LogHookParams, LogHookState};
+use crate::logging::journald::JournaldParams;
+use crate::logging::syslog::SyslogParams;
 use anyhow::Context;
 use config::{any_err, from_lua_value, get_or_create_module, CallbackSignature};
-use flume::{bounded, Sender, TrySendError};
+use flume::{bounded, Receiver, Sender, TrySendError};
 pub use kumo_log_types::*;
 use kumo_prometheus::declare_metric;
 use kumo_prometheus::prometheus::Histogram;
@@ -17,6 +19,7 @@ use parking_lot::FairMutex as Mutex;
 use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::future::Future;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, LazyLock};
@@ -24,11 +27,14 @@ use tokio::sync::Mutex as TokioMutex;
 use tokio::task::JoinHandle;
 
 pub(crate) mod classify;
+pub(crate) mod columnar;
 pub(crate) mod disposition;
 pub(crate) mod disposition_hooks;
 pub(crate) mod files;
 pub(crate) mod hooks;
+pub(crate) mod journald;
 pub(crate) mod rejection;
+pub(crate) mod syslog;
 
 declare_metric! {
 /// how many times submission of a log event hit the back_pressure
@@ -82,6 +88,86 @@ fn default_true() -> bool {
     true
 }
 
+/// Compile the templates that are specified via per_record
+fn compile_templates(
+    per_record: &HashMap<RecordType, LogRecordParams>,
+) -> anyhow::Result<TemplateEngine> {
+    let mut template_engine = TemplateEngine::new();
+
+    for (kind, per_rec) in per_record {
+        if let Some(template_source) = &per_rec.template {
+            template_engine
+                .add_template(format!("{kind:?}"), template_source.clone())
+                .with_context(|| {
+                    format!("compiling template:\n{template_source}\nfor log record type {kind:?}")
+                })?;
+        }
+    }
+
+    Ok(template_engine)
+}
+
+/// Produce the text of a record; either its template expansion
+/// as configured via per_record, or its json representation.
+/// Any trailing newline is removed.
+pub(crate) fn render_record(
+    per_record: &HashMap<RecordType, LogRecordParams>,
+    template_engine: &mut TemplateEngine,
+    record: &JsonLogRecord,
+) -> anyhow::Result<String> {
+    let template_name = match per_record.get(&record.kind) {
+        Some(pr) => pr.template.as_ref().map(|_| format!("{:?}", record.kind)),
+        None => per_record
+            .get(&RecordType::Any)
+            .and_then(|pr| pr.template.as_ref())
+            .map(|_| "Any".to_string()),
+    };
+
+    let mut text = match template_name {
+        Some(name) => {
+            template_engine.add_global("log_record", record)?;
+            template_engine.get_template(&name)?.render(record)?
+        }
+        None => serde_json::to_string(record).context("serializing record")?,
+    };
+    while text.ends_with('\n') {
+        text.pop();
+    }
+    Ok(text)
+}
+
+/// Produce the text of a captured meta or header value for sinks
+/// that represent it as a plain string: strings are used as-is,
+/// while other values use their json representation
+pub(crate) fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_string(),
+        value => value.to_string(),
+    }
+}
+
+/// A log sink that forwards log records to some external
+/// logging service from its own task on the logging runtime.
+/// The common options are used to configure the Logger that
+/// feeds records to the sink.
+pub(crate) trait LogSink: Send + Sized + 'static {
+    /// Used to label the metrics for this logger
+    fn logger_name(&self) -> String;
+    fn back_pressure(&self) -> usize;
+    fn meta(&self) -> &[String];
+    fn headers(&self) -> &[String];
+    fn per_record(&self) -> &HashMap<RecordType, LogRecordParams>;
+    fn filter_event(&self) -> Option<&str>;
+
+    /// Process records from receiver until LogCommand::Terminate
+    /// is received
+    fn run(
+        self,
+        receiver: Receiver<LogCommand>,
+        template_engine: TemplateEngine,
+    ) -> impl Future<Output = ()> + Send;
+}
+
 #[derive(Debug)]
 pub(crate) enum LogCommand {
     Record(JsonLogRecord, Option<Message>),
@@ -172,19 +258,7 @@ impl Logger {
             );
         }
 
-        let mut template_engine = TemplateEngine::new();
-
-        for (kind, per_rec) in &params.per_record {
-            if let Some(template_source) = &per_rec.template {
-                template_engine
-                    .add_template(format!("{kind:?}"), template_source.clone())
-                    .with_context(|| {
-                        format!(
-                            "compiling template:\n{template_source}\nfor log record type {kind:?}"
-                        )
-                    })?;
-            }
-        }
+        let template_engine = compile_templates(&params.per_record)?;
 
         let mut enabled = HashMap::new();
         for (kind, cfg) in &params.per_record {
@@ -226,19 +300,8 @@ impl Logger {
     }
 
     pub async fn init(params: LogFileParams) -> anyhow::Result<()> {
-        let mut template_engine = TemplateEngine::new();
-
-        for (kind, per_rec) in &params.per_record {
-            if let Some(template_source) = &per_rec.template {
-                template_engine
-                    .add_template(format!("{kind:?}"), template_source.clone())
-                    .with_context(|| {
-                        format!(
-                            "compiling template:\n{template_source}\nfor log record type {kind:?}"
-                        )
-                    })?;
-            }
-        }
+        let template_engine = compile_templates(&params.per_record)?;
+        let parquet_schema = params.parquet_schema()?;
 
         std::fs::create_dir_all(&params.log_dir)
             .with_context(|| format!("creating log directory {}", params.log_dir.display()))?;
@@ -268,6 +331,7 @@ impl Logger {
                 params,
                 receiver,
                 template_engine,
+                parquet_schema,
                 file_map: HashMap::new(),
             };
             state.logger_thread().await
@@ -295,6 +359,47 @@ impl Logger {
         Ok(())
     }
 
+    pub(crate) async fn init_sink<S: LogSink>(sink: S) -> anyhow::Result<()> {
+        let template_engine = compile_templates(sink.per_record())?;
+
+        let mut enabled = HashMap::new();
+        for (kind, cfg) in sink.per_record() {
+            enabled.insert(*kind, cfg.enable);
+        }
+
+        let headers = sink.headers().to_vec();
+        let meta = sink.meta().to_vec();
+        let filter_event = sink.filter_event().map(|s| s.to_string());
+        let name = sink.logger_name();
+        let (sender, receiver) = bounded(sink.back_pressure());
+
+        let thread = LOGGING_RUNTIME.spawn(format!("log {name}"), async move {
+            tracing::debug!("calling sink.run()");
+            sink.run(receiver, template_engine).await
+        })?;
+
+        let submit_latency = SUBMIT_LATENCY.get_metric_with_label_values(&[&name])?;
+
+        let implementation = LoggerImpl::Queue {
+            sender,
+            thread: TokioMutex::new(Some(thread)),
+        };
+
+        let logger = Self {
+            implementation,
+            meta,
+            headers,
+            enabled,
+            filter_event,
+            hook_name: None,
+            name,
+            submit_latency,
+        };
+
+        LOGGER.lock().push(Arc::new(logger));
+        Ok(())
+    }
+
     pub fn record_is_enabled(&self, kind: RecordType) -> bool {
         if let Some(enabled) = self.enabled.get(&kind) {
             return *enabled;
@@ -465,6 +570,22 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
         })?,
     )?;
 
+    kumo_mod.set(
+        "configure_syslog_logs",
+        lua.create_async_function(|lua, params: LuaValue| async move {
+            let params: SyslogParams = from_lua_value(&lua, params)?;
+            Logger::init_sink(params).await.map_err(any_err)
+        })?,
+    )?;
+
+    kumo_mod.set(
+        "configure_journald_logs",
+        lua.create_async_function(|lua, params: LuaValue| async move {
+            let params: JournaldParams = from_lua_value(&lua, params)?;
+            Logger::init_sink(params).await.map_err(any_err)
+        })?,
+    )?;
+
     kumo_mod.set(
         "configure_log_disposition_hook",
         lua.create_async_function(|lua, params: LuaValue| async move {
@@ -49,6 +49,8 @@ pub async fn log_rejection(args: LogRejection) {
             egress_source: None,
             bounce_classification: BounceClass::default(),
             feedback_report: None,
+            dmarc_aggregate_report: None,
+            tls_report: None,
             headers: HashMap::new(),
             meta,
             delivery_protocol: None,
@@ -60,6 +62,8 @@ pub async fn log_rejection(args: LogRejection) {
             source_address: None,
             provider_name: None,
             session_id: args.session_id,
+            trace_id: None,
+            span_id: None,
         };
         if let Err(err) = logger.log(record, None).await {
             tracing::error!("failed to log: {err:#}");
@@ -0,0 +1,696 @@
+//! Forwards log records to a remote syslog server.
+//! Records are formatted according to RFC 5424, with the key
+//! fields of the record, along with any captured meta and headers,
+//! expressed as structured data.
+//! UDP transport follows RFC 5426, while TCP and TLS use the
+//! octet-counting framing described by RFC 6587 and RFC 5425.
+use crate::logging::{render_record, value_text, LogCommand, LogRecordParams, LogSink};
+use anyhow::Context;
+use chrono::SecondsFormat;
+use flume::Receiver;
+pub use kumo_log_types::*;
+use kumo_template::TemplateEngine;
+use rfc5321::tokio_rustls::rustls::pki_types::ServerName;
+use rfc5321::{BoxedAsyncReadAndWrite, TlsOptions};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::{timeout, Duration};
+
+/// The longest time that we will wait between attempts to
+/// reconnect to the syslog server
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The largest UDP payloads for IPv4 and IPv6, as described by
+/// RFC 5426 section 3.2. Longer messages are truncated to fit.
+const MAX_UDP_PAYLOAD_V4: usize = 65507;
+const MAX_UDP_PAYLOAD_V6: usize = 65527;
+
+static PROCID: LazyLock<String> = LazyLock::new(|| std::process::id().to_string());
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyslogTransport {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+}
+
+/// The facility codes defined by RFC 5424
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyslogFacility {
+    Kern = 0,
+    User = 1,
+    #[default]
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// The RFC 5424 severity that is used for a given record type.
+/// This is shared with the journald sink, which uses the same
+/// values for its PRIORITY field.
+pub fn severity_for_record(kind: RecordType) -> u8 {
+    const WARNING: u8 = 4;
+    const NOTICE: u8 = 5;
+    const INFO: u8 = 6;
+
+    match kind {
+        RecordType::Bounce | RecordType::Expiration | RecordType::AdminBounce => WARNING,
+        RecordType::TransientFailure | RecordType::Rejection => NOTICE,
+        _ => INFO,
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SyslogParams {
+    /// The `host:port` of the syslog server
+    pub address: String,
+
+    #[serde(default)]
+    pub transport: SyslogTransport,
+
+    /// The name to use when verifying the certificate of the
+    /// server when using TLS. Defaults to the host portion
+    /// of the address.
+    #[serde(default)]
+    pub tls_server_name: Option<String>,
+
+    /// Skip verification of the server certificate when using TLS
+    #[serde(default)]
+    pub insecure_tls: bool,
+
+    #[serde(default)]
+    pub facility: SyslogFacility,
+
+    #[serde(default = "SyslogParams::default_app_name")]
+    pub app_name: String,
+
+    /// The HOSTNAME field. Defaults to the hostname of the system.
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    /// The private enterprise number used to qualify the
+    /// structured data IDs
+    #[serde(default = "SyslogParams::default_enterprise_id")]
+    pub enterprise_id: u32,
+
+    /// How long to wait to connect to, or write to, the server
+    #[serde(default = "SyslogParams::default_timeout", with = "duration_serde")]
+    pub timeout: Duration,
+
+    /// Maximum number of outstanding items to be logged before
+    /// the submission will block; helps to avoid runaway issues
+    /// spiralling out of control.
+    #[serde(default = "crate::logging::files::LogFileParams::default_back_pressure")]
+    pub back_pressure: usize,
+
+    /// List of meta fields to capture in the log
+    #[serde(default)]
+    pub meta: Vec<String>,
+
+    /// List of message headers to capture in the log
+    #[serde(default)]
+    pub headers: Vec<String>,
+
+    #[serde(default)]
+    pub per_record: HashMap<RecordType, LogRecordParams>,
+
+    /// The name of an event which can be used to filter
+    /// out log records which should not be sent to this
+    /// syslog server
+    #[serde(default)]
+    pub filter_event: Option<String>,
+}
+
+impl SyslogParams {
+    fn default_app_name() -> String {
+        "kumod".to_string()
+    }
+
+    fn default_enterprise_id() -> u32 {
+        // The example enterprise number reserved by RFC 5612
+        32473
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    fn host(&self) -> &str {
+        let host = match self.address.rsplit_once(':') {
+            Some((host, _port)) => host,
+            None => &self.address,
+        };
+        host.trim_start_matches('[').trim_end_matches(']')
+    }
+}
+
+impl LogSink for SyslogParams {
+    fn logger_name(&self) -> String {
+        format!("syslog-{}", self.address)
+    }
+    fn back_pressure(&self) -> usize {
+        self.back_pressure
+    }
+    fn meta(&self) -> &[String] {
+        &self.meta
+    }
+    fn headers(&self) -> &[String] {
+        &self.headers
+    }
+    fn per_record(&self) -> &HashMap<RecordType, LogRecordParams> {
+        &self.per_record
+    }
+    fn filter_event(&self) -> Option<&str> {
+        self.filter_event.as_deref()
+    }
+
+    fn run(
+        self,
+        receiver: Receiver<LogCommand>,
+        template_engine: TemplateEngine,
+    ) -> impl Future<Output = ()> + Send {
+        async move {
+            let hostname = self.hostname.clone().unwrap_or_else(|| {
+                gethostname::gethostname()
+                    .to_str()
+                    .unwrap_or("localhost")
+                    .to_string()
+            });
+            let mut state = SyslogState {
+                params: self,
+                hostname,
+                receiver,
+                template_engine,
+                connection: None,
+            };
+            state.logger_thread().await
+        }
+    }
+}
+
+/// An error that occurred while sending a record
+enum SendError {
+    /// The server couldn't be reached, or the connection was lost.
+    /// The record can be sent again on a fresh connection.
+    Connection(anyhow::Error),
+    /// Sending the record again cannot succeed, for example
+    /// because of a configuration problem
+    Fatal(anyhow::Error),
+}
+
+impl SendError {
+    /// Classify err according to its kind
+    fn io(err: std::io::Error, context: String) -> Self {
+        let retry = is_connection_error(&err);
+        let err = anyhow::Error::new(err).context(context);
+        if retry {
+            Self::Connection(err)
+        } else {
+            Self::Fatal(err)
+        }
+    }
+}
+
+fn is_connection_error(err: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        err.kind(),
+        ConnectionRefused
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | BrokenPipe
+            | TimedOut
+            | UnexpectedEof
+            | WriteZero
+            | Interrupted
+            | WouldBlock
+            | AddrNotAvailable
+            | HostUnreachable
+            | NetworkUnreachable
+            | NetworkDown
+    )
+}
+
+enum SyslogConnection {
+    Udp(UdpSocket),
+    Stream(BoxedAsyncReadAndWrite),
+}
+
+struct SyslogState {
+    params: SyslogParams,
+    hostname: String,
+    receiver: Receiver<LogCommand>,
+    template_engine: TemplateEngine,
+    connection: Option<SyslogConnection>,
+}
+
+impl SyslogState {
+    async fn logger_thread(&mut self) {
+        tracing::debug!("SyslogParams: {:#?}", self.params);
+
+        loop {
+            let cmd = match self.receiver.recv_async().await {
+                Ok(cmd) => cmd,
+                other => {
+                    tracing::debug!("logging channel closed {other:?}");
+                    return;
+                }
+            };
+            match cmd {
+                LogCommand::Terminate => {
+                    tracing::debug!("LogCommand::Terminate received. Stopping writing logs");
+                    break;
+                }
+                LogCommand::Record(record, _msg) => {
+                    if let Err(err) = self.do_record(record).await {
+                        tracing::error!("failed to log: {err:#}");
+                    };
+                }
+            }
+        }
+
+        if let Some(SyslogConnection::Stream(mut stream)) = self.connection.take() {
+            stream.shutdown().await.ok();
+        }
+    }
+
+    async fn do_record(&mut self, record: JsonLogRecord) -> anyhow::Result<()> {
+        tracing::trace!("do_record {record:?}");
+        let text = render_record(&self.params.per_record, &mut self.template_engine, &record)?;
+        let line = format_rfc5424(&self.params, &self.hostname, &record, &text);
+
+        let frame = match self.params.transport {
+            SyslogTransport::Udp => line.into_bytes(),
+            SyslogTransport::Tcp | SyslogTransport::Tls => {
+                format!("{} {line}", line.len()).into_bytes()
+            }
+        };
+
+        // While the server is unreachable we hold on to the record
+        // and keep retrying. That causes the logging channel to fill
+        // up, so that back_pressure applies to the submitters rather
+        // than silently dropping records. Other errors cannot be
+        // resolved by retrying, so the record is discarded.
+        let mut delay = Duration::from_secs(1);
+        loop {
+            match self.send(&frame).await {
+                Ok(()) => return Ok(()),
+                Err(SendError::Fatal(err)) => {
+                    self.connection.take();
+                    return Err(err);
+                }
+                Err(SendError::Connection(err)) => {
+                    self.connection.take();
+                    if kumo_server_lifecycle::is_shutting_down() {
+                        return Err(err.context("giving up on record during shutdown"));
+                    }
+                    tracing::error!(
+                        "failed to send record to syslog server {}, will retry in {delay:?}: {err:#}",
+                        self.params.address
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_INTERVAL);
+                }
+            }
+        }
+    }
+
+    async fn send(&mut self, frame: &[u8]) -> Result<(), SendError> {
+        let address = &self.params.address;
+        if self.connection.is_none() {
+            let connection = timeout(self.params.timeout, self.connect())
+                .await
+                .map_err(|_| {
+                    SendError::Connection(anyhow::anyhow!("timed out connecting to {address}"))
+                })??;
+            self.connection.replace(connection);
+        }
+
+        let Some(connection) = self.connection.as_mut() else {
+            return Err(SendError::Fatal(anyhow::anyhow!("no syslog connection!?")));
+        };
+
+        let write = async {
+            match connection {
+                SyslogConnection::Udp(socket) => {
+                    let limit = match socket.peer_addr() {
+                        Ok(addr) if addr.is_ipv6() => MAX_UDP_PAYLOAD_V6,
+                        _ => MAX_UDP_PAYLOAD_V4,
+                    };
+                    let datagram = truncate_frame(frame, limit);
+                    if datagram.len() < frame.len() {
+                        tracing::warn!(
+                            "truncated {} byte record to {} bytes to fit in a datagram to {address}",
+                            frame.len(),
+                            datagram.len()
+                        );
+                    }
+                    socket.send(datagram).await?;
+                }
+                SyslogConnection::Stream(stream) => {
+                    stream.write_all(frame).await?;
+                    stream.flush().await?;
+                }
+            }
+            std::io::Result::<()>::Ok(())
+        };
+        timeout(self.params.timeout, write)
+            .await
+            .map_err(|_| SendError::Connection(anyhow::anyhow!("timed out writing to {address}")))?
+            .map_err(|err| SendError::io(err, format!("writing to {address}")))
+    }
+
+    async fn connect(&self) -> Result<SyslogConnection, SendError> {
+        let address = &self.params.address;
+        match self.params.transport {
+            SyslogTransport::Udp => {
+                let addr = lookup_host(address)
+                    .await
+                    .with_context(|| format!("resolving {address}"))
+                    .map_err(SendError::Connection)?
+                    .next()
+                    .with_context(|| format!("no addresses for {address}"))
+                    .map_err(SendError::Connection)?;
+                let local: SocketAddr = if addr.is_ipv4() {
+                    (Ipv4Addr::UNSPECIFIED, 0).into()
+                } else {
+                    (Ipv6Addr::UNSPECIFIED, 0).into()
+                };
+                let socket = UdpSocket::bind(local)
+                    .await
+                    .map_err(|err| SendError::io(err, format!("binding {local}")))?;
+                socket
+                    .connect(addr)
+                    .await
+                    .with_context(|| format!("connecting to {address}"))
+                    .map_err(SendError::Connection)?;
+                Ok(SyslogConnection::Udp(socket))
+            }
+            SyslogTransport::Tcp => {
+                let stream = TcpStream::connect(address)
+                    .await
+                    .with_context(|| format!("connecting to {address}"))
+                    .map_err(SendError::Connection)?;
+                Ok(SyslogConnection::Stream(Box::new(stream)))
+            }
+            SyslogTransport::Tls => {
+                let connector = TlsOptions {
+                    insecure: self.params.insecure_tls,
+                    ..Default::default()
+                }
+                .build_tls_connector()
+                .await
+                .map_err(SendError::Fatal)?;
+                let name = self
+                    .params
+                    .tls_server_name
+                    .as_deref()
+                    .unwrap_or_else(|| self.params.host());
+                let server_name = match IpAddr::from_str(name) {
+                    Ok(ip) => ServerName::IpAddress(ip.into()),
+                    Err(_) => ServerName::try_from(name.to_string())
+                        .with_context(|| format!("invalid tls_server_name {name}"))
+                        .map_err(SendError::Fatal)?,
+                };
+                let stream = TcpStream::connect(address)
+                    .await
+                    .with_context(|| format!("connecting to {address}"))
+                    .map_err(SendError::Connection)?;
+                // Certificate verification problems are reported
+                // as InvalidData, and so are not retried
+                let stream = connector
+                    .connect(server_name, stream)
+                    .await
+                    .map_err(|err| SendError::io(err, format!("TLS handshake with {address}")))?;
+                Ok(SyslogConnection::Stream(Box::new(stream)))
+            }
+        }
+    }
+}
+
+/// Truncate frame to no more than limit bytes, without splitting
+/// a UTF-8 sequence
+fn truncate_frame(frame: &[u8], limit: usize) -> &[u8] {
+    if frame.len() <= limit {
+        return frame;
+    }
+    let mut end = limit;
+    while end > 0 && (frame[end] & 0xc0) == 0x80 {
+        end -= 1;
+    }
+    &frame[..end]
+}
+
+/// Produce an RFC 5424 header field, which must be printable
+/// US-ASCII of no more than max_len characters, or the NILVALUE
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// Produce an SD-NAME, which excludes `=`, space, `]` and `"`
+/// and is limited to 32 characters
+fn sd_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '=' | ']' | '"' => '_',
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .take(32)
+        .collect()
+}
+
+/// Produce an SD-PARAM, escaping the characters that RFC 5424
+/// requires to be escaped in a PARAM-VALUE
+fn write_sd_param(sd: &mut String, name: &str, value: &str) {
+    write!(sd, " {}=\"", sd_name(name)).ok();
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            sd.push('\\');
+        }
+        sd.push(c);
+    }
+    sd.push('"');
+}
+
+fn write_sd_element(sd: &mut String, id: &str, fields: &HashMap<String, Value>) {
+    if fields.is_empty() {
+        return;
+    }
+    let mut names: Vec<&String> = fields.keys().collect();
+    names.sort();
+
+    write!(sd, "[{id}").ok();
+    for name in names {
+        write_sd_param(sd, name, &value_text(&fields[name]));
+    }
+    sd.push(']');
+}
+
+/// Format record as an RFC 5424 syslog message with text as its MSG
+pub fn format_rfc5424(
+    params: &SyslogParams,
+    hostname: &str,
+    record: &JsonLogRecord,
+    text: &str,
+) -> String {
+    let pri = (params.facility as u8) * 8 + severity_for_record(record.kind);
+    let timestamp = record
+        .timestamp
+        .to_rfc3339_opts(SecondsFormat::Micros, true);
+    let eid = params.enterprise_id;
+
+    let mut sd = String::new();
+    write!(sd, "[kumo@{eid}").ok();
+    write_sd_param(&mut sd, "id", &record.id);
+    write_sd_param(&mut sd, "queue", &record.queue);
+    if !record.site.is_empty() {
+        write_sd_param(&mut sd, "site", &record.site);
+    }
+    write_sd_param(&mut sd, "sender", &record.sender);
+    for recip in &record.recipient {
+        write_sd_param(&mut sd, "recipient", recip);
+    }
+    write_sd_param(&mut sd, "code", &record.response.code.to_string());
+    write_sd_param(&mut sd, "num_attempts", &record.num_attempts.to_string());
+    if let Some(pool) = &record.egress_pool {
+        write_sd_param(&mut sd, "egress_pool", pool);
+    }
+    if let Some(source) = &record.egress_source {
+        write_sd_param(&mut sd, "egress_source", source);
+    }
+    if let Some(trace_id) = &record.trace_id {
+        write_sd_param(&mut sd, "trace_id", trace_id);
+    }
+    if let Some(span_id) = &record.span_id {
+        write_sd_param(&mut sd, "span_id", span_id);
+    }
+    sd.push(']');
+    write_sd_element(&mut sd, &format!("meta@{eid}"), &record.meta);
+    write_sd_element(&mut sd, &format!("headers@{eid}"), &record.headers);
+
+    format!(
+        "<{pri}>1 {timestamp} {hostname} {app_name} {procid} {msgid} {sd} {text}",
+        hostname = header_field(hostname, 255),
+        app_name = header_field(&params.app_name, 48),
+        procid = *PROCID,
+        msgid = header_field(&format!("{:?}", record.kind), 32),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn make_params() -> SyslogParams {
+        let params: SyslogParams = serde_json::from_value(serde_json::json!({
+            "address": "syslog.example.com:6514",
+            "transport": "Tls",
+            "facility": "Local3",
+        }))
+        .unwrap();
+        params
+    }
+
+    #[test]
+    fn params() {
+        let params = make_params();
+        assert_eq!(params.transport, SyslogTransport::Tls);
+        assert_eq!(params.facility, SyslogFacility::Local3);
+        assert_eq!(params.app_name, "kumod");
+        assert_eq!(params.host(), "syslog.example.com");
+
+        let params = SyslogParams {
+            address: "[::1]:514".to_string(),
+            ..params
+        };
+        assert_eq!(params.host(), "::1");
+    }
+
+    #[test]
+    fn rfc5424() {
+        let params = make_params();
+        let record = JsonLogRecord {
+            kind: RecordType::Bounce,
+            id: "d7ef132b5d7711eea8c8000c29c33806".to_string(),
+            sender: "sender@example.com".to_string(),
+            recipient: vec!["recip@example.com".to_string()],
+            queue: "example.com".to_string(),
+            site: "mx.example.com".to_string(),
+            size: 1024,
+            response: rfc5321::Response {
+                code: 550,
+                enhanced_code: None,
+                content: "no such user".to_string(),
+                command: None,
+            },
+            peer_address: None,
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 0).unwrap(),
+            created: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+            num_attempts: 1,
+            bounce_classification: Default::default(),
+            egress_pool: None,
+            egress_source: None,
+            source_address: None,
+            feedback_report: None,
+            dmarc_aggregate_report: None,
+            tls_report: None,
+            meta: [("tenant".to_string(), Value::String("a]\"b".to_string()))]
+                .into_iter()
+                .collect(),
+            headers: [("Subject".to_string(), serde_json::json!(["one", "two"]))]
+                .into_iter()
+                .collect(),
+            delivery_protocol: None,
+            reception_protocol: None,
+            nodeid: Default::default(),
+            tls_cipher: None,
+            tls_protocol_version: None,
+            tls_peer_subject_name: None,
+            provider_name: None,
+            session_id: None,
+            trace_id: None,
+            span_id: None,
+        };
+
+        k9::assert_equal!(
+            format_rfc5424(&params, "mta one.example.com", &record, "bounced"),
+            format!(
+                "<156>1 2024-03-01T12:30:00.000000Z mtaone.example.com kumod {} Bounce \
+                 [kumo@32473 id=\"d7ef132b5d7711eea8c8000c29c33806\" queue=\"example.com\" \
+                 site=\"mx.example.com\" sender=\"sender@example.com\" \
+                 recipient=\"recip@example.com\" code=\"550\" num_attempts=\"1\"]\
+                 [meta@32473 tenant=\"a\\]\\\"b\"]\
+                 [headers@32473 Subject=\"[\\\"one\\\",\\\"two\\\"\\]\"] bounced",
+                std::process::id()
+            )
+        );
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate_frame(b"hello", 10), b"hello");
+        assert_eq!(truncate_frame(b"hello", 4), b"hell");
+        // Don't split the two byte encoding of é
+        let frame = "caf\u{e9}".as_bytes();
+        assert_eq!(truncate_frame(frame, 4), b"caf");
+        assert_eq!(truncate_frame(frame, 5), frame);
+    }
+
+    #[test]
+    fn error_classification() {
+        use std::io::{Error, ErrorKind};
+        let classify = |kind| match SendError::io(Error::from(kind), "test".to_string()) {
+            SendError::Connection(_) => "connection",
+            SendError::Fatal(_) => "fatal",
+        };
+        assert_eq!(classify(ErrorKind::ConnectionReset), "connection");
+        assert_eq!(classify(ErrorKind::BrokenPipe), "connection");
+        assert_eq!(classify(ErrorKind::InvalidData), "fatal");
+        assert_eq!(classify(ErrorKind::InvalidInput), "fatal");
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(sd_name("a b=c\"d]e"), "a_b_c_d_e");
+        assert_eq!(sd_name(&"x".repeat(40)).len(), 32);
+        assert_eq!(header_field("", 48), "-");
+        assert_eq!(header_field("kumo d", 3), "kum");
+    }
+}
@@ -240,7 +240,7 @@ impl QueueDispatcher for LuaQueueDispatcher {
                                 response: response.clone(),
                                 egress_pool: Some(&dispatcher.egress_pool),
                                 egress_source: Some(&dispatcher.egress_source.name),
-                                relay_disposition: None,
+                                received_report: None,
                                 delivery_protocol: Some("Lua"),
                                 tls_info: None,
                                 source_address: None,
@@ -283,7 +283,7 @@ impl QueueDispatcher for LuaQueueDispatcher {
                                 response: response.clone(),
                                 egress_pool: Some(&dispatcher.egress_pool),
                                 egress_source: Some(&dispatcher.egress_source.name),
-                                relay_disposition: None,
+                                received_report: None,
                                 delivery_protocol: Some("Lua"),
                                 tls_info: None,
                                 source_address: None,
@@ -322,7 +322,7 @@ impl QueueDispatcher for LuaQueueDispatcher {
                         response: response.clone(),
                         egress_pool: Some(&dispatcher.egress_pool),
                         egress_source: Some(&dispatcher.egress_source.name),
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: Some("Lua"),
                         tls_info: None,
                         source_address: None,
@@ -22,12 +22,17 @@ pub static VALIDATE_SIG: Multiple("validate_config") -> ();
 }
 
 mod accounting;
+mod admin_db;
+mod broker_deliver;
 mod delivery_metrics;
 mod dmarc;
+mod dmarc_reporting;
 mod egress_source;
+mod http_deliver;
 mod http_server;
 mod logging;
 mod lua_deliver;
+mod message_trace;
 mod metrics_helper;
 mod mod_kumo;
 mod queue;
@@ -36,6 +41,7 @@ mod smtp_dispatcher;
 mod smtp_server;
 mod spf;
 mod spool;
+mod tls_reporting;
 mod xfer;
 
 /// KumoMTA Daemon.
@@ -317,6 +323,9 @@ async fn perform_init(opts: Opt) -> anyhow::Result<()> {
     } else {
         config::epoch::start_monitor();
         lruttl::spawn_memory_monitor();
+        crate::admin_db::restore_admin_entries()
+            .await
+            .context("restore_admin_entries")?;
         crate::spool::SpoolManager::get()
             .start_spool(start_time)
             .await
@@ -360,6 +369,8 @@ async fn run(opts: Opt) -> anyhow::Result<()> {
             message::dkim::register,
             crate::spf::register,
             crate::dmarc::register,
+            crate::dmarc_reporting::register,
+            crate::tls_reporting::register,
             crate::xfer::lua::register,
         ],
         policy: &opts.policy,
@@ -382,6 +393,7 @@ async fn run(opts: Opt) -> anyhow::Result<()> {
         if let Err(err) = crate::accounting::ACCT.wait_for_shutdown().await {
             tracing::error!("error flushing ACCT: {err:#}");
         }
+        crate::admin_db::wait_for_shutdown().await;
     }
 
     if let Err(err) = crate::spool::SpoolManager::shutdown().await {
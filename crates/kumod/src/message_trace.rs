@@ -0,0 +1,86 @@
+//! Tracing of the message lifecycle.
+//! The W3C traceparent of the span under which a message was
+//! received is stored in its meta, so that the spans for its
+//! subsequent processing, both here and in any downstream kumod
+//! that it is transferred to via xfer, belong to the same trace.
+use kumo_server_common::otel::{is_enabled, parse_traceparent, SpanKind, TraceSpan};
+use message::Message;
+use std::time::SystemTime;
+
+/// The meta key that holds the traceparent of the message.
+/// It is namespaced so that it won't collide with meta that
+/// is set by policy, such as a `traceparent` header that was
+/// imported from the message.
+pub const TRACEPARENT_META: &str = "kumo.traceparent";
+
+async fn get_traceparent(msg: &Message) -> Option<String> {
+    msg.get_meta_string(TRACEPARENT_META).await.ok().flatten()
+}
+
+async fn start_impl(
+    msg: &Message,
+    name: &'static str,
+    kind: SpanKind,
+    start: Option<SystemTime>,
+) -> TraceSpan {
+    if !is_enabled() {
+        return TraceSpan::disabled();
+    }
+    let parent = get_traceparent(msg).await;
+    let mut span = match start {
+        Some(start) => TraceSpan::start_at(name, kind, parent.as_deref(), start),
+        None => TraceSpan::start(name, kind, parent.as_deref()),
+    };
+    span.set_attribute("kumo.message.id", msg.id().to_string());
+    span
+}
+
+/// Start a span that is a child of the trace context of the message
+pub async fn start_span(msg: &Message, name: &'static str, kind: SpanKind) -> TraceSpan {
+    start_impl(msg, name, kind, None).await
+}
+
+/// Start a span that is a child of the trace context of the message,
+/// and which began at some point in the past
+pub async fn start_span_at(
+    msg: &Message,
+    name: &'static str,
+    kind: SpanKind,
+    start: SystemTime,
+) -> TraceSpan {
+    start_impl(msg, name, kind, Some(start)).await
+}
+
+/// Start the span that covers the reception of the message, and
+/// make it the trace context of the message.
+/// The span continues any trace context already present in the
+/// meta, such as that of an upstream kumod.
+pub async fn start_reception(
+    msg: &Message,
+    name: &'static str,
+    kind: SpanKind,
+) -> anyhow::Result<TraceSpan> {
+    let span = start_span(msg, name, kind).await;
+    set_trace_context(msg, span.traceparent()).await?;
+    Ok(span)
+}
+
+/// Record traceparent as the trace context of the message
+pub async fn set_trace_context(msg: &Message, traceparent: Option<String>) -> anyhow::Result<()> {
+    if let Some(traceparent) = traceparent {
+        msg.set_meta(TRACEPARENT_META, traceparent).await?;
+    }
+    Ok(())
+}
+
+/// Returns the trace id and span id of the trace context of the message
+pub async fn get_trace_ids(msg: &Message) -> (Option<String>, Option<String>) {
+    match get_traceparent(msg)
+        .await
+        .as_deref()
+        .and_then(parse_traceparent)
+    {
+        Some((trace_id, span_id)) => (Some(trace_id), Some(span_id)),
+        None => (None, None),
+    }
+}
@@ -3,12 +3,13 @@ use crate::logging::rfc3464::Report;
 use crate::queue::{InsertReason, QueueConfig, QueueManager};
 use crate::ready_queue::GET_EGRESS_PATH_CONFIG_SIG;
 use crate::smtp_server::{
-    EsmtpDomain, EsmtpListenerParams, RejectDisconnect, RejectError, TraceHeaders,
+    EsmtpDomain, EsmtpListenerParams, LmtpListenerParams, RejectDisconnect, RejectError,
+    TraceHeaders,
 };
 use anyhow::Context;
 use config::{any_err, from_lua_value, get_or_create_module};
 use kumo_api_types::egress_path::EgressPathConfig;
-use kumo_log_types::rfc3464::ReportGenerationParams;
+use kumo_log_types::rfc3464::{ReportGenerationParams, DSN_META_KEY};
 use kumo_log_types::JsonLogRecord;
 use kumo_server_common::http_server::HttpListenerParams;
 use kumo_server_lifecycle::ShutdownSubcription;
@@ -24,6 +25,7 @@ use throttle::ThrottleSpec;
 pub fn register(lua: &Lua) -> anyhow::Result<()> {
     let kumo_mod = get_or_create_module(lua, "kumo")?;
 
+    crate::http_server::admin_hold_v1::register(lua)?;
     crate::http_server::admin_suspend_ready_q_v1::register(lua)?;
     crate::http_server::admin_suspend_v1::register(lua)?;
     crate::http_server::admin_bounce_v1::register(lua)?;
@@ -54,6 +56,17 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
         })?,
     )?;
 
+    kumo_mod.set(
+        "start_lmtp_listener",
+        lua.create_async_function(|lua, params: Value| async move {
+            let params: LmtpListenerParams = from_lua_value(&lua, params)?;
+            if !config::is_validating() {
+                params.run().await.map_err(any_err)?;
+            }
+            Ok(())
+        })?,
+    )?;
+
     kumo_mod.set(
         "set_httpinject_threads",
         lua.create_function(move |_, limit: usize| {
@@ -256,6 +269,14 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
         })?,
     )?;
 
+    kumo_mod.set(
+        "configure_admin_db_path",
+        lua.create_function(|_lua, file_name: String| {
+            *crate::admin_db::DB_PATH.lock() = file_name;
+            Ok(())
+        })?,
+    )?;
+
     kumo_mod.set(
         "make_throttle",
         lua.create_function(move |_lua, (name, spec): (String, String)| {
@@ -270,9 +291,20 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
         log_record: JsonLogRecord,
         orig_msg: Option<Message>,
     ) -> anyhow::Result<Option<Message>> {
+        let mut log_record = log_record;
         let orig_msg_data;
         let orig_msg = match orig_msg {
             Some(msg) => {
+                // The log record only includes the meta fields that
+                // the logger was configured to capture, so pull in
+                // the DSN parameters from the message itself
+                if !log_record.meta.contains_key(DSN_META_KEY) {
+                    if let Some(dsn) = msg.get_dsn_params().await? {
+                        log_record
+                            .meta
+                            .insert(DSN_META_KEY.to_string(), serde_json::to_value(dsn)?);
+                    }
+                }
                 orig_msg_data = msg.data().await?;
                 Some(MimePart::parse(orig_msg_data.as_ref().as_ref())?)
             }
@@ -1,9 +1,11 @@
 use crate::queue::delivery_proto::DeliveryProto;
 use crate::queue::strategy::QueueStrategy;
+use bounce_classify::BounceClass;
 use kumo_api_types::egress_path::{ConfigRefreshStrategy, MemoryReductionPolicy};
 use mlua::prelude::*;
 use mlua::UserDataMethods;
-use serde::{Deserialize, Serialize};
+use rfc5321::Response;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::time::Duration;
 use throttle::ThrottleSpec;
 
@@ -26,6 +28,13 @@ pub struct QueueConfig {
     #[serde(default, with = "duration_serde")]
     pub max_retry_interval: Option<Duration>,
 
+    /// Explicit retry schedules that are used instead of the
+    /// exponential backoff described by retry_interval and
+    /// max_retry_interval. The first schedule that matches
+    /// the response from the failed attempt is used.
+    #[serde(default)]
+    pub retry_schedule: Vec<RetrySchedule>,
+
     /// Limits how long a message can remain in the queue
     #[serde(default = "QueueConfig::default_max_age", with = "duration_serde")]
     pub max_age: Duration,
@@ -90,6 +99,187 @@ pub struct QueueShrinkPolicy {
     pub policy: MemoryReductionPolicy,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RetrySchedule {
+    /// Only use this schedule when the failed attempt was
+    /// assigned one of these bounce classifications
+    #[serde(default)]
+    pub bounce_classification: Vec<BounceClass>,
+
+    /// Only use this schedule when the response code of the
+    /// failed attempt matches one of these patterns
+    #[serde(default)]
+    pub response_code: Vec<ResponseCodePattern>,
+
+    /// The delay before each successive attempt.
+    /// The final interval is repeated for any later attempts.
+    #[serde(
+        deserialize_with = "deserialize_intervals",
+        serialize_with = "duration_serde::serialize"
+    )]
+    pub intervals: Vec<Duration>,
+}
+
+fn deserialize_intervals<'de, D>(d: D) -> Result<Vec<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let intervals: Vec<Duration> = duration_serde::deserialize(d)?;
+    if intervals.is_empty() {
+        return Err(serde::de::Error::custom(
+            "retry_schedule intervals must not be empty",
+        ));
+    }
+    Ok(intervals)
+}
+
+impl RetrySchedule {
+    /// A schedule with no criteria applies to any failure
+    pub fn is_unconditional(&self) -> bool {
+        self.bounce_classification.is_empty() && self.response_code.is_empty()
+    }
+
+    /// Returns true if this schedule applies to the response.
+    /// `classification` is only consulted if the schedule
+    /// specifies bounce_classification criteria.
+    pub fn matches(&self, response: &Response, classification: Option<&BounceClass>) -> bool {
+        if !self.response_code.is_empty() && !self.response_code.iter().any(|p| p.matches(response))
+        {
+            return false;
+        }
+        if !self.bounce_classification.is_empty() {
+            match classification {
+                Some(class) if self.bounce_classification.contains(class) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    pub fn delay_for_attempt(&self, attempt: u16) -> Duration {
+        self.intervals
+            .get(attempt as usize)
+            .or_else(|| self.intervals.last())
+            .copied()
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+/// Matches either the three digit SMTP reply code, such as `421`
+/// or `4xx`, or the RFC 3463 enhanced status code, such as `4.2.2`
+/// or `4.7.x`. `x` or `*` matches any value in that position,
+/// and omitted trailing components of an enhanced status code
+/// match any value, so `4.7` is equivalent to `4.7.x`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct ResponseCodePattern {
+    text: String,
+    kind: ResponseCodePatternKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ResponseCodePatternKind {
+    Code([Option<u16>; 3]),
+    Enhanced([Option<u16>; 3]),
+}
+
+fn component_matches(pattern: &[Option<u16>; 3], actual: [u16; 3]) -> bool {
+    pattern
+        .iter()
+        .zip(actual)
+        .all(|(p, a)| p.map(|p| p == a).unwrap_or(true))
+}
+
+impl ResponseCodePattern {
+    pub fn matches(&self, response: &Response) -> bool {
+        match &self.kind {
+            ResponseCodePatternKind::Code(_) => self.matches_code(response.code),
+            ResponseCodePatternKind::Enhanced(pattern) => match &response.enhanced_code {
+                Some(enh) => {
+                    component_matches(pattern, [enh.class.into(), enh.subject, enh.detail])
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Returns true if this pattern matches an enhanced status code,
+    /// such as `4.7.x`, rather than a three digit reply code
+    pub fn is_enhanced(&self) -> bool {
+        matches!(self.kind, ResponseCodePatternKind::Enhanced(_))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Match a bare three digit status code, such as an HTTP status.
+    /// Enhanced status code patterns never match.
+    pub fn matches_code(&self, code: u16) -> bool {
+        match &self.kind {
+            ResponseCodePatternKind::Code(pattern) => {
+                component_matches(pattern, [code / 100, (code / 10) % 10, code % 10])
+            }
+            ResponseCodePatternKind::Enhanced(_) => false,
+        }
+    }
+}
+
+impl TryFrom<String> for ResponseCodePattern {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        fn component(s: &str) -> Option<Option<u16>> {
+            match s {
+                "x" | "X" | "*" => Some(None),
+                s => s.parse().ok().map(Some),
+            }
+        }
+
+        let invalid = || {
+            format!(
+                "invalid response code pattern '{text}'; expected \
+                 a reply code like '421' or '4xx', or an enhanced \
+                 status code like '4.2.2' or '4.7.x'"
+            )
+        };
+
+        let kind = if text.contains('.') {
+            let mut pattern = [None; 3];
+            let mut parts = text.split('.');
+            for slot in pattern.iter_mut() {
+                if let Some(part) = parts.next() {
+                    *slot = component(part).ok_or_else(invalid)?;
+                }
+            }
+            if parts.next().is_some() {
+                return Err(invalid());
+            }
+            ResponseCodePatternKind::Enhanced(pattern)
+        } else {
+            let mut pattern = [None; 3];
+            let mut chars = text.chars();
+            for slot in pattern.iter_mut() {
+                let c = chars.next().ok_or_else(invalid)?;
+                *slot = component(c.encode_utf8(&mut [0; 4])).ok_or_else(invalid)?;
+            }
+            if chars.next().is_some() {
+                return Err(invalid());
+            }
+            ResponseCodePatternKind::Code(pattern)
+        };
+
+        Ok(Self { text, kind })
+    }
+}
+
+impl From<ResponseCodePattern> for String {
+    fn from(pattern: ResponseCodePattern) -> String {
+        pattern.text
+    }
+}
+
 impl LuaUserData for QueueConfig {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
         config::impl_pairs_and_index(methods);
@@ -101,6 +291,7 @@ impl Default for QueueConfig {
         Self {
             retry_interval: Self::default_retry_interval(),
             max_retry_interval: None,
+            retry_schedule: vec![],
             max_age: Self::default_max_age(),
             egress_pool: None,
             protocol: DeliveryProto::default(),
@@ -155,7 +346,60 @@ impl QueueConfig {
         }
     }
 
+    /// Returns true if any retry_schedule entry needs to know the
+    /// bounce classification of the response in order to match
+    pub fn retry_schedule_uses_classification(&self) -> bool {
+        self.retry_schedule
+            .iter()
+            .any(|sched| !sched.bounce_classification.is_empty())
+    }
+
+    /// Returns the retry_schedule entry that applies to a failure
+    /// with the specified response, or to any failure when the
+    /// response is not known.
+    pub fn select_retry_schedule(
+        &self,
+        response: Option<&Response>,
+        classification: Option<&BounceClass>,
+    ) -> Option<&RetrySchedule> {
+        self.retry_schedule.iter().find(|sched| match response {
+            Some(response) => sched.matches(response, classification),
+            None => sched.is_unconditional(),
+        })
+    }
+
+    /// The shortest interval that might be used between attempts.
+    /// This is used to scale jitter and timer wheel ticks.
+    pub fn min_retry_interval(&self) -> Duration {
+        self.retry_schedule
+            .iter()
+            .flat_map(|sched| sched.intervals.iter().copied())
+            .fold(self.retry_interval, Duration::min)
+    }
+
+    /// Compute the delay for attempt, which is zero-based, using the
+    /// retry_schedule entry that matches the response, falling back
+    /// to exponential backoff if none match.
+    pub fn delay_for_attempt_with_response(
+        &self,
+        attempt: u16,
+        response: Option<&Response>,
+        classification: Option<&BounceClass>,
+    ) -> chrono::Duration {
+        match self.select_retry_schedule(response, classification) {
+            Some(sched) => Self::to_chrono_seconds(sched.delay_for_attempt(attempt).as_secs()),
+            None => self.exponential_delay_for_attempt(attempt),
+        }
+    }
+
+    /// Compute the delay for attempt, which is zero-based, when the
+    /// response is not known, such as when inferring the schedule of
+    /// a message that was loaded from spool.
     pub fn delay_for_attempt(&self, attempt: u16) -> chrono::Duration {
+        self.delay_for_attempt_with_response(attempt, None, None)
+    }
+
+    fn exponential_delay_for_attempt(&self, attempt: u16) -> chrono::Duration {
         let delay = self
             .retry_interval
             .as_secs()
@@ -166,6 +410,10 @@ impl QueueConfig {
             Some(limit) => delay.min(limit),
         };
 
+        Self::to_chrono_seconds(delay)
+    }
+
+    fn to_chrono_seconds(delay: u64) -> chrono::Duration {
         chrono::Duration::try_seconds((delay as i64).min(MAX_CHRONO_SECONDS))
             .expect("seconds to always be <= MAX_CHRONO_SECONDS")
     }
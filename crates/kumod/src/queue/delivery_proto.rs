@@ -1,3 +1,7 @@
+use crate::broker_deliver::amqp::AmqpDeliveryProtocol;
+use crate::broker_deliver::kafka::KafkaDeliveryProtocol;
+use crate::broker_deliver::redact_uri;
+use crate::http_deliver::HttpDeliveryProtocol;
 use crate::queue::LuaDeliveryProtocol;
 use crate::smtp_dispatcher::SmtpProtocol;
 use kumo_api_types::xfer::XferProtocol;
@@ -20,6 +24,15 @@ pub enum DeliveryProto {
     Xfer {
         xfer: XferProtocol,
     },
+    Http {
+        http: HttpDeliveryProtocol,
+    },
+    Kafka {
+        kafka: KafkaDeliveryProtocol,
+    },
+    Amqp {
+        amqp: AmqpDeliveryProtocol,
+    },
     HttpInjectionGenerator,
     DeferredSmtpInjection,
     Null,
@@ -35,6 +48,9 @@ impl DeliveryProto {
             Self::DeferredSmtpInjection { .. } => "defersmtpinject",
             Self::Null { .. } => "null",
             Self::Xfer { .. } => "xfer",
+            Self::Http { .. } => "http",
+            Self::Kafka { .. } => "kafka",
+            Self::Amqp { .. } => "amqp",
         }
     }
 
@@ -48,6 +64,13 @@ impl DeliveryProto {
                 format!("{proto_name}:{maildir_path}")
             }
             Self::Lua { custom_lua } => format!("{proto_name}:{}", custom_lua.constructor),
+            Self::Http { http } => format!("{proto_name}:{}", redact_uri(&http.url)),
+            Self::Kafka { kafka } => {
+                format!("{proto_name}:{}/{}", kafka.bootstrap_servers(), kafka.topic)
+            }
+            Self::Amqp { amqp } => {
+                format!("{proto_name}:{}/{}", redact_uri(&amqp.uri), amqp.exchange)
+            }
             Self::HttpInjectionGenerator => format!("{proto_name}:generator"),
         }
     }
@@ -85,6 +85,9 @@ pub enum InsertReason {
     /// The delivery protocol associated with the queue implicitly via the
     /// scheduled queue configuration changed
     ProtocolChanged,
+    /// The message matched an administrative hold and was
+    /// rescheduled to its release time
+    AdminHold,
 }
 
 #[cfg(test)]
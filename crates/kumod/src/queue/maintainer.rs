@@ -129,7 +129,7 @@ pub async fn maintain_named_queue(q: &QueueHandle) -> anyhow::Result<()> {
                 return Ok(());
             }
 
-            let (messages, next_due_in) = q.queue.pop();
+            let (mut messages, next_due_in) = q.queue.pop();
 
             let now = Instant::now();
 
@@ -139,7 +139,8 @@ pub async fn maintain_named_queue(q: &QueueHandle) -> anyhow::Result<()> {
                 // We do this based on the retry duration; the product default
                 // is a 20m retry duration for which we want to tick once per
                 // minute.
-                // For shorter intervals we scale this accordingly.
+                // For shorter intervals, including those in any retry_schedule,
+                // we scale this accordingly.
                 // To avoid very excessively wakeups for very short or very
                 // long intervals, we clamp to between 1s and 1m.
 
@@ -150,7 +151,7 @@ pub async fn maintain_named_queue(q: &QueueHandle) -> anyhow::Result<()> {
 
                 let queue_config = q.queue_config.borrow();
                 now + queue_config.timerwheel_tick_interval.unwrap_or(
-                    (queue_config.retry_interval / 20)
+                    (queue_config.min_retry_interval() / 20)
                         .max(ONE_SECOND)
                         .min(ONE_MINUTE),
                 )
@@ -163,6 +164,7 @@ pub async fn maintain_named_queue(q: &QueueHandle) -> anyhow::Result<()> {
                 tracing::debug!("{} {} msgs are now ready", q.name, messages.len());
 
                 wait_for_message_batch(&messages).await;
+                sort_by_priority(&mut messages);
 
                 for msg in messages {
                     q.insert_ready(msg, InsertReason::DueTimeWasReached.into(), None)
@@ -173,6 +175,14 @@ pub async fn maintain_named_queue(q: &QueueHandle) -> anyhow::Result<()> {
     }
 }
 
+/// Order a batch of messages that became due at the same time
+/// so that the higher priority messages are placed into the
+/// ready queue first. The sort is stable, so messages of equal
+/// priority retain their scheduled order.
+fn sort_by_priority(messages: &mut [Message]) {
+    messages.sort_by_key(|msg| std::cmp::Reverse(msg.get_priority()));
+}
+
 fn reinsert_ready(msg: Message, queue: &QueueHandle) -> Option<Message> {
     fn remove(q: &FairMutex<HashSet<Message>>, msg: &Message) -> bool {
         q.lock().remove(msg)
@@ -229,7 +239,8 @@ async fn reinsert_batch(messages: Vec<(Message, QueueHandle)>, total_scheduled:
 
     for (_queue_name, entry) in by_queue.drain() {
         let queue = entry.queue.clone();
-        let messages = entry.messages;
+        let mut messages = entry.messages;
+        sort_by_priority(&mut messages);
         QMAINT_RUNTIME
             .spawn("reinsert", async move {
                 for msg in messages {
@@ -301,11 +312,12 @@ async fn reinsert_ready_v2(
     Ok(())
 }
 
-async fn reinsert_batch_v2(messages: Vec<Message>, total_scheduled: usize) {
+async fn reinsert_batch_v2(mut messages: Vec<Message>, total_scheduled: usize) {
     let mut to_shrink = HashMap::new();
     let mut reinserted = 0;
 
     wait_for_message_batch(&messages).await;
+    sort_by_priority(&mut messages);
     for msg in messages {
         reinserted += 1;
         if let Err(err) = reinsert_ready_v2(msg, &mut to_shrink).await {
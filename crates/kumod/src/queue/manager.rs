@@ -1,4 +1,5 @@
 use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
+use crate::message_trace::start_span;
 use crate::queue::insert_context::{InsertContext, InsertReason};
 use crate::queue::maintainer::queue_meta_maintainer;
 use crate::queue::queue::QueueHandle;
@@ -9,6 +10,7 @@ use ::config::{declare_event, load_config};
 use config::SerdeWrappedValue;
 use dashmap::DashMap;
 use kumo_prometheus::declare_metric;
+use kumo_server_common::otel::SpanKind;
 use message::Message;
 use mod_time::TimeDelta;
 use rfc5321::Response;
@@ -92,13 +94,22 @@ impl QueueManager {
         deadline: Option<Instant>,
     ) -> anyhow::Result<()> {
         tracing::trace!("QueueManager::insert {context:?}");
+        let mut span = start_span(&msg, "queue_insert", SpanKind::Internal).await;
+        span.set_attribute("kumo.queue.name", name.to_string());
 
-        let timer = RESOLVE_LATENCY.start_timer();
-        let entry = opt_timeout_at(deadline, Self::resolve(name)).await?;
-        timer.stop_and_record();
+        let result: anyhow::Result<()> = async {
+            let timer = RESOLVE_LATENCY.start_timer();
+            let entry = opt_timeout_at(deadline, Self::resolve(name)).await?;
+            timer.stop_and_record();
 
-        let _timer = INSERT_LATENCY.start_timer();
-        entry.insert(msg, context, deadline).await
+            let _timer = INSERT_LATENCY.start_timer();
+            entry.insert(msg, context, deadline).await
+        }
+        .await;
+        if let Err(err) = &result {
+            span.set_error(format!("{err:#}"));
+        }
+        result
     }
 
     /// Insert message into a queue named `name`, unwinding it in the case
@@ -173,7 +184,7 @@ impl QueueManager {
                     },
                     egress_source: None,
                     egress_pool: None,
-                    relay_disposition: None,
+                    received_report: None,
                     delivery_protocol: None,
                     tls_info: None,
                     source_address: None,
@@ -262,7 +273,7 @@ impl QueueManager {
                                 },
                                 egress_pool: None,
                                 egress_source: None,
-                                relay_disposition: None,
+                                received_report: None,
                                 delivery_protocol: None,
                                 tls_info: None,
                                 source_address: None,
@@ -293,7 +304,7 @@ impl QueueManager {
 
         let queue = QueueManager::resolve(&queue_name).await?;
         queue
-            .requeue_message_internal(msg, increment_attempts, delay, context)
+            .requeue_message_internal(msg, increment_attempts, delay, Some(&response), context)
             .await
     }
 
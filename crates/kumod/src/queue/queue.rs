@@ -1,9 +1,11 @@
 use crate::egress_source::{EgressPool, EgressPoolSourceSelector, SourceInsertResult};
 use crate::http_server::admin_bounce_v1::AdminBounceEntry;
+use crate::http_server::admin_hold_v1::AdminHoldEntry;
 use crate::http_server::admin_rebind_v1::AdminRebindEntry;
 use crate::http_server::admin_suspend_v1::AdminSuspendEntry;
 use crate::http_server::inject_v1::{make_generate_queue_config, GENERATOR_QUEUE_NAME};
 use crate::http_server::queue_name_multi_index::CachedEntry;
+use crate::logging::classify::classify_response;
 use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
 use crate::queue::config::QueueConfig;
 use crate::queue::delivery_proto::DeliveryProto;
@@ -429,6 +431,7 @@ impl Queue {
                         msg,
                         IncrementAttempts::No,
                         delay,
+                        None,
                         context.add(InsertReason::MessageGetQueueNameFailed),
                     )
                     .await
@@ -482,7 +485,7 @@ impl Queue {
                 },
                 egress_pool: None,
                 egress_source: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: None,
                 tls_info: None,
                 source_address: None,
@@ -494,7 +497,7 @@ impl Queue {
         }
 
         if let Err(err) = queue
-            .requeue_message_internal(msg, IncrementAttempts::No, delay, context)
+            .requeue_message_internal(msg, IncrementAttempts::No, delay, None, context)
             .await
         {
             tracing::error!(
@@ -567,7 +570,7 @@ impl Queue {
                 },
                 egress_pool: None,
                 egress_source: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: None,
                 tls_info: None,
                 source_address: None,
@@ -583,6 +586,7 @@ impl Queue {
                 msg,
                 IncrementAttempts::No,
                 Some(chrono::Duration::seconds(0)),
+                None,
                 context,
             )
             .await
@@ -618,6 +622,7 @@ impl Queue {
                 msg,
                 IncrementAttempts::No,
                 Some(chrono::Duration::seconds(0)),
+                None,
                 context,
             )
             .await
@@ -674,7 +679,7 @@ impl Queue {
             },
             egress_pool: None,
             egress_source: None,
-            relay_disposition: None,
+            received_report: None,
             delivery_protocol: None,
             tls_info: None,
             source_address: None,
@@ -689,6 +694,7 @@ impl Queue {
                 msg,
                 IncrementAttempts::No,
                 Some(chrono::Duration::seconds(0)),
+                None,
                 context,
             )
             .await
@@ -743,57 +749,93 @@ impl Queue {
         }
     }
 
-    /// Utility method exposed for the benefit of xfer processing
-    /// in a requeue_message event handler
-    pub async fn increment_attempts_and_update_delay_without_expiry(
+    /// Compute the delay until the next attempt for msg, whose most
+    /// recent attempt failed with response, and increment its
+    /// number of attempts.
+    /// Returns the delay along with the number of seconds of jitter
+    /// that should be added to it.
+    async fn compute_retry_delay(
         &self,
         msg: &Message,
-    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        response: Option<&Response>,
+    ) -> anyhow::Result<(chrono::Duration, i64)> {
+        let uses_classification = self
+            .queue_config
+            .borrow()
+            .retry_schedule_uses_classification();
+        let classification = match response {
+            Some(response) if uses_classification => Some(classify_response(response).await),
+            _ => None,
+        };
+
         // Pre-calculate the delay, prior to incrementing the number of attempts,
         // as the delay_for_attempt uses a zero-based attempt number to figure
-        // the interval
+        // the interval.  The number of attempts counts every prior attempt,
+        // regardless of which retry_schedule entry applied to it, so a
+        // message that switches to a different schedule continues at the
+        // corresponding position within that schedule.
         let num_attempts = msg.get_num_attempts();
-        let delay = self.queue_config.borrow().delay_for_attempt(num_attempts);
+        let (delay, jitter_basis) = {
+            let queue_config = self.queue_config.borrow();
+            (
+                queue_config.delay_for_attempt_with_response(
+                    num_attempts,
+                    response,
+                    classification.as_ref(),
+                ),
+                queue_config.min_retry_interval(),
+            )
+        };
         msg.increment_num_attempts();
 
         // Compute some jitter. The default retry_interval is 20 minutes for
         // which 1 minute is desired. To accomodate different intervals we translate
-        // that to allowing up to 1/20th of the retry_interval as jitter, but we
+        // that to allowing up to 1/20th of the shortest retry interval as jitter, but we
         // cap it to 1 minute so that it doesn't result in excessive divergence
         // for very large intervals.
-        let jitter_magnitude =
-            (self.queue_config.borrow().retry_interval.as_secs_f32() / 20.0).min(60.0);
-        let jitter = (rand::random::<f32>() * jitter_magnitude) - (jitter_magnitude / 2.0);
-        let delay = kumo_chrono_helper::seconds(delay.num_seconds() + jitter as i64)?;
-        msg.delay_by(delay).await
+        // The jitter is centered on the computed delay.
+        let jitter_magnitude = (jitter_basis.as_secs_f32() / 20.0).min(60.0) as i64;
+        Ok((
+            kumo_chrono_helper::seconds(delay.num_seconds() - jitter_magnitude / 2)?,
+            jitter_magnitude,
+        ))
+    }
+
+    /// Increment the number of attempts for msg, and delay it until
+    /// its next attempt.  Returns the time at which it is next due.
+    async fn apply_retry_delay(
+        &self,
+        msg: &Message,
+        response: Option<&Response>,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let (delay, jitter) = self.compute_retry_delay(msg, response).await?;
+        msg.delay_with_jitter(delay, jitter).await
+    }
+
+    /// Utility method exposed for the benefit of xfer processing
+    /// in a requeue_message event handler
+    pub async fn increment_attempts_and_update_delay_without_expiry(
+        &self,
+        msg: &Message,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
+        self.apply_retry_delay(msg, None).await
     }
 
     async fn increment_attempts_and_update_delay(
         &self,
         msg: Message,
+        response: Option<&Response>,
     ) -> anyhow::Result<Option<Message>> {
         let id = *msg.id();
-        // Pre-calculate the delay, prior to incrementing the number of attempts,
-        // as the delay_for_attempt uses a zero-based attempt number to figure
-        // the interval
         let num_attempts = msg.get_num_attempts();
-        let delay = self.queue_config.borrow().delay_for_attempt(num_attempts);
-        msg.increment_num_attempts();
-
-        // Compute some jitter. The default retry_interval is 20 minutes for
-        // which 1 minute is desired. To accomodate different intervals we translate
-        // that to allowing up to 1/20th of the retry_interval as jitter, but we
-        // cap it to 1 minute so that it doesn't result in excessive divergence
-        // for very large intervals.
-        let jitter_magnitude =
-            (self.queue_config.borrow().retry_interval.as_secs_f32() / 20.0).min(60.0);
-        let jitter = (rand::random::<f32>() * jitter_magnitude) - (jitter_magnitude / 2.0);
-        let delay = kumo_chrono_helper::seconds(delay.num_seconds() + jitter as i64)?;
+        let now = Utc::now();
+        let next_due = self.apply_retry_delay(&msg, response).await?;
+        let delay = next_due.map(|due| due - now).unwrap_or_default();
 
         match msg.get_scheduling().await?.and_then(|sched| sched.expires) {
             Some(expires) => {
                 // Per-message expiry
-                match msg.delay_by(delay).await? {
+                match next_due {
                     Some(next_due) => {
                         if next_due >= expires {
                             tracing::debug!(
@@ -820,7 +862,7 @@ impl Queue {
                                 },
                                 egress_pool: self.queue_config.borrow().egress_pool.as_deref(),
                                 egress_source: None,
-                                relay_disposition: None,
+                                received_report: None,
                                 delivery_protocol: None,
                                 tls_info: None,
                                 source_address: None,
@@ -850,7 +892,6 @@ impl Queue {
             None => {
                 // Regular queue based expiry
 
-                let now = Utc::now();
                 let max_age = self.queue_config.borrow().get_max_age();
                 let age = msg.age(now);
                 let delayed_age = age + delay;
@@ -879,7 +920,7 @@ impl Queue {
                         },
                         egress_pool: self.queue_config.borrow().egress_pool.as_deref(),
                         egress_source: None,
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: None,
                         tls_info: None,
                         source_address: None,
@@ -891,7 +932,6 @@ impl Queue {
                     SpoolManager::remove_from_spool(id).await?;
                     return Ok(None);
                 }
-                let next_due = msg.delay_by(delay).await?;
                 tracing::trace!(
                     "increment_attempts_and_update_delay: delaying {id} \
                     by {delay} (num_attempts={num_attempts}), next_due={next_due:?}"
@@ -904,16 +944,22 @@ impl Queue {
 
     /// Performs the raw re-insertion of a message into a scheduled queue.
     /// The requeue_message event is NOT called by this function.
-    #[instrument(skip(self, msg))]
+    /// response is the outcome of the failed attempt, if any, and is
+    /// used to select the retry_schedule when incrementing attempts.
+    #[instrument(skip(self, msg, response))]
     pub async fn requeue_message_internal(
         self: &Arc<Self>,
         msg: Message,
         increment_attempts: IncrementAttempts,
         delay: Option<chrono::Duration>,
+        response: Option<&Response>,
         context: InsertContext,
     ) -> anyhow::Result<()> {
         if increment_attempts == IncrementAttempts::Yes {
-            match self.increment_attempts_and_update_delay(msg).await? {
+            match self
+                .increment_attempts_and_update_delay(msg, response)
+                .await?
+            {
                 Some(msg) => {
                     return self.insert(msg, context, None).await;
                 }
@@ -925,7 +971,7 @@ impl Queue {
         } else if let Some(delay) = delay {
             msg.delay_by(delay).await?;
         } else {
-            msg.delay_with_jitter(60).await?;
+            msg.delay_with_jitter(chrono::Duration::zero(), 60).await?;
         }
 
         if let Some(due) = msg.get_due() {
@@ -952,7 +998,7 @@ impl Queue {
                     },
                     egress_pool: self.queue_config.borrow().egress_pool.as_deref(),
                     egress_source: None,
-                    relay_disposition: None,
+                    received_report: None,
                     delivery_protocol: None,
                     tls_info: None,
                     source_address: None,
@@ -1015,7 +1061,7 @@ impl Queue {
                 // Maybe delay_with_jitter computed an immediate
                 // time? Let's try again
                 InsertResult::Ready(_) => {
-                    msg.delay_with_jitter(60).await?;
+                    msg.delay_with_jitter(chrono::Duration::zero(), 60).await?;
                     continue;
                 }
             }
@@ -1115,7 +1161,7 @@ impl Queue {
                 },
                 egress_source: None,
                 egress_pool: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: None,
                 tls_info: None,
                 source_address: None,
@@ -1167,6 +1213,11 @@ impl Queue {
             return Ok(());
         }
 
+        // Defer held messages until their release time
+        if let Some(hold) = AdminHoldEntry::get_for_queue_name(&self.name) {
+            return hold.hold_message(msg).await;
+        }
+
         // Don't promote to ready queue while suspended
         if let Some(suspend) = AdminSuspendEntry::get_for_queue_name(&self.name) {
             let remaining = suspend.get_duration();
@@ -1194,7 +1245,7 @@ impl Queue {
                 response: response.clone(),
                 egress_source: None,
                 egress_pool: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: None,
                 provider: None,
                 tls_info: None,
@@ -1347,6 +1398,9 @@ impl Queue {
             DeliveryProto::Smtp { .. }
             | DeliveryProto::Lua { .. }
             | DeliveryProto::Xfer { .. }
+            | DeliveryProto::Http { .. }
+            | DeliveryProto::Kafka { .. }
+            | DeliveryProto::Amqp { .. }
             | DeliveryProto::HttpInjectionGenerator => {
                 let source_selector = self.source_selector.load();
                 match source_selector
@@ -1381,7 +1435,7 @@ impl Queue {
                             },
                             egress_pool: Some(&source_selector.name),
                             egress_source: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             tls_info: None,
                             source_address: None,
@@ -1418,7 +1472,7 @@ impl Queue {
                             },
                             egress_pool: Some(&source_selector.name),
                             egress_source: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             tls_info: None,
                             source_address: None,
@@ -1452,7 +1506,7 @@ impl Queue {
                             },
                             egress_pool: None,
                             egress_source: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             tls_info: None,
                             source_address: None,
@@ -1509,7 +1563,7 @@ impl Queue {
                             },
                             egress_pool: None,
                             egress_source: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             tls_info: None,
                             source_address: None,
@@ -1639,7 +1693,7 @@ impl Queue {
                         },
                         egress_pool: None,
                         egress_source: None,
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: Some("Maildir"),
                         tls_info: None,
                         source_address: None,
@@ -1674,7 +1728,7 @@ impl Queue {
                         },
                         egress_pool: None,
                         egress_source: None,
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: Some("Maildir"),
                         tls_info: None,
                         source_address: None,
@@ -188,3 +188,132 @@ fn bigger_delay() {
         ]
     );
 }
+
+fn response(code: u16, enhanced: Option<(u8, u16, u16)>) -> rfc5321::Response {
+    rfc5321::Response {
+        code,
+        enhanced_code: enhanced.map(|(class, subject, detail)| rfc5321::EnhancedStatusCode {
+            class,
+            subject,
+            detail,
+        }),
+        content: "try again later".to_string(),
+        command: None,
+    }
+}
+
+#[test]
+fn response_code_pattern() {
+    use crate::queue::config::ResponseCodePattern;
+
+    let pattern = |s: &str| ResponseCodePattern::try_from(s.to_string()).unwrap();
+
+    assert!(pattern("421").matches(&response(421, None)));
+    assert!(!pattern("421").matches(&response(451, None)));
+    assert!(pattern("4xx").matches(&response(451, None)));
+    assert!(pattern("45*").matches(&response(452, Some((4, 2, 2)))));
+
+    assert!(pattern("4.2.2").matches(&response(452, Some((4, 2, 2)))));
+    assert!(!pattern("4.2.2").matches(&response(452, Some((4, 2, 1)))));
+    assert!(pattern("4.7.x").matches(&response(451, Some((4, 7, 1)))));
+    assert!(pattern("4.7").matches(&response(451, Some((4, 7, 26)))));
+    assert!(!pattern("4.7").matches(&response(451, None)));
+
+    for bad in ["", "4", "4210", "4.2.2.2", "4.y.1", "abc"] {
+        assert!(
+            ResponseCodePattern::try_from(bad.to_string()).is_err(),
+            "{bad} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn retry_schedule() {
+    let config: QueueConfig = serde_json::from_value(serde_json::json!({
+        "retry_interval": "20m",
+        "max_age": "1h",
+        "retry_schedule": [
+            {
+                "response_code": ["4.7.x"],
+                "intervals": ["5m"],
+            },
+            {
+                "response_code": ["4.2.2"],
+                "intervals": ["1h", "4h", "12h"],
+            },
+            {
+                "bounce_classification": ["QuotaIssues"],
+                "intervals": ["2h"],
+            },
+        ]
+    }))
+    .unwrap();
+
+    let greylisted = response(451, Some((4, 7, 1)));
+    let mailbox_full = response(452, Some((4, 2, 2)));
+    let other = response(421, None);
+
+    let delays = |response: &rfc5321::Response, class: Option<&bounce_classify::BounceClass>| {
+        (0..4)
+            .map(|attempt| {
+                config
+                    .delay_for_attempt_with_response(attempt, Some(response), class)
+                    .num_seconds()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(delays(&greylisted, None), vec![300, 300, 300, 300]);
+    assert_eq!(
+        delays(&mailbox_full, None),
+        vec![3600, 4 * 3600, 12 * 3600, 12 * 3600]
+    );
+    // The position within a schedule is the overall attempt count, so
+    // a message that was greylisted twice and is now over quota picks
+    // up the third interval of the quota schedule rather than the first
+    assert_eq!(
+        config
+            .delay_for_attempt_with_response(2, Some(&mailbox_full), None)
+            .num_seconds(),
+        12 * 3600
+    );
+    // No match falls back to exponential backoff
+    assert_eq!(delays(&other, None), vec![1200, 2400, 4800, 9600]);
+
+    let quota = bounce_classify::BounceClass::from("QuotaIssues".to_string());
+    assert_eq!(delays(&other, Some(&quota)), vec![7200, 7200, 7200, 7200]);
+
+    // There is no unconditional schedule, so the schedule for a
+    // message loaded from spool is the exponential backoff
+    assert_eq!(compute_schedule(&config), vec![1200]);
+    assert_eq!(config.min_retry_interval(), Duration::from_secs(300));
+
+    let empty: Result<QueueConfig, _> = serde_json::from_value(serde_json::json!({
+        "retry_schedule": [{"intervals": []}]
+    }));
+    assert!(format!("{:#}", empty.unwrap_err()).contains("must not be empty"));
+}
+
+#[test]
+fn unconditional_retry_schedule() {
+    let config: QueueConfig = serde_json::from_value(serde_json::json!({
+        "max_age": "1d",
+        "retry_schedule": [
+            {
+                "intervals": ["10m", "30m", "2h", "6h"],
+            },
+        ]
+    }))
+    .unwrap();
+
+    assert_eq!(
+        compute_schedule(&config),
+        vec![600, 1800, 7200, 21600, 21600, 21600]
+    );
+    assert_eq!(
+        config
+            .delay_for_attempt_with_response(1, Some(&response(421, None)), None)
+            .num_seconds(),
+        1800
+    );
+}
@@ -1,6 +1,10 @@
+use crate::broker_deliver::amqp::AmqpQueueDispatcher;
+use crate::broker_deliver::kafka::KafkaQueueDispatcher;
 use crate::delivery_metrics::{DeliveryMetrics, ReadyCountBundle};
 use crate::egress_source::EgressSource;
+use crate::http_deliver::HttpQueueDispatcher;
 use crate::http_server::admin_bounce_v1::AdminBounceEntry;
+use crate::http_server::admin_hold_v1::AdminHoldEntry;
 use crate::http_server::admin_suspend_ready_q_v1::{
     AdminSuspendReadyQEntry, AdminSuspendReadyQEntryRef,
 };
@@ -9,6 +13,7 @@ use crate::http_server::inject_v1::HttpInjectionGeneratorDispatcher;
 use crate::http_server::queue_name_multi_index::CachedEntry;
 use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
 use crate::lua_deliver::LuaQueueDispatcher;
+use crate::message_trace::start_span_at;
 use crate::metrics_helper::TOTAL_READYQ_RUNS;
 use crate::queue::{
     DeliveryProto, IncrementAttempts, InsertContext, InsertReason, Queue, QueueConfig,
@@ -30,6 +35,7 @@ use kumo_api_types::egress_path::{
 };
 use kumo_prometheus::declare_metric;
 use kumo_server_common::config_handle::ConfigHandle;
+use kumo_server_common::otel::SpanKind;
 use kumo_server_lifecycle::{is_shutting_down, Activity, ShutdownSubcription, ShuttingDownError};
 use kumo_server_memory::{
     get_headroom, memory_status, subscribe_to_memory_status_changes_async, MemoryStatus,
@@ -78,6 +84,9 @@ static INSERT_LATENCY: Histogram("ready_queue_insert_latency");
 }
 
 const ONE_MINUTE: Duration = Duration::from_secs(60);
+/// How many consecutive times a non-empty lower priority lane
+/// may be passed over before it is given a turn
+const PRIORITY_STARVATION_LIMIT: usize = 8;
 const AGE_OUT_INTERVAL: Duration = Duration::from_secs(10 * 60);
 static READYQ_THREADS: AtomicUsize = AtomicUsize::new(0);
 
@@ -139,8 +148,111 @@ impl FifoReservation {
     }
 }
 
+struct PriorityLane {
+    priority: i8,
+    messages: MessageList,
+    /// How many messages have been dispatched from higher
+    /// priority lanes while this lane had messages waiting
+    bypassed: usize,
+}
+
+/// The contents of a ready queue, partitioned by message priority.
+/// Messages are taken from the highest priority lane first, except
+/// that a lane that has been bypassed PRIORITY_STARVATION_LIMIT times
+/// is given a turn, so that bulk mail continues to make progress while
+/// there is a sustained supply of higher priority mail.
+#[derive(Default)]
+struct PriorityLanes {
+    /// Ordered by descending priority. Empty lanes are removed,
+    /// so this typically holds a single lane.
+    lanes: Vec<PriorityLane>,
+    len: usize,
+}
+
+impl PriorityLanes {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push_back(&mut self, msg: Message) {
+        let priority = msg.get_priority();
+        let idx = match self
+            .lanes
+            .binary_search_by(|lane| priority.cmp(&lane.priority))
+        {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.lanes.insert(
+                    idx,
+                    PriorityLane {
+                        priority,
+                        messages: MessageList::new(),
+                        bypassed: 0,
+                    },
+                );
+                idx
+            }
+        };
+        self.lanes[idx].messages.push_back(msg);
+        self.len += 1;
+    }
+
+    fn pop_front(&mut self) -> Option<Message> {
+        if self.lanes.is_empty() {
+            return None;
+        }
+        let idx = self
+            .lanes
+            .iter()
+            .position(|lane| lane.bypassed >= PRIORITY_STARVATION_LIMIT)
+            .unwrap_or(0);
+        for lane in &mut self.lanes[idx + 1..] {
+            lane.bypassed += 1;
+        }
+        self.lanes[idx].bypassed = 0;
+        self.take_from_lane(idx)
+    }
+
+    /// Take from the lowest priority lane; used to shed
+    /// excess messages when the capacity is reduced
+    fn pop_back(&mut self) -> Option<Message> {
+        let idx = self.lanes.len().checked_sub(1)?;
+        let lane = &mut self.lanes[idx];
+        let msg = lane.messages.pop_back()?;
+        if lane.messages.is_empty() {
+            self.lanes.remove(idx);
+        }
+        self.len -= 1;
+        Some(msg)
+    }
+
+    fn take_from_lane(&mut self, idx: usize) -> Option<Message> {
+        let lane = &mut self.lanes[idx];
+        let msg = lane.messages.pop_front()?;
+        if lane.messages.is_empty() {
+            self.lanes.remove(idx);
+        }
+        self.len -= 1;
+        Some(msg)
+    }
+
+    /// Take all of the messages, in descending priority order
+    fn take(&mut self) -> MessageList {
+        let mut lanes = std::mem::take(&mut self.lanes);
+        self.len = 0;
+        if lanes.len() == 1 {
+            return lanes.pop().expect("one lane").messages;
+        }
+        let mut result = MessageList::new();
+        for lane in lanes {
+            result.extend_from_iter(lane.messages.into_iter());
+        }
+        result
+    }
+}
+
 pub struct Fifo {
-    list: FairMutex<MessageList>,
+    list: FairMutex<PriorityLanes>,
     count: ReadyCountBundle,
     capacity: AtomicUsize,
     num_reserved: AtomicUsize,
@@ -151,7 +263,7 @@ impl Fifo {
     pub fn new(capacity: usize, count: ReadyCountBundle) -> Self {
         Self {
             count,
-            list: FairMutex::new(MessageList::new()),
+            list: FairMutex::new(PriorityLanes::default()),
             capacity: AtomicUsize::new(capacity),
             num_reserved: AtomicUsize::new(0),
             closed: AtomicBool::new(false),
@@ -1420,6 +1532,9 @@ impl Dispatcher {
             DeliveryProto::Smtp { .. } => "ESMTP".to_string(),
             DeliveryProto::Lua { .. } => "Lua".to_string(),
             DeliveryProto::Xfer { .. } => "Xfer".to_string(),
+            DeliveryProto::Http { .. } => "Http".to_string(),
+            DeliveryProto::Kafka { .. } => "Kafka".to_string(),
+            DeliveryProto::Amqp { .. } => "Amqp".to_string(),
             DeliveryProto::Maildir { .. } => "Maildir".to_string(),
             DeliveryProto::DeferredSmtpInjection => "DeferredSmtpInjection".to_string(),
             DeliveryProto::HttpInjectionGenerator => "HttpInjectionGenerator".to_string(),
@@ -1477,6 +1592,9 @@ impl Dispatcher {
             DeliveryProto::Xfer { xfer } => {
                 Box::new(XferDispatcher::init(&mut dispatcher, xfer).await?)
             }
+            DeliveryProto::Http { http } => Box::new(HttpQueueDispatcher::new(http.clone())),
+            DeliveryProto::Kafka { kafka } => Box::new(KafkaQueueDispatcher::new(kafka.clone())),
+            DeliveryProto::Amqp { amqp } => Box::new(AmqpQueueDispatcher::new(amqp.clone())),
             DeliveryProto::Null => {
                 anyhow::bail!("Should not have a ready_queue for the null queue")
             }
@@ -1573,7 +1691,7 @@ impl Dispatcher {
                                 response: response.clone(),
                                 egress_pool: Some(&dispatcher.egress_pool),
                                 egress_source: Some(&dispatcher.egress_source.name),
-                                relay_disposition: None,
+                                received_report: None,
                                 delivery_protocol: Some(&dispatcher.delivery_protocol),
                                 tls_info: None,
                                 source_address: None,
@@ -1661,7 +1779,7 @@ impl Dispatcher {
                         response: response.clone(),
                         egress_pool: Some(&dispatcher.egress_pool),
                         egress_source: Some(&dispatcher.egress_source.name),
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: Some(&dispatcher.delivery_protocol),
                         tls_info: None,
                         source_address: None,
@@ -1922,7 +2040,7 @@ impl Dispatcher {
                 response: response.clone(),
                 egress_pool: Some(&self.egress_pool),
                 egress_source: Some(&self.egress_source.name),
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: None,
                 tls_info: None,
                 source_address: None,
@@ -2003,6 +2121,10 @@ impl Dispatcher {
                         SpoolManager::remove_from_spool(*msg.id()).await.ok();
                         continue;
                     }
+                    if let Some(hold) = AdminHoldEntry::get_for_queue_name(&queue_name) {
+                        hold.hold_message(msg).await.ok();
+                        continue;
+                    }
                     if let Some(suspend) = AdminSuspendEntry::get_for_queue_name(&queue_name) {
                         let response = rfc5321::Response {
                             code: 451,
@@ -2025,7 +2147,7 @@ impl Dispatcher {
                             response: response.clone(),
                             egress_source: None,
                             egress_pool: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             provider: None,
                             tls_info: None,
@@ -2047,6 +2169,17 @@ impl Dispatcher {
                         continue;
                     }
                 }
+                // The message has been waiting since it became due
+                let created = msg.id().created();
+                let waited_since = msg.get_due().unwrap_or(created).max(created);
+                let mut wait_span = start_span_at(
+                    &msg,
+                    "ready_queue_wait",
+                    SpanKind::Internal,
+                    waited_since.into(),
+                )
+                .await;
+                wait_span.set_attribute("kumo.ready_queue.name", self.name.clone());
                 self.msgs.push(msg);
             } else {
                 break;
@@ -2252,6 +2385,73 @@ pub fn ideal_connection_count(queue_size: usize, connection_limit: usize) -> usi
 #[cfg(test)]
 mod test {
     use super::*;
+    use message::EnvelopeAddress;
+    use spool::SpoolId;
+
+    async fn new_msg(priority: i8) -> Message {
+        let msg = Message::new_dirty(
+            SpoolId::new(),
+            EnvelopeAddress::parse("sender@example.com").unwrap(),
+            vec![EnvelopeAddress::parse("recip@example.com").unwrap()],
+            serde_json::json!({}),
+            Arc::new(b"Subject: hello\r\n\r\nwoot".to_vec().into_boxed_slice()),
+        )
+        .unwrap();
+        msg.set_priority(priority).await.unwrap();
+        msg
+    }
+
+    #[tokio::test]
+    async fn priority_lanes() {
+        let mut lanes = PriorityLanes::default();
+        let mut bulk = vec![];
+        for _ in 0..3 {
+            let msg = new_msg(0).await;
+            bulk.push(*msg.id());
+            lanes.push_back(msg);
+        }
+        let mut urgent = vec![];
+        for _ in 0..(PRIORITY_STARVATION_LIMIT * 2) {
+            let msg = new_msg(10).await;
+            urgent.push(*msg.id());
+            lanes.push_back(msg);
+        }
+        let low = new_msg(-5).await;
+        let low_id = *low.id();
+        lanes.push_back(low);
+        assert_eq!(lanes.len(), PRIORITY_STARVATION_LIMIT * 2 + 4);
+
+        let mut order = vec![];
+        while let Some(msg) = lanes.pop_front() {
+            order.push(*msg.id());
+        }
+        assert_eq!(lanes.len(), 0);
+        assert!(lanes.lanes.is_empty());
+
+        // Urgent messages go first, but bulk gets a turn after
+        // being bypassed PRIORITY_STARVATION_LIMIT times
+        let mut expect = urgent[0..PRIORITY_STARVATION_LIMIT].to_vec();
+        expect.push(bulk[0]);
+        expect.push(low_id);
+        expect.extend_from_slice(&urgent[PRIORITY_STARVATION_LIMIT..]);
+        expect.extend_from_slice(&bulk[1..]);
+        assert_eq!(order, expect);
+    }
+
+    #[tokio::test]
+    async fn priority_lanes_shed_lowest_first() {
+        let mut lanes = PriorityLanes::default();
+        let high = new_msg(1).await;
+        let high_id = *high.id();
+        let normal = new_msg(0).await;
+        let normal_id = *normal.id();
+        lanes.push_back(normal);
+        lanes.push_back(high);
+
+        assert_eq!(*lanes.pop_back().unwrap().id(), normal_id);
+        let remaining: Vec<SpoolId> = lanes.take().into_iter().map(|m| *m.id()).collect();
+        assert_eq!(remaining, vec![high_id]);
+    }
 
     fn compute_targets_for_limit(max_connections: usize) -> Vec<(usize, usize)> {
         let sizes = [
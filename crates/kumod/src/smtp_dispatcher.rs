@@ -3,9 +3,11 @@ use crate::http_server::admin_trace_smtp_client_v1::{
     SmtpClientTraceEventPayload, SmtpClientTracerImpl,
 };
 use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
+use crate::message_trace::start_span;
 use crate::queue::{IncrementAttempts, InsertReason, QueueManager, QueueState};
 use crate::ready_queue::{AttemptConnectionDisposition, Dispatcher, QueueDispatcher};
 use crate::spool::SpoolManager;
+use crate::tls_reporting::TlsReportingSession;
 use anyhow::Context;
 use async_trait::async_trait;
 use bounce_classify::{BounceClass, PreDefinedBounceClass};
@@ -13,16 +15,18 @@ use config::{load_config, CallbackSignature};
 use data_loader::KeySource;
 use dns_resolver::{has_colon_port, resolve_a_or_aaaa, IpLookupStrategy, ResolvedMxAddresses};
 use kumo_address::socket::SocketAddress;
-use kumo_api_types::egress_path::{EgressPathConfig, ReconnectStrategy, Tls};
+use kumo_api_types::egress_path::{EgressPathConfig, ReconnectStrategy, SmtpAuthMechanism, Tls};
 use kumo_log_types::{MaybeProxiedSourceAddress, ResolvedAddress};
+use kumo_server_common::otel::{SpanKind, TraceSpan};
 use kumo_server_lifecycle::{ShutdownSubcription, ShuttingDownError};
 use kumo_server_runtime::spawn;
 use message::message::QueueNameComponents;
 use message::Message;
 use mta_sts::policy::PolicyMode;
+use mta_sts::tlsrpt::{PolicyType, ResultType};
 use rfc5321::{
-    ClientError, EnhancedStatusCode, ForwardPath, IsTooManyRecipients, Response, ReversePath,
-    SmtpClient, TlsInformation, TlsOptions, TlsStatus,
+    ClientError, EnhancedStatusCode, EsmtpParameter, ForwardPath, IsTooManyRecipients, Response,
+    ReversePath, SmtpClient, TlsInformation, TlsOptions, TlsStatus,
 };
 use serde::{Deserialize, Serialize};
 use spool::SpoolId;
@@ -114,6 +118,13 @@ pub struct SmtpDispatcher {
     source_address: Option<MaybeProxiedSourceAddress>,
     ehlo_name: String,
     tls_info: Option<TlsInformation>,
+    /// true if the current connection is using TLS that was
+    /// validated via MTA-STS or DANE, making it eligible to
+    /// carry RFC 8689 REQUIRETLS messages
+    tls_validated_by_policy: bool,
+    /// The reasons that the hosts tried so far could not carry
+    /// the current RFC 8689 REQUIRETLS message
+    require_tls_failures: Vec<String>,
     tracer: Arc<SmtpClientTracerImpl>,
     site_has_broken_tls: bool,
     terminated_ok: bool,
@@ -289,6 +300,8 @@ impl SmtpDispatcher {
             client_address: None,
             ehlo_name,
             tls_info: None,
+            tls_validated_by_policy: false,
+            require_tls_failures: vec![],
             source_address: None,
             tracer,
             site_has_broken_tls: false,
@@ -449,6 +462,7 @@ impl SmtpDispatcher {
         let ehlo_name = self.ehlo_name.to_string();
         let mx_host = address.name.to_string();
         let mut enable_tls = path_config.enable_tls;
+        self.tls_validated_by_policy = false;
         let port = dispatcher
             .egress_source
             .remote_port
@@ -477,6 +491,7 @@ impl SmtpDispatcher {
             let tracer = self.tracer.clone();
             let enable_rset = path_config.enable_rset;
             let enable_pipelining = path_config.enable_pipelining;
+            let enable_chunking = path_config.enable_chunking;
 
             // We need to spawn the connection attempt into another task,
             // otherwise the select! invocation below won't run it in parallel with
@@ -527,6 +542,7 @@ impl SmtpDispatcher {
 
                 client.set_tracer(tracer);
                 client.set_enable_pipelining(enable_pipelining);
+                client.set_enable_chunking(enable_chunking);
                 client.set_enable_rset(enable_rset);
 
                 // Read banner
@@ -567,6 +583,7 @@ impl SmtpDispatcher {
 
         let mut dane_tlsa = vec![];
         let mut mta_sts_eligible = true;
+        let mut tls_required_by_policy = false;
 
         let mut certificate_from_pem = None;
         let mut private_key_from_pem = None;
@@ -584,6 +601,13 @@ impl SmtpDispatcher {
         let openssl_cipher_suites = path_config.openssl_cipher_suites.clone();
         let rustls_cipher_suites = path_config.rustls_cipher_suites.clone();
 
+        let mut tls_report = TlsReportingSession::new(
+            dispatcher.mx.as_ref().map(|mx| mx.domain_name.as_str()),
+            dispatcher.egress_source.source_address,
+            &mx_host,
+            address.addr.ip(),
+        );
+
         if path_config.enable_dane {
             if let Some(mx) = &dispatcher.mx {
                 match dns_resolver::resolve_dane(&mx.domain_name, port).await {
@@ -594,6 +618,12 @@ impl SmtpDispatcher {
                         });
                         if !dane_tlsa.is_empty() {
                             enable_tls = Tls::Required;
+                            tls_required_by_policy = true;
+                            tls_report.set_policy(
+                                PolicyType::Tlsa,
+                                dane_tlsa.iter().map(|tlsa| tlsa.to_string()).collect(),
+                                vec![],
+                            );
                             // Do not use MTA-STS when there are DANE results
                             mta_sts_eligible = false;
                         }
@@ -605,6 +635,7 @@ impl SmtpDispatcher {
                             format!("DANE resolve error for {}: {err:#}", mx.domain_name)
                         });
                         tracing::error!("DANE result for {}: {err:#}", mx.domain_name);
+                        tls_report.failure(ResultType::DnssecInvalid, Some(format!("{err:#}")));
                         // TODO: should we prevent continuing in the clear here? probably
                     }
                 }
@@ -629,10 +660,22 @@ impl SmtpDispatcher {
                             format!("MTA-STS policy for {} is {:?}", mx.domain_name, policy.mode)
                         });
 
+                        if policy.mode != PolicyMode::None {
+                            tls_report.set_policy(
+                                PolicyType::Sts,
+                                policy.to_policy_strings(),
+                                policy.mx.clone(),
+                            );
+                        }
+
                         match policy.mode {
                             PolicyMode::Enforce => {
                                 enable_tls = Tls::Required;
                                 if !policy.mx_name_matches(&address.name) {
+                                    tls_report.failure(
+                                        ResultType::ValidationFailure,
+                                        Some("mx-host-mismatch".to_string()),
+                                    );
                                     anyhow::bail!(
                                         "MTA-STS policy for {domain} is set to \
                                      enforce but the current MX candidate \
@@ -642,6 +685,7 @@ impl SmtpDispatcher {
                                         mx_host = address.name
                                     );
                                 }
+                                tls_required_by_policy = true;
                             }
                             PolicyMode::Testing => {
                                 enable_tls = Tls::OpportunisticInsecure;
@@ -692,6 +736,7 @@ impl SmtpDispatcher {
 
         let tls_enabled = match (enable_tls, has_tls, broken_tls) {
             (Tls::Required | Tls::RequiredInsecure, AdvTls::No, _) => {
+                tls_report.failure(ResultType::StarttlsNotSupported, None);
                 anyhow::bail!("tls policy is {enable_tls:?} but STARTTLS is not advertised by {address:?}:{port}");
             }
             (Tls::Disabled, _, _) => {
@@ -704,6 +749,7 @@ impl SmtpDispatcher {
             }
             (Tls::Opportunistic | Tls::OpportunisticInsecure, AdvTls::No, _) => {
                 // TLS is not advertised, don't try to use it
+                tls_report.failure(ResultType::StarttlsNotSupported, None);
                 false
             }
             (Tls::OpportunisticInsecure, AdvTls::Yes, BrokenTls::No) => {
@@ -720,9 +766,11 @@ impl SmtpDispatcher {
                         openssl_cipher_suites,
                         rustls_cipher_suites,
                     })
-                    .await?
+                    .await
+                    .inspect_err(|err| tls_report.starttls_error(err))?
                 {
                     TlsStatus::FailedHandshake(handshake_error) => {
+                        tls_report.handshake_failure(&handshake_error.to_string());
                         tracing::debug!(
                             "TLS handshake with {address}:{port} failed: \
                         {handshake_error}, but continuing in clear text because \
@@ -746,6 +794,7 @@ impl SmtpDispatcher {
                     }
                     TlsStatus::Info(info) => {
                         // TLS is available
+                        tls_report.success();
                         tracing::trace!("TLS: {info:?}");
                         self.tls_info.replace(info);
                         (true, "OK".to_string())
@@ -797,9 +846,11 @@ impl SmtpDispatcher {
                         openssl_cipher_suites,
                         rustls_cipher_suites,
                     })
-                    .await?
+                    .await
+                    .inspect_err(|err| tls_report.starttls_error(err))?
                 {
                     TlsStatus::FailedHandshake(handshake_error) => {
+                        tls_report.handshake_failure(&handshake_error.to_string());
                         self.remember_broken_tls(&dispatcher.name, &path_config)
                             .await;
 
@@ -827,10 +878,13 @@ impl SmtpDispatcher {
                         );
                     }
                     TlsStatus::Info(info) => {
+                        tls_report.success();
                         self.tracer
                             .diagnostic(Level::INFO, || format!("TLS: {info:?}"));
                         tracing::trace!("TLS: {info:?}");
                         self.tls_info.replace(info);
+                        self.tls_validated_by_policy =
+                            tls_required_by_policy && !enable_tls.allow_insecure();
                     }
                 }
 
@@ -860,9 +914,17 @@ impl SmtpDispatcher {
         };
 
         if let Some(username) = &path_config.smtp_auth_plain_username {
+            let mechanism = path_config.smtp_auth_mechanism;
+            let mech_name = match mechanism {
+                SmtpAuthMechanism::Plain => "PLAIN",
+                SmtpAuthMechanism::Login => "LOGIN",
+                SmtpAuthMechanism::CramMd5 => "CRAM-MD5",
+                SmtpAuthMechanism::XOAuth2 => "XOAUTH2",
+            };
+
             if !tls_enabled && !path_config.allow_smtp_auth_plain_without_tls {
                 anyhow::bail!(
-                    "TLS is not enabled and AUTH PLAIN is required. Skipping ({address:?}:{port})"
+                    "TLS is not enabled and AUTH {mech_name} is required. Skipping ({address:?}:{port})"
                 );
             }
 
@@ -879,14 +941,27 @@ impl SmtpDispatcher {
                 None
             };
 
-            client
-                .auth_plain(username, password.as_deref())
-                .await
-                .with_context(|| {
-                    format!(
-                        "authenticating as {username} via SMTP AUTH PLAIN to {address:?}:{port}"
-                    )
-                })?;
+            let result = match mechanism {
+                SmtpAuthMechanism::Plain => client.auth_plain(username, password.as_deref()).await,
+                SmtpAuthMechanism::Login => client.auth_login(username, password.as_deref()).await,
+                SmtpAuthMechanism::CramMd5 => {
+                    client.auth_cram_md5(username, password.as_deref()).await
+                }
+                SmtpAuthMechanism::XOAuth2 => {
+                    let token = password.as_deref().ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "smtp_auth_plain_password must be set to the OAuth2 \
+                            access token when using XOAuth2"
+                        )
+                    })?;
+                    client.auth_xoauth2(username, token).await
+                }
+            };
+            result.with_context(|| {
+                format!(
+                    "authenticating as {username} via SMTP AUTH {mech_name} to {address:?}:{port}"
+                )
+            })?;
         }
 
         self.client
@@ -955,7 +1030,7 @@ impl SmtpDispatcher {
             peer_address: self.client_address.as_ref(),
             egress_pool: Some(&dispatcher.egress_pool),
             egress_source: Some(&dispatcher.egress_source.name),
-            relay_disposition: None,
+            received_report: None,
             delivery_protocol: Some(&dispatcher.delivery_protocol),
             tls_info: self.tls_info.as_ref(),
             source_address: self.source_address.clone(),
@@ -1023,12 +1098,34 @@ impl QueueDispatcher for SmtpDispatcher {
         &mut self,
         dispatcher: &mut Dispatcher,
     ) -> anyhow::Result<AttemptConnectionDisposition> {
-        self.attempt_connection_impl(dispatcher)
-            .await
-            .map_err(|err| {
+        // Only trace the establishment of new connections
+        let is_connected = self
+            .client
+            .as_ref()
+            .map(|client| client.is_connected())
+            .unwrap_or(false);
+        let mut span = match dispatcher.msgs.first() {
+            Some(msg) if !is_connected => {
+                start_span(msg, "smtp_client_connection", SpanKind::Client).await
+            }
+            _ => TraceSpan::disabled(),
+        };
+        span.set_attribute("kumo.site", dispatcher.name.clone());
+
+        let result = self.attempt_connection_impl(dispatcher).await;
+        match &result {
+            Ok(_) => {
+                if let Some(address) = &self.client_address {
+                    span.set_attribute("server.address", address.name.clone());
+                    span.set_attribute("network.peer.address", address.addr.to_string());
+                }
+            }
+            Err(err) => {
                 self.tracer.diagnostic(Level::ERROR, || format!("{err:#}"));
-                err
-            })
+                span.set_error(format!("{err:#}"));
+            }
+        }
+        result
     }
 
     async fn have_more_connection_candidates(&mut self, _dispatcher: &mut Dispatcher) -> bool {
@@ -1057,9 +1154,105 @@ impl QueueDispatcher for SmtpDispatcher {
             .await?
             .try_into()
             .map_err(|err| anyhow::anyhow!("{err}"))?;
+        // Relay any RFC 3461 parameters if the next hop supports them.
+        // If it does, then it becomes responsible for generating any
+        // success DSNs, so we record that alongside the parameters.
+        let next_hop_supports_dsn = self
+            .client
+            .as_ref()
+            .map(|client| client.capabilities().contains_key("DSN"))
+            .unwrap_or(false);
+        let mut dsn = msg.get_dsn_params().await?;
+        if let Some(dsn) = &mut dsn {
+            if dsn.relayed != next_hop_supports_dsn {
+                dsn.relayed = next_hop_supports_dsn;
+                msg.set_dsn_params(dsn).await?;
+            }
+        }
+        let dsn = dsn.filter(|_| next_hop_supports_dsn);
+
+        let mut mail_from_params = vec![];
+        let mut dsn_rcpt_to_params = HashMap::new();
+        if let Some(dsn) = &dsn {
+            mail_from_params = dsn
+                .to_mail_from_parameters()
+                .map_err(|err| anyhow::anyhow!("{err}"))?;
+        }
+
+        // RFC 8689: a REQUIRETLS message may only be relayed over TLS
+        // that was validated via MTA-STS or DANE, to a next hop that
+        // itself supports REQUIRETLS.  We must not fall back to
+        // opportunistic TLS or clear text for it.
+        // A host that cannot satisfy this is treated in the same way
+        // as one that we failed to connect to: we move on to the next
+        // candidate host, and bounce only once they have all failed.
+        let require_tls = msg.get_require_tls().await?;
+        let mut require_tls_failure = None;
+        if require_tls {
+            let next_hop_supports_require_tls = self
+                .client
+                .as_ref()
+                .map(|client| client.capabilities().contains_key("REQUIRETLS"))
+                .unwrap_or(false);
+            if !self.tls_validated_by_policy {
+                require_tls_failure.replace(format!(
+                    "connection to {:?} is not using TLS validated via MTA-STS or DANE",
+                    self.client_address
+                ));
+            } else if !next_hop_supports_require_tls {
+                require_tls_failure.replace(format!(
+                    "{:?} does not support REQUIRETLS",
+                    self.client_address
+                ));
+            } else {
+                mail_from_params.push(EsmtpParameter {
+                    name: "REQUIRETLS".to_string(),
+                    value: None,
+                });
+            }
+        }
+        match require_tls_failure.take() {
+            Some(reason) => {
+                tracing::debug!("REQUIRETLS: {reason}");
+                self.require_tls_failures.push(reason);
+                if !self.addresses.is_empty() {
+                    // Leave the message in the dispatcher so that it
+                    // is attempted on the connection to the next host
+                    if let Some(mut client) = self.client.take() {
+                        client.send_command(&rfc5321::Command::Quit).await.ok();
+                    }
+                    return Ok(());
+                }
+                require_tls_failure
+                    .replace(std::mem::take(&mut self.require_tls_failures).join(", "));
+            }
+            None => self.require_tls_failures.clear(),
+        }
+
+        // Content received as BINARYMIME must be relayed as such;
+        // the client will refuse to send it to a peer that cannot
+        // accept it via BDAT
+        if msg.get_binary_mime().await? {
+            mail_from_params.push(EsmtpParameter {
+                name: "BODY".to_string(),
+                value: Some("BINARYMIME".to_string()),
+            });
+        }
+
         let mut recipients: Vec<ForwardPath> = vec![];
         for recip in msg.recipient_list().await? {
+            let recip_dsn = dsn
+                .as_ref()
+                .and_then(|dsn| dsn.recipient(&recip.to_string()));
             let recip: ForwardPath = recip.try_into().map_err(|err| anyhow::anyhow!("{err:#}"))?;
+            if let Some(recip_dsn) = recip_dsn {
+                dsn_rcpt_to_params.insert(
+                    recip.clone(),
+                    recip_dsn
+                        .to_rcpt_to_parameters()
+                        .map_err(|err| anyhow::anyhow!("{err}"))?,
+                );
+            }
             recips_this_txn.insert(
                 (spool_id, recip.clone()),
                 1 + self
@@ -1128,12 +1321,47 @@ impl QueueDispatcher for SmtpDispatcher {
             }
         }
 
-        let send_result = self
-            .client
-            .as_mut()
-            .unwrap()
-            .send_mail_multi_recip(sender, recipients_this_batch.clone(), &*data)
-            .await;
+        let mut txn_span = start_span(&msg, "smtp_client_transaction", SpanKind::Client).await;
+        let send_result = match require_tls_failure {
+            Some(reason) => Err(ClientError::Rejected(Response {
+                code: 550,
+                enhanced_code: Some(EnhancedStatusCode {
+                    class: 5,
+                    subject: 7,
+                    detail: 30,
+                }),
+                content: format!("KumoMTA internal: REQUIRETLS support required: {reason}"),
+                command: None,
+            })),
+            None => {
+                self.client
+                    .as_mut()
+                    .unwrap()
+                    .send_mail_multi_recip_with_params(
+                        sender,
+                        mail_from_params,
+                        recipients_this_batch
+                            .iter()
+                            .map(|recip| {
+                                (
+                                    recip.clone(),
+                                    dsn_rcpt_to_params.get(recip).cloned().unwrap_or_default(),
+                                )
+                            })
+                            .collect(),
+                        &*data,
+                    )
+                    .await
+            }
+        };
+
+        match &send_result {
+            Ok(status) => {
+                txn_span.set_attribute("smtp.response.code", i64::from(status.response.code))
+            }
+            Err(err) => txn_span.set_error(format!("{err:#}")),
+        }
+        drop(txn_span);
 
         let mut result_per_rcpt = vec![];
         let mut rewrite_eligible = false;
@@ -2,8 +2,9 @@ use crate::delivery_metrics::MetricsWrappedConnection;
 use crate::http_server::admin_trace_smtp_server_v1::{
     SmtpServerTraceEvent, SmtpServerTraceEventPayload, SmtpServerTraceManager,
 };
-use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
+use crate::logging::disposition::{log_disposition, LogDisposition, ReceivedReport, RecordType};
 use crate::logging::rejection::{log_rejection, LogRejection};
+use crate::message_trace::{start_reception, start_span};
 use crate::metrics_helper::smtp_rejected_for_service;
 use crate::queue::{DeliveryProto, IncrementAttempts, InsertReason, QueueConfig, QueueManager};
 use crate::ready_queue::{AttemptConnectionDisposition, Dispatcher, QueueDispatcher};
@@ -24,6 +25,7 @@ use kumo_prometheus::{declare_metric, AtomicCounter};
 use kumo_server_common::acct::{log_authn, AuthnAuditRecord};
 use kumo_server_common::authn_authz::{AuthInfo, Identity, IdentityContext};
 use kumo_server_common::http_server::auth::AuthKindResult;
+use kumo_server_common::otel::SpanKind;
 use kumo_server_lifecycle::{Activity, ShutdownSubcription, ShuttingDownError};
 use kumo_server_runtime::{spawn, Runtime};
 use lruttl::declare_cache;
@@ -36,8 +38,8 @@ use openssl::x509::X509;
 use parking_lot::FairMutex as Mutex;
 use ppp::{HeaderResult, PartialResult};
 use rfc5321::{
-    subject_name, AsyncReadAndWrite, BoxedAsyncReadAndWrite, Command, Response, TlsInformation,
-    XClientParameter,
+    subject_name, AsyncReadAndWrite, BoxedAsyncReadAndWrite, Command, DsnParams, RecipientDsn,
+    Response, TlsInformation, XClientParameter,
 };
 use rustls::ServerConfig;
 use serde::{Deserialize, Serialize};
@@ -46,12 +48,13 @@ use spool::SpoolId;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::net::{IpAddr, SocketAddr};
+use std::os::unix::fs::FileTypeExt;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, LazyLock};
 use std::time::{Duration, Instant};
 use thiserror::Error;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, UnixListener};
 use tokio::time::timeout_at;
 use tokio_rustls::TlsAcceptor;
 use tracing::{error, instrument, Level};
@@ -117,6 +120,81 @@ static SMTP_SERVER_AUTH_PLAIN: Single(
 ) -> SerdeWrappedValue<AuthKindResult>;
 }
 
+declare_event! {
+static SMTP_SERVER_AUTH_CRAM_MD5: Single(
+    "smtp_server_auth_cram_md5",
+    authc: &str,
+    connection_metadata: ConnectionMetaData
+) -> Option<String>;
+}
+
+declare_event! {
+static SMTP_SERVER_AUTH_XOAUTH2: Single(
+    "smtp_server_auth_xoauth2",
+    user: &str,
+    token: &str,
+    connection_metadata: ConnectionMetaData
+) -> SerdeWrappedValue<AuthKindResult>;
+}
+
+/// The SASL mechanisms that we support for AUTH, in the order
+/// in which they are advertised in the EHLO response
+const SUPPORTED_SASL_MECHANISMS: &[&str] = &["PLAIN", "LOGIN", "CRAM-MD5", "XOAUTH2"];
+
+/// Returns the subset of SUPPORTED_SASL_MECHANISMS for which a
+/// policy handler has been registered to validate the credentials.
+/// PLAIN and LOGIN are both validated by smtp_server_auth_plain.
+async fn available_sasl_mechanisms() -> anyhow::Result<Vec<&'static str>> {
+    let config = load_config().await?;
+    let mechs = SUPPORTED_SASL_MECHANISMS
+        .iter()
+        .copied()
+        .filter(|mech| match *mech {
+            "PLAIN" | "LOGIN" => config.has_callback(&SMTP_SERVER_AUTH_PLAIN),
+            "CRAM-MD5" => config.has_callback(&SMTP_SERVER_AUTH_CRAM_MD5),
+            "XOAUTH2" => config.has_callback(&SMTP_SERVER_AUTH_XOAUTH2),
+            _ => false,
+        })
+        .collect();
+    config.put();
+    Ok(mechs)
+}
+
+/// Record the LMTP response for a recipient that is covered by a
+/// message.  When a recipient is covered by more than one message,
+/// a failure for any of them takes precedence over success, and
+/// the first such failure is the one that is reported.
+fn record_recipient_response(slot: &mut Option<(u16, String)>, response: &(u16, String)) {
+    let replace = match slot {
+        None => true,
+        Some((code, _)) => *code < 400 && response.0 >= 400,
+    };
+    if replace {
+        slot.replace(response.clone());
+    }
+}
+
+/// Returns the context with which to record the identity that was
+/// authenticated via the named SASL mechanism
+fn sasl_authentication_context(mech: &str) -> IdentityContext {
+    match mech {
+        "LOGIN" => IdentityContext::SmtpAuthLoginAuthentication,
+        "CRAM-MD5" => IdentityContext::SmtpAuthCramMd5Authentication,
+        "XOAUTH2" => IdentityContext::SmtpAuthXOAuth2Authentication,
+        _ => IdentityContext::SmtpAuthPlainAuthentication,
+    }
+}
+
+/// The credentials and the policy decision produced by one
+/// of the supported SASL mechanisms
+struct SaslOutcome {
+    authz: String,
+    authc: String,
+    /// The final client response of the exchange
+    response: String,
+    result: AuthKindResult,
+}
+
 static CRLF: LazyLock<Finder> = LazyLock::new(|| Finder::new("\r\n"));
 
 declare_metric! {
@@ -279,6 +357,10 @@ pub struct EsmtpDomain {
     #[serde(default)]
     pub log_arf: LogReportDisposition,
     #[serde(default)]
+    pub log_dmarc: LogReportDisposition,
+    #[serde(default)]
+    pub log_tlsrpt: LogReportDisposition,
+    #[serde(default)]
     pub relay_to: bool,
     #[serde(default)]
     pub relay_from: CidrSet,
@@ -549,12 +631,12 @@ impl ConcreteEsmtpListenerParams {
     }
 }
 
-pub fn connection_gauge() -> AtomicCounter {
-    crate::metrics_helper::connection_gauge_for_service("esmtp_listener")
+pub fn connection_gauge(protocol: ListenerProtocol) -> AtomicCounter {
+    crate::metrics_helper::connection_gauge_for_service(protocol.service_name())
 }
 
-pub fn connection_denied_counter() -> AtomicCounter {
-    crate::metrics_helper::connection_denied_for_service("esmtp_listener")
+pub fn connection_denied_counter(protocol: ListenerProtocol) -> AtomicCounter {
+    crate::metrics_helper::connection_denied_for_service(protocol.service_name())
 }
 
 pub fn default_hostname() -> String {
@@ -687,6 +769,126 @@ pub struct EsmtpListenerParams {
 
     #[serde(default = "EsmtpListenerParams::default_max_connections")]
     max_connections: usize,
+
+    /// Set to Lmtp when started via kumo.start_lmtp_listener
+    #[serde(skip)]
+    protocol: ListenerProtocol,
+}
+
+/// The protocol spoken by a listener
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ListenerProtocol {
+    #[default]
+    Esmtp,
+    /// RFC 2033
+    Lmtp,
+}
+
+impl ListenerProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Esmtp => "ESMTP",
+            Self::Lmtp => "LMTP",
+        }
+    }
+
+    /// The label used when logging about the listener
+    fn log_label(&self) -> &'static str {
+        match self {
+            Self::Esmtp => "smtp",
+            Self::Lmtp => "lmtp",
+        }
+    }
+
+    /// The name of the service, as used in metrics
+    pub fn service_name(&self) -> &'static str {
+        match self {
+            Self::Esmtp => "esmtp_listener",
+            Self::Lmtp => "lmtp_listener",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LmtpListenerParams {
+    #[serde(default = "LmtpListenerParams::default_listen")]
+    pub listen: String,
+
+    #[serde(flatten)]
+    base: GenericEsmtpListenerParams,
+
+    #[serde(default = "EsmtpListenerParams::default_max_connections")]
+    max_connections: usize,
+}
+
+impl LmtpListenerParams {
+    fn default_listen() -> String {
+        "127.0.0.1:2024".to_string()
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        EsmtpListenerParams {
+            listen: self.listen,
+            base: self.base,
+            max_connections: self.max_connections,
+            protocol: ListenerProtocol::Lmtp,
+        }
+        .run()
+        .await
+    }
+}
+
+/// A bound listening socket for the ESMTP or LMTP service
+enum ListenSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl ListenSocket {
+    async fn bind(listen: &str) -> anyhow::Result<Self> {
+        // As with SocketAddress, an absolute path is taken to
+        // be the path to a unix domain socket
+        if listen.starts_with('/') {
+            // Remove any socket left behind by a prior instance,
+            // otherwise the bind will fail
+            if std::fs::symlink_metadata(listen)
+                .map(|m| m.file_type().is_socket())
+                .unwrap_or(false)
+            {
+                std::fs::remove_file(listen)
+                    .with_context(|| format!("failed to remove stale socket {listen}"))?;
+            }
+            let listener = UnixListener::bind(listen)
+                .with_context(|| format!("failed to bind to {listen}"))?;
+            return Ok(Self::Unix(listener));
+        }
+
+        let listener = TcpListener::bind(listen)
+            .await
+            .with_context(|| format!("failed to bind to {listen}"))?;
+        Ok(Self::Tcp(listener))
+    }
+
+    /// Accept a connection, returning it along with the local and
+    /// peer addresses.  Unix domain connections are local by definition
+    /// and are reported as using the loopback address at both ends.
+    async fn accept(&self) -> std::io::Result<(BoxedAsyncReadAndWrite, SocketAddr, SocketAddr)> {
+        match self {
+            Self::Tcp(listener) => {
+                let (socket, peer_address) = listener.accept().await?;
+                // No need for Nagle with SMTP request/response
+                socket.set_nodelay(true)?;
+                let my_address = socket.local_addr()?;
+                Ok((Box::new(socket), my_address, peer_address))
+            }
+            Self::Unix(listener) => {
+                let (socket, _) = listener.accept().await?;
+                let loopback = SocketAddr::from(([127, 0, 0, 1], 0));
+                Ok((Box::new(socket), loopback, loopback))
+            }
+        }
+    }
 }
 
 impl EsmtpListenerParams {
@@ -699,23 +901,25 @@ impl EsmtpListenerParams {
     }
 
     pub async fn run(self) -> anyhow::Result<()> {
-        let connection_gauge = connection_gauge();
+        let protocol = self.protocol;
+        let connection_gauge = connection_gauge(protocol);
 
-        let listener = TcpListener::bind(&self.listen)
-            .await
-            .with_context(|| format!("failed to bind to {}", self.listen))?;
+        let listener = ListenSocket::bind(&self.listen).await?;
 
-        let addr = listener.local_addr()?;
-        tracing::info!("smtp listener on {addr:?}");
+        let addr = match &listener {
+            ListenSocket::Tcp(listener) => format!("{:?}", listener.local_addr()?),
+            ListenSocket::Unix(_) => self.listen.clone(),
+        };
+        tracing::info!("{} listener on {addr}", protocol.log_label());
 
         let mut shutting_down = ShutdownSubcription::get();
         let connection_limiter = Arc::new(tokio::sync::Semaphore::new(self.max_connections));
-        spawn(format!("esmtp_listener {addr:?}"), async move {
-            let denied = connection_denied_counter();
+        spawn(format!("{} {addr}", protocol.service_name()), async move {
+            let denied = connection_denied_counter(protocol);
             loop {
                 tokio::select! {
                     _ = shutting_down.shutting_down() => {
-                        tracing::info!("smtp listener on {addr:?} -> stopping");
+                        tracing::info!("{} listener on {addr} -> stopping", protocol.log_label());
                         // Keep the connection gauge alive for the lifetime
                         // of this listener so that it doesn't get reaped out
                         // of the reported set of metrics
@@ -723,7 +927,7 @@ impl EsmtpListenerParams {
                         return Ok::<(), anyhow::Error>(());
                     }
                     result = listener.accept() => {
-                        let (mut socket, peer_address) = result?;
+                        let (mut socket, my_address, peer_address) = result?;
                         let Ok(permit) = connection_limiter.clone().try_acquire_owned() else {
                             // We're over the limit. We make a "best effort" to respond;
                             // don't strain too hard here, as the purpose of the limit is
@@ -759,9 +963,6 @@ impl EsmtpListenerParams {
                             continue;
                         };
 
-                        // No need for Nagle with SMTP request/response
-                        socket.set_nodelay(true)?;
-                        let my_address = socket.local_addr()?;
                         let params = self.clone();
                         SMTPSRV.spawn(
                             format!("SmtpServerSession {peer_address:?}"),
@@ -906,6 +1107,8 @@ pub struct SmtpServerSession {
     rcpt_count: usize,
     authorization_id: Option<String>,
     authentication_id: Option<String>,
+    /// The SASL mechanisms available for AUTH; determined on first use
+    sasl_mechanisms: Option<Vec<&'static str>>,
     meta: ConnectionMetaData,
     global_reception_count: AtomicCounter,
     reception_count: AtomicCounter,
@@ -918,6 +1121,15 @@ pub struct SmtpServerSession {
 struct TransactionState {
     sender: EnvelopeAddress,
     recipients: Vec<EnvelopeAddress>,
+    /// Set when MAIL FROM specified BODY=BINARYMIME
+    binary_mime: bool,
+    /// RFC 3461 parameters from MAIL FROM and RCPT TO
+    dsn: DsnParams,
+    /// Set when MAIL FROM specified the RFC 8689 REQUIRETLS parameter
+    require_tls: bool,
+    /// Accumulates the chunks received via BDAT
+    #[derive_where(skip)]
+    bdat_data: Option<Vec<u8>>,
     #[derive_where(skip)]
     _timer: HistogramTimer,
 }
@@ -929,11 +1141,56 @@ pub struct RelayDisposition {
     /// Should accept to process ARF reports
     pub log_arf: LogReportDisposition,
     pub log_oob: LogReportDisposition,
+    /// Should accept to process DMARC aggregate reports
+    pub log_dmarc: LogReportDisposition,
+    /// Should accept to process TLS aggregate reports
+    pub log_tlsrpt: LogReportDisposition,
 }
 
 impl RelayDisposition {
     pub fn accept_rcpt_to(&self) -> bool {
-        self.relay || self.log_arf.should_log() || self.log_oob.should_log()
+        self.relay
+            || self.log_arf.should_log()
+            || self.log_oob.should_log()
+            || self.log_dmarc.should_log()
+            || self.log_tlsrpt.should_log()
+    }
+
+    /// Parse the message as the first of the report types that
+    /// are configured to be logged, returning the report if the
+    /// message is one of them
+    pub async fn parse_report(&self, message: &Message) -> Option<ReceivedReport> {
+        if self.log_arf.should_log() {
+            if let Ok(Some(report)) = message.parse_rfc5965().await {
+                return Some(ReceivedReport::Feedback(Box::new(report)));
+            }
+        }
+        if self.log_oob.should_log() {
+            if let Ok(Some(report)) = message.parse_rfc3464().await {
+                return Some(ReceivedReport::Oob(Box::new(report)));
+            }
+        }
+        if self.log_dmarc.should_log() {
+            if let Ok(Some(report)) = message.parse_dmarc_aggregate().await {
+                return Some(ReceivedReport::DmarcAggregate(Box::new(report)));
+            }
+        }
+        if self.log_tlsrpt.should_log() {
+            if let Ok(Some(report)) = message.parse_tlsrpt().await {
+                return Some(ReceivedReport::Tls(Box::new(report)));
+            }
+        }
+        None
+    }
+
+    /// Whether a message containing `report` should be relayed
+    pub fn report_should_relay(&self, report: &ReceivedReport) -> bool {
+        match report {
+            ReceivedReport::Feedback(_) => self.log_arf.should_relay(),
+            ReceivedReport::Oob(_) => self.log_oob.should_relay(),
+            ReceivedReport::DmarcAggregate(_) => self.log_dmarc.should_relay(),
+            ReceivedReport::Tls(_) => self.log_tlsrpt.should_relay(),
+        }
     }
 }
 
@@ -949,9 +1206,14 @@ impl SmtpServerSession {
         T: AsyncReadAndWrite + Debug + Send + 'static,
     {
         let socket: BoxedAsyncReadAndWrite = Box::new(socket);
+        let protocol = params.protocol;
         let mut meta = ConnectionMetaData::new();
-        meta.set_meta("reception_protocol", "ESMTP");
-        meta.set_meta("received_via", my_address.to_string());
+        meta.set_meta("reception_protocol", protocol.as_str());
+        if params.listen.starts_with('/') {
+            meta.set_meta("received_via", params.listen.clone());
+        } else {
+            meta.set_meta("received_via", my_address.to_string());
+        }
         meta.set_meta("received_from", peer_address.to_string());
         meta.auth_info
             .lock()
@@ -962,7 +1224,11 @@ impl SmtpServerSession {
 
         concrete_params.apply_generic(params.base.clone(), &my_address, &peer_address, &mut meta);
 
-        let service = format!("esmtp_listener:{my_address}");
+        let service = if params.listen.starts_with('/') {
+            format!("{}:{}", protocol.service_name(), params.listen)
+        } else {
+            format!("{}:{my_address}", protocol.service_name())
+        };
 
         let mut server = SmtpServerSession {
             socket: Some(socket),
@@ -979,17 +1245,18 @@ impl SmtpServerSession {
             rcpt_count: 0,
             authorization_id: None,
             authentication_id: None,
+            sasl_mechanisms: None,
             meta,
             reception_count: crate::metrics_helper::total_msgs_received_for_service(&service),
             global_reception_count: crate::metrics_helper::total_msgs_received_for_service(
-                "esmtp_listener",
+                protocol.service_name(),
             ),
             session_id: Uuid::new_v4(),
             domains: HashMap::new(),
             config_params: params,
         };
 
-        connection_gauge().inc();
+        connection_gauge(protocol).inc();
 
         SmtpServerTraceManager::submit(|| SmtpServerTraceEvent {
             conn_meta: server.meta.clone_inner(),
@@ -1011,7 +1278,7 @@ impl SmtpServerSession {
                     .ok();
             }
         }
-        connection_gauge().dec();
+        connection_gauge(protocol).dec();
 
         SmtpServerTraceManager::submit(|| SmtpServerTraceEvent {
             conn_meta: server.meta.clone_inner(),
@@ -1022,6 +1289,10 @@ impl SmtpServerSession {
         Ok(())
     }
 
+    fn is_lmtp(&self) -> bool {
+        self.config_params.protocol == ListenerProtocol::Lmtp
+    }
+
     fn peer_in_cidr_list(&self, cidr: &CidrSet) -> bool {
         cidr.contains(self.peer_address.ip())
     }
@@ -1115,11 +1386,15 @@ impl SmtpServerSession {
         let mut relay_to_allowed = None;
         let mut log_arf = LogReportDisposition::Ignore;
         let mut log_oob = LogReportDisposition::Ignore;
+        let mut log_dmarc = LogReportDisposition::Ignore;
+        let mut log_tlsrpt = LogReportDisposition::Ignore;
 
         if let Some(dom) = self.lookup_listener_domain(&recipient_domain).await? {
             relay_to_allowed.replace(dom.relay_to);
             log_arf = dom.log_arf;
             log_oob = dom.log_oob;
+            log_dmarc = dom.log_dmarc;
+            log_tlsrpt = dom.log_tlsrpt;
         }
 
         // Check the rules for relaying-from first; that allows
@@ -1138,13 +1413,16 @@ impl SmtpServerSession {
              recip={recipient_domain} relay_to_allowed={relay_to_allowed:?} \
              relay_hosts_allowed={relay_hosts_allowed} \
              relay_from_allowed={relay_from_allowed} \
-             -> log_arf={log_arf:?} log_oob={log_oob:?} relay={relay}"
+             -> log_arf={log_arf:?} log_oob={log_oob:?} \
+             log_dmarc={log_dmarc:?} log_tlsrpt={log_tlsrpt:?} relay={relay}"
         );
 
         Ok(RelayDisposition {
             relay,
             log_arf,
             log_oob,
+            log_dmarc,
+            log_tlsrpt,
         })
     }
 
@@ -1377,6 +1655,81 @@ impl SmtpServerSession {
         }
     }
 
+    /// Read exactly `size` octets of BDAT chunk data.
+    /// If `keep` is false, the data is consumed and discarded.
+    #[instrument(skip(self))]
+    async fn read_chunk(&mut self, size: usize, keep: bool) -> anyhow::Result<ReadData> {
+        tracing::trace!("reading {size} byte chunk");
+
+        let mut chunk = vec![];
+        if keep {
+            chunk.reserve(size);
+        }
+        let mut remaining = size;
+        let mut data = DebugabbleReadBuffer(vec![0u8; self.params.data_buffer_size]);
+
+        loop {
+            let available = remaining.min(self.read_buffer.len());
+            if available > 0 {
+                if keep {
+                    chunk.extend_from_slice(&self.read_buffer[0..available]);
+                }
+                self.read_buffer.drain(0..available);
+                remaining -= available;
+            }
+
+            if remaining == 0 {
+                tracing::trace!("returning ReadData::Data {:?}", DebugPrintBuffer(&chunk));
+                return Ok(ReadData::Data(chunk));
+            }
+
+            // Need more of the chunk, fill up the buffer
+            tokio::select! {
+                _ = tokio::time::sleep(self.params.client_timeout) => {
+                    return Ok(ReadData::TimedOut);
+                }
+                size = self.socket.as_mut().unwrap().read(&mut data) => {
+                    match size {
+                        Err(err) => {
+                            tracing::trace!("error reading: {err:#}");
+                            SmtpServerTraceManager::submit(|| SmtpServerTraceEvent {
+                                conn_meta: self.meta.clone_inner(),
+                                payload: SmtpServerTraceEventPayload::Diagnostic {
+                                    level: Level::ERROR,
+                                    message: format!("error reading: {err:#}"),
+                                },
+                                when: Utc::now(),
+                            });
+                            return Ok(ReadData::Disconnected);
+                        }
+                        Ok(size) if size == 0 => {
+                            SmtpServerTraceManager::submit(|| SmtpServerTraceEvent {
+                                conn_meta: self.meta.clone_inner(),
+                                payload: SmtpServerTraceEventPayload::Diagnostic {
+                                    level: Level::ERROR,
+                                    message: "Peer Disconnected".to_string(),
+                                },
+                                when: Utc::now(),
+                            });
+                            return Ok(ReadData::Disconnected);
+                        }
+                        Ok(size) => {
+                            SmtpServerTraceManager::submit(|| SmtpServerTraceEvent {
+                                conn_meta: self.meta.clone_inner(),
+                                payload: SmtpServerTraceEventPayload::Read(data[0..size].to_vec()),
+                                when: Utc::now(),
+                            });
+                            self.read_buffer.extend_from_slice(&data[0..size]);
+                        }
+                    }
+                }
+                _ = self.shutdown.shutting_down() => {
+                    return Ok(ReadData::ShuttingDown);
+                }
+            };
+        }
+    }
+
     #[instrument(skip(self))]
     async fn read_line(&mut self, override_limit: Option<usize>) -> anyhow::Result<ReadLine> {
         if self.socket.is_none() {
@@ -1549,7 +1902,7 @@ impl SmtpServerSession {
         if kumo_server_memory::get_headroom() == 0 {
             // Bump connection_denied_counter because the operator may care to
             // investigate this, and we don't otherwise log this class of rejection.
-            connection_denied_counter().inc();
+            connection_denied_counter(self.config_params.protocol).inc();
 
             // Using too much memory
             self.write_response(
@@ -1564,7 +1917,7 @@ impl SmtpServerSession {
         if kumo_server_common::disk_space::is_over_limit() {
             // Bump connection_denied_counter because the operator may care to
             // investigate this, and we don't otherwise log this class of rejection.
-            connection_denied_counter().inc();
+            connection_denied_counter(self.config_params.protocol).inc();
 
             self.write_response(
                 421,
@@ -1782,18 +2135,52 @@ impl SmtpServerSession {
                         return Ok(());
                     }
                 }
-                Ok(Command::Ehlo(domain)) => {
+                Ok(Command::Ehlo(_) | Command::Helo(_)) if self.is_lmtp() => {
+                    // RFC 2033: LHLO replaces HELO and EHLO
+                    self.write_response(
+                        500,
+                        "5.5.1 LMTP requires LHLO",
+                        Some(line),
+                        RejectDisconnect::If421,
+                    )
+                    .await?;
+                }
+                Ok(Command::Lhlo(_)) if !self.is_lmtp() => {
+                    self.write_response(
+                        502,
+                        format!("5.5.1 Command unimplemented"),
+                        Some(line),
+                        RejectDisconnect::If421,
+                    )
+                    .await?;
+                }
+                Ok(Command::Ehlo(domain) | Command::Lhlo(domain)) => {
                     let domain = domain.to_string();
 
-                    let mut extensions =
-                        vec!["PIPELINING", "ENHANCEDSTATUSCODES", "8BITMIME", "SMTPUTF8"];
+                    let mut extensions: Vec<String> = [
+                        "PIPELINING",
+                        "ENHANCEDSTATUSCODES",
+                        "8BITMIME",
+                        "SMTPUTF8",
+                        "CHUNKING",
+                        "BINARYMIME",
+                        "DSN",
+                    ]
+                    .iter()
+                    .map(|ext| ext.to_string())
+                    .collect();
                     if self.tls_active.is_none() {
-                        extensions.push("STARTTLS");
+                        extensions.push("STARTTLS".to_string());
                     } else {
-                        extensions.push("AUTH PLAIN");
+                        let mechs = self.sasl_mechanisms().await?;
+                        if !mechs.is_empty() {
+                            extensions.push(format!("AUTH {}", mechs.join(" ")));
+                        }
+                        // RFC 8689 only permits REQUIRETLS within a TLS session
+                        extensions.push("REQUIRETLS".to_string());
                     }
                     if self.params.allow_xclient {
-                        extensions.push("XCLIENT ADDR PORT DESTADDR DESTPORT");
+                        extensions.push("XCLIENT ADDR PORT DESTADDR DESTPORT".to_string());
                     }
 
                     let extensions = match self
@@ -1849,7 +2236,7 @@ impl SmtpServerSession {
                 }
                 Ok(Command::MailFrom {
                     address,
-                    parameters: _,
+                    parameters,
                 }) => {
                     if self.state.is_some() {
                         self.write_response(
@@ -1862,6 +2249,19 @@ impl SmtpServerSession {
                         continue;
                     }
                     let address = EnvelopeAddress::parse(&address.to_string())?;
+                    let dsn = match DsnParams::from_mail_from_parameters(&parameters) {
+                        Ok(dsn) => dsn,
+                        Err(err) => {
+                            self.write_response(
+                                501,
+                                format!("5.5.4 {err}"),
+                                Some(line),
+                                RejectDisconnect::If421,
+                            )
+                            .await?;
+                            continue;
+                        }
+                    };
                     if let Err(rej) = self
                         .call_callback::<(), _, _>(
                             "smtp_server_mail_from",
@@ -1874,9 +2274,49 @@ impl SmtpServerSession {
                         continue;
                     }
 
+                    let binary_mime = parameters.iter().any(|p| {
+                        p.name.eq_ignore_ascii_case("BODY")
+                            && p.value
+                                .as_deref()
+                                .map(|v| v.eq_ignore_ascii_case("BINARYMIME"))
+                                .unwrap_or(false)
+                    });
+
+                    let require_tls = match parameters
+                        .iter()
+                        .find(|p| p.name.eq_ignore_ascii_case("REQUIRETLS"))
+                    {
+                        None => false,
+                        Some(p) if p.value.is_some() => {
+                            self.write_response(
+                                501,
+                                "5.5.4 REQUIRETLS does not accept a value",
+                                Some(line),
+                                RejectDisconnect::If421,
+                            )
+                            .await?;
+                            continue;
+                        }
+                        Some(_) if self.tls_active.is_none() => {
+                            self.write_response(
+                                530,
+                                "5.7.10 REQUIRETLS is only permitted in a TLS session",
+                                Some(line),
+                                RejectDisconnect::If421,
+                            )
+                            .await?;
+                            continue;
+                        }
+                        Some(_) => true,
+                    };
+
                     self.state.replace(TransactionState {
                         sender: address.clone(),
                         recipients: vec![],
+                        binary_mime,
+                        dsn,
+                        require_tls,
+                        bdat_data: None,
                         _timer: TXN_LATENCY.start_timer(),
                     });
                     self.write_response(
@@ -1889,7 +2329,7 @@ impl SmtpServerSession {
                 }
                 Ok(Command::RcptTo {
                     address,
-                    parameters: _,
+                    parameters,
                 }) => {
                     if self.state.is_none() {
                         self.write_response(
@@ -1902,6 +2342,19 @@ impl SmtpServerSession {
                         continue;
                     }
                     let address = EnvelopeAddress::parse(&address.to_string())?;
+                    let recipient_dsn = match RecipientDsn::from_rcpt_to_parameters(&parameters) {
+                        Ok(dsn) => dsn,
+                        Err(err) => {
+                            self.write_response(
+                                501,
+                                format!("5.5.4 {err}"),
+                                Some(line),
+                                RejectDisconnect::If421,
+                            )
+                            .await?;
+                            continue;
+                        }
+                    };
 
                     let sender = self.state.as_ref().unwrap().sender.clone();
                     let relay_disposition = self.check_relaying(&sender, &address).await?;
@@ -1982,11 +2435,11 @@ impl SmtpServerSession {
                         RejectDisconnect::If421,
                     )
                     .await?;
-                    self.state
-                        .as_mut()
-                        .expect("checked state above")
-                        .recipients
-                        .push(address);
+                    let state = self.state.as_mut().expect("checked state above");
+                    if let Some(dsn) = recipient_dsn {
+                        state.dsn.recipients.insert(address.to_string(), dsn);
+                    }
+                    state.recipients.push(address);
                 }
                 Ok(Command::Data) => {
                     if self.state.is_none() {
@@ -2014,6 +2467,23 @@ impl SmtpServerSession {
                         .await?;
                         continue;
                     }
+                    if let Some(state) = &self.state {
+                        // RFC 3030: BINARYMIME can only be transferred
+                        // via BDAT, and DATA cannot be mixed with BDAT
+                        // in the same transaction
+                        let reason = if state.bdat_data.is_some() {
+                            Some("5.5.1 DATA cannot be used after BDAT")
+                        } else if state.binary_mime {
+                            Some("5.5.1 BODY=BINARYMIME requires BDAT")
+                        } else {
+                            None
+                        };
+                        if let Some(reason) = reason {
+                            self.write_response(503, reason, Some(line), RejectDisconnect::If421)
+                                .await?;
+                            continue;
+                        }
+                    }
 
                     self.write_response(
                         354,
@@ -2024,11 +2494,13 @@ impl SmtpServerSession {
                     .await?;
 
                     let read_data_timer = READ_DATA_LATENCY.start_timer();
+                    let responses = self.data_response_count();
                     let data = match self.read_data().await? {
                         ReadData::Disconnected => return Ok(()),
                         ReadData::Data(data) => data,
                         ReadData::TooBig => {
-                            self.write_response(
+                            self.write_data_response(
+                                responses,
                                 552,
                                 "5.3.4 message too big",
                                 Some(line),
@@ -2038,7 +2510,8 @@ impl SmtpServerSession {
                             continue;
                         }
                         ReadData::TooLong => {
-                            self.write_response(
+                            self.write_data_response(
+                                responses,
                                 500,
                                 "5.2.3 line too long",
                                 Some(line),
@@ -2071,7 +2544,14 @@ impl SmtpServerSession {
                     read_data_timer.stop_and_record();
 
                     let _process_data_timer = PROCESS_DATA_LATENCY.start_timer();
-                    Box::pin(self.process_data(data, &activity)).await?;
+                    Box::pin(self.process_data(data, &activity, "DATA")).await?;
+                }
+                Ok(Command::Bdat { chunk_size, last }) => {
+                    if self.process_bdat(line, chunk_size, last, &activity).await?
+                        == CommandDisposition::Terminate
+                    {
+                        return Ok(());
+                    }
                 }
                 Ok(Command::Rset) => {
                     self.state.take();
@@ -2090,7 +2570,7 @@ impl SmtpServerSession {
                 Ok(Command::XClient(params)) => {
                     self.process_xclient(&params).await?;
                 }
-                Ok(Command::Vrfy(_) | Command::Expn(_) | Command::Help(_) | Command::Lhlo(_)) => {
+                Ok(Command::Vrfy(_) | Command::Expn(_) | Command::Help(_)) => {
                     self.write_response(
                         502,
                         format!("5.5.1 Command unimplemented"),
@@ -2450,6 +2930,17 @@ impl SmtpServerSession {
         Ok(())
     }
 
+    /// Returns the SASL mechanisms available for AUTH in this session.
+    /// They are determined once per session, rather than each time
+    /// that they are advertised or used.
+    async fn sasl_mechanisms(&mut self) -> anyhow::Result<Vec<&'static str>> {
+        if self.sasl_mechanisms.is_none() {
+            self.sasl_mechanisms
+                .replace(available_sasl_mechanisms().await?);
+        }
+        Ok(self.sasl_mechanisms.clone().unwrap_or_default())
+    }
+
     async fn process_auth(
         &mut self,
         line: String,
@@ -2476,7 +2967,8 @@ impl SmtpServerSession {
             .await?;
             return Ok(CommandDisposition::Continue);
         }
-        if sasl_mech != "PLAIN" {
+        let sasl_mech = sasl_mech.to_ascii_uppercase();
+        if !self.sasl_mechanisms().await?.contains(&sasl_mech.as_str()) {
             self.write_response(
                 504,
                 format!("5.5.4 AUTH {sasl_mech} not supported"),
@@ -2497,126 +2989,29 @@ impl SmtpServerSession {
             return Ok(CommandDisposition::Continue);
         }
 
-        let response = if let Some(r) = initial_response {
-            r
-        } else {
-            self.write_response(334, " ", None, RejectDisconnect::If421)
-                .await?;
-            match self.read_line(Some(16384)).await? {
-                ReadLine::Disconnected => return Ok(CommandDisposition::Terminate),
-                ReadLine::Line(line) => line,
-                ReadLine::TimedOut => {
-                    self.write_response(
-                        421,
-                        format!("4.3.2 {} idle too long", self.params.hostname),
-                        Some(line),
-                        RejectDisconnect::If421,
-                    )
-                    .await?;
-                    return Ok(CommandDisposition::Terminate);
-                }
-                ReadLine::ShuttingDown => {
-                    self.write_response(
-                        421,
-                        format!("4.3.2 {} shutting down", self.params.hostname),
-                        Some(line),
-                        RejectDisconnect::If421,
-                    )
-                    .await?;
-                    return Ok(CommandDisposition::Terminate);
-                }
-                ReadLine::TooLong => {
-                    self.write_response(
-                        500,
-                        "5.5.6 authentication exchange line too long",
-                        Some(line),
-                        RejectDisconnect::If421,
-                    )
-                    .await?;
-                    return Ok(CommandDisposition::Continue);
-                }
-            }
-        };
-
-        if response == "*" {
-            self.write_response(
-                501,
-                "5.5.0 AUTH cancelled by client",
-                Some(line),
-                RejectDisconnect::If421,
-            )
-            .await?;
-            return Ok(CommandDisposition::Continue);
-        }
-
-        let Ok(payload) = BASE64.decode(response.as_bytes()) else {
-            self.write_response(
-                501,
-                "5.5.2 Invalid base64 response",
-                Some(response),
-                RejectDisconnect::If421,
-            )
-            .await?;
-            return Ok(CommandDisposition::Continue);
-        };
-
-        // RFC 4616 says that the message is:
-        // [authzid] NUL authcid NUL passwd
-        let fields: Vec<_> = payload.split(|&b| b == 0).collect();
-        let (authz, authc, pass) = match fields.len() {
-            3 => (
-                std::str::from_utf8(&fields[0]),
-                std::str::from_utf8(&fields[1]),
-                std::str::from_utf8(&fields[2]),
-            ),
-            _ => {
-                self.write_response(
-                    501,
-                    "5.5.2 Invalid decoded PLAIN response",
-                    Some(response),
-                    RejectDisconnect::If421,
-                )
-                .await?;
-                return Ok(CommandDisposition::Continue);
-            }
-        };
-
-        let (authz, authc, pass) = match (authz, authc, pass) {
-            (Ok(a), Ok(b), Ok(c)) => (a, b, c),
-            _ => {
-                self.write_response(
-                    501,
-                    "5.5.2 Invalid UTF8 in decoded PLAIN response",
-                    Some(response),
-                    RejectDisconnect::If421,
-                )
-                .await?;
-                return Ok(CommandDisposition::Continue);
-            }
+        let outcome = match sasl_mech.as_str() {
+            "PLAIN" => self.sasl_plain(&line, initial_response).await?,
+            "LOGIN" => self.sasl_login(&line, initial_response).await?,
+            "CRAM-MD5" => self.sasl_cram_md5(&line, initial_response).await?,
+            "XOAUTH2" => self.sasl_xoauth2(&line, initial_response).await?,
+            _ => unreachable!(),
         };
-
-        // If no authorization id was set, assume the same as
-        // the authenticated id
-        let authz = if authz.is_empty() { authc } else { authz };
-
-        let wrapped = match self
-            .call_callback_sig(
-                &SMTP_SERVER_AUTH_PLAIN,
-                (authz, authc, pass, self.meta.clone()),
-            )
-            .await?
-        {
-            Err(rej) => {
-                self.write_response(rej.code, rej.message, Some(response), rej.disconnect)
-                    .await?;
-                return Ok(CommandDisposition::Continue);
-            }
-            Ok(wrapped) => wrapped.0,
+        let SaslOutcome {
+            authz,
+            authc,
+            response,
+            result: wrapped,
+        } = match outcome {
+            Ok(outcome) => outcome,
+            Err(disposition) => return Ok(disposition),
         };
+        let authz = authz.as_str();
+        let authc = authc.as_str();
 
+        let authc_context = sasl_authentication_context(&sasl_mech);
         let attempted_identity = Identity {
             identity: authc.to_string(),
-            context: IdentityContext::SmtpAuthPlainAuthentication,
+            context: authc_context,
         };
 
         let (success, auth_info) = match wrapped {
@@ -2631,13 +3026,16 @@ impl SmtpServerSession {
                 self.meta.set_meta("authn_id", authc);
 
                 let mut auth_info = self.meta.auth_info.lock();
-                auth_info.add_identity(Identity {
-                    identity: authz.to_string(),
-                    context: IdentityContext::SmtpAuthPlainAuthorization,
-                });
+                // Only PLAIN conveys a distinct authorization identity
+                if sasl_mech == "PLAIN" {
+                    auth_info.add_identity(Identity {
+                        identity: authz.to_string(),
+                        context: IdentityContext::SmtpAuthPlainAuthorization,
+                    });
+                }
                 auth_info.add_identity(Identity {
                     identity: authc.to_string(),
-                    context: IdentityContext::SmtpAuthPlainAuthentication,
+                    context: authc_context,
                 });
 
                 (true, auth_info.clone())
@@ -2645,8 +3043,8 @@ impl SmtpServerSession {
             AuthKindResult::AuthInfo(info) => {
                 // Reconcile what they returned.
                 // In particular, they may not have explicitly
-                // populated identities with SmtpAuthPlainAuthentication or
-                // SmtpAuthPlainAuthorization contexts so we may
+                // populated identities with an SMTP AUTH authentication
+                // or SmtpAuthPlainAuthorization context so we may
                 // need to infer something reasonable
                 let mut seen_authc = false;
                 let mut seen_authz = false;
@@ -2660,7 +3058,10 @@ impl SmtpServerSession {
 
                 for ident in &info.identities {
                     match ident.context {
-                        IdentityContext::SmtpAuthPlainAuthentication => {
+                        IdentityContext::SmtpAuthPlainAuthentication
+                        | IdentityContext::SmtpAuthLoginAuthentication
+                        | IdentityContext::SmtpAuthCramMd5Authentication
+                        | IdentityContext::SmtpAuthXOAuth2Authentication => {
                             seen_authc = true;
                             self.authentication_id.replace(ident.identity.to_string());
                         }
@@ -2719,12 +3120,527 @@ impl SmtpServerSession {
         Ok(CommandDisposition::Continue)
     }
 
-    async fn process_data(&mut self, mut data: Vec<u8>, activity: &Activity) -> anyhow::Result<()> {
+    /// Obtain the next client response in a SASL exchange, prompting
+    /// with `challenge` unless an initial response was supplied with
+    /// the AUTH command.
+    /// Returns the raw response line along with its decoded payload,
+    /// or the disposition that the caller should return if the
+    /// exchange cannot continue.
+    async fn read_sasl_response(
+        &mut self,
+        line: &str,
+        challenge: &str,
+        initial_response: Option<String>,
+    ) -> anyhow::Result<Result<(String, Vec<u8>), CommandDisposition>> {
+        let response = if let Some(r) = initial_response {
+            r
+        } else {
+            self.write_response(334, challenge, None, RejectDisconnect::If421)
+                .await?;
+            match self.read_line(Some(16384)).await? {
+                ReadLine::Disconnected => return Ok(Err(CommandDisposition::Terminate)),
+                ReadLine::Line(line) => line,
+                ReadLine::TimedOut => {
+                    self.write_response(
+                        421,
+                        format!("4.3.2 {} idle too long", self.params.hostname),
+                        Some(line.to_string()),
+                        RejectDisconnect::If421,
+                    )
+                    .await?;
+                    return Ok(Err(CommandDisposition::Terminate));
+                }
+                ReadLine::ShuttingDown => {
+                    self.write_response(
+                        421,
+                        format!("4.3.2 {} shutting down", self.params.hostname),
+                        Some(line.to_string()),
+                        RejectDisconnect::If421,
+                    )
+                    .await?;
+                    return Ok(Err(CommandDisposition::Terminate));
+                }
+                ReadLine::TooLong => {
+                    self.write_response(
+                        500,
+                        "5.5.6 authentication exchange line too long",
+                        Some(line.to_string()),
+                        RejectDisconnect::If421,
+                    )
+                    .await?;
+                    return Ok(Err(CommandDisposition::Continue));
+                }
+            }
+        };
+
+        if response == "*" {
+            self.write_response(
+                501,
+                "5.5.0 AUTH cancelled by client",
+                Some(line.to_string()),
+                RejectDisconnect::If421,
+            )
+            .await?;
+            return Ok(Err(CommandDisposition::Continue));
+        }
+
+        // RFC 4954: a lone "=" is an empty initial response
+        if response == "=" {
+            return Ok(Ok((response, vec![])));
+        }
+
+        let Ok(payload) = BASE64.decode(response.as_bytes()) else {
+            self.write_response(
+                501,
+                "5.5.2 Invalid base64 response",
+                Some(response),
+                RejectDisconnect::If421,
+            )
+            .await?;
+            return Ok(Err(CommandDisposition::Continue));
+        };
+
+        Ok(Ok((response, payload)))
+    }
+
+    /// Respond with a 501 for a malformed SASL response
+    async fn sasl_malformed(
+        &mut self,
+        message: &str,
+        response: String,
+    ) -> anyhow::Result<Result<SaslOutcome, CommandDisposition>> {
+        self.write_response(501, message, Some(response), RejectDisconnect::If421)
+            .await?;
+        Ok(Err(CommandDisposition::Continue))
+    }
+
+    /// Call an authentication event, responding with its rejection
+    /// if it raised one
+    async fn call_sasl_callback<
+        R: FromLuaMulti + Default + serde::Serialize,
+        A: IntoLuaMulti + Clone,
+    >(
+        &mut self,
+        sig: &CallbackSignature<A, R>,
+        args: A,
+        response: &str,
+    ) -> anyhow::Result<Result<R, CommandDisposition>> {
+        match self.call_callback_sig(sig, args).await? {
+            Err(rej) => {
+                self.write_response(
+                    rej.code,
+                    rej.message,
+                    Some(response.to_string()),
+                    rej.disconnect,
+                )
+                .await?;
+                Ok(Err(CommandDisposition::Continue))
+            }
+            Ok(result) => Ok(Ok(result)),
+        }
+    }
+
+    /// RFC 4616 PLAIN
+    async fn sasl_plain(
+        &mut self,
+        line: &str,
+        initial_response: Option<String>,
+    ) -> anyhow::Result<Result<SaslOutcome, CommandDisposition>> {
+        let (response, payload) = match self.read_sasl_response(line, " ", initial_response).await?
+        {
+            Ok(r) => r,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        // RFC 4616 says that the message is:
+        // [authzid] NUL authcid NUL passwd
+        let fields: Vec<_> = payload.split(|&b| b == 0).collect();
+        let (authz, authc, pass) = match fields.len() {
+            3 => (
+                std::str::from_utf8(&fields[0]),
+                std::str::from_utf8(&fields[1]),
+                std::str::from_utf8(&fields[2]),
+            ),
+            _ => {
+                return self
+                    .sasl_malformed("5.5.2 Invalid decoded PLAIN response", response)
+                    .await;
+            }
+        };
+
+        let (authz, authc, pass) = match (authz, authc, pass) {
+            (Ok(a), Ok(b), Ok(c)) => (a, b, c),
+            _ => {
+                return self
+                    .sasl_malformed("5.5.2 Invalid UTF8 in decoded PLAIN response", response)
+                    .await;
+            }
+        };
+
+        // If no authorization id was set, assume the same as
+        // the authenticated id
+        let authz = if authz.is_empty() { authc } else { authz };
+
+        let result = match self
+            .call_sasl_callback(
+                &SMTP_SERVER_AUTH_PLAIN,
+                (authz, authc, pass, self.meta.clone()),
+                &response,
+            )
+            .await?
+        {
+            Ok(wrapped) => wrapped.0,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        Ok(Ok(SaslOutcome {
+            authz: authz.to_string(),
+            authc: authc.to_string(),
+            response,
+            result,
+        }))
+    }
+
+    /// The LOGIN mechanism is not formally specified, but is widely
+    /// deployed. The username and password are solicited in turn
+    /// and then passed to smtp_server_auth_plain.
+    async fn sasl_login(
+        &mut self,
+        line: &str,
+        initial_response: Option<String>,
+    ) -> anyhow::Result<Result<SaslOutcome, CommandDisposition>> {
+        // "Username:"
+        let (response, username) = match self
+            .read_sasl_response(line, "VXNlcm5hbWU6", initial_response)
+            .await?
+        {
+            Ok(r) => r,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+        let Ok(username) = String::from_utf8(username) else {
+            return self
+                .sasl_malformed("5.5.2 Invalid UTF8 in decoded LOGIN username", response)
+                .await;
+        };
+
+        // "Password:"
+        let (response, password) = match self.read_sasl_response(line, "UGFzc3dvcmQ6", None).await?
+        {
+            Ok(r) => r,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+        let Ok(password) = String::from_utf8(password) else {
+            return self
+                .sasl_malformed("5.5.2 Invalid UTF8 in decoded LOGIN password", response)
+                .await;
+        };
+
+        let result = match self
+            .call_sasl_callback(
+                &SMTP_SERVER_AUTH_PLAIN,
+                (
+                    username.as_str(),
+                    username.as_str(),
+                    password.as_str(),
+                    self.meta.clone(),
+                ),
+                &response,
+            )
+            .await?
+        {
+            Ok(wrapped) => wrapped.0,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        Ok(Ok(SaslOutcome {
+            authz: username.clone(),
+            authc: username,
+            response,
+            result,
+        }))
+    }
+
+    /// RFC 2195 CRAM-MD5. The smtp_server_auth_cram_md5 event
+    /// provides the shared secret for the user, and we verify
+    /// the digest computed by the client.
+    async fn sasl_cram_md5(
+        &mut self,
+        line: &str,
+        initial_response: Option<String>,
+    ) -> anyhow::Result<Result<SaslOutcome, CommandDisposition>> {
+        if initial_response.is_some() {
+            return self
+                .sasl_malformed(
+                    "5.5.2 CRAM-MD5 does not permit an initial response",
+                    line.to_string(),
+                )
+                .await;
+        }
+
+        let challenge = format!(
+            "<{}.{}@{}>",
+            rand::random::<u32>(),
+            Utc::now().timestamp(),
+            self.params.hostname
+        );
+        let (response, payload) = match self
+            .read_sasl_response(line, &BASE64.encode(challenge.as_bytes()), None)
+            .await?
+        {
+            Ok(r) => r,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        // The response is: username SP digest
+        let Some((username, digest)) = std::str::from_utf8(&payload)
+            .ok()
+            .and_then(|p| p.rsplit_once(' '))
+        else {
+            return self
+                .sasl_malformed("5.5.2 Invalid decoded CRAM-MD5 response", response)
+                .await;
+        };
+        let username = username.to_string();
+        let digest = digest.to_ascii_lowercase();
+
+        let secret: Option<String> = match self
+            .call_sasl_callback(
+                &SMTP_SERVER_AUTH_CRAM_MD5,
+                (username.as_str(), self.meta.clone()),
+                &response,
+            )
+            .await?
+        {
+            Ok(secret) => secret,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        let success = match secret {
+            Some(secret) => {
+                let expected = rfc5321::cram_md5_digest(secret.as_bytes(), challenge.as_bytes())?;
+                expected.len() == digest.len()
+                    && openssl::memcmp::eq(expected.as_bytes(), digest.as_bytes())
+            }
+            None => false,
+        };
+
+        Ok(Ok(SaslOutcome {
+            authz: username.clone(),
+            authc: username,
+            response,
+            result: AuthKindResult::Bool(success),
+        }))
+    }
+
+    /// XOAUTH2, as used by Gmail and Microsoft 365.
+    /// The client response is:
+    /// `user=` username ^A `auth=Bearer ` token ^A ^A
+    async fn sasl_xoauth2(
+        &mut self,
+        line: &str,
+        initial_response: Option<String>,
+    ) -> anyhow::Result<Result<SaslOutcome, CommandDisposition>> {
+        let (response, payload) = match self.read_sasl_response(line, " ", initial_response).await?
+        {
+            Ok(r) => r,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        let Ok(payload) = String::from_utf8(payload) else {
+            return self
+                .sasl_malformed("5.5.2 Invalid UTF8 in decoded XOAUTH2 response", response)
+                .await;
+        };
+
+        let mut user = None;
+        let mut token = None;
+        for field in payload.split('\x01') {
+            if let Some(value) = field.strip_prefix("user=") {
+                user.replace(value);
+            } else if let Some(value) = field.strip_prefix("auth=") {
+                token = value.strip_prefix("Bearer ");
+            }
+        }
+
+        let (Some(user), Some(token)) = (user, token) else {
+            return self
+                .sasl_malformed("5.5.2 Invalid decoded XOAUTH2 response", response)
+                .await;
+        };
+
+        let result = match self
+            .call_sasl_callback(
+                &SMTP_SERVER_AUTH_XOAUTH2,
+                (user, token, self.meta.clone()),
+                &response,
+            )
+            .await?
+        {
+            Ok(wrapped) => wrapped.0,
+            Err(disposition) => return Ok(Err(disposition)),
+        };
+
+        Ok(Ok(SaslOutcome {
+            authz: user.to_string(),
+            authc: user.to_string(),
+            response,
+            result,
+        }))
+    }
+
+    async fn process_bdat(
+        &mut self,
+        line: String,
+        chunk_size: usize,
+        last: bool,
+        activity: &Activity,
+    ) -> anyhow::Result<CommandDisposition> {
+        // RFC 3030 requires that we consume the chunk even when we
+        // are going to reject it, otherwise we would interpret the
+        // message data as commands.
+        let rejection = match &self.state {
+            None => Some((503, "5.5.0 MAIL FROM must be issued first")),
+            Some(state) if state.recipients.is_empty() => {
+                Some((503, "5.5.0 RCPT TO must be issued first"))
+            }
+            Some(state) => {
+                let received = state.bdat_data.as_ref().map(|d| d.len()).unwrap_or(0);
+                if received.saturating_add(chunk_size) > self.params.max_message_size {
+                    Some((552, "5.3.4 message too big"))
+                } else {
+                    None
+                }
+            }
+        };
+
+        let read_data_timer = READ_DATA_LATENCY.start_timer();
+        let chunk = match self.read_chunk(chunk_size, rejection.is_none()).await? {
+            ReadData::Disconnected => return Ok(CommandDisposition::Terminate),
+            ReadData::Data(data) => data,
+            ReadData::TimedOut => {
+                self.write_response(
+                    421,
+                    format!("4.3.2 {} idle too long", self.params.hostname),
+                    Some(line),
+                    RejectDisconnect::If421,
+                )
+                .await?;
+                return Ok(CommandDisposition::Terminate);
+            }
+            ReadData::ShuttingDown => {
+                self.write_response(
+                    421,
+                    format!("4.3.2 {} shutting down", self.params.hostname),
+                    Some(line),
+                    RejectDisconnect::If421,
+                )
+                .await?;
+                return Ok(CommandDisposition::Terminate);
+            }
+            ReadData::TooBig | ReadData::TooLong => {
+                unreachable!("read_chunk doesn't check sizes or line lengths")
+            }
+        };
+        read_data_timer.stop_and_record();
+
+        if let Some((code, message)) = rejection {
+            if code == 552 {
+                // The transaction has failed. The client must not send
+                // any further chunks, but it may already have pipelined
+                // some; they will be consumed and rejected as being
+                // outside of a transaction.
+                self.state.take();
+            }
+            self.write_response(code, message, Some(line), RejectDisconnect::If421)
+                .await?;
+            return Ok(CommandDisposition::Continue);
+        }
+
+        let state = self.state.as_mut().expect("checked state above");
+        state
+            .bdat_data
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(&chunk);
+        drop(chunk);
+
+        if !last {
+            self.write_response(
+                250,
+                format!("2.0.0 {chunk_size} octets received"),
+                None,
+                RejectDisconnect::If421,
+            )
+            .await?;
+            return Ok(CommandDisposition::Continue);
+        }
+
+        let data = state.bdat_data.take().unwrap_or_default();
+        if !state.binary_mime && !check_line_lengths(&data, self.params.line_length_hard_limit) {
+            SmtpServerTraceManager::submit(|| SmtpServerTraceEvent {
+                conn_meta: self.meta.clone_inner(),
+                payload: SmtpServerTraceEventPayload::Diagnostic {
+                    level: Level::ERROR,
+                    message: "Line too long".to_string(),
+                },
+                when: Utc::now(),
+            });
+            let responses = self.data_response_count();
+            self.state.take();
+            self.write_data_response(
+                responses,
+                500,
+                "5.2.3 line too long",
+                Some(line),
+                RejectDisconnect::If421,
+            )
+            .await?;
+            return Ok(CommandDisposition::Continue);
+        }
+
+        let _process_data_timer = PROCESS_DATA_LATENCY.start_timer();
+        Box::pin(self.process_data(data, activity, "BDAT")).await?;
+        Ok(CommandDisposition::Continue)
+    }
+
+    /// The number of responses required to complete a DATA or
+    /// BDAT LAST command: LMTP requires one per accepted recipient
+    fn data_response_count(&self) -> usize {
+        match &self.state {
+            Some(state) if self.is_lmtp() => state.recipients.len().max(1),
+            _ => 1,
+        }
+    }
+
+    /// Write a response to DATA or BDAT LAST that applies to the
+    /// transaction as a whole, repeating it `count` times as
+    /// required by LMTP
+    async fn write_data_response<S: AsRef<str> + Debug>(
+        &mut self,
+        count: usize,
+        status: u16,
+        message: S,
+        command: Option<String>,
+        disconnect: RejectDisconnect,
+    ) -> Result<(), WriteError> {
+        for _ in 0..count {
+            self.write_response(status, message.as_ref(), command.clone(), disconnect)
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn process_data(
+        &mut self,
+        mut data: Vec<u8>,
+        activity: &Activity,
+        data_command: &'static str,
+    ) -> anyhow::Result<()> {
         let start = Instant::now();
         let deadline = start + self.params.data_processing_timeout;
 
         self.reception_count.inc();
         self.global_reception_count.inc();
+        let responses = self.data_response_count();
         let state = self
             .state
             .take()
@@ -2732,14 +3648,17 @@ impl SmtpServerSession {
 
         tracing::trace!(?state);
 
-        let lone_lf = mailparsing::has_lone_cr_or_lf(&data);
+        // BINARYMIME content is not line oriented, so there is
+        // no sense in checking it for line ending conformance
+        let lone_lf = !state.binary_mime && mailparsing::has_lone_cr_or_lf(&data);
         if lone_lf {
             match self.params.invalid_line_endings {
                 ConformanceDisposition::Deny => {
-                    self.write_response(
+                    self.write_data_response(
+                        responses,
                         552,
                         "5.6.0 message data must use CRLF for line endings",
-                        Some("DATA".into()),
+                        Some(data_command.into()),
                         RejectDisconnect::If421,
                     )
                     .await?;
@@ -2781,6 +3700,15 @@ impl SmtpServerSession {
             Arc::new(data.clone().into_boxed_slice()),
         )?;
         drop(data);
+        if !state.dsn.is_empty() {
+            base_message.set_dsn_params(&state.dsn).await?;
+        }
+        if state.require_tls {
+            base_message.set_require_tls(true).await?;
+        }
+        if state.binary_mime {
+            base_message.set_binary_mime(true).await?;
+        }
 
         match timeout_at(
             deadline.into(),
@@ -2795,10 +3723,11 @@ impl SmtpServerSession {
         {
             Ok(Ok(Ok(_))) => {}
             Err(_) => {
-                self.write_response(
+                self.write_data_response(
+                    responses,
                     451,
                     "4.4.5 data_processing_timeout exceeded (rx)",
-                    Some("DATA".into()),
+                    Some(data_command.into()),
                     RejectDisconnect::If421,
                 )
                 .await?;
@@ -2809,8 +3738,14 @@ impl SmtpServerSession {
                 // Rejecting any one message from a batch in
                 // smtp_server_message_received will reject the
                 // entire batch
-                self.write_response(rej.code, rej.message, Some("DATA".into()), rej.disconnect)
-                    .await?;
+                self.write_data_response(
+                    responses,
+                    rej.code,
+                    rej.message,
+                    Some(data_command.into()),
+                    rej.disconnect,
+                )
+                .await?;
                 return Ok(());
             }
             Ok(Err(err)) => {
@@ -2827,6 +3762,10 @@ impl SmtpServerSession {
         // here. If anything rejects, we return before we've committed to doing
         // any real work
         let mut accepted_messages = vec![];
+        // The RCPT TO indices covered by each of accepted_messages
+        let mut accepted_rcpt_indices = vec![];
+        // The reception spans are held until we have responded
+        let mut reception_spans = vec![];
 
         let datestamp = Utc::now().to_rfc2822();
 
@@ -2838,6 +3777,25 @@ impl SmtpServerSession {
 
         let mut batches: Vec<Vec<EnvelopeAddress>> = vec![];
 
+        // For LMTP, we must respond to each of the recipients that
+        // were accepted via RCPT TO, so we need to know which of those
+        // are covered by each batch.  Policy may have rewritten the
+        // recipients in smtp_server_data; if it did so one-for-one,
+        // then the rewritten list still corresponds positionally to
+        // state.recipients.  Otherwise there is no way to relate the
+        // rewritten recipients to those of RCPT TO, so every message
+        // is considered to cover all of them, and each recipient is
+        // given the outcome of the transaction as a whole.
+        let rcpt_addresses: Option<Vec<String>> = {
+            let recipients = base_message.recipient_list_string().await?;
+            if recipients.len() == state.recipients.len() {
+                Some(recipients)
+            } else {
+                None
+            }
+        };
+        let mut rcpt_claimed = vec![false; state.recipients.len()];
+
         match timeout_at(
             deadline.into(),
             Box::pin(self.call_callback_sig(
@@ -2881,10 +3839,11 @@ impl SmtpServerSession {
                 }
             }
             Err(_) => {
-                self.write_response(
+                self.write_data_response(
+                    responses,
                     451,
                     "4.4.5 data_processing_timeout exceeded (rx)",
-                    Some("DATA".into()),
+                    Some(data_command.into()),
                     RejectDisconnect::If421,
                 )
                 .await?;
@@ -2892,8 +3851,14 @@ impl SmtpServerSession {
             }
             Ok(Ok(Err(rej))) => {
                 // Explicity kumo.reject'ed.
-                self.write_response(rej.code, rej.message, Some("DATA".into()), rej.disconnect)
-                    .await?;
+                self.write_data_response(
+                    responses,
+                    rej.code,
+                    rej.message,
+                    Some(data_command.into()),
+                    rej.disconnect,
+                )
+                .await?;
                 return Ok(());
             }
             Ok(Err(err)) => {
@@ -2905,13 +3870,41 @@ impl SmtpServerSession {
 
         for recip_list in batches {
             let id = SpoolId::new();
+
+            // Resolve the RCPT TO indices covered by this batch,
+            // taking care to claim each one only once so that
+            // duplicated addresses are each accounted for
+            let mut rcpt_indices = vec![];
+            match &rcpt_addresses {
+                Some(rcpt_addresses) => {
+                    for recip in &recip_list {
+                        let recip = recip.to_string();
+                        if let Some(idx) = rcpt_addresses
+                            .iter()
+                            .zip(&rcpt_claimed)
+                            .position(|(addr, claimed)| !claimed && *addr == recip)
+                        {
+                            rcpt_claimed[idx] = true;
+                            rcpt_indices.push(idx);
+                        }
+                    }
+                }
+                None => rcpt_indices.extend(0..state.recipients.len()),
+            }
+
             let body = if self.params.trace_headers.received_header {
                 let received = {
-                    let protocol = match (&self.authentication_id, &self.tls_active) {
-                        (Some(_auth), Some(_tls)) => "ESMTPSA",
-                        (Some(_auth), None) => "ESMTP", // There is no ESMTPA
-                        (None, Some(_tls)) => "ESMTPS",
-                        (None, None) => "ESMTP",
+                    let protocol = match (self.is_lmtp(), &self.authentication_id, &self.tls_active)
+                    {
+                        (false, Some(_auth), Some(_tls)) => "ESMTPSA",
+                        (false, Some(_auth), None) => "ESMTP", // There is no ESMTPA
+                        (false, None, Some(_tls)) => "ESMTPS",
+                        (false, None, None) => "ESMTP",
+                        // RFC 3848
+                        (true, Some(_auth), Some(_tls)) => "LMTPSA",
+                        (true, Some(_auth), None) => "LMTPA",
+                        (true, None, Some(_tls)) => "LMTPS",
+                        (true, None, None) => "LMTP",
                     };
 
                     let tls_info = match &self.tls_active {
@@ -2944,6 +3937,13 @@ impl SmtpServerSession {
                 base_message.data().await?
             };
 
+            // Each message carries only the DSN parameters
+            // that are relevant to its own recipients
+            let recip_names: Vec<String> = recip_list.iter().map(|r| r.to_string()).collect();
+            let dsn = state
+                .dsn
+                .for_recipients(recip_names.iter().map(|r| r.as_str()));
+
             let message = Message::new_dirty(
                 id,
                 state.sender.clone(),
@@ -2951,25 +3951,48 @@ impl SmtpServerSession {
                 base_message.get_meta_obj().await?,
                 body,
             )?;
+            if !state.dsn.is_empty() {
+                message.set_dsn_params(&dsn).await?;
+            }
+
+            let mut reception_span =
+                start_reception(&message, "smtp_server_reception", SpanKind::Server).await?;
+            reception_span.set_attribute("kumo.session.id", self.session_id.to_string());
+            reception_span.set_attribute("client.address", self.peer_address.ip().to_string());
 
             if self.params.deferred_queue {
                 message.set_meta("queue", DEFERRED_QUEUE_NAME).await?;
             } else {
-                match timeout_at(
+                let mut policy_span =
+                    start_span(&message, "smtp_server_message_received", SpanKind::Internal).await;
+                let result = timeout_at(
                     deadline.into(),
                     Box::pin(self.call_callback_sig(
                         &SMTP_SERVER_MSG_RX,
                         (message.clone(), self.meta.clone()),
                     )),
                 )
-                .await
-                {
+                .await;
+                let error = match &result {
+                    Ok(Ok(Ok(_))) => None,
+                    Err(_) => Some("data_processing_timeout exceeded".to_string()),
+                    Ok(Ok(Err(rej))) => Some(rej.message.clone()),
+                    Ok(Err(err)) => Some(format!("{err:#}")),
+                };
+                if let Some(error) = error {
+                    policy_span.set_error(&error);
+                    reception_span.set_error(error);
+                }
+                drop(policy_span);
+
+                match result {
                     Ok(Ok(Ok(_))) => {}
                     Err(_) => {
-                        self.write_response(
+                        self.write_data_response(
+                            responses,
                             451,
                             "4.4.5 data_processing_timeout exceeded (rx)",
-                            Some("DATA".into()),
+                            Some(data_command.into()),
                             RejectDisconnect::If421,
                         )
                         .await?;
@@ -2980,10 +4003,11 @@ impl SmtpServerSession {
                         // Rejecting any one message from a batch in
                         // smtp_server_message_received will reject the
                         // entire batch
-                        self.write_response(
+                        self.write_data_response(
+                            responses,
                             rej.code,
                             rej.message,
-                            Some("DATA".into()),
+                            Some(data_command.into()),
                             rej.disconnect,
                         )
                         .await?;
@@ -2997,6 +4021,8 @@ impl SmtpServerSession {
                 }
             }
             accepted_messages.push(message);
+            accepted_rcpt_indices.push(rcpt_indices);
+            reception_spans.push(reception_span);
         }
 
         // At this point we've nominally accepted the batch; let's
@@ -3013,10 +4039,11 @@ impl SmtpServerSession {
             let queue_name = message.get_queue_name().await?;
             match timeout_at(deadline.into(), QueueManager::resolve(&queue_name)).await {
                 Err(_) => {
-                    self.write_response(
+                    self.write_data_response(
+                        responses,
                         451,
                         "4.4.5 data_processing_timeout exceeded (resolve)",
-                        Some("DATA".into()),
+                        Some(data_command.into()),
                         RejectDisconnect::If421,
                     )
                     .await?;
@@ -3026,7 +4053,8 @@ impl SmtpServerSession {
                     let err = format!("{error:#}");
 
                     if activity.is_shutting_down() && ShuttingDownError::is_shutting_down(&error) {
-                        self.write_response(
+                        self.write_data_response(
+                            responses,
                             421,
                             format!("4.3.2 {} shutting down", self.params.hostname),
                             None,
@@ -3043,7 +4071,13 @@ impl SmtpServerSession {
         }
 
         let mut messages: Vec<(/* queue_name */ String, Message)> = vec![];
-        for message in accepted_messages {
+        // For LMTP, the response to be sent for each RCPT TO,
+        // indexed in the same way as state.recipients
+        let mut recipient_responses: Vec<Option<(u16, String)>> =
+            vec![None; state.recipients.len()];
+        // For LMTP, the RCPT TO indices covered by each spooled message
+        let mut message_rcpt_indices: HashMap<SpoolId, Vec<usize>> = HashMap::new();
+        for (message, rcpt_indices) in accepted_messages.into_iter().zip(accepted_rcpt_indices) {
             self.params
                 .trace_headers
                 .apply_supplemental(&message)
@@ -3062,18 +4096,12 @@ impl SmtpServerSession {
                 .await?;
 
             let mut relay_this_one = relay_disposition.relay;
-
-            if relay_disposition.log_arf.should_log()
-                && matches!(message.parse_rfc5965().await, Ok(Some(_)))
-            {
-                was_arf_or_oob = true;
-                relay_this_one = relay_disposition.log_arf.should_relay();
-            } else if relay_disposition.log_oob.should_log()
-                && matches!(message.parse_rfc3464().await, Ok(Some(_)))
-            {
-                was_arf_or_oob = true;
-                relay_this_one = relay_disposition.log_oob.should_relay();
+            let received_report = relay_disposition.parse_report(&message).await;
+            let is_report = received_report.is_some();
+            if let Some(report) = &received_report {
+                relay_this_one = relay_disposition.report_should_relay(report);
             }
+            was_arf_or_oob |= is_report;
 
             let sender = message
                 .sender()
@@ -3132,7 +4160,7 @@ impl SmtpServerSession {
                                 },
                                 egress_source: None,
                                 egress_pool: None,
-                                relay_disposition: None,
+                                received_report: None,
                                 delivery_protocol: None,
                                 tls_info: None,
                                 source_address: None,
@@ -3144,10 +4172,11 @@ impl SmtpServerSession {
                         }
 
                         if err.root_cause().is::<tokio::time::error::Elapsed>() {
-                            self.write_response(
+                            self.write_data_response(
+                                responses,
                                 451,
                                 "4.4.5 data_processing_timeout exceeded (spool)",
-                                Some("DATA".into()),
+                                Some(data_command.into()),
                                 RejectDisconnect::If421,
                             )
                             .await?;
@@ -3175,7 +4204,7 @@ impl SmtpServerSession {
                 },
                 egress_pool: None,
                 egress_source: None,
-                relay_disposition: Some(relay_disposition),
+                received_report,
                 delivery_protocol: None,
                 tls_info: self.tls_active.as_ref(),
                 source_address: None,
@@ -3184,6 +4213,19 @@ impl SmtpServerSession {
                 recipient_list: None,
             })
             .await;
+
+            if self.is_lmtp() {
+                let response = if relay_this_one || is_report || queue_name == "null" {
+                    (250, format!("2.0.0 OK id={}", message.id()))
+                } else {
+                    (550, "5.7.1 relaying not permitted".to_string())
+                };
+                for &idx in &rcpt_indices {
+                    record_recipient_response(&mut recipient_responses[idx], &response);
+                }
+                message_rcpt_indices.insert(*message.id(), rcpt_indices);
+            }
+
             if queue_name != "null" {
                 if relay_this_one {
                     messages.push((queue_name, message));
@@ -3220,17 +4262,56 @@ impl SmtpServerSession {
                     when: Utc::now(),
                 });
 
-                if err.root_cause().is::<tokio::time::error::Elapsed>() {
+                let timed_out = err.root_cause().is::<tokio::time::error::Elapsed>();
+                if timed_out {
                     expired_count += 1;
                 }
+
+                if self.is_lmtp() {
+                    let response = if timed_out {
+                        "4.4.5 data_processing_timeout exceeded (insert)".to_string()
+                    } else {
+                        format!("4.3.0 {} technical difficulties", self.params.hostname)
+                    };
+                    let response = (451, response);
+                    for &idx in message_rcpt_indices.get(&id).into_iter().flatten() {
+                        record_recipient_response(&mut recipient_responses[idx], &response);
+                    }
+                }
+            }
+        }
+
+        if self.is_lmtp() {
+            // RFC 2033: one response for each accepted recipient,
+            // in the order in which they were accepted.
+            // A recipient that isn't covered by any of the messages
+            // that we accepted, for example because it was removed
+            // by smtp_server_split_transaction, must not be reported
+            // as delivered.
+            for response in recipient_responses {
+                let (code, message) = response.unwrap_or_else(|| {
+                    (
+                        451,
+                        "4.3.0 no message was accepted for this recipient".to_string(),
+                    )
+                });
+                self.write_response(
+                    code,
+                    message,
+                    Some(data_command.into()),
+                    RejectDisconnect::If421,
+                )
+                .await?;
             }
+            return Ok(());
         }
 
         if !black_holed && !relayed_any && !was_arf_or_oob {
-            self.write_response(
+            self.write_data_response(
+                responses,
                 550,
                 "5.7.1 relaying not permitted",
-                Some("DATA".into()),
+                Some(data_command.into()),
                 RejectDisconnect::If421,
             )
             .await?;
@@ -3239,10 +4320,11 @@ impl SmtpServerSession {
 
             if expired_count == failed.len() {
                 // They were all timeout errors
-                self.write_response(
+                self.write_data_response(
+                    responses,
                     451,
                     "4.4.5 data_processing_timeout exceeded (insert)",
-                    Some("DATA".into()),
+                    Some(data_command.into()),
                     RejectDisconnect::If421,
                 )
                 .await?;
@@ -3259,10 +4341,11 @@ impl SmtpServerSession {
             let disposition = if !failed.is_empty() { "PARTIAL" } else { "OK" };
 
             let ids = ids.join(" ");
-            self.write_response(
+            self.write_data_response(
+                responses,
                 250,
                 format!("{disposition} ids={ids}"),
-                Some("DATA".into()),
+                Some(data_command.into()),
                 RejectDisconnect::If421,
             )
             .await?;
@@ -3536,7 +4619,7 @@ impl QueueDispatcher for DeferredSmtpInjectionDispatcher {
             response: response.clone(),
             egress_pool: None,
             egress_source: None,
-            relay_disposition: None,
+            received_report: None,
             delivery_protocol: Some("DeferredSmtpInjection"),
             tls_info: None,
             source_address: None,
@@ -1,8 +1,10 @@
 use crate::logging::disposition::{log_disposition, LogDisposition, RecordType};
 use crate::queue::{InsertReason, Queue, QueueManager};
 use anyhow::Context;
+use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use config::{any_err, from_lua_value, get_or_create_module, CallbackSignature};
+use data_loader::KeySource;
 use humansize::{format_size, DECIMAL};
 use humantime::format_duration;
 use kumo_server_common::disk_space::{MinFree, MonitoredPath};
@@ -13,9 +15,17 @@ use message::Message;
 use mlua::{Lua, Value};
 use rfc5321::{EnhancedStatusCode, Response};
 use serde::Deserialize;
+use spool::compressed::CompressedSpool;
+use spool::encrypted::{EncryptedSpool, SpoolEncryptionKey};
+use spool::journal::JournalParams;
 use spool::local_disk::LocalDiskSpool;
+use spool::object_store::{
+    ObjectStoreConfig, ObjectStoreCredentialProvider, ObjectStoreCredentials, ObjectStoreSpool,
+};
 use spool::rocks::{RocksSpool, RocksSpoolParams};
-use spool::{get_data_spool, get_meta_spool, Spool as SpoolTrait, SpoolEntry, SpoolId};
+use spool::{
+    get_data_spool, get_meta_spool, join_enumerate, Spool as SpoolTrait, SpoolEntry, SpoolId,
+};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
@@ -67,6 +77,7 @@ impl Spool {}
 pub enum SpoolKind {
     LocalDisk,
     RocksDB,
+    ObjectStore,
 }
 impl Default for SpoolKind {
     fn default() -> Self {
@@ -78,13 +89,23 @@ impl Default for SpoolKind {
 #[serde(deny_unknown_fields)]
 pub struct DefineSpoolParams {
     pub name: String,
-    pub path: PathBuf,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
     #[serde(default)]
     pub kind: SpoolKind,
     #[serde(default)]
     pub flush: bool,
     #[serde(default)]
     pub rocks_params: Option<RocksSpoolParams>,
+    #[serde(default)]
+    pub journal: Option<JournalParams>,
+    #[serde(default)]
+    pub object_store: Option<ObjectStoreParams>,
+
+    #[serde(default)]
+    pub encryption: Option<SpoolEncryptionParams>,
+    #[serde(default)]
+    pub compression: Option<SpoolCompressionParams>,
 
     #[serde(default)]
     pub min_free_space: MinFree,
@@ -92,14 +113,178 @@ pub struct DefineSpoolParams {
     pub min_free_inodes: MinFree,
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolEncryptionParams {
+    /// The first key is used to encrypt new records; the others
+    /// are retained to decrypt records written before a rotation
+    pub keys: Vec<SpoolEncryptionKeyParams>,
+    /// Treat records that are not encrypted as corrupt, rather
+    /// than assuming that they were written before encryption
+    /// was enabled
+    #[serde(default)]
+    pub require_encrypted: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolEncryptionKeyParams {
+    pub id: String,
+    pub key: KeySource,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ObjectStoreParams {
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default = "ObjectStoreParams::default_region")]
+    pub region: String,
+    pub access_key: KeySource,
+    pub secret_key: KeySource,
+    #[serde(default)]
+    pub session_token: Option<KeySource>,
+    #[serde(default)]
+    pub path_style: bool,
+    #[serde(
+        default = "ObjectStoreParams::default_timeout",
+        with = "duration_serde"
+    )]
+    pub timeout: Duration,
+    /// How often to reload the credentials from their KeySources
+    #[serde(
+        default = "ObjectStoreParams::default_credential_refresh_interval",
+        with = "duration_serde"
+    )]
+    pub credential_refresh_interval: Duration,
+}
+
+/// Loads the object store credentials from their KeySources,
+/// so that they are reloaded each time that they are refreshed
+struct KeySourceCredentials {
+    access_key: KeySource,
+    secret_key: KeySource,
+    session_token: Option<KeySource>,
+}
+
+#[async_trait]
+impl ObjectStoreCredentialProvider for KeySourceCredentials {
+    async fn credentials(&self) -> anyhow::Result<ObjectStoreCredentials> {
+        async fn load_string(source: &KeySource, what: &str) -> anyhow::Result<String> {
+            let data = source
+                .get()
+                .await
+                .with_context(|| format!("loading object store {what}"))?;
+            Ok(String::from_utf8(data)
+                .with_context(|| format!("object store {what} is not UTF-8"))?
+                .trim()
+                .to_string())
+        }
+
+        let session_token = match &self.session_token {
+            Some(token) => Some(load_string(token, "session_token").await?),
+            None => None,
+        };
+
+        Ok(ObjectStoreCredentials {
+            access_key: load_string(&self.access_key, "access_key").await?,
+            secret_key: load_string(&self.secret_key, "secret_key").await?,
+            session_token,
+        })
+    }
+}
+
+impl ObjectStoreParams {
+    fn default_region() -> String {
+        "us-east-1".to_string()
+    }
+
+    fn default_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_credential_refresh_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    async fn load_config(&self) -> anyhow::Result<ObjectStoreConfig> {
+        let credentials = KeySourceCredentials {
+            access_key: self.access_key.clone(),
+            secret_key: self.secret_key.clone(),
+            session_token: self.session_token.clone(),
+        };
+        // Surface configuration problems when the spool is defined,
+        // rather than on the first request
+        credentials.credentials().await?;
+
+        Ok(ObjectStoreConfig {
+            endpoint: self.endpoint.clone(),
+            bucket: self.bucket.clone(),
+            prefix: self.prefix.clone(),
+            region: self.region.clone(),
+            credentials: Arc::new(credentials),
+            credential_refresh: self.credential_refresh_interval,
+            path_style: self.path_style,
+            timeout: self.timeout,
+        })
+    }
+}
+
+impl DefineSpoolParams {
+    fn local_path(&self) -> anyhow::Result<&PathBuf> {
+        self.path
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("spool {} requires a path", self.name))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpoolCompressionParams {
+    #[serde(default = "SpoolCompressionParams::default_level")]
+    pub level: i32,
+    /// A dictionary trained on representative message content
+    #[serde(default)]
+    pub dictionary: Option<KeySource>,
+    /// Dictionaries that were previously used, which are retained
+    /// to decompress the records that were written using them
+    #[serde(default)]
+    pub previous_dictionaries: Vec<KeySource>,
+}
+
+impl SpoolCompressionParams {
+    fn default_level() -> i32 {
+        3
+    }
+}
+
+impl SpoolEncryptionParams {
+    async fn load_keys(&self) -> anyhow::Result<Vec<SpoolEncryptionKey>> {
+        let mut keys = vec![];
+        for key in &self.keys {
+            let material = key
+                .key
+                .get()
+                .await
+                .with_context(|| format!("loading spool encryption key {}", key.id))?;
+            keys.push(SpoolEncryptionKey::new(&key.id, &material)?);
+        }
+        Ok(keys)
+    }
+}
+
 async fn define_spool(params: DefineSpoolParams) -> anyhow::Result<()> {
-    MonitoredPath {
-        name: format!("{} spool", params.name),
-        path: params.path.clone(),
-        min_free_space: params.min_free_space,
-        min_free_inodes: params.min_free_inodes,
+    if !matches!(params.kind, SpoolKind::ObjectStore) {
+        MonitoredPath {
+            name: format!("{} spool", params.name),
+            path: params.local_path()?.clone(),
+            min_free_space: params.min_free_space,
+            min_free_inodes: params.min_free_inodes,
+        }
+        .register();
     }
-    .register();
 
     crate::spool::SpoolManager::get()
         .new_local_disk(params)
@@ -189,34 +374,110 @@ impl SpoolManager {
     }
 
     pub async fn new_local_disk(&self, params: DefineSpoolParams) -> anyhow::Result<()> {
-        tracing::debug!(
-            "Defining local disk spool '{}' on {}",
-            params.name,
-            params.path.display()
+        tracing::debug!("Defining spool '{}'", params.name);
+        anyhow::ensure!(
+            params.journal.is_none() || matches!(params.kind, SpoolKind::LocalDisk),
+            "spool {}: journal is only supported by the LocalDisk spool kind",
+            params.name
         );
+        let spool: Arc<dyn SpoolTrait + Send + Sync> = match params.kind {
+            SpoolKind::LocalDisk => Arc::new(
+                LocalDiskSpool::with_journal(
+                    params.local_path()?,
+                    params.flush,
+                    params.journal.clone(),
+                    kumo_server_runtime::get_main_runtime(),
+                )
+                .with_context(|| format!("Opening spool {}", params.name))?,
+            ),
+            SpoolKind::RocksDB => Arc::new(
+                RocksSpool::new(
+                    params.local_path()?,
+                    params.flush,
+                    params.rocks_params,
+                    kumo_server_runtime::get_main_runtime(),
+                )
+                .with_context(|| format!("Opening spool {}", params.name))?,
+            ),
+            SpoolKind::ObjectStore => {
+                // The meta spool is rewritten every time a message is
+                // deferred, which is too chatty for an object store
+                anyhow::ensure!(
+                    params.name != "meta",
+                    "the ObjectStore spool kind can only be used for the data spool"
+                );
+                let object_store = params.object_store.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "spool {} is of kind ObjectStore but has no object_store parameters",
+                        params.name
+                    )
+                })?;
+                let config = object_store
+                    .load_config()
+                    .await
+                    .with_context(|| format!("Opening spool {}", params.name))?;
+                Arc::new(
+                    ObjectStoreSpool::new(config, kumo_server_runtime::get_main_runtime())
+                        .with_context(|| format!("Opening spool {}", params.name))?,
+                )
+            }
+        };
+
+        let spool: Arc<dyn SpoolTrait + Send + Sync> = match &params.encryption {
+            Some(encryption) => {
+                let keys = encryption
+                    .load_keys()
+                    .await
+                    .with_context(|| format!("Opening spool {}", params.name))?;
+                Arc::new(EncryptedSpool::new(
+                    spool,
+                    keys,
+                    encryption.require_encrypted,
+                )?)
+            }
+            None => spool,
+        };
+
+        // Compression is applied to the plaintext, so it must wrap
+        // the encryption layer. We always wrap the spool so that
+        // records remain readable if compression is later disabled.
+        let spool: Arc<dyn SpoolTrait + Send + Sync> = {
+            let (level, dictionary, previous_dictionaries) = match &params.compression {
+                Some(compression) => {
+                    let dictionary = match &compression.dictionary {
+                        Some(source) => Some(source.get().await.with_context(|| {
+                            format!("loading compression dictionary for spool {}", params.name)
+                        })?),
+                        None => None,
+                    };
+                    let mut previous_dictionaries = vec![];
+                    for source in &compression.previous_dictionaries {
+                        previous_dictionaries.push(source.get().await.with_context(|| {
+                            format!(
+                                "loading previous compression dictionary for spool {}",
+                                params.name
+                            )
+                        })?);
+                    }
+                    (Some(compression.level), dictionary, previous_dictionaries)
+                }
+                None => (None, None, vec![]),
+            };
+            Arc::new(CompressedSpool::new(
+                spool,
+                &params.name,
+                level,
+                dictionary.as_deref(),
+                &previous_dictionaries,
+                kumo_server_runtime::get_main_runtime(),
+            )?)
+        };
+
         self.named.lock().await.insert(
             params.name.to_string(),
             SpoolHandle(Arc::new(Spool {
                 maintainer: StdMutex::new(None),
-                spool: match params.kind {
-                    SpoolKind::LocalDisk => Arc::new(
-                        LocalDiskSpool::new(
-                            &params.path,
-                            params.flush,
-                            kumo_server_runtime::get_main_runtime(),
-                        )
-                        .with_context(|| format!("Opening spool {}", params.name))?,
-                    ),
-                    SpoolKind::RocksDB => Arc::new(
-                        RocksSpool::new(
-                            &params.path,
-                            params.flush,
-                            params.rocks_params,
-                            kumo_server_runtime::get_main_runtime(),
-                        )
-                        .with_context(|| format!("Opening spool {}", params.name))?,
-                    ),
-                },
+                spool,
             })),
         );
         Ok(())
@@ -325,7 +586,7 @@ impl SpoolManager {
                             },
                             egress_pool: None,
                             egress_source: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             tls_info: None,
                             source_address: None,
@@ -369,7 +630,7 @@ impl SpoolManager {
                             },
                             egress_pool: None,
                             egress_source: None,
-                            relay_disposition: None,
+                            received_report: None,
                             delivery_protocol: None,
                             tls_info: None,
                             source_address: None,
@@ -480,7 +741,7 @@ impl SpoolManager {
                                     },
                                     egress_pool,
                                     egress_source,
-                                    relay_disposition: None,
+                                    received_report: None,
                                     delivery_protocol: None,
                                     tls_info: None,
                                     source_address: None,
@@ -536,10 +797,23 @@ impl SpoolManager {
                         async move {
                             // start enumeration
                             if let Some(tx) = tx {
-                                if let Err(err) = spool.enumerate(tx, start_time) {
-                                    tracing::error!(
-                                        "error during spool enumeration for {name}: {err:#}"
-                                    );
+                                match spool.enumerate(tx, start_time) {
+                                    Ok(enumeration) => {
+                                        let name = name.clone();
+                                        tokio::spawn(async move {
+                                            if let Err(err) = join_enumerate(enumeration).await {
+                                                tracing::error!(
+                                                    "error during spool enumeration \
+                                                     for {name}: {err:#}"
+                                                );
+                                            }
+                                        });
+                                    }
+                                    Err(err) => {
+                                        tracing::error!(
+                                            "error during spool enumeration for {name}: {err:#}"
+                                        );
+                                    }
                                 }
                             }
 
@@ -0,0 +1,940 @@
+//! This module implements SMTP TLS Reporting, RFC 8460.
+//! The outcome of TLS negotiation for each connection to a destination
+//! that we resolved via MX records is recorded against its policy domain,
+//! and a daily aggregate report is sent to each policy domain that
+//! publishes a TLSRPT record.
+//! The accumulated data is periodically saved to a state file, so
+//! that it is not lost when kumod is restarted.
+
+use crate::queue::{InsertReason, QueueManager};
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use config::{any_err, declare_event, load_config};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use kumo_server_lifecycle::ShutdownSubcription;
+use kumo_server_runtime::{get_main_runtime, rt_spawn};
+use mailparsing::{AttachmentOptions, MimePart};
+use message::{EnvelopeAddress, Message};
+use mlua::{Lua, LuaSerdeExt};
+use mta_sts::tlsrpt::{
+    get_tlsrpt_record_for_domain, DateRange, FailureDetails, Policy, PolicyReport, PolicyType,
+    Report, ReportUri, ResultType, Summary,
+};
+use parking_lot::FairMutex as Mutex;
+use rfc5321::ClientError;
+use serde::{Deserialize, Serialize};
+use spool::SpoolId;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+declare_event! {
+static TLS_REPORT_GENERATED: Single(
+    "tls_report_generated",
+    message: Message,
+) -> ();
+}
+
+static CONFIG: LazyLock<Mutex<Option<Arc<TlsReportingParams>>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+static AGGREGATE: LazyLock<Mutex<Aggregate>> = LazyLock::new(|| Mutex::new(Aggregate::default()));
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TlsReportingParams {
+    /// Used as the `organization-name` field of the report
+    pub organization_name: String,
+    /// Used as the `contact-info` field of the report
+    pub contact_info: String,
+    /// The domain that is submitting the reports; it is used in the
+    /// report filename and in the `TLS-Report-Submitter` header
+    pub reporting_domain: String,
+    /// The envelope sender and `From` address of reports that
+    /// are sent via email
+    pub sender: String,
+    /// How often to generate reports. The reporting period is
+    /// aligned to a multiple of this interval since the unix
+    /// epoch, so the default of 1 day produces reports covering
+    /// each UTC day.
+    #[serde(
+        default = "TlsReportingParams::default_report_interval",
+        with = "duration_serde"
+    )]
+    pub report_interval: Duration,
+    /// Where to save the data that has been accumulated for the
+    /// current reporting period
+    #[serde(default = "TlsReportingParams::default_state_path")]
+    pub state_path: PathBuf,
+    /// How often to save the accumulated data to `state_path`
+    #[serde(
+        default = "TlsReportingParams::default_save_interval",
+        with = "duration_serde"
+    )]
+    pub save_interval: Duration,
+}
+
+impl TlsReportingParams {
+    fn default_report_interval() -> Duration {
+        Duration::from_secs(86400)
+    }
+
+    fn default_state_path() -> PathBuf {
+        "/var/spool/kumomta/tls-rpt.json".into()
+    }
+
+    fn default_save_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+struct PolicyCounts {
+    successful_sessions: u64,
+    failed_sessions: HashMap<FailureDetails, u64>,
+}
+
+#[derive(Default, Debug, Clone)]
+struct Aggregate {
+    policies: HashMap<Policy, PolicyCounts>,
+}
+
+/// The form in which the aggregate for a reporting period is saved
+#[derive(Serialize, Deserialize, Debug)]
+struct PersistedAggregate {
+    period_start: DateTime<Utc>,
+    policies: Vec<PolicyReport>,
+}
+
+impl Aggregate {
+    fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    /// Fold previously reported sections back into the aggregate
+    fn merge_policy_reports(&mut self, reports: Vec<PolicyReport>) {
+        for report in reports {
+            let counts = self.policies.entry(report.policy).or_default();
+            counts.successful_sessions += report.summary.total_successful_session_count;
+            for mut failure in report.failure_details {
+                let count = std::mem::take(&mut failure.failed_session_count);
+                *counts.failed_sessions.entry(failure).or_default() += count;
+            }
+        }
+    }
+
+    fn to_persisted(&self, period_start: DateTime<Utc>) -> PersistedAggregate {
+        PersistedAggregate {
+            period_start,
+            policies: self
+                .clone()
+                .into_policy_reports()
+                .into_values()
+                .flatten()
+                .collect(),
+        }
+    }
+
+    fn record_success(&mut self, policy: &Policy) {
+        self.policies
+            .entry(policy.clone())
+            .or_default()
+            .successful_sessions += 1;
+    }
+
+    fn record_failure(&mut self, policy: &Policy, failure: FailureDetails) {
+        *self
+            .policies
+            .entry(policy.clone())
+            .or_default()
+            .failed_sessions
+            .entry(failure)
+            .or_default() += 1;
+    }
+
+    /// Produce the per-policy report sections, grouped by policy domain
+    fn into_policy_reports(self) -> BTreeMap<String, Vec<PolicyReport>> {
+        let mut result: BTreeMap<String, Vec<PolicyReport>> = BTreeMap::new();
+        for (policy, counts) in self.policies {
+            let mut failure_details: Vec<FailureDetails> = counts
+                .failed_sessions
+                .into_iter()
+                .map(|(mut failure, count)| {
+                    failure.failed_session_count = count;
+                    failure
+                })
+                .collect();
+            failure_details.sort();
+
+            let summary = Summary {
+                total_successful_session_count: counts.successful_sessions,
+                total_failure_session_count: failure_details
+                    .iter()
+                    .map(|f| f.failed_session_count)
+                    .sum(),
+            };
+
+            result
+                .entry(policy.policy_domain.clone())
+                .or_default()
+                .push(PolicyReport {
+                    policy,
+                    summary,
+                    failure_details,
+                });
+        }
+        for reports in result.values_mut() {
+            reports.sort_by(|a, b| a.policy.cmp(&b.policy));
+        }
+        result
+    }
+}
+
+/// Tracks the TLS policy that applies to a connection attempt,
+/// and records the outcome of TLS negotiation for that connection
+pub struct TlsReportingSession {
+    policy: Option<Policy>,
+    sending_mta_ip: Option<IpAddr>,
+    receiving_mx_hostname: String,
+    receiving_ip: Option<IpAddr>,
+}
+
+impl TlsReportingSession {
+    /// Prepare to record the outcome of a connection to the specified
+    /// MX host. `policy_domain` should be None if the destination was
+    /// not resolved via MX records, in which case nothing will be recorded.
+    pub fn new(
+        policy_domain: Option<&str>,
+        sending_mta_ip: Option<IpAddr>,
+        receiving_mx_hostname: &str,
+        receiving_ip: Option<IpAddr>,
+    ) -> Self {
+        let enabled = CONFIG.lock().is_some();
+        Self {
+            policy: policy_domain.filter(|_| enabled).map(|domain| Policy {
+                policy_type: PolicyType::NoPolicyFound,
+                policy_string: vec![],
+                policy_domain: domain.trim_end_matches('.').to_string(),
+                mx_host: vec![],
+            }),
+            sending_mta_ip,
+            receiving_mx_hostname: receiving_mx_hostname.trim_end_matches('.').to_string(),
+            receiving_ip,
+        }
+    }
+
+    /// Record the policy that was discovered for the destination
+    pub fn set_policy(
+        &mut self,
+        policy_type: PolicyType,
+        policy_string: Vec<String>,
+        mx_host: Vec<String>,
+    ) {
+        if let Some(policy) = &mut self.policy {
+            policy.policy_type = policy_type;
+            policy.policy_string = policy_string;
+            policy.mx_host = mx_host;
+        }
+    }
+
+    /// Record a successful TLS session
+    pub fn success(&self) {
+        if let Some(policy) = &self.policy {
+            AGGREGATE.lock().record_success(policy);
+        }
+    }
+
+    /// Record a failed TLS session
+    pub fn failure(&self, result_type: ResultType, failure_reason_code: Option<String>) {
+        if let Some(policy) = &self.policy {
+            AGGREGATE.lock().record_failure(
+                policy,
+                FailureDetails {
+                    result_type,
+                    sending_mta_ip: self.sending_mta_ip.map(|ip| ip.to_string()),
+                    receiving_mx_hostname: Some(self.receiving_mx_hostname.clone()),
+                    receiving_mx_helo: None,
+                    receiving_ip: self.receiving_ip.map(|ip| ip.to_string()),
+                    failed_session_count: 0,
+                    additional_information: None,
+                    failure_reason_code,
+                },
+            );
+        }
+    }
+
+    /// Record a failed TLS handshake, classifying the failure
+    /// based on the error message
+    pub fn handshake_failure(&self, error: &str) {
+        let result_type = if self.is_dane() {
+            classify_dane_handshake_error(error)
+        } else {
+            classify_handshake_error(error)
+        };
+        self.failure(result_type, Some(error.to_string()));
+    }
+
+    /// Record a STARTTLS attempt that failed before the handshake
+    /// could take place
+    pub fn starttls_error(&self, error: &ClientError) {
+        match error {
+            ClientError::Rejected(response) => self.failure(
+                ResultType::StarttlsNotSupported,
+                Some(response.to_single_line()),
+            ),
+            // The connector can only fail to build for a reason that
+            // is attributable to the destination when none of its
+            // TLSA records are usable
+            ClientError::FailedToBuildConnector { error } if self.is_dane() => {
+                self.failure(ResultType::TlsaInvalid, Some(error.to_string()))
+            }
+            _ => {}
+        }
+    }
+
+    fn is_dane(&self) -> bool {
+        self.policy
+            .as_ref()
+            .is_some_and(|policy| policy.policy_type == PolicyType::Tlsa)
+    }
+}
+
+/// When DANE is in use, the certificate is validated against the
+/// TLSA records rather than the web PKI, so a verification failure
+/// means that none of the TLSA records matched
+fn classify_dane_handshake_error(error: &str) -> ResultType {
+    let lower = error.to_lowercase();
+    if lower.contains("tlsa")
+        || lower.contains("dane")
+        || lower.contains("certificate verify failed")
+        || classify_handshake_error(error) != ResultType::ValidationFailure
+    {
+        ResultType::TlsaInvalid
+    } else {
+        ResultType::ValidationFailure
+    }
+}
+
+fn classify_handshake_error(error: &str) -> ResultType {
+    let lower = error.to_lowercase();
+    if lower.contains("expired") {
+        ResultType::CertificateExpired
+    } else if lower.contains("notvalidforname")
+        || lower.contains("hostname mismatch")
+        || lower.contains("not valid for name")
+    {
+        ResultType::CertificateHostMismatch
+    } else if lower.contains("unknownissuer")
+        || lower.contains("self-signed")
+        || lower.contains("self signed")
+        || lower.contains("unable to get local issuer")
+    {
+        ResultType::CertificateNotTrusted
+    } else {
+        ResultType::ValidationFailure
+    }
+}
+
+/// Returns the start of the reporting period that contains `now`
+pub(crate) fn period_start(now: DateTime<Utc>, interval: Duration) -> DateTime<Utc> {
+    let interval = interval.as_secs().max(1) as i64;
+    let start = now.timestamp() - now.timestamp().rem_euclid(interval);
+    Utc.timestamp_opt(start, 0).single().unwrap_or(now)
+}
+
+fn build_report(
+    params: &TlsReportingParams,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    policies: Vec<PolicyReport>,
+) -> Report {
+    Report {
+        organization_name: params.organization_name.clone(),
+        date_range: DateRange {
+            start_datetime: start,
+            end_datetime: end,
+        },
+        contact_info: params.contact_info.clone(),
+        report_id: Uuid::new_v4().to_string(),
+        policies,
+    }
+}
+
+fn gzip_report(report: &Report) -> anyhow::Result<Vec<u8>> {
+    let json = serde_json::to_vec(report)?;
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(&json)?;
+    Ok(encoder.finish()?)
+}
+
+/// Build the RFC 8460 section 5.3 email representation of a report
+fn build_report_message(
+    params: &TlsReportingParams,
+    policy_domain: &str,
+    report: &Report,
+    compressed: &[u8],
+    recipient: &str,
+) -> anyhow::Result<MimePart<'static>> {
+    let file_name = format!(
+        "{submitter}!{policy_domain}!{start}!{end}!{id}.json.gz",
+        submitter = params.reporting_domain,
+        start = report.date_range.start_datetime.timestamp(),
+        end = report.date_range.end_datetime.timestamp(),
+        id = report.report_id
+    );
+
+    let exposition = format!(
+        "This is an aggregate TLS report from {org} for {policy_domain}\r\n\
+         covering the period {start} to {end}.\r\n",
+        org = params.organization_name,
+        start = report.date_range.start_datetime.to_rfc3339(),
+        end = report.date_range.end_datetime.to_rfc3339(),
+    );
+
+    let parts = vec![
+        MimePart::new_text_plain(&exposition).context("new_text_plain")?,
+        MimePart::new_binary(
+            "application/tlsrpt+gzip",
+            compressed,
+            Some(&AttachmentOptions {
+                file_name: Some(file_name),
+                inline: false,
+                content_id: None,
+            }),
+        )
+        .context("new_binary")?,
+    ];
+
+    let mut report_msg = MimePart::new_multipart("multipart/report", parts, None)?;
+
+    let mut ct = report_msg
+        .headers()
+        .content_type()
+        .context("get content_type")?
+        .expect("assigned during construction");
+    ct.set("report-type", "tlsrpt");
+    let headers = report_msg.headers_mut();
+    headers.set_content_type(ct).context("set_content_type")?;
+    headers
+        .set_subject(
+            format!(
+                "Report Domain: {policy_domain} Submitter: {submitter} Report-ID: <{id}>",
+                submitter = params.reporting_domain,
+                id = report.report_id
+            )
+            .as_str(),
+        )
+        .context("set_subject")?;
+    headers.append_header("TLS-Report-Domain", policy_domain);
+    headers.append_header("TLS-Report-Submitter", params.reporting_domain.as_str());
+    headers
+        .set_mime_version("1.0")
+        .context("set_mime_version")?;
+    headers
+        .set_message_id(format!("<{}@{}>", report.report_id, params.reporting_domain).as_str())
+        .context("set_message_id")?;
+    headers.set_to(recipient).context("set_to")?;
+    headers
+        .set_from(params.sender.as_str())
+        .context("set_from")?;
+    headers.set_date(Utc::now()).context("set_date")?;
+
+    Ok(report_msg)
+}
+
+async fn send_report_via_mail(
+    params: &TlsReportingParams,
+    policy_domain: &str,
+    report: &Report,
+    compressed: &[u8],
+    recipient: &str,
+) -> anyhow::Result<()> {
+    let report_msg = build_report_message(params, policy_domain, report, compressed, recipient)?;
+
+    let msg = Message::new_dirty(
+        SpoolId::new(),
+        EnvelopeAddress::parse(&params.sender)?,
+        vec![EnvelopeAddress::parse(recipient)?],
+        serde_json::json!({}),
+        Arc::new(
+            report_msg
+                .to_message_string()
+                .into_bytes()
+                .into_boxed_slice(),
+        ),
+    )?;
+
+    let mut config = load_config().await?;
+    config
+        .async_call_callback(&TLS_REPORT_GENERATED, msg.clone())
+        .await?;
+    config.put();
+
+    let queue_name = msg.get_queue_name().await?;
+    if queue_name == "null" {
+        return Ok(());
+    }
+    msg.save(None).await?;
+    QueueManager::insert(&queue_name, msg, InsertReason::Received.into()).await
+}
+
+async fn send_report_via_https(
+    client: &reqwest::Client,
+    url: &str,
+    compressed: &[u8],
+) -> anyhow::Result<()> {
+    let response = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/tlsrpt+gzip")
+        .body(compressed.to_vec())
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!("failed to POST report to {url}: {status}");
+    }
+    Ok(())
+}
+
+/// Send out the reports for the data accumulated during a reporting period
+async fn send_reports(
+    params: &TlsReportingParams,
+    client: &reqwest::Client,
+    aggregate: Aggregate,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) {
+    for (policy_domain, policies) in aggregate.into_policy_reports() {
+        let record = match get_tlsrpt_record_for_domain(&policy_domain).await {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::debug!("not sending TLS report for {policy_domain}: {err:#}");
+                continue;
+            }
+        };
+
+        let report = build_report(params, start, end, policies);
+        let compressed = match gzip_report(&report) {
+            Ok(c) => c,
+            Err(err) => {
+                tracing::error!("failed to encode TLS report for {policy_domain}: {err:#}");
+                continue;
+            }
+        };
+
+        for uri in &record.rua {
+            let result = match uri {
+                ReportUri::Mailto(recipient) => {
+                    send_report_via_mail(params, &policy_domain, &report, &compressed, recipient)
+                        .await
+                }
+                ReportUri::Https(url) => send_report_via_https(client, url, &compressed).await,
+            };
+            if let Err(err) = result {
+                tracing::error!(
+                    "failed to send TLS report for {policy_domain} to {uri:?}: {err:#}"
+                );
+            }
+        }
+    }
+}
+
+fn load_state(path: &Path) -> anyhow::Result<Option<PersistedAggregate>> {
+    match std::fs::read(path) {
+        Ok(data) => Ok(Some(serde_json::from_slice(&data).with_context(|| {
+            format!("parsing TLS reporting state {}", path.display())
+        })?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => {
+            Err(err).with_context(|| format!("reading TLS reporting state {}", path.display()))
+        }
+    }
+}
+
+fn save_state(path: &Path, state: &PersistedAggregate) -> anyhow::Result<()> {
+    let data = serde_json::to_vec(state)?;
+    // Write to a temporary file and rename it into place, so that
+    // a crash part way through doesn't leave a truncated file
+    let temp_path = path.with_extension("tmp");
+    std::fs::write(&temp_path, data)
+        .with_context(|| format!("writing TLS reporting state {}", temp_path.display()))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("renaming TLS reporting state to {}", path.display()))?;
+    Ok(())
+}
+
+/// Save the data accumulated so far for the period beginning at `start`
+async fn persist_aggregate(params: &TlsReportingParams, start: DateTime<Utc>) {
+    let state = AGGREGATE.lock().to_persisted(start);
+    let path = params.state_path.clone();
+    let result = match get_main_runtime()
+        .spawn_blocking(move || save_state(&path, &state))
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => Err(err.into()),
+    };
+    if let Err(err) = result {
+        tracing::error!("failed to save TLS reporting state: {err:#}");
+    }
+}
+
+/// Load the data saved by a previous run. Data for the current
+/// reporting period is restored into the aggregate, while the
+/// data for an earlier period is returned so that it can be reported.
+async fn restore_aggregate(
+    params: &TlsReportingParams,
+    current_start: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, Aggregate)> {
+    let path = params.state_path.clone();
+    let state = match get_main_runtime()
+        .spawn_blocking(move || load_state(&path))
+        .await
+    {
+        Ok(Ok(Some(state))) => state,
+        Ok(Ok(None)) => return None,
+        Ok(Err(err)) => {
+            tracing::error!("failed to restore TLS reporting state: {err:#}");
+            return None;
+        }
+        Err(err) => {
+            tracing::error!("failed to restore TLS reporting state: {err:#}");
+            return None;
+        }
+    };
+
+    if state.period_start == current_start {
+        AGGREGATE.lock().merge_policy_reports(state.policies);
+        return None;
+    }
+
+    let mut aggregate = Aggregate::default();
+    aggregate.merge_policy_reports(state.policies);
+    if aggregate.is_empty() {
+        None
+    } else {
+        Some((state.period_start, aggregate))
+    }
+}
+
+async fn report_generator(params: Arc<TlsReportingParams>) {
+    let mut shutdown = ShutdownSubcription::get();
+    let interval =
+        chrono::Duration::from_std(params.report_interval).unwrap_or(chrono::Duration::days(1));
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("TLS report generator failed to build HTTP client: {err:#}");
+            return;
+        }
+    };
+
+    let mut start = period_start(Utc::now(), params.report_interval);
+    if let Some((prior_start, prior)) = restore_aggregate(&params, start).await {
+        // The data is from a period that ended while we were not running
+        let prior_end = (prior_start + interval).min(start);
+        send_reports(
+            &params,
+            &client,
+            prior,
+            prior_start,
+            prior_end - chrono::Duration::seconds(1),
+        )
+        .await;
+        persist_aggregate(&params, start).await;
+    }
+
+    let mut save_interval = tokio::time::interval(params.save_interval);
+    loop {
+        let end = start + interval;
+        let wait = (end - Utc::now()).to_std().unwrap_or(Duration::ZERO);
+
+        tokio::select! {
+            _ = shutdown.shutting_down() => {
+                tracing::debug!("TLS report generator shutting down");
+                persist_aggregate(&params, start).await;
+                return;
+            }
+            _ = save_interval.tick() => {
+                persist_aggregate(&params, start).await;
+                continue;
+            }
+            _ = tokio::time::sleep(wait) => {}
+        };
+
+        // The report covers the period up to, but not including, `end`
+        let aggregate = std::mem::take(&mut *AGGREGATE.lock());
+        send_reports(
+            &params,
+            &client,
+            aggregate,
+            start,
+            end - chrono::Duration::seconds(1),
+        )
+        .await;
+        start = end;
+        persist_aggregate(&params, start).await;
+    }
+}
+
+pub fn register(lua: &Lua) -> anyhow::Result<()> {
+    let kumo_mod = config::get_or_create_module(lua, "kumo")?;
+
+    kumo_mod.set(
+        "configure_tls_reporting",
+        lua.create_function(|lua, params: mlua::Value| {
+            let params: TlsReportingParams = lua.from_value(params)?;
+            if config::is_validating() {
+                return Ok(());
+            }
+
+            let params = Arc::new(params);
+            let mut config = CONFIG.lock();
+            let already_started = config.is_some();
+            config.replace(params.clone());
+            if !already_started {
+                rt_spawn("tls_report_generator", report_generator(params)).map_err(any_err)?;
+            }
+            Ok(())
+        })?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy(domain: &str) -> Policy {
+        Policy {
+            policy_type: PolicyType::Sts,
+            policy_string: vec!["version: STSv1".to_string(), "mode: enforce".to_string()],
+            policy_domain: domain.to_string(),
+            mx_host: vec!["*.example.com".to_string()],
+        }
+    }
+
+    fn failure(result_type: ResultType) -> FailureDetails {
+        FailureDetails {
+            result_type,
+            sending_mta_ip: Some("10.0.0.1".to_string()),
+            receiving_mx_hostname: Some("mx.example.com".to_string()),
+            receiving_mx_helo: None,
+            receiving_ip: None,
+            failed_session_count: 0,
+            additional_information: None,
+            failure_reason_code: None,
+        }
+    }
+
+    #[test]
+    fn aggregation() {
+        let mut aggregate = Aggregate::default();
+        let example = policy("example.com");
+        aggregate.record_success(&example);
+        aggregate.record_success(&example);
+        aggregate.record_failure(&example, failure(ResultType::CertificateExpired));
+        aggregate.record_failure(&example, failure(ResultType::CertificateExpired));
+        aggregate.record_failure(&example, failure(ResultType::StarttlsNotSupported));
+        aggregate.record_success(&policy("example.net"));
+
+        let reports = aggregate.into_policy_reports();
+        assert_eq!(
+            reports.keys().collect::<Vec<_>>(),
+            vec!["example.com", "example.net"]
+        );
+
+        let report = &reports["example.com"][0];
+        assert_eq!(
+            report.summary,
+            Summary {
+                total_successful_session_count: 2,
+                total_failure_session_count: 3,
+            }
+        );
+        k9::snapshot!(
+            serde_json::to_string_pretty(&report.failure_details).unwrap(),
+            r#"
+[
+  {
+    "result-type": "starttls-not-supported",
+    "sending-mta-ip": "10.0.0.1",
+    "receiving-mx-hostname": "mx.example.com",
+    "failed-session-count": 1
+  },
+  {
+    "result-type": "certificate-expired",
+    "sending-mta-ip": "10.0.0.1",
+    "receiving-mx-hostname": "mx.example.com",
+    "failed-session-count": 2
+  }
+]
+"#
+        );
+    }
+
+    #[test]
+    fn persistence() {
+        let mut aggregate = Aggregate::default();
+        let example = policy("example.com");
+        aggregate.record_success(&example);
+        aggregate.record_failure(&example, failure(ResultType::CertificateExpired));
+        aggregate.record_failure(&example, failure(ResultType::CertificateExpired));
+        aggregate.record_success(&policy("example.net"));
+
+        let path = std::env::temp_dir().join(format!("kumod-tls-rpt-{}.json", Uuid::new_v4()));
+        let start = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        save_state(&path, &aggregate.to_persisted(start)).unwrap();
+        let state = load_state(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(state.period_start, start);
+
+        // Restoring into an aggregate that has accumulated more data
+        // in the meantime sums the counts
+        let mut restored = Aggregate::default();
+        restored.record_failure(&example, failure(ResultType::CertificateExpired));
+        restored.merge_policy_reports(state.policies);
+
+        assert_eq!(
+            restored.into_policy_reports(),
+            BTreeMap::from([
+                (
+                    "example.com".to_string(),
+                    vec![PolicyReport {
+                        policy: example.clone(),
+                        summary: Summary {
+                            total_successful_session_count: 1,
+                            total_failure_session_count: 3,
+                        },
+                        failure_details: vec![FailureDetails {
+                            failed_session_count: 3,
+                            ..failure(ResultType::CertificateExpired)
+                        }],
+                    }]
+                ),
+                (
+                    "example.net".to_string(),
+                    vec![PolicyReport {
+                        policy: policy("example.net"),
+                        summary: Summary {
+                            total_successful_session_count: 1,
+                            total_failure_session_count: 0,
+                        },
+                        failure_details: vec![],
+                    }]
+                ),
+            ])
+        );
+
+        assert!(load_state(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn reporting_period() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 13, 45, 10).unwrap();
+        assert_eq!(
+            period_start(now, Duration::from_secs(86400)),
+            Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            period_start(now, Duration::from_secs(3600)),
+            Utc.with_ymd_and_hms(2024, 3, 5, 13, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn classify() {
+        assert_eq!(
+            classify_handshake_error("invalid peer certificate: Expired"),
+            ResultType::CertificateExpired
+        );
+        assert_eq!(
+            classify_handshake_error("invalid peer certificate: NotValidForName"),
+            ResultType::CertificateHostMismatch
+        );
+        assert_eq!(
+            classify_handshake_error("invalid peer certificate: UnknownIssuer"),
+            ResultType::CertificateNotTrusted
+        );
+        assert_eq!(
+            classify_handshake_error("something else"),
+            ResultType::ValidationFailure
+        );
+
+        assert_eq!(
+            classify_dane_handshake_error(
+                "error:0A000086:SSL routines:tls_post_process_server_certificate:\
+                 certificate verify failed"
+            ),
+            ResultType::TlsaInvalid
+        );
+        assert_eq!(
+            classify_dane_handshake_error("invalid peer certificate: Expired"),
+            ResultType::TlsaInvalid
+        );
+        assert_eq!(
+            classify_dane_handshake_error("unsupported protocol"),
+            ResultType::ValidationFailure
+        );
+    }
+
+    #[test]
+    fn report_message() {
+        let params = TlsReportingParams {
+            organization_name: "Example Org".to_string(),
+            contact_info: "postmaster@example.org".to_string(),
+            reporting_domain: "example.org".to_string(),
+            sender: "tlsrpt@example.org".to_string(),
+            report_interval: TlsReportingParams::default_report_interval(),
+            state_path: TlsReportingParams::default_state_path(),
+            save_interval: TlsReportingParams::default_save_interval(),
+        };
+        let start = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 3, 5, 23, 59, 59).unwrap();
+        let mut report = build_report(&params, start, end, vec![]);
+        report.report_id = "REPORTID".to_string();
+        let compressed = gzip_report(&report).unwrap();
+
+        let msg = build_report_message(
+            &params,
+            "example.com",
+            &report,
+            &compressed,
+            "reports@example.com",
+        )
+        .unwrap();
+
+        let headers = msg.headers();
+        assert_eq!(
+            headers.subject().unwrap().unwrap(),
+            "Report Domain: example.com Submitter: example.org Report-ID: <REPORTID>"
+        );
+        assert_eq!(
+            headers
+                .get_first("TLS-Report-Domain")
+                .unwrap()
+                .get_raw_value(),
+            "example.com"
+        );
+        let ct = headers.content_type().unwrap().unwrap();
+        assert_eq!(ct.value, "multipart/report");
+        assert_eq!(ct.get("report-type").unwrap(), "tlsrpt");
+
+        let attachment = &msg.child_parts()[1];
+        let ct = attachment.headers().content_type().unwrap().unwrap();
+        assert_eq!(ct.value, "application/tlsrpt+gzip");
+        assert_eq!(
+            ct.get("name").unwrap(),
+            "example.org!example.com!1709596800!1709683199!REPORTID.json.gz"
+        );
+    }
+}
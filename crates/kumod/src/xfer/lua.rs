@@ -65,7 +65,9 @@ pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
                 } else if let Some(delay) = delay {
                     msg.delay_by((*delay).into()).await.map_err(any_err)?;
                 } else {
-                    msg.delay_with_jitter(60).await.map_err(any_err)?;
+                    msg.delay_with_jitter(chrono::Duration::zero(), 60)
+                        .await
+                        .map_err(any_err)?;
                 }
 
                 SavedQueueInfo::save_info(&msg).await.map_err(any_err)?;
@@ -91,7 +93,7 @@ pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
                         },
                         egress_pool: None,
                         egress_source: None,
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: None,
                         tls_info: None,
                         source_address: None,
@@ -157,7 +159,7 @@ pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
                         },
                         egress_pool: None,
                         egress_source: None,
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: None,
                         tls_info: None,
                         source_address: None,
@@ -202,7 +204,7 @@ pub fn register<'lua>(lua: &'lua Lua) -> anyhow::Result<()> {
                         },
                         egress_pool: None,
                         egress_source: None,
-                        relay_disposition: None,
+                        received_report: None,
                         delivery_protocol: None,
                         tls_info: None,
                         source_address: None,
@@ -1,5 +1,6 @@
 use crate::http_server::inject_v1::activity_for_peer;
 use crate::logging::disposition::{log_disposition, LogDisposition};
+use crate::message_trace::{start_reception, start_span, TRACEPARENT_META};
 use crate::queue::{DeliveryProto, QueueConfig, QueueManager};
 use crate::ready_queue::{AttemptConnectionDisposition, Dispatcher, QueueDispatcher};
 use crate::spool::SpoolManager;
@@ -17,6 +18,7 @@ use kumo_api_types::xfer::XferProtocol;
 use kumo_log_types::{RecordType, ResolvedAddress};
 use kumo_server_common::authn_authz::AuthInfo;
 use kumo_server_common::http_server::{AppError, AppState};
+use kumo_server_common::otel::SpanKind;
 use message::scheduling::Scheduling;
 use message::Message;
 use reqwest::StatusCode;
@@ -175,11 +177,18 @@ impl QueueDispatcher for XferDispatcher {
 
         // Capture some originating info that might be useful
         // for the target node
-        let additional_meta = json!({
+        let mut additional_meta = json!({
             "xfer_prior_id": msg.id(),
             "xfer_prior_node": nodeid,
         });
 
+        // The spans on the target node will be children of this one
+        let mut span = start_span(&msg, "xfer_client_transfer", SpanKind::Client).await;
+        span.set_attribute("kumo.xfer.target", self.proto.target.to_string());
+        if let Some(traceparent) = span.traceparent() {
+            additional_meta[TRACEPARENT_META] = traceparent.into();
+        }
+
         let serialized = msg.serialize_for_xfer(additional_meta).await?;
 
         // Compress with gzip
@@ -240,7 +249,7 @@ impl QueueDispatcher for XferDispatcher {
                 },
                 egress_pool: None,
                 egress_source: None,
-                relay_disposition: None,
+                received_report: None,
                 delivery_protocol: Some("xfer"),
                 tls_info: None,
                 source_address: None,
@@ -312,6 +321,9 @@ pub async fn inject_xfer_v1(
         .await?;
     msg.set_meta("xfer_auth", auth.summarize_for_http_auth())
         .await?;
+    // Continue the trace from the sending node
+    let mut span = start_reception(&msg, "xfer_reception", SpanKind::Server).await?;
+    span.set_attribute("client.address", peer_address.to_string());
 
     // set up the next due time using the source due+scheduling info
     SavedQueueInfo::restore_info(&msg).await?;
@@ -341,7 +353,7 @@ pub async fn inject_xfer_v1(
         },
         egress_source: None,
         egress_pool: None,
-        relay_disposition: None,
+        received_report: None,
         delivery_protocol: Some("xfer"),
         tls_info: None,
         source_address: None,
@@ -15,8 +15,10 @@ use intrusive_collections::{intrusive_adapter, LinkedList, LinkedListAtomicLink}
 use kumo_chrono_helper::*;
 #[cfg(feature = "impl")]
 use kumo_dkim::arc::ARC;
-use kumo_log_types::rfc3464::Report;
+use kumo_log_types::rfc3464::{Report, DSN_META_KEY};
 use kumo_log_types::rfc5965::ARFReport;
+use kumo_log_types::rfc7489::AggregateReport as DmarcAggregateReport;
+use kumo_log_types::rfc8460::Report as TlsReport;
 use kumo_prometheus::declare_metric;
 #[cfg(feature = "impl")]
 use mailparsing::{AuthenticationResult, AuthenticationResults, EncodeHeaderValue};
@@ -28,6 +30,7 @@ use mlua::{IntoLua, LuaSerdeExt, UserData, UserDataMethods};
 #[cfg(feature = "impl")]
 use mod_dns_resolver::get_resolver_instance;
 use parking_lot::Mutex;
+use rfc5321::DsnParams;
 use serde::{Deserialize, Serialize};
 use serde_with::formats::PreferOne;
 use serde_with::{serde_as, OneOrMany};
@@ -129,6 +132,9 @@ struct MessageInner {
     flags: MessageFlags,
     num_attempts: u16,
     due: Option<DateTime<Utc>>,
+    /// Mirrors MetaData::priority so that it can be consulted
+    /// by the queues without loading the metadata
+    priority: i8,
 }
 
 #[derive(Debug)]
@@ -285,15 +291,53 @@ impl WeakMessage {
     }
 }
 
+/// The metadata of a message, in the form that is stored in
+/// the meta spool
 #[serde_as]
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub(crate) struct MetaData {
+pub struct MetaData {
     pub sender: EnvelopeAddress,
     #[serde_as(as = "OneOrMany<_, PreferOne>")]
     pub recipient: Vec<EnvelopeAddress>,
     pub meta: serde_json::Value,
     #[serde(default)]
     pub schedule: Option<Scheduling>,
+    #[serde(default, skip_serializing_if = "is_default_priority")]
+    pub priority: i8,
+}
+
+impl MetaData {
+    /// Retrieve `key` from the meta object as a string
+    fn get_meta_string(&self, key: &str) -> anyhow::Result<Option<&str>> {
+        match self.meta.get(key) {
+            Some(serde_json::Value::String(value)) => Ok(Some(value)),
+            Some(serde_json::Value::Null) | None => Ok(None),
+            Some(hmm) => {
+                anyhow::bail!("expected '{key}' to be a string value, got {hmm:?}");
+            }
+        }
+    }
+
+    /// Returns the name of the scheduled queue to which the
+    /// message belongs; see [Message::get_queue_name]
+    pub fn queue_name(&self) -> anyhow::Result<String> {
+        Ok(match self.get_meta_string("queue")? {
+            Some(name) => name.to_string(),
+            None => QueueNameComponents::format(
+                self.get_meta_string("campaign")?,
+                self.get_meta_string("tenant")?,
+                match self.recipient.first() {
+                    Some(recip) => recip.domain().to_lowercase(),
+                    None => anyhow::bail!("recipient list is empty!?"),
+                },
+                self.get_meta_string("routing_domain")?,
+            ),
+        })
+    }
+}
+
+fn is_default_priority(priority: &i8) -> bool {
+    *priority == 0
 }
 
 impl Drop for MessageInner {
@@ -331,11 +375,13 @@ impl Message {
                         recipient,
                         meta,
                         schedule: None,
+                        priority: 0,
                     })),
                     data,
                     flags: MessageFlags::META_DIRTY | MessageFlags::DATA_DIRTY,
                     num_attempts: 0,
                     due: None,
+                    priority: 0,
                 }),
                 link: LinkedListAtomicLink::default(),
             }),
@@ -361,6 +407,7 @@ impl Message {
         } else {
             MessageFlags::empty()
         };
+        let priority = metadata.priority;
 
         Ok(Self {
             msg_and_id: Arc::new(MessageWithId {
@@ -371,6 +418,7 @@ impl Message {
                     flags,
                     num_attempts: 0,
                     due: None,
+                    priority,
                 }),
                 link: LinkedListAtomicLink::default(),
             }),
@@ -386,6 +434,7 @@ impl Message {
         } else {
             MessageFlags::empty()
         };
+        let priority = metadata.priority;
 
         Self {
             msg_and_id: Arc::new(MessageWithId {
@@ -396,6 +445,7 @@ impl Message {
                     flags: flags | MessageFlags::META_DIRTY | MessageFlags::DATA_DIRTY,
                     num_attempts: 0,
                     due: None,
+                    priority,
                 }),
                 link: LinkedListAtomicLink::default(),
             }),
@@ -451,15 +501,42 @@ impl Message {
         Ok(inner.metadata.as_ref().and_then(|meta| meta.schedule))
     }
 
+    /// Returns the delivery priority of the message.
+    /// Higher values are dispatched ahead of lower values
+    /// within the same queue. The default is 0.
+    pub fn get_priority(&self) -> i8 {
+        let inner = self.msg_and_id.inner.lock();
+        inner.priority
+    }
+
+    pub async fn set_priority(&self, priority: i8) -> anyhow::Result<()> {
+        self.load_meta_if_needed().await?;
+        let mut inner = self.msg_and_id.inner.lock();
+        match &mut inner.metadata {
+            None => anyhow::bail!("set_priority: metadata must be loaded first"),
+            Some(meta) => {
+                meta.priority = priority;
+                inner.priority = priority;
+                inner.flags.set(MessageFlags::META_DIRTY, true);
+                Ok(())
+            }
+        }
+    }
+
     pub fn get_due(&self) -> Option<DateTime<Utc>> {
         let inner = self.msg_and_id.inner.lock();
         inner.due
     }
 
-    pub async fn delay_with_jitter(&self, limit: i64) -> anyhow::Result<Option<DateTime<Utc>>> {
+    /// Delay by `delay` plus a random jitter of up to `limit` seconds
+    pub async fn delay_with_jitter(
+        &self,
+        delay: chrono::Duration,
+        limit: i64,
+    ) -> anyhow::Result<Option<DateTime<Utc>>> {
         let scale = rand::random::<f32>();
         let value = (scale * limit as f32) as i64;
-        self.delay_by(seconds(value)?).await
+        self.delay_by(delay + seconds(value)?).await
     }
 
     pub async fn delay_by(
@@ -928,22 +1005,56 @@ impl Message {
     }
 
     pub async fn get_queue_name(&self) -> anyhow::Result<String> {
-        Ok(match self.get_meta_string("queue").await? {
-            Some(name) => name,
-            None => {
-                let name = QueueNameComponents::format(
-                    self.get_meta_string("campaign").await?,
-                    self.get_meta_string("tenant").await?,
-                    self.first_recipient()
-                        .await?
-                        .domain()
-                        .to_string()
-                        .to_lowercase(),
-                    self.get_meta_string("routing_domain").await?,
-                );
-                name.to_string()
-            }
-        })
+        self.load_meta_if_needed().await?;
+        let inner = self.msg_and_id.inner.lock();
+        match &inner.metadata {
+            Some(meta) => meta.queue_name(),
+            None => anyhow::bail!("get_queue_name: metadata must be loaded first"),
+        }
+    }
+
+    /// Retrieve the RFC 3461 DSN parameters that were recorded
+    /// for this message when it was received, if any
+    pub async fn get_dsn_params(&self) -> anyhow::Result<Option<DsnParams>> {
+        match self.get_meta(DSN_META_KEY).await? {
+            serde_json::Value::Null => Ok(None),
+            value => Ok(Some(
+                serde_json::from_value(value).context("parsing dsn metadata")?,
+            )),
+        }
+    }
+
+    pub async fn set_dsn_params(&self, params: &DsnParams) -> anyhow::Result<()> {
+        self.set_meta(DSN_META_KEY, serde_json::to_value(params)?)
+            .await
+    }
+
+    /// Returns true if the message must only be relayed over validated
+    /// TLS, as requested via the RFC 8689 REQUIRETLS parameter
+    pub async fn get_require_tls(&self) -> anyhow::Result<bool> {
+        match self.get_meta("requiretls").await? {
+            serde_json::Value::Bool(b) => Ok(b),
+            serde_json::Value::Null => Ok(false),
+            value => anyhow::bail!("requiretls metadata must be a boolean, got {value:?}"),
+        }
+    }
+
+    pub async fn set_require_tls(&self, require_tls: bool) -> anyhow::Result<()> {
+        self.set_meta("requiretls", require_tls).await
+    }
+
+    /// Returns true if the message was received with the RFC 3030
+    /// BODY=BINARYMIME parameter, and must be relayed the same way
+    pub async fn get_binary_mime(&self) -> anyhow::Result<bool> {
+        match self.get_meta("binarymime").await? {
+            serde_json::Value::Bool(b) => Ok(b),
+            serde_json::Value::Null => Ok(false),
+            value => anyhow::bail!("binarymime metadata must be a boolean, got {value:?}"),
+        }
+    }
+
+    pub async fn set_binary_mime(&self, binary_mime: bool) -> anyhow::Result<()> {
+        self.set_meta("binarymime", binary_mime).await
     }
 
     #[cfg(feature = "impl")]
@@ -1074,6 +1185,18 @@ impl Message {
         ARFReport::parse(&data)
     }
 
+    /// Parse the message as an RFC 7489 DMARC aggregate report
+    pub async fn parse_dmarc_aggregate(&self) -> anyhow::Result<Option<DmarcAggregateReport>> {
+        let data = self.data().await?;
+        DmarcAggregateReport::parse(&data)
+    }
+
+    /// Parse the message as an RFC 8460 TLS aggregate report
+    pub async fn parse_tlsrpt(&self) -> anyhow::Result<Option<TlsReport>> {
+        let data = self.data().await?;
+        TlsReport::parse(&data)
+    }
+
     pub async fn prepend_header(&self, name: Option<&str>, value: &str) -> anyhow::Result<()> {
         let data = self.data().await?;
         let mut new_data = Vec::with_capacity(size_header(name, value) + 2 + data.len());
@@ -1443,6 +1566,11 @@ impl UserData for Message {
             Ok(this.increment_num_attempts())
         });
 
+        methods.add_method("priority", move |_, this, _: ()| Ok(this.get_priority()));
+        methods.add_async_method("set_priority", move |_, this, priority: i8| async move {
+            this.set_priority(priority).await.map_err(any_err)
+        });
+
         methods.add_async_method("queue_name", move |_, this, _: ()| async move {
             this.get_queue_name().await.map_err(any_err)
         });
@@ -1714,6 +1842,22 @@ impl UserData for Message {
             }
         });
 
+        methods.add_async_method("parse_dmarc_aggregate", |lua, this, _: ()| async move {
+            let report = this.parse_dmarc_aggregate().await.map_err(any_err)?;
+            match report {
+                Some(report) => lua.to_value_with(&report, serialize_options()),
+                None => Ok(mlua::Value::Nil),
+            }
+        });
+
+        methods.add_async_method("parse_tlsrpt", |lua, this, _: ()| async move {
+            let report = this.parse_tlsrpt().await.map_err(any_err)?;
+            match report {
+                Some(report) => lua.to_value_with(&report, serialize_options()),
+                None => Ok(mlua::Value::Nil),
+            }
+        });
+
         methods.add_async_method("save", |_, this, ()| async move {
             this.save(None).await.map_err(any_err)
         });
@@ -2300,6 +2444,27 @@ Body\r
         Ok(())
     }
 
+    #[tokio::test]
+    async fn priority() -> anyhow::Result<()> {
+        let msg = new_msg_body(MULTI_HEADER_CONTENT);
+        assert_eq!(msg.get_priority(), 0);
+
+        // The default priority is not recorded in the metadata
+        let meta = serde_json::to_value(msg.clone_meta_data().await?)?;
+        assert!(meta.get("priority").is_none());
+
+        msg.set_priority(10).await?;
+        assert_eq!(msg.get_priority(), 10);
+        assert!(msg.needs_save());
+
+        // and survives a round trip through the spool
+        let meta = serde_json::to_vec(&msg.clone_meta_data().await?)?;
+        let loaded = Message::new_from_spool(*msg.id(), meta)?;
+        assert_eq!(loaded.get_priority(), 10);
+
+        Ok(())
+    }
+
     #[cfg(all(test, target_pointer_width = "64"))]
     #[test]
     fn sizes() {
@@ -12,6 +12,8 @@ struct MetaDataV1 {
     sender: EnvelopeAddress,
     recipient: Vec<EnvelopeAddress>,
     meta: serde_json::Value,
+    #[serde(default)]
+    priority: i8,
 }
 
 impl Message {
@@ -36,6 +38,7 @@ impl Message {
             sender: meta.sender,
             recipient: meta.recipient,
             meta: meta.meta,
+            priority: meta.priority,
         };
 
         let serialized_meta = serde_json::to_string(&meta)?;
@@ -62,6 +65,7 @@ impl Message {
             recipient: meta.recipient,
             meta: meta.meta,
             schedule: None,
+            priority: meta.priority,
         };
 
         // Create a new id with *this* nodes mac but the source
@@ -90,6 +94,7 @@ mod test {
     async fn xfer_serialization() {
         let msg = new_msg_body("Subject: simple message\r\n\r\nHello\r\n");
         msg.set_meta("canary", true).await.unwrap();
+        msg.set_priority(5).await.unwrap();
         let serialized = msg
             .serialize_for_xfer(json!({"additional": "meta"}))
             .await
@@ -99,6 +104,7 @@ mod test {
         let round_trip = Message::deserialize_from_xfer(&serialized).unwrap();
         assert_eq!(round_trip.get_meta("canary").await.unwrap(), true);
         assert_eq!(round_trip.get_meta("additional").await.unwrap(), "meta");
+        assert_eq!(round_trip.get_priority(), 5);
         eprintln!(
             "deserialized message:\n{}",
             String::from_utf8_lossy(&round_trip.data().await.unwrap())
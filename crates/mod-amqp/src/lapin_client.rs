@@ -164,7 +164,9 @@ impl LuaUserData for Confirm {
     }
 }
 
-pub async fn build_client(uri: String) -> anyhow::Result<AMQPClient> {
+/// Establish a connection to the AMQP broker at `uri`, driven
+/// by the main runtime
+pub async fn connect(uri: &str) -> anyhow::Result<Connection> {
     let options = ConnectionProperties::default()
         .with_executor(
             tokio_executor_trait::Tokio::default()
@@ -174,15 +176,17 @@ pub async fn build_client(uri: String) -> anyhow::Result<AMQPClient> {
 
     let connect_timeout = tokio::time::Duration::from_secs(20);
 
-    let connection = timeout(connect_timeout, Connection::connect(&uri, options))
-        .await
-        .map_err(any_err)?
-        .map_err(any_err)?;
+    let connection = timeout(connect_timeout, Connection::connect(uri, options)).await??;
 
     connection.on_error(|err| {
         tracing::error!("RabbitMQ connection broken {err:#}");
     });
 
+    Ok(connection)
+}
+
+pub async fn build_client(uri: String) -> anyhow::Result<AMQPClient> {
+    let connection = connect(&uri).await?;
     let channel = connection.create_channel().await.map_err(any_err)?;
 
     Ok(AMQPClient {
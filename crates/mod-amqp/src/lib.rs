@@ -4,6 +4,8 @@ use mlua::{Lua, LuaSerdeExt};
 mod amqprs_client;
 mod lapin_client;
 
+pub use lapin_client::connect;
+
 pub fn register(lua: &Lua) -> anyhow::Result<()> {
     let amqp_mod = get_or_create_sub_module(lua, "amqp")?;
 
@@ -12,6 +12,7 @@ static CACHE: LruCacheWithTtl<Name, CachedPolicy>::new("mta_sts_policy", 64 * 10
 
 pub mod dns;
 pub mod policy;
+pub mod tlsrpt;
 
 #[derive(Clone, Debug)]
 struct CachedPolicy {
@@ -8,6 +8,16 @@ pub enum PolicyMode {
     None,
 }
 
+impl PolicyMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Enforce => "enforce",
+            Self::Testing => "testing",
+            Self::None => "none",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MtaStsPolicy {
     pub mode: PolicyMode,
@@ -82,6 +92,26 @@ impl MtaStsPolicy {
         })
     }
 
+    /// Returns the policy as a list of `key: value` lines, which
+    /// is the form required for the `policy-string` field of an
+    /// RFC 8460 TLS report
+    pub fn to_policy_strings(&self) -> Vec<String> {
+        let mut lines = vec![
+            "version: STSv1".to_string(),
+            format!("mode: {}", self.mode.as_str()),
+        ];
+        for mx in &self.mx {
+            lines.push(format!("mx: {mx}"));
+        }
+        lines.push(format!("max_age: {}", self.max_age));
+        for (key, values) in &self.fields {
+            for value in values {
+                lines.push(format!("{key}: {value}"));
+            }
+        }
+        lines
+    }
+
     /// Returns true if `name` matches any of the allowed mx
     /// host name patterns.
     /// `name` must be lowercase.
@@ -204,6 +234,25 @@ MtaStsPolicy {
         );
     }
 
+    #[test]
+    fn policy_strings() {
+        k9::snapshot!(
+            MtaStsPolicy::parse(SAMPLE_POLICY)
+                .unwrap()
+                .to_policy_strings(),
+            r#"
+[
+    "version: STSv1",
+    "mode: enforce",
+    "mx: mail.example.com",
+    "mx: *.example.net",
+    "mx: backupmx.example.com",
+    "max_age: 604800",
+]
+"#
+        );
+    }
+
     #[test]
     fn name_matching() {
         assert!(name_match("foo.com", "foo.com"));
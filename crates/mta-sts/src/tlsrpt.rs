@@ -0,0 +1,149 @@
+use dns_resolver::Resolver;
+use std::collections::BTreeMap;
+
+pub use kumo_log_types::rfc8460::{
+    DateRange, FailureDetails, Policy, PolicyReport, PolicyType, Report, ResultType, Summary,
+};
+
+// <https://datatracker.ietf.org/doc/html/rfc8460>
+
+/// A destination for aggregate reports, taken from the `rua`
+/// field of the `_smtp._tls` TXT record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportUri {
+    /// The report is to be emailed to the contained address
+    Mailto(String),
+    /// The report is to be POSTed to the contained URL
+    Https(String),
+}
+
+#[derive(Debug)]
+pub struct TlsRptDnsRecord {
+    pub rua: Vec<ReportUri>,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl TlsRptDnsRecord {
+    pub fn parse(txt: &str) -> anyhow::Result<Self> {
+        let mut fields = BTreeMap::new();
+
+        for pair in txt.split(';') {
+            let pair = pair.trim();
+            if pair.is_empty() {
+                continue;
+            }
+            let (key, value) = pair.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!("invalid element in TLSRPT text record: {pair}. Full record: {txt}")
+            })?;
+
+            fields.insert(key.trim().to_string(), value.trim().to_string());
+        }
+
+        if fields.get("v").map(|s| s.as_str()) != Some("TLSRPTv1") {
+            anyhow::bail!("TXT record is not a TLSRPTv1 record {txt}");
+        }
+
+        let rua = fields.get("rua").ok_or_else(|| {
+            anyhow::anyhow!("TLSRPTv1 TXT record is missing rua parameter. {txt}")
+        })?;
+
+        let mut uris = vec![];
+        for uri in rua.split(',') {
+            let uri = uri.trim();
+            if let Some(addr) = uri.strip_prefix("mailto:") {
+                // Discard any hfields, such as ?subject=
+                let addr = addr.split_once('?').map(|(addr, _)| addr).unwrap_or(addr);
+                uris.push(ReportUri::Mailto(addr.to_string()));
+            } else if uri.starts_with("https:") {
+                uris.push(ReportUri::Https(uri.to_string()));
+            } else {
+                anyhow::bail!("TLSRPTv1 TXT record has unsupported rua {uri}. {txt}");
+            }
+        }
+
+        Ok(Self { rua: uris, fields })
+    }
+}
+
+/// Resolve the TLSRPT policy for the specified domain
+pub async fn resolve_dns_record(
+    policy_domain: &str,
+    resolver: &dyn Resolver,
+) -> anyhow::Result<TlsRptDnsRecord> {
+    let policy_domain = policy_domain.trim_end_matches('.');
+    let dns_name = format!("_smtp._tls.{policy_domain}");
+    let records: Vec<String> = resolver
+        .resolve_txt(&dns_name)
+        .await?
+        .as_txt()
+        .into_iter()
+        .filter(|txt| txt.starts_with("v=TLSRPTv1"))
+        .collect();
+
+    // <https://datatracker.ietf.org/doc/html/rfc8460#section-3>
+    // If the number of resulting records is not one, senders MUST
+    // assume the recipient domain does not implement TLSRPT.
+    match records.as_slice() {
+        [txt] => TlsRptDnsRecord::parse(txt),
+        [] => anyhow::bail!("no TLSRPTv1 record found for {dns_name}"),
+        _ => anyhow::bail!("multiple TLSRPTv1 records found for {dns_name}"),
+    }
+}
+
+pub async fn get_tlsrpt_record_for_domain(policy_domain: &str) -> anyhow::Result<TlsRptDnsRecord> {
+    let resolver = dns_resolver::get_resolver();
+    resolve_dns_record(policy_domain, &**resolver).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dns_resolver::TestResolver;
+
+    #[tokio::test]
+    async fn test_parse_dns_record() {
+        let resolver = TestResolver::default()
+            .with_txt_multiple(
+                "_smtp._tls.example.com",
+                vec![
+                    "v=TLSRPTv1; rua=mailto:reports@example.com?subject=tls,https://reporting.example.com/v1/tlsrpt".to_owned(),
+                    "some other record".to_owned(),
+                ],
+            );
+
+        let result = resolve_dns_record("example.com.", &resolver).await.unwrap();
+
+        k9::snapshot!(
+            result,
+            r#"
+TlsRptDnsRecord {
+    rua: [
+        Mailto(
+            "reports@example.com",
+        ),
+        Https(
+            "https://reporting.example.com/v1/tlsrpt",
+        ),
+    ],
+    fields: {
+        "rua": "mailto:reports@example.com?subject=tls,https://reporting.example.com/v1/tlsrpt",
+        "v": "TLSRPTv1",
+    },
+}
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multiple_dns_records() {
+        let resolver = TestResolver::default().with_txt_multiple(
+            "_smtp._tls.example.com",
+            vec![
+                "v=TLSRPTv1; rua=mailto:a@example.com".to_owned(),
+                "v=TLSRPTv1; rua=mailto:b@example.com".to_owned(),
+            ],
+        );
+
+        assert!(resolve_dns_record("example.com", &resolver).await.is_err());
+    }
+}
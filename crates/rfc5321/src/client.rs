@@ -25,6 +25,8 @@ pub use openssl;
 pub use tokio_rustls;
 
 const MAX_LINE_LEN: usize = 4096;
+/// The size of the chunks that we'll send via BDAT
+const BDAT_CHUNK_SIZE: usize = 1024 * 1024;
 
 #[derive(Error, Debug, Clone)]
 pub enum ClientError {
@@ -141,6 +143,18 @@ impl ClientError {
     }
 }
 
+/// Compute the RFC 2195 CRAM-MD5 digest of `challenge`, keyed by
+/// `secret`, returning it as a lowercase hex string
+pub fn cram_md5_digest(
+    secret: &[u8],
+    challenge: &[u8],
+) -> Result<String, openssl::error::ErrorStack> {
+    let key = openssl::pkey::PKey::hmac(secret)?;
+    let mut signer = openssl::sign::Signer::new(openssl::hash::MessageDigest::md5(), &key)?;
+    signer.update(challenge)?;
+    Ok(data_encoding::HEXLOWER.encode(&signer.sign_to_vec()?))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EsmtpCapability {
     pub name: String,
@@ -219,7 +233,10 @@ pub struct SmtpClient {
     use_rset: bool,
     enable_rset: bool,
     enable_pipelining: bool,
+    enable_chunking: bool,
     ignore_8bit_checks: bool,
+    /// Set when we greeted the peer with LHLO
+    lmtp: bool,
 }
 
 fn extract_hostname(hostname: &str) -> &str {
@@ -269,6 +286,8 @@ impl SmtpClient {
             use_rset: false,
             enable_rset: false,
             enable_pipelining: false,
+            enable_chunking: false,
+            lmtp: false,
             ignore_8bit_checks: false,
         }
     }
@@ -290,6 +309,13 @@ impl SmtpClient {
         self.enable_pipelining = enable;
     }
 
+    /// Setting this to true allows the RFC 3030 CHUNKING extension
+    /// to be used to send the message via BDAT in place of DATA,
+    /// when the server advertises it
+    pub fn set_enable_chunking(&mut self, enable: bool) {
+        self.enable_chunking = enable;
+    }
+
     pub fn set_tracer(&mut self, tracer: Arc<dyn SmtpClientTracer + Send + Sync>) {
         self.tracer.replace(tracer);
     }
@@ -298,6 +324,12 @@ impl SmtpClient {
         &self.timeouts
     }
 
+    /// Returns the capabilities that were advertised by the server
+    /// in response to the most recent EHLO or LHLO
+    pub fn capabilities(&self) -> &HashMap<String, EsmtpCapability> {
+        &self.capabilities
+    }
+
     async fn read_line(
         &mut self,
         timeout_duration: Duration,
@@ -683,6 +715,7 @@ impl SmtpClient {
         let response = self
             .send_command(&Command::Lhlo(Domain::Name(ehlo_name.to_string())))
             .await?;
+        self.lmtp = true;
         self.ehlo_common(response)
     }
 
@@ -693,6 +726,7 @@ impl SmtpClient {
         let response = self
             .send_command(&Command::Ehlo(Domain::Name(ehlo_name.to_string())))
             .await?;
+        self.lmtp = false;
         self.ehlo_common(response)
     }
 
@@ -747,6 +781,135 @@ impl SmtpClient {
         Ok(())
     }
 
+    /// Authenticate using the LOGIN SASL mechanism.
+    /// LOGIN was never formally standardized, but remains widely
+    /// deployed; the username and password are each sent in
+    /// response to a server prompt.
+    pub async fn auth_login(
+        &mut self,
+        username: &str,
+        password: Option<&str>,
+    ) -> Result<(), ClientError> {
+        let response = self
+            .send_command(&Command::Auth {
+                sasl_mech: "LOGIN".to_string(),
+                initial_response: None,
+            })
+            .await?;
+        if response.code != 334 {
+            return Err(ClientError::Rejected(response));
+        }
+
+        let response = self
+            .send_sasl_response(&data_encoding::BASE64.encode(username.as_bytes()))
+            .await?;
+        if response.code != 334 {
+            return Err(ClientError::Rejected(response));
+        }
+
+        let password = password.unwrap_or("");
+        let response = self
+            .send_sasl_response(&data_encoding::BASE64.encode(password.as_bytes()))
+            .await?;
+        if response.code != 235 {
+            return Err(ClientError::Rejected(response));
+        }
+
+        Ok(())
+    }
+
+    /// Authenticate using the RFC 2195 CRAM-MD5 SASL mechanism
+    pub async fn auth_cram_md5(
+        &mut self,
+        username: &str,
+        password: Option<&str>,
+    ) -> Result<(), ClientError> {
+        let response = self
+            .send_command(&Command::Auth {
+                sasl_mech: "CRAM-MD5".to_string(),
+                initial_response: None,
+            })
+            .await?;
+        if response.code != 334 {
+            return Err(ClientError::Rejected(response));
+        }
+
+        let challenge = data_encoding::BASE64
+            .decode(response.content.trim().as_bytes())
+            .map_err(|err| {
+                ClientError::MalformedResponseLine(format!(
+                    "invalid CRAM-MD5 challenge {}: {err}",
+                    response.content
+                ))
+            })?;
+        let digest = cram_md5_digest(password.unwrap_or("").as_bytes(), &challenge)?;
+
+        let response = self
+            .send_sasl_response(
+                &data_encoding::BASE64.encode(format!("{username} {digest}").as_bytes()),
+            )
+            .await?;
+        if response.code != 235 {
+            return Err(ClientError::Rejected(response));
+        }
+
+        Ok(())
+    }
+
+    /// Authenticate using the XOAUTH2 SASL mechanism, as used by
+    /// Gmail and Microsoft 365. `token` is an OAuth 2.0 access token.
+    pub async fn auth_xoauth2(&mut self, username: &str, token: &str) -> Result<(), ClientError> {
+        let payload = format!("user={username}\x01auth=Bearer {token}\x01\x01");
+        let payload = data_encoding::BASE64.encode(payload.as_bytes());
+
+        let response = self
+            .send_command(&Command::Auth {
+                sasl_mech: "XOAUTH2".to_string(),
+                initial_response: Some(payload),
+            })
+            .await?;
+
+        if response.code == 334 {
+            // The server has sent a base64 encoded JSON error description.
+            // We must send an empty response to receive the final status.
+            let response = self.send_sasl_response("").await?;
+            return Err(ClientError::Rejected(response));
+        }
+        if response.code != 235 {
+            return Err(ClientError::Rejected(response));
+        }
+
+        Ok(())
+    }
+
+    /// Send a response line as part of a SASL exchange initiated
+    /// by an AUTH command, and read the server reply
+    async fn send_sasl_response(&mut self, response: &str) -> Result<Response, ClientError> {
+        let line = format!("{response}\r\n");
+        tracing::trace!("send->{}: {line}", self.hostname);
+        if self.socket.is_some() {
+            if let Some(tracer) = &self.tracer {
+                WriteTracer::trace(tracer, &line);
+            }
+        }
+
+        let timeout_duration = self.timeouts.auth_timeout;
+        self.write_all_with_timeout(
+            timeout_duration,
+            line.as_bytes(),
+            || ClientError::TimeOutRequest {
+                duration: timeout_duration,
+                commands: vec![],
+            },
+            |error| ClientError::WriteError {
+                error,
+                commands: vec![],
+            },
+        )
+        .await?;
+        self.read_response(None, timeout_duration).await
+    }
+
     /// Attempt TLS handshake.
     /// Returns Err for IO errors.
     /// On completion, return an option that will be:
@@ -898,26 +1061,88 @@ impl SmtpClient {
         sender: SENDER,
         recipient_list: Vec<ForwardPath>,
         data: B,
+    ) -> Result<BatchSendSuccess, ClientError> {
+        self.send_mail_multi_recip_with_params(
+            sender,
+            vec![],
+            recipient_list
+                .into_iter()
+                .map(|recipient| (recipient, vec![]))
+                .collect(),
+            data,
+        )
+        .await
+    }
+
+    /// Like `send_mail_multi_recip`, but allows the caller to pass
+    /// additional ESMTP parameters for the `MAIL FROM` command and
+    /// for each individual `RCPT TO` command.
+    /// The caller is responsible for checking that the server advertised
+    /// the corresponding extensions.
+    pub async fn send_mail_multi_recip_with_params<B: AsRef<[u8]>, SENDER: Into<ReversePath>>(
+        &mut self,
+        sender: SENDER,
+        extra_mail_from_params: Vec<EsmtpParameter>,
+        recipient_list: Vec<(ForwardPath, Vec<EsmtpParameter>)>,
+        data: B,
     ) -> Result<BatchSendSuccess, ClientError> {
         let sender = sender.into();
 
         let data: &[u8] = data.as_ref();
         let stuffed;
 
-        let data = match apply_dot_stuffing(data) {
-            Some(d) => {
-                stuffed = d;
-                &stuffed
+        // BDAT transmits the data verbatim, so there is no need
+        // to perform dot stuffing when we use it.
+        // We don't use BDAT with LMTP, as we only handle the
+        // per-recipient responses that follow DATA.
+        let use_chunking =
+            self.enable_chunking && !self.lmtp && self.capabilities.contains_key("CHUNKING");
+
+        let data = if use_chunking {
+            data
+        } else {
+            match apply_dot_stuffing(data) {
+                Some(d) => {
+                    stuffed = d;
+                    &stuffed
+                }
+                None => data,
             }
-            None => data,
         };
 
+        // RFC 3030: BINARYMIME content can only be sent via BDAT,
+        // and only to a peer that advertises BINARYMIME
+        let binary_mime = extra_mail_from_params.iter().any(|p| {
+            p.name.eq_ignore_ascii_case("BODY")
+                && p.value
+                    .as_deref()
+                    .map(|v| v.eq_ignore_ascii_case("BINARYMIME"))
+                    .unwrap_or(false)
+        });
+        if binary_mime && !(use_chunking && self.capabilities.contains_key("BINARYMIME")) {
+            return Err(ClientError::Rejected(Response {
+                code: 554,
+                command: None,
+                enhanced_code: Some(EnhancedStatusCode {
+                    class: 5,
+                    subject: 6,
+                    detail: 3,
+                }),
+                content: "KumoMTA internal: message was received as BODY=BINARYMIME, \
+                    destination does not support BINARYMIME with CHUNKING"
+                    .to_string(),
+            }));
+        }
+
         let data_is_8bit = data.iter().any(|&b| b >= 0x80);
-        let envelope_is_8bit =
-            !sender.is_ascii() || recipient_list.iter().any(|recipient| !recipient.is_ascii());
+        let envelope_is_8bit = !sender.is_ascii()
+            || recipient_list
+                .iter()
+                .any(|(recipient, _)| !recipient.is_ascii());
 
         let mut mail_from_params = vec![];
-        if data_is_8bit {
+        // BODY=BINARYMIME, when present, already covers 8bit content
+        if data_is_8bit && !binary_mime {
             if self.capabilities.contains_key("8BITMIME") {
                 mail_from_params.push(EsmtpParameter {
                     name: "BODY".to_string(),
@@ -977,24 +1202,44 @@ impl SmtpClient {
         if self.use_rset {
             commands.push(Command::Rset);
         }
+        mail_from_params.extend(extra_mail_from_params);
         commands.push(Command::MailFrom {
             address: sender,
             parameters: mail_from_params,
         });
 
-        for recipient in &recipient_list {
+        for (recipient, parameters) in &recipient_list {
             commands.push(Command::RcptTo {
                 address: recipient.clone(),
-                parameters: vec![],
+                parameters: parameters.clone(),
+            });
+        }
+
+        let chunks = bdat_chunks(data);
+        // RFC 3030 allows BDAT to be pipelined along with the envelope,
+        // which saves a round trip in the common case where the message
+        // fits in a single chunk. The peer will reject the chunk if none
+        // of the recipients were accepted, just as it would for DATA.
+        let pipeline_first_chunk =
+            use_chunking && self.enable_pipelining && self.capabilities.contains_key("PIPELINING");
+        if !use_chunking {
+            commands.push(Command::Data);
+        } else if pipeline_first_chunk {
+            commands.push(Command::Bdat {
+                chunk_size: chunks[0].len(),
+                last: chunks.len() == 1,
             });
         }
-        commands.push(Command::Data);
 
         // Assume that something might break below: if it does, we want
         // to ensure that we RSET the connection on the next go around.
         self.use_rset = true;
 
-        let mut responses = self.pipeline_commands(commands).await;
+        let mut responses = if pipeline_first_chunk {
+            self.pipeline_commands_with_chunk(commands, chunks[0]).await
+        } else {
+            self.pipeline_commands(commands).await
+        };
 
         // This is a little awkward. We want to handle the RFC 2090 3.1 case
         // below, which requires deferring checking the actual response codes
@@ -1026,6 +1271,37 @@ impl SmtpClient {
             return Err(ClientError::RejectedBatch(rcpt_responses));
         }
 
+        if use_chunking {
+            let first_chunk_resp = if pipeline_first_chunk {
+                Some(responses.remove(0))
+            } else {
+                None
+            };
+
+            // Any remaining chunks are only sent once we know
+            // that the envelope was accepted.
+            if mail_resp.code != 250 {
+                return Err(ClientError::Rejected(mail_resp));
+            }
+            if rcpt_responses.iter().all(|resp| resp.code != 250) {
+                if rcpt_responses.len() == 1 {
+                    return Err(ClientError::Rejected(
+                        rcpt_responses.pop().expect("have at least one"),
+                    ));
+                }
+                return Err(ClientError::RejectedBatch(rcpt_responses));
+            }
+
+            let resp = self.send_data_via_bdat(&chunks, first_chunk_resp).await?;
+
+            self.use_rset = self.enable_rset;
+
+            return Ok(BatchSendSuccess {
+                response: resp,
+                rcpt_responses,
+            });
+        }
+
         let data_resp = responses.remove(0)?;
         if is_err && data_resp.code != 354 {
             return Err(ClientError::Rejected(data_resp));
@@ -1085,6 +1361,12 @@ impl SmtpClient {
         let resp = self
             .read_response(Some(&data_dot), data_dot.client_timeout(&self.timeouts))
             .await?;
+        let resp = if self.lmtp {
+            self.read_lmtp_data_responses(resp, &mut rcpt_responses)
+                .await?
+        } else {
+            resp
+        };
         if resp.code != 250 {
             return Err(ClientError::Rejected(resp));
         }
@@ -1098,6 +1380,153 @@ impl SmtpClient {
             rcpt_responses,
         })
     }
+
+    /// RFC 2033: following the message data, an LMTP server returns
+    /// a response for each recipient that it accepted, in order.
+    /// `resp` is the first of those responses; this method reads the
+    /// remainder and records any failures in `rcpt_responses` so that
+    /// they are attributed to the appropriate recipients.
+    /// Returns a successful response if any recipient succeeded.
+    async fn read_lmtp_data_responses(
+        &mut self,
+        resp: Response,
+        rcpt_responses: &mut Vec<Response>,
+    ) -> Result<Response, ClientError> {
+        let accepted: Vec<usize> = rcpt_responses
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.code == 250)
+            .map(|(idx, _)| idx)
+            .collect();
+
+        let data_dot = Command::DataDot;
+        let mut data_responses = vec![resp];
+        for _ in 1..accepted.len() {
+            data_responses.push(
+                self.read_response(Some(&data_dot), data_dot.client_timeout(&self.timeouts))
+                    .await?,
+            );
+        }
+
+        let mut success = None;
+        let mut failures = vec![];
+        for (idx, data_resp) in accepted.into_iter().zip(data_responses) {
+            if data_resp.code == 250 {
+                success.get_or_insert(data_resp);
+            } else {
+                failures.push(data_resp.clone());
+                rcpt_responses[idx] = data_resp;
+            }
+        }
+
+        match success {
+            Some(resp) => Ok(resp),
+            None if failures.len() == 1 => {
+                Err(ClientError::Rejected(failures.pop().expect("have one")))
+            }
+            None => Err(ClientError::RejectedBatch(rcpt_responses.clone())),
+        }
+    }
+
+    /// Issue a series of pipelined commands, the last of which is a
+    /// BDAT command whose chunk data is written immediately after
+    /// the commands, and return the responses to those commands.
+    async fn pipeline_commands_with_chunk(
+        &mut self,
+        commands: Vec<Command>,
+        chunk: &[u8],
+    ) -> Vec<Result<Response, ClientError>> {
+        let written = match self.write_pipeline_request(&commands).await {
+            Ok(()) => self.write_data_with_timeout(chunk).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = written {
+            // Synthesize failures for all of the commands
+            return commands.iter().map(|_| Err(err.clone())).collect();
+        }
+
+        let mut results: Vec<Result<Response, ClientError>> = vec![];
+        for cmd in &commands {
+            results.push(
+                self.read_response(Some(cmd), cmd.client_timeout(&self.timeouts))
+                    .await,
+            );
+        }
+        results
+    }
+
+    /// Transmit the message data as a series of RFC 3030 BDAT chunks,
+    /// returning the response to the final `BDAT LAST` chunk.
+    /// If PIPELINING is available, all of the chunks are written
+    /// before reading any of the responses.
+    /// If the first chunk was already pipelined along with the envelope,
+    /// `first_chunk_resp` holds its response and only the remaining
+    /// chunks are sent.
+    async fn send_data_via_bdat(
+        &mut self,
+        chunks: &[&[u8]],
+        first_chunk_resp: Option<Result<Response, ClientError>>,
+    ) -> Result<Response, ClientError> {
+        let pipeline = self.enable_pipelining && self.capabilities.contains_key("PIPELINING");
+        let num_chunks = chunks.len();
+
+        tracing::trace!(
+            "message data is {} bytes, sending in {num_chunks} BDAT chunks",
+            chunks.iter().map(|chunk| chunk.len()).sum::<usize>()
+        );
+
+        let mut start = 0;
+        if let Some(resp) = first_chunk_resp {
+            let resp = resp?;
+            if resp.code != 250 {
+                return Err(ClientError::Rejected(resp));
+            }
+            if num_chunks == 1 {
+                return Ok(resp);
+            }
+            start = 1;
+        }
+
+        let mut pending = vec![];
+        let mut first_rejection = None;
+        for (idx, chunk) in chunks.iter().enumerate().skip(start) {
+            let command = Command::Bdat {
+                chunk_size: chunk.len(),
+                last: idx + 1 == num_chunks,
+            };
+            self.write_command_request(&command).await?;
+            self.write_data_with_timeout(chunk).await?;
+            pending.push(command);
+
+            if pipeline && idx + 1 < num_chunks {
+                continue;
+            }
+
+            for command in pending.drain(..) {
+                let resp = self
+                    .read_response(Some(&command), command.client_timeout(&self.timeouts))
+                    .await?;
+                if resp.code != 250 && first_rejection.is_none() {
+                    first_rejection.replace(resp);
+                    continue;
+                }
+                if matches!(command, Command::Bdat { last: true, .. }) {
+                    if let Some(rejection) = first_rejection.take() {
+                        return Err(ClientError::Rejected(rejection));
+                    }
+                    return Ok(resp);
+                }
+            }
+
+            // Without pipelining, we can stop sending as soon as
+            // an intermediate chunk is rejected
+            if let Some(rejection) = first_rejection.take() {
+                return Err(ClientError::Rejected(rejection));
+            }
+        }
+
+        unreachable!("the final BDAT LAST chunk always returns above");
+    }
 }
 
 #[derive(Debug)]
@@ -1147,6 +1576,17 @@ fn parse_response_line(line: &'_ str) -> Result<ResponseLine<'_>, ClientError> {
     }
 }
 
+/// Split the message data into chunks suitable for transmission
+/// via BDAT. An empty message is still sent as a single, empty,
+/// `BDAT 0 LAST` chunk.
+fn bdat_chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks: Vec<&[u8]> = data.chunks(BDAT_CHUNK_SIZE).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    chunks
+}
+
 fn apply_dot_stuffing(data: &[u8]) -> Option<Vec<u8>> {
     static LFDOT: LazyLock<Finder> = LazyLock::new(|| memchr::memmem::Finder::new("\n."));
 
@@ -1385,4 +1825,17 @@ mod test {
             r#"Timed Out waiting 10s for response to cmd=MAIL FROM:<user@host>"#
         );
     }
+
+    #[test]
+    fn test_cram_md5_digest() {
+        // Example from RFC 2195
+        assert_eq!(
+            cram_md5_digest(
+                b"tanstaaftanstaaf",
+                b"<1896.697170952@postoffice.reston.mci.net>"
+            )
+            .unwrap(),
+            "b913a602c7eda7a495b4e6e7334d3890"
+        );
+    }
 }
@@ -273,7 +273,8 @@ impl Response {
                     Command::MailFrom { .. }
                     | Command::RcptTo { .. }
                     | Command::Data
-                    | Command::DataDot => true,
+                    | Command::DataDot
+                    | Command::Bdat { .. } => true,
                     Command::Ehlo(_)
                     | Command::Helo(_)
                     | Command::Lhlo(_)
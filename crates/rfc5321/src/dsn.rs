@@ -0,0 +1,416 @@
+//! This module implements the RFC 3461 Delivery Status Notification
+//! ESMTP parameters: `RET` and `ENVID` for `MAIL FROM`, and
+//! `NOTIFY` and `ORCPT` for `RCPT TO`.
+use crate::parser::{xtext_decode, xtext_encode, EsmtpParameter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// RFC 3461 section 4.4: ENVID is limited to 100 characters
+const MAX_ENVID_LEN: usize = 100;
+/// RFC 3461 section 4.2: ORCPT is limited to 500 characters
+const MAX_ORCPT_LEN: usize = 500;
+
+/// The conditions under which a DSN should be generated
+/// for a recipient, as specified via the `NOTIFY` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DsnNotify {
+    Never,
+    Success,
+    Failure,
+    Delay,
+}
+
+impl std::fmt::Display for DsnNotify {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let label = match self {
+            Self::Never => "NEVER",
+            Self::Success => "SUCCESS",
+            Self::Failure => "FAILURE",
+            Self::Delay => "DELAY",
+        };
+        write!(fmt, "{label}")
+    }
+}
+
+/// How much of the original message should be returned in a
+/// failure DSN, as specified via the `RET` parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum DsnReturn {
+    Full,
+    Hdrs,
+}
+
+impl std::fmt::Display for DsnReturn {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let label = match self {
+            Self::Full => "FULL",
+            Self::Hdrs => "HDRS",
+        };
+        write!(fmt, "{label}")
+    }
+}
+
+/// The `ORCPT` parameter, with its xtext encoding removed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OriginalRecipient {
+    /// The address type; typically `rfc822` or `utf-8`
+    pub addr_type: String,
+    pub address: String,
+}
+
+impl std::fmt::Display for OriginalRecipient {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "{};{}", self.addr_type, self.address)
+    }
+}
+
+/// The DSN parameters that were specified for a single recipient
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecipientDsn {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify: Option<Vec<DsnNotify>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orcpt: Option<OriginalRecipient>,
+}
+
+impl RecipientDsn {
+    /// Extract the DSN parameters from the parameters of a `RCPT TO`
+    /// command. Returns `Ok(None)` if there were no DSN parameters.
+    pub fn from_rcpt_to_parameters(parameters: &[EsmtpParameter]) -> Result<Option<Self>, String> {
+        let mut result = Self::default();
+
+        for param in parameters {
+            if param.name.eq_ignore_ascii_case("NOTIFY") {
+                if result.notify.is_some() {
+                    return Err("NOTIFY specified more than once".to_string());
+                }
+                let value = param
+                    .value
+                    .as_deref()
+                    .ok_or_else(|| "NOTIFY requires a value".to_string())?;
+                result.notify.replace(parse_notify(value)?);
+            } else if param.name.eq_ignore_ascii_case("ORCPT") {
+                if result.orcpt.is_some() {
+                    return Err("ORCPT specified more than once".to_string());
+                }
+                let value = param
+                    .value
+                    .as_deref()
+                    .ok_or_else(|| "ORCPT requires a value".to_string())?;
+                if value.len() > MAX_ORCPT_LEN {
+                    return Err(format!("ORCPT exceeds {MAX_ORCPT_LEN} characters"));
+                }
+                let (addr_type, address) = value
+                    .split_once(';')
+                    .ok_or_else(|| "ORCPT must be of the form addr-type;xtext".to_string())?;
+                if addr_type.is_empty() {
+                    return Err("ORCPT addr-type must not be empty".to_string());
+                }
+                result.orcpt.replace(OriginalRecipient {
+                    addr_type: addr_type.to_string(),
+                    address: xtext_decode(address)?,
+                });
+            }
+        }
+
+        if result.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(result))
+        }
+    }
+
+    /// Produce the parameters that should be passed with `RCPT TO`
+    /// when relaying to a DSN-capable host
+    pub fn to_rcpt_to_parameters(&self) -> Result<Vec<EsmtpParameter>, String> {
+        let mut parameters = vec![];
+        if let Some(notify) = &self.notify {
+            parameters.push(EsmtpParameter {
+                name: "NOTIFY".to_string(),
+                value: Some(
+                    notify
+                        .iter()
+                        .map(|n| n.to_string())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                ),
+            });
+        }
+        if let Some(orcpt) = &self.orcpt {
+            parameters.push(EsmtpParameter {
+                name: "ORCPT".to_string(),
+                value: Some(format!(
+                    "{};{}",
+                    orcpt.addr_type,
+                    xtext_encode(&orcpt.address)?
+                )),
+            });
+        }
+        Ok(parameters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notify.is_none() && self.orcpt.is_none()
+    }
+
+    /// Returns true if a DSN should be generated for this recipient
+    /// for the specified condition.
+    /// When `NOTIFY` was not specified, RFC 3461 section 4.1 allows
+    /// us to behave as though `NOTIFY=FAILURE,DELAY` was specified.
+    pub fn should_notify(&self, condition: DsnNotify) -> bool {
+        match &self.notify {
+            Some(notify) => notify.contains(&condition),
+            None => matches!(condition, DsnNotify::Failure | DsnNotify::Delay),
+        }
+    }
+}
+
+fn parse_notify(value: &str) -> Result<Vec<DsnNotify>, String> {
+    let mut result = vec![];
+    for item in value.split(',') {
+        let item = if item.eq_ignore_ascii_case("NEVER") {
+            DsnNotify::Never
+        } else if item.eq_ignore_ascii_case("SUCCESS") {
+            DsnNotify::Success
+        } else if item.eq_ignore_ascii_case("FAILURE") {
+            DsnNotify::Failure
+        } else if item.eq_ignore_ascii_case("DELAY") {
+            DsnNotify::Delay
+        } else {
+            return Err(format!("invalid NOTIFY value {item}"));
+        };
+        if !result.contains(&item) {
+            result.push(item);
+        }
+    }
+    if result.contains(&DsnNotify::Never) && result.len() > 1 {
+        return Err("NOTIFY=NEVER cannot be combined with other values".to_string());
+    }
+    Ok(result)
+}
+
+/// The complete set of DSN parameters for a message.
+/// This is the representation that kumod stores in the `dsn`
+/// metadata key of a message.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DsnParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ret: Option<DsnReturn>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envid: Option<String>,
+    /// Per-recipient parameters, keyed by the recipient address
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub recipients: BTreeMap<String, RecipientDsn>,
+    /// Set when the most recent delivery attempt was made to a
+    /// DSN-capable next hop, which then becomes responsible for
+    /// any success notifications
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub relayed: bool,
+}
+
+impl DsnParams {
+    /// Extract the DSN parameters from the parameters of a `MAIL FROM`
+    /// command.
+    pub fn from_mail_from_parameters(parameters: &[EsmtpParameter]) -> Result<Self, String> {
+        let mut result = Self::default();
+
+        for param in parameters {
+            if param.name.eq_ignore_ascii_case("RET") {
+                if result.ret.is_some() {
+                    return Err("RET specified more than once".to_string());
+                }
+                let value = param
+                    .value
+                    .as_deref()
+                    .ok_or_else(|| "RET requires a value".to_string())?;
+                let ret = if value.eq_ignore_ascii_case("FULL") {
+                    DsnReturn::Full
+                } else if value.eq_ignore_ascii_case("HDRS") {
+                    DsnReturn::Hdrs
+                } else {
+                    return Err(format!("invalid RET value {value}"));
+                };
+                result.ret.replace(ret);
+            } else if param.name.eq_ignore_ascii_case("ENVID") {
+                if result.envid.is_some() {
+                    return Err("ENVID specified more than once".to_string());
+                }
+                let value = param
+                    .value
+                    .as_deref()
+                    .ok_or_else(|| "ENVID requires a value".to_string())?;
+                if value.len() > MAX_ENVID_LEN {
+                    return Err(format!("ENVID exceeds {MAX_ENVID_LEN} characters"));
+                }
+                result.envid.replace(xtext_decode(value)?);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Produce the parameters that should be passed with `MAIL FROM`
+    /// when relaying to a DSN-capable host
+    pub fn to_mail_from_parameters(&self) -> Result<Vec<EsmtpParameter>, String> {
+        let mut parameters = vec![];
+        if let Some(ret) = &self.ret {
+            parameters.push(EsmtpParameter {
+                name: "RET".to_string(),
+                value: Some(ret.to_string()),
+            });
+        }
+        if let Some(envid) = &self.envid {
+            parameters.push(EsmtpParameter {
+                name: "ENVID".to_string(),
+                value: Some(xtext_encode(envid)?),
+            });
+        }
+        Ok(parameters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ret.is_none() && self.envid.is_none() && self.recipients.is_empty()
+    }
+
+    /// Returns the parameters for the specified recipient, if any
+    pub fn recipient(&self, recipient: &str) -> Option<&RecipientDsn> {
+        self.recipients.get(recipient)
+    }
+
+    /// Returns true if a DSN should be generated for the specified
+    /// recipient under the specified condition
+    pub fn should_notify(&self, recipient: &str, condition: DsnNotify) -> bool {
+        match self.recipient(recipient) {
+            Some(dsn) => dsn.should_notify(condition),
+            None => RecipientDsn::default().should_notify(condition),
+        }
+    }
+
+    /// Returns a copy of these parameters that includes only
+    /// the per-recipient entries for the specified recipients
+    pub fn for_recipients<'a>(&self, recipients: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut result = Self {
+            ret: self.ret,
+            envid: self.envid.clone(),
+            recipients: BTreeMap::new(),
+            relayed: self.relayed,
+        };
+        for recip in recipients {
+            if let Some(dsn) = self.recipients.get(recip) {
+                result.recipients.insert(recip.to_string(), dsn.clone());
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn param(name: &str, value: &str) -> EsmtpParameter {
+        EsmtpParameter {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+        }
+    }
+
+    #[test]
+    fn mail_from_params() {
+        let params = DsnParams::from_mail_from_parameters(&[
+            param("RET", "hdrs"),
+            param("ENVID", "QQ314159+2bx"),
+            param("BODY", "8BITMIME"),
+        ])
+        .unwrap();
+        assert_eq!(
+            params,
+            DsnParams {
+                ret: Some(DsnReturn::Hdrs),
+                envid: Some("QQ314159+x".to_string()),
+                recipients: BTreeMap::new(),
+                relayed: false,
+            }
+        );
+        assert_eq!(
+            params.to_mail_from_parameters().unwrap(),
+            vec![param("RET", "HDRS"), param("ENVID", "QQ314159+2bx")]
+        );
+
+        assert_eq!(
+            DsnParams::from_mail_from_parameters(&[param("RET", "SOME")]).unwrap_err(),
+            "invalid RET value SOME"
+        );
+        assert_eq!(
+            DsnParams::from_mail_from_parameters(&[param("RET", "FULL"), param("RET", "HDRS")])
+                .unwrap_err(),
+            "RET specified more than once"
+        );
+        assert!(
+            DsnParams::from_mail_from_parameters(&[param("BODY", "8BITMIME")])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rcpt_to_params() {
+        let dsn = RecipientDsn::from_rcpt_to_parameters(&[
+            param("NOTIFY", "success,FAILURE"),
+            param("ORCPT", "rfc822;Bob+2bsmith@example.com"),
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            dsn,
+            RecipientDsn {
+                notify: Some(vec![DsnNotify::Success, DsnNotify::Failure]),
+                orcpt: Some(OriginalRecipient {
+                    addr_type: "rfc822".to_string(),
+                    address: "Bob+smith@example.com".to_string(),
+                }),
+            }
+        );
+        assert!(dsn.should_notify(DsnNotify::Success));
+        assert!(dsn.should_notify(DsnNotify::Failure));
+        assert!(!dsn.should_notify(DsnNotify::Delay));
+        assert_eq!(
+            dsn.to_rcpt_to_parameters().unwrap(),
+            vec![
+                param("NOTIFY", "SUCCESS,FAILURE"),
+                param("ORCPT", "rfc822;Bob+2bsmith@example.com")
+            ]
+        );
+
+        assert_eq!(
+            RecipientDsn::from_rcpt_to_parameters(&[param("NOTIFY", "NEVER,SUCCESS")]).unwrap_err(),
+            "NOTIFY=NEVER cannot be combined with other values"
+        );
+        assert_eq!(
+            RecipientDsn::from_rcpt_to_parameters(&[param("ORCPT", "nope")]).unwrap_err(),
+            "ORCPT must be of the form addr-type;xtext"
+        );
+        assert_eq!(RecipientDsn::from_rcpt_to_parameters(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn default_notify() {
+        let never = RecipientDsn::from_rcpt_to_parameters(&[param("NOTIFY", "NEVER")])
+            .unwrap()
+            .unwrap();
+        let mut params = DsnParams::default();
+        params
+            .recipients
+            .insert("never@example.com".to_string(), never);
+
+        assert!(!params.should_notify("never@example.com", DsnNotify::Failure));
+        assert!(params.should_notify("other@example.com", DsnNotify::Failure));
+        assert!(params.should_notify("other@example.com", DsnNotify::Delay));
+        assert!(!params.should_notify("other@example.com", DsnNotify::Success));
+
+        let subset = params.for_recipients(["other@example.com"]);
+        assert!(subset.recipients.is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 #[cfg(feature = "client")]
 pub mod client;
 pub mod client_types;
+pub mod dsn;
 pub mod parser;
 
 // Re-export TLS types from kumo-tls-helper for backwards compatibility
@@ -12,4 +13,5 @@ pub use kumo_tls_helper::{AsyncReadAndWrite, BoxedAsyncReadAndWrite};
 #[cfg(feature = "client")]
 pub use client::*;
 pub use client_types::*;
+pub use dsn::*;
 pub use parser::*;
@@ -20,6 +20,7 @@ impl Parser {
             Rule::rcpt => Self::parse_rcpt(result.into_inner()),
             Rule::ehlo => Self::parse_ehlo(result.into_inner()),
             Rule::helo => Self::parse_helo(result.into_inner()),
+            Rule::lhlo => Self::parse_lhlo(result.into_inner()),
             Rule::data => Ok(Command::Data),
             Rule::rset => Ok(Command::Rset),
             Rule::quit => Ok(Command::Quit),
@@ -30,6 +31,7 @@ impl Parser {
             Rule::noop => Self::parse_noop(result.into_inner()),
             Rule::auth => Self::parse_auth(result.into_inner()),
             Rule::xclient => Self::parse_xclient(result.into_inner()),
+            Rule::bdat => Self::parse_bdat(result.into_inner()),
             _ => Err(format!("unexpected {result:?}")),
         }
     }
@@ -67,6 +69,11 @@ impl Parser {
         Ok(Command::Helo(Self::parse_domain(domain)?))
     }
 
+    fn parse_lhlo(mut pairs: Pairs<Rule>) -> Result<Command, String> {
+        let domain = pairs.next().unwrap();
+        Ok(Command::Lhlo(Self::parse_domain(domain)?))
+    }
+
     fn parse_vrfy(mut pairs: Pairs<Rule>) -> Result<Command, String> {
         let param = pairs.next().unwrap().as_str().to_string();
         Ok(Command::Vrfy(param))
@@ -109,6 +116,15 @@ impl Parser {
         Ok(Command::XClient(params))
     }
 
+    fn parse_bdat(mut pairs: Pairs<Rule>) -> Result<Command, String> {
+        let size = pairs.next().unwrap().as_str();
+        let chunk_size = size
+            .parse::<usize>()
+            .map_err(|err| format!("invalid BDAT chunk size {size}: {err:#}"))?;
+        let last = pairs.next().is_some();
+        Ok(Command::Bdat { chunk_size, last })
+    }
+
     fn parse_rcpt(mut pairs: Pairs<Rule>) -> Result<Command, String> {
         let forward_path = pairs.next().unwrap().into_inner().next().unwrap();
         let mut no_angles = false;
@@ -411,7 +427,7 @@ impl ToString for Domain {
     }
 }
 
-fn xtext_encode(s: &str) -> Result<String, String> {
+pub(crate) fn xtext_encode(s: &str) -> Result<String, String> {
     let mut result = String::new();
 
     for c in s.chars() {
@@ -430,7 +446,7 @@ fn xtext_encode(s: &str) -> Result<String, String> {
     Ok(result)
 }
 
-fn xtext_decode(s: &str) -> Result<String, String> {
+pub(crate) fn xtext_decode(s: &str) -> Result<String, String> {
     let mut bytes = vec![];
 
     let mut iter = s.chars();
@@ -543,6 +559,12 @@ pub enum Command {
         initial_response: Option<String>,
     },
     XClient(Vec<XClientParameter>),
+    /// RFC 3030 CHUNKING. The command line is followed by exactly
+    /// `chunk_size` octets of message data.
+    Bdat {
+        chunk_size: usize,
+        last: bool,
+    },
 }
 
 impl Command {
@@ -606,6 +628,14 @@ impl Command {
                 }
                 format!("XCLIENT{s}\r\n")
             }
+            Self::Bdat {
+                chunk_size,
+                last: true,
+            } => format!("BDAT {chunk_size} LAST\r\n"),
+            Self::Bdat {
+                chunk_size,
+                last: false,
+            } => format!("BDAT {chunk_size}\r\n"),
         }
     }
 
@@ -617,6 +647,9 @@ impl Command {
             Self::RcptTo { .. } => timeouts.rcpt_to_timeout,
             Self::Data { .. } => timeouts.data_timeout,
             Self::DataDot => timeouts.data_dot_timeout,
+            // The response to a BDAT is returned after the chunk
+            // has been received, so it is comparable to the dot
+            Self::Bdat { .. } => timeouts.data_dot_timeout,
             Self::Rset => timeouts.rset_timeout,
             Self::StartTls => timeouts.starttls_timeout,
             Self::Quit | Self::Vrfy(_) | Self::Expn(_) | Self::Help(_) | Self::Noop(_) => {
@@ -695,6 +728,18 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_lhlo() {
+        assert_eq!(
+            Parser::parse_command("LHLO there").unwrap(),
+            Command::Lhlo(Domain::Name("there".to_string()))
+        );
+        assert_eq!(
+            Parser::parse_command("lhlo [127.0.0.1]").unwrap(),
+            Command::Lhlo(Domain::V4("127.0.0.1".to_string()))
+        );
+    }
+
     #[test]
     fn parse_helo() {
         assert_eq!(
@@ -728,6 +773,36 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_bdat() {
+        assert_eq!(
+            Parser::parse_command("BDAT 1024").unwrap(),
+            Command::Bdat {
+                chunk_size: 1024,
+                last: false,
+            }
+        );
+        assert_eq!(
+            Parser::parse_command("bdat 0 last").unwrap(),
+            Command::Bdat {
+                chunk_size: 0,
+                last: true,
+            }
+        );
+        assert!(Parser::parse_command("BDAT").is_err());
+        assert!(Parser::parse_command("BDAT nope").is_err());
+        assert!(Parser::parse_command("BDAT 1024 junk").is_err());
+        assert!(Parser::parse_command("BDAT 0 LAST junk").is_err());
+        assert_eq!(
+            Command::Bdat {
+                chunk_size: 42,
+                last: true
+            }
+            .encode(),
+            "BDAT 42 LAST\r\n"
+        );
+    }
+
     #[test]
     fn parse_rcpt_to_punycode() {
         assert_eq!(
@@ -1028,6 +1103,7 @@ mod test {
                 },
             ])
         );
+        assert!(Parser::parse_command("XCLIENT NAME=spike.porcupine.org junk").is_err());
     }
 
     #[test]
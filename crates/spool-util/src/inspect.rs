@@ -0,0 +1,643 @@
+use chrono::Utc;
+use clap::{Args, Parser};
+use message::message::MetaData;
+use serde_json::Value;
+use spool::{join_enumerate, Spool, SpoolEntry, SpoolId};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Criteria used to select messages from the meta spool.
+/// All of the specified criteria must match.
+#[derive(Debug, Args)]
+pub struct MessageFilter {
+    /// Only select messages with these spool ids.
+    /// May be specified multiple times.
+    #[arg(long = "id")]
+    ids: Vec<String>,
+    /// Only select messages in this scheduled queue
+    #[arg(long)]
+    queue: Option<String>,
+    /// Only select messages whose envelope sender is in this domain
+    #[arg(long)]
+    sender_domain: Option<String>,
+    /// Only select messages with at least one recipient in this domain
+    #[arg(long)]
+    recipient_domain: Option<String>,
+}
+
+impl MessageFilter {
+    fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+            && self.queue.is_none()
+            && self.sender_domain.is_none()
+            && self.recipient_domain.is_none()
+    }
+
+    fn parse_ids(&self) -> anyhow::Result<Vec<SpoolId>> {
+        self.ids
+            .iter()
+            .map(|id| SpoolId::from_str(id).ok_or_else(|| anyhow::anyhow!("invalid spool id {id}")))
+            .collect()
+    }
+
+    fn matches(&self, ids: &[SpoolId], id: SpoolId, metadata: &MetaData, queue: &str) -> bool {
+        if !ids.is_empty() && !ids.contains(&id) {
+            return false;
+        }
+        if let Some(wanted) = &self.queue {
+            if queue != wanted {
+                return false;
+            }
+        }
+        if let Some(domain) = &self.sender_domain {
+            if !domain.eq_ignore_ascii_case(metadata.sender.domain()) {
+                return false;
+            }
+        }
+        if let Some(domain) = &self.recipient_domain {
+            if !metadata
+                .recipient
+                .iter()
+                .any(|recip| domain.eq_ignore_ascii_case(recip.domain()))
+            {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Parse an entry from the meta spool, returning the metadata
+/// and the name of the scheduled queue to which it belongs
+fn parse_meta(data: &[u8]) -> anyhow::Result<(MetaData, String)> {
+    let metadata: MetaData = serde_json::from_slice(data)?;
+    let queue = metadata.queue_name()?;
+    Ok((metadata, queue))
+}
+
+/// Enumerate the meta spool, calling `func` for each message that
+/// matches the filter
+async fn for_each_matching(
+    meta_spool: &dyn Spool,
+    filter: &MessageFilter,
+    mut func: impl FnMut(SpoolId, MetaData, String) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let ids = filter.parse_ids()?;
+    let (tx, rx) = flume::bounded(1024);
+    let enumeration = meta_spool.enumerate(tx, Utc::now())?;
+    while let Ok(info) = rx.recv_async().await {
+        match info {
+            SpoolEntry::Item { id, data } => {
+                let (metadata, queue) = match parse_meta(&data) {
+                    Ok(parsed) => parsed,
+                    Err(err) => {
+                        eprintln!("ERROR: metadata for {id} is invalid: {err:#}");
+                        continue;
+                    }
+                };
+                if filter.matches(&ids, id, &metadata, &queue) {
+                    (func)(id, metadata, queue)?;
+                }
+            }
+            SpoolEntry::Corrupt { id, error } => {
+                eprintln!("ERROR: entry {id} is corrupt: {error}");
+            }
+        }
+    }
+    join_enumerate(enumeration).await
+}
+
+/// List the messages in the spool
+#[derive(Debug, Parser)]
+pub struct ListCommand {
+    #[command(flatten)]
+    filter: MessageFilter,
+
+    /// Instead of listing each message, show the number
+    /// of matching messages in each queue
+    #[arg(long)]
+    summary: bool,
+}
+
+impl ListCommand {
+    pub async fn run(&self, meta_spool: &dyn Spool) -> anyhow::Result<()> {
+        let mut count = 0;
+        let mut by_queue: BTreeMap<String, usize> = BTreeMap::new();
+        let summary = self.summary;
+
+        for_each_matching(meta_spool, &self.filter, |id, metadata, queue| {
+            count += 1;
+            if summary {
+                *by_queue.entry(queue).or_default() += 1;
+            } else {
+                println!(
+                    "{id} queue={queue} sender={} recipients={}",
+                    metadata.sender.to_string(),
+                    metadata
+                        .recipient
+                        .iter()
+                        .map(|recip| recip.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                );
+            }
+            Ok(())
+        })
+        .await?;
+
+        for (queue, n) in by_queue {
+            println!("{n:>10} {queue}");
+        }
+        eprintln!("{count} matching messages");
+        Ok(())
+    }
+}
+
+/// Show the metadata, and optionally the message content,
+/// for a message
+#[derive(Debug, Parser)]
+pub struct DumpCommand {
+    /// The spool id of the message
+    #[arg(long)]
+    id: String,
+
+    /// Also print the message content after the metadata
+    #[arg(long)]
+    body: bool,
+}
+
+impl DumpCommand {
+    pub async fn run(&self, meta_spool: &dyn Spool, data_spool: &dyn Spool) -> anyhow::Result<()> {
+        let id = SpoolId::from_str(&self.id)
+            .ok_or_else(|| anyhow::anyhow!("invalid spool id {}", self.id))?;
+
+        let metadata = meta_spool.load(id).await?;
+        match serde_json::from_slice::<Value>(&metadata) {
+            Ok(metadata) => println!("{}", serde_json::to_string_pretty(&metadata)?),
+            Err(err) => {
+                eprintln!("ERROR: metadata for {id} is not valid json: {err:#}");
+                println!("{}", String::from_utf8_lossy(&metadata));
+            }
+        }
+
+        if self.body {
+            let data = data_spool.load(id).await?;
+            println!();
+            std::io::stdout().write_all(&data)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove selected messages from the spool
+#[derive(Debug, Parser)]
+pub struct DeleteCommand {
+    #[command(flatten)]
+    filter: MessageFilter,
+
+    /// Only show which messages would be deleted
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl DeleteCommand {
+    pub async fn run(&self, meta_spool: &dyn Spool, data_spool: &dyn Spool) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.filter.is_empty(),
+            "refusing to delete every message; please specify some selection criteria"
+        );
+
+        // Collect first; the spool must not be modified
+        // while it is being enumerated
+        let mut ids = vec![];
+        for_each_matching(meta_spool, &self.filter, |id, _metadata, _queue| {
+            ids.push(id);
+            Ok(())
+        })
+        .await?;
+
+        for &id in &ids {
+            if self.dry_run {
+                println!("would delete {id}");
+                continue;
+            }
+            if let Err(err) = data_spool.remove(id).await {
+                eprintln!("ERROR: {err:#}");
+            }
+            meta_spool.remove(id).await?;
+            println!("deleted {id}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Modify the metadata of selected messages
+#[derive(Debug, Parser)]
+pub struct RewriteCommand {
+    #[command(flatten)]
+    filter: MessageFilter,
+
+    /// Assign the messages to this scheduled queue
+    #[arg(long)]
+    set_queue: Option<String>,
+
+    /// Remove any scheduling constraints and expiration
+    /// that were set on the messages
+    #[arg(long)]
+    clear_schedule: bool,
+
+    /// Set a metadata value. The value is parsed as json, falling
+    /// back to a string if it isn't valid json.
+    /// May be specified multiple times.
+    #[arg(long, value_name = "KEY=VALUE")]
+    set_meta: Vec<String>,
+
+    /// Remove a metadata value. May be specified multiple times.
+    #[arg(long, value_name = "KEY")]
+    remove_meta: Vec<String>,
+
+    /// Only show what would be changed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl RewriteCommand {
+    fn apply(&self, metadata: &mut MetaData) -> anyhow::Result<()> {
+        if self.clear_schedule {
+            metadata.schedule = None;
+        }
+
+        let meta = metadata
+            .meta
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("meta is not an object"))?;
+
+        if let Some(queue) = &self.set_queue {
+            meta.insert("queue".to_string(), Value::String(queue.to_string()));
+        }
+        for key in &self.remove_meta {
+            meta.remove(key);
+        }
+        for kv in &self.set_meta {
+            let (key, value) = kv
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("--set-meta {kv} is not of the form KEY=VALUE"))?;
+            let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+            meta.insert(key.to_string(), value);
+        }
+
+        Ok(())
+    }
+
+    pub async fn run(&self, meta_spool: &dyn Spool) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.filter.is_empty(),
+            "refusing to rewrite every message; please specify some selection criteria"
+        );
+        anyhow::ensure!(
+            self.set_queue.is_some()
+                || self.clear_schedule
+                || !self.set_meta.is_empty()
+                || !self.remove_meta.is_empty(),
+            "no changes were specified"
+        );
+
+        let mut updates = vec![];
+        for_each_matching(meta_spool, &self.filter, |id, mut metadata, _queue| {
+            self.apply(&mut metadata)?;
+            updates.push((id, metadata));
+            Ok(())
+        })
+        .await?;
+
+        for (id, metadata) in updates {
+            if self.dry_run {
+                println!("would rewrite {id}: {}", serde_json::to_string(&metadata)?);
+                continue;
+            }
+            let data = Arc::new(serde_json::to_vec(&metadata)?.into_boxed_slice());
+            meta_spool.store(id, data, true, None).await?;
+            println!("rewrote {id}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Move corrupt entries out of the spool and into a quarantine
+/// directory, so that they no longer fail when kumod starts.
+///
+/// Both the meta and data entries for an affected message are
+/// moved into a subdirectory named after its spool id, along
+/// with an `error.txt` file that explains the problem.
+#[derive(Debug, Parser)]
+pub struct QuarantineCommand {
+    /// The directory into which corrupt entries will be moved
+    #[arg(long)]
+    dir: PathBuf,
+
+    /// Only show which entries would be quarantined
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl QuarantineCommand {
+    async fn find_corrupt(
+        label: &str,
+        spool: &dyn Spool,
+        check_meta: bool,
+        found: &mut HashMap<SpoolId, Vec<String>>,
+    ) -> anyhow::Result<()> {
+        let (tx, rx) = flume::bounded(1024);
+        let enumeration = spool.enumerate(tx, Utc::now())?;
+        while let Ok(info) = rx.recv_async().await {
+            match info {
+                SpoolEntry::Item { id, data } => {
+                    if !check_meta {
+                        continue;
+                    }
+                    if let Err(err) = parse_meta(&data) {
+                        found
+                            .entry(id)
+                            .or_default()
+                            .push(format!("{label}: invalid metadata: {err:#}"));
+                    }
+                }
+                SpoolEntry::Corrupt { id, error } => {
+                    found
+                        .entry(id)
+                        .or_default()
+                        .push(format!("{label}: {error}"));
+                }
+            }
+        }
+        join_enumerate(enumeration).await
+    }
+
+    pub async fn run(&self, meta_spool: &dyn Spool, data_spool: &dyn Spool) -> anyhow::Result<()> {
+        let mut found = HashMap::new();
+        Self::find_corrupt("meta", meta_spool, true, &mut found).await?;
+        Self::find_corrupt("data", data_spool, false, &mut found).await?;
+
+        for (id, errors) in &found {
+            if self.dry_run {
+                println!("would quarantine {id}: {}", errors.join("; "));
+                continue;
+            }
+
+            let dir = self.dir.join(id.to_string());
+            std::fs::create_dir_all(&dir)?;
+            std::fs::write(dir.join("error.txt"), errors.join("\n"))?;
+
+            for (label, spool) in [("meta", meta_spool), ("data", data_spool)] {
+                // Preserve whatever we are able to read
+                if let Ok(data) = spool.load(*id).await {
+                    std::fs::write(dir.join(label), data)?;
+                }
+                spool.remove(*id).await.ok();
+            }
+            println!("quarantined {id} to {}", dir.display());
+        }
+
+        eprintln!("{} corrupt messages", found.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use spool::local_disk::LocalDiskSpool;
+    use std::collections::HashSet;
+    use tokio::runtime::Handle;
+
+    struct TestSpools {
+        _dir: tempfile::TempDir,
+        meta: LocalDiskSpool,
+        data: LocalDiskSpool,
+    }
+
+    impl TestSpools {
+        fn new() -> anyhow::Result<Self> {
+            let dir = tempfile::tempdir()?;
+            let meta = LocalDiskSpool::new(&dir.path().join("meta"), false, Handle::current())?;
+            let data = LocalDiskSpool::new(&dir.path().join("data"), false, Handle::current())?;
+            Ok(Self {
+                _dir: dir,
+                meta,
+                data,
+            })
+        }
+
+        async fn store(&self, meta: Vec<u8>, data: &str) -> anyhow::Result<SpoolId> {
+            let id = SpoolId::new();
+            self.meta
+                .store(id, Arc::new(meta.into_boxed_slice()), false, None)
+                .await?;
+            self.data
+                .store(id, Arc::new(data.as_bytes().into()), false, None)
+                .await?;
+            Ok(id)
+        }
+
+        async fn store_message(&self, metadata: Value) -> anyhow::Result<SpoolId> {
+            self.store(
+                serde_json::to_vec(&metadata)?,
+                "Subject: hello\r\n\r\nhi\r\n",
+            )
+            .await
+        }
+
+        async fn load_meta(&self, id: SpoolId) -> anyhow::Result<MetaData> {
+            Ok(serde_json::from_slice(&self.meta.load(id).await?)?)
+        }
+    }
+
+    fn filter() -> MessageFilter {
+        MessageFilter {
+            ids: vec![],
+            queue: None,
+            sender_domain: None,
+            recipient_domain: None,
+        }
+    }
+
+    async fn matching(
+        spools: &TestSpools,
+        filter: &MessageFilter,
+    ) -> anyhow::Result<HashSet<SpoolId>> {
+        let mut ids = HashSet::new();
+        for_each_matching(&spools.meta, filter, |id, _metadata, _queue| {
+            ids.insert(id);
+            Ok(())
+        })
+        .await?;
+        Ok(ids)
+    }
+
+    #[tokio::test]
+    async fn filter_messages() -> anyhow::Result<()> {
+        let spools = TestSpools::new()?;
+        let tenant = spools
+            .store_message(json!({
+                "sender": "sender@example.com",
+                "recipient": "user@Example.NET",
+                "meta": {"tenant": "mytenant"},
+            }))
+            .await?;
+        let multi = spools
+            .store_message(json!({
+                "sender": "sender@other.example.com",
+                "recipient": ["one@example.org", "two@example.net"],
+                "meta": {"queue": "custom"},
+            }))
+            .await?;
+        // Invalid metadata is reported and skipped
+        spools.store(b"not json".to_vec(), "").await?;
+
+        let all = HashSet::from([tenant, multi]);
+        assert_eq!(matching(&spools, &filter()).await?, all);
+
+        let by_queue = MessageFilter {
+            queue: Some("mytenant@example.net".to_string()),
+            ..filter()
+        };
+        assert_eq!(matching(&spools, &by_queue).await?, HashSet::from([tenant]));
+
+        let by_queue = MessageFilter {
+            queue: Some("custom".to_string()),
+            ..filter()
+        };
+        assert_eq!(matching(&spools, &by_queue).await?, HashSet::from([multi]));
+
+        let by_sender = MessageFilter {
+            sender_domain: Some("EXAMPLE.com".to_string()),
+            ..filter()
+        };
+        assert_eq!(
+            matching(&spools, &by_sender).await?,
+            HashSet::from([tenant])
+        );
+
+        let by_recipient = MessageFilter {
+            recipient_domain: Some("example.net".to_string()),
+            ..filter()
+        };
+        assert_eq!(matching(&spools, &by_recipient).await?, all);
+
+        let by_id_and_recipient = MessageFilter {
+            ids: vec![tenant.to_string()],
+            recipient_domain: Some("example.org".to_string()),
+            ..filter()
+        };
+        assert!(matching(&spools, &by_id_and_recipient).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rewrite() -> anyhow::Result<()> {
+        let spools = TestSpools::new()?;
+        let id = spools
+            .store_message(json!({
+                "sender": "sender@example.com",
+                "recipient": "user@example.net",
+                "meta": {"tenant": "mytenant", "remove-me": true},
+                "schedule": {"expires": "2030-01-01T00:00:00+00:00"},
+            }))
+            .await?;
+        let other = spools
+            .store_message(json!({
+                "sender": "sender@example.com",
+                "recipient": "user@example.org",
+                "meta": {},
+            }))
+            .await?;
+
+        let command = RewriteCommand {
+            filter: MessageFilter {
+                recipient_domain: Some("example.net".to_string()),
+                ..filter()
+            },
+            set_queue: Some("quarantined".to_string()),
+            clear_schedule: true,
+            set_meta: vec!["count=42".to_string(), "label=not json".to_string()],
+            remove_meta: vec!["remove-me".to_string()],
+            dry_run: false,
+        };
+        command.run(&spools.meta).await?;
+
+        let metadata = spools.load_meta(id).await?;
+        assert_eq!(metadata.queue_name()?, "quarantined");
+        assert!(metadata.schedule.is_none());
+        assert_eq!(
+            metadata.meta,
+            json!({
+                "tenant": "mytenant",
+                "queue": "quarantined",
+                "count": 42,
+                "label": "not json",
+            })
+        );
+        assert_eq!(metadata.recipient[0].domain(), "example.net");
+
+        // Messages that didn't match are left alone
+        let metadata = spools.load_meta(other).await?;
+        assert_eq!(metadata.meta, json!({}));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quarantine() -> anyhow::Result<()> {
+        let spools = TestSpools::new()?;
+        let good = spools
+            .store_message(json!({
+                "sender": "sender@example.com",
+                "recipient": "user@example.net",
+                "meta": {},
+            }))
+            .await?;
+        let bad = spools.store(b"not json".to_vec(), "the content").await?;
+        let no_recipient = spools
+            .store_message(json!({
+                "sender": "sender@example.com",
+                "recipient": [],
+                "meta": {},
+            }))
+            .await?;
+
+        let quarantine_dir = tempfile::tempdir()?;
+        let command = QuarantineCommand {
+            dir: quarantine_dir.path().to_path_buf(),
+            dry_run: false,
+        };
+        command.run(&spools.meta, &spools.data).await?;
+
+        spools.load_meta(good).await?;
+        spools.data.load(good).await?;
+
+        for id in [bad, no_recipient] {
+            assert!(spools.meta.load(id).await.is_err());
+            assert!(spools.data.load(id).await.is_err());
+
+            let dir = quarantine_dir.path().join(id.to_string());
+            let error = std::fs::read_to_string(dir.join("error.txt"))?;
+            assert!(error.starts_with("meta: invalid metadata:"), "{error}");
+            assert!(dir.join("meta").exists());
+            assert!(dir.join("data").exists());
+        }
+        assert_eq!(
+            std::fs::read(quarantine_dir.path().join(bad.to_string()).join("data"))?,
+            b"the content"
+        );
+        assert!(!quarantine_dir.path().join(good.to_string()).exists());
+
+        Ok(())
+    }
+}
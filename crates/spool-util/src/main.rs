@@ -1,10 +1,19 @@
+use anyhow::Context;
 use chrono::Utc;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use human_bytes::human_bytes;
+use spool::compressed::CompressedSpool;
+use spool::encrypted::{EncryptedSpool, SpoolEncryptionKey};
+use spool::local_disk::LocalDiskSpool;
 use spool::rocks::RocksSpool;
-use spool::{Spool, SpoolEntry};
-use std::path::PathBuf;
+use spool::{join_enumerate, Spool, SpoolEntry, SpoolId};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::runtime::Handle;
+use tokio::task::JoinSet;
+
+mod inspect;
 
 /// KumoMTA Spool Utility
 ///
@@ -18,20 +27,438 @@ struct Opt {
     #[arg(long)]
     data: PathBuf,
 
+    /// The kind of spool found at the meta and data paths
+    #[arg(long, value_enum, default_value_t = SpoolKind::RocksDB)]
+    kind: SpoolKind,
+
+    /// Decrypt an encrypted spool, using the key material found in
+    /// the file at PATH for the key named ID. May be specified multiple
+    /// times; the first key is used to encrypt any records that are written.
+    #[arg(long, value_name = "ID=PATH")]
+    encryption_key: Vec<String>,
+
+    /// A zstd dictionary that kumod was configured to use when
+    /// compressing the spool. Records that were compressed without
+    /// a dictionary can be read without specifying this option.
+    /// May be specified multiple times, to read records that were
+    /// compressed both before and after a change of dictionary.
+    #[arg(long, value_name = "PATH")]
+    compression_dictionary: Vec<PathBuf>,
+
     #[command(subcommand)]
     cmd: SubCommand,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SpoolKind {
+    #[value(name = "local-disk")]
+    LocalDisk,
+    #[value(name = "rocksdb")]
+    RocksDB,
+}
+
+/// How records are to be compressed when they are written.
+/// Compressed records are always decompressed when they are read.
+#[derive(Default)]
+struct Compression {
+    level: Option<i32>,
+    dictionary: Option<Vec<u8>>,
+    /// Used only to decompress records
+    previous_dictionaries: Vec<Vec<u8>>,
+}
+
+impl Compression {
+    fn new(
+        level: Option<i32>,
+        dictionary: Option<&Path>,
+        previous_dictionaries: &[PathBuf],
+    ) -> anyhow::Result<Self> {
+        fn read_dictionary(path: &Path) -> anyhow::Result<Vec<u8>> {
+            std::fs::read(path).with_context(|| format!("reading compression dictionary {path:?}"))
+        }
+
+        let dictionary = match dictionary {
+            Some(path) => Some(read_dictionary(path)?),
+            None => None,
+        };
+        let previous_dictionaries = previous_dictionaries
+            .iter()
+            .map(|path| read_dictionary(path))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            level,
+            dictionary,
+            previous_dictionaries,
+        })
+    }
+}
+
+impl SpoolKind {
+    fn open(
+        self,
+        path: &Path,
+        flush: bool,
+        encryption_keys: &[String],
+        compression: &Compression,
+    ) -> anyhow::Result<Arc<dyn Spool>> {
+        let spool: Arc<dyn Spool + Send + Sync> = match self {
+            Self::LocalDisk => Arc::new(LocalDiskSpool::new(path, flush, Handle::current())?),
+            Self::RocksDB => Arc::new(RocksSpool::new(path, flush, None, Handle::current())?),
+        };
+        let spool = Self::encrypt(spool, encryption_keys)?;
+        Ok(Arc::new(CompressedSpool::new(
+            spool,
+            &path.display().to_string(),
+            compression.level,
+            compression.dictionary.as_deref(),
+            &compression.previous_dictionaries,
+            Handle::current(),
+        )?))
+    }
+
+    fn encrypt(
+        spool: Arc<dyn Spool + Send + Sync>,
+        encryption_keys: &[String],
+    ) -> anyhow::Result<Arc<dyn Spool + Send + Sync>> {
+        if encryption_keys.is_empty() {
+            return Ok(spool);
+        }
+
+        let mut keys = vec![];
+        for spec in encryption_keys {
+            let (id, key_path) = spec.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!("--encryption-key {spec} is not of the form ID=PATH")
+            })?;
+            let material = std::fs::read(key_path)
+                .with_context(|| format!("reading encryption key {id} from {key_path}"))?;
+            keys.push(SpoolEncryptionKey::new(id, &material)?);
+        }
+        Ok(Arc::new(EncryptedSpool::new(spool, keys, false)?))
+    }
+}
+
 #[derive(Debug, Parser)]
 enum SubCommand {
     MetaSize,
     DataSize,
+    Migrate(MigrateCommand),
+    List(inspect::ListCommand),
+    Dump(inspect::DumpCommand),
+    Delete(inspect::DeleteCommand),
+    Rewrite(inspect::RewriteCommand),
+    Quarantine(inspect::QuarantineCommand),
+    TrainDictionary(TrainDictionaryCommand),
+}
+
+/// Copy every message from this spool into another spool,
+/// which may be of a different kind.
+///
+/// The data spool is copied before the meta spool, so that an
+/// interrupted migration never leaves metadata in the destination
+/// without its corresponding data.
+///
+/// Entries that are already present in the destination with identical
+/// contents are skipped, so the migration can be resumed by running
+/// the same command again.
+///
+/// The source spool is not modified.
+#[derive(Debug, Parser)]
+struct MigrateCommand {
+    /// The kind of spool to create at the destination
+    #[arg(long, value_enum)]
+    dest_kind: SpoolKind,
+    /// The path for the destination meta spool
+    #[arg(long)]
+    dest_meta: PathBuf,
+    /// The path for the destination data spool
+    #[arg(long)]
+    dest_data: PathBuf,
+
+    /// Read back each entry after it has been written, and compare
+    /// it with the source
+    #[arg(long)]
+    verify: bool,
+
+    /// Encrypt the destination, using the key material found in
+    /// the file at PATH for the key named ID. May be specified multiple
+    /// times; the first key is used to encrypt the records.
+    #[arg(long, value_name = "ID=PATH")]
+    dest_encryption_key: Vec<String>,
+
+    /// Compress the records written to the destination using
+    /// zstd at the specified level
+    #[arg(long, value_name = "LEVEL")]
+    dest_compression_level: Option<i32>,
+
+    /// Use the zstd dictionary found in the file at PATH when
+    /// compressing the records written to the destination
+    #[arg(long, value_name = "PATH", requires = "dest_compression_level")]
+    dest_compression_dictionary: Option<PathBuf>,
+
+    /// How many entries to copy concurrently
+    #[arg(long, default_value = "32")]
+    concurrency: usize,
+
+    /// When the destination is a rocksdb spool, skip the full
+    /// compaction that is otherwise performed after the copy
+    #[arg(long)]
+    no_compact: bool,
+}
+
+#[derive(Default, Debug)]
+struct MigrateStats {
+    copied: usize,
+    skipped: usize,
+    corrupt: usize,
+    failed: usize,
+    /// Entries that were copied, but that could not be found
+    /// when the destination was enumerated afterwards
+    missing: usize,
+}
+
+enum CopyOutcome {
+    Copied,
+    Skipped,
+}
+
+async fn copy_entry(
+    dest: Arc<dyn Spool>,
+    id: SpoolId,
+    data: Vec<u8>,
+    verify: bool,
+) -> anyhow::Result<CopyOutcome> {
+    if let Ok(existing) = dest.load(id).await {
+        if existing == data {
+            return Ok(CopyOutcome::Skipped);
+        }
+    }
+
+    let data: Arc<Box<[u8]>> = Arc::new(data.into_boxed_slice());
+    dest.store(id, data.clone(), false, None).await?;
+
+    if verify {
+        let stored = dest.load(id).await?;
+        anyhow::ensure!(
+            stored.as_slice() == &data[..],
+            "verification failed: stored data does not match the source"
+        );
+    }
+
+    Ok(CopyOutcome::Copied)
+}
+
+async fn migrate_spool(
+    label: &str,
+    source: &dyn Spool,
+    dest: Arc<dyn Spool>,
+    cmd: &MigrateCommand,
+) -> anyhow::Result<MigrateStats> {
+    let start = std::time::Instant::now();
+    let (tx, rx) = flume::bounded(1024);
+    let enumeration = source.enumerate(tx, Utc::now())?;
+
+    let mut stats = MigrateStats::default();
+    let mut source_ids = HashSet::new();
+    let mut tasks = JoinSet::new();
+    let concurrency = cmd.concurrency.max(1);
+
+    fn record_outcome(
+        label: &str,
+        stats: &mut MigrateStats,
+        result: Result<(SpoolId, anyhow::Result<CopyOutcome>), tokio::task::JoinError>,
+    ) -> anyhow::Result<()> {
+        match result? {
+            (_, Ok(CopyOutcome::Copied)) => stats.copied += 1,
+            (_, Ok(CopyOutcome::Skipped)) => stats.skipped += 1,
+            (id, Err(err)) => {
+                eprintln!("ERROR: failed to copy {label} entry {id}: {err:#}");
+                stats.failed += 1;
+            }
+        }
+        let total = stats.copied + stats.skipped + stats.failed;
+        if total % 100_000 == 0 {
+            eprintln!("{label}: processed {total} entries so far");
+        }
+        Ok(())
+    }
+
+    eprintln!("migrating {label}...");
+    while let Ok(info) = rx.recv_async().await {
+        match info {
+            SpoolEntry::Item { id, data } => {
+                source_ids.insert(id);
+                while tasks.len() >= concurrency {
+                    if let Some(result) = tasks.join_next().await {
+                        record_outcome(label, &mut stats, result)?;
+                    }
+                }
+                let dest = dest.clone();
+                let verify = cmd.verify;
+                tasks.spawn(async move { (id, copy_entry(dest, id, data, verify).await) });
+            }
+            SpoolEntry::Corrupt { id, error } => {
+                eprintln!("ERROR: {label} entry {id} is corrupt and was not copied: {error}");
+                stats.corrupt += 1;
+            }
+        }
+    }
+    while let Some(result) = tasks.join_next().await {
+        record_outcome(label, &mut stats, result)?;
+    }
+    join_enumerate(enumeration)
+        .await
+        .with_context(|| format!("enumerating source {label} spool"))?;
+
+    // Confirm that everything that we read from the source can
+    // be found in the destination
+    eprintln!("{label}: checking destination...");
+    let (tx, rx) = flume::bounded(1024);
+    let enumeration = dest.enumerate(tx, Utc::now())?;
+    while let Ok(info) = rx.recv_async().await {
+        match info {
+            SpoolEntry::Item { id, .. } | SpoolEntry::Corrupt { id, .. } => {
+                source_ids.remove(&id);
+            }
+        }
+    }
+    join_enumerate(enumeration)
+        .await
+        .with_context(|| format!("enumerating destination {label} spool"))?;
+    for id in &source_ids {
+        eprintln!("ERROR: {label} entry {id} is missing from the destination");
+    }
+    stats.missing = source_ids.len();
+
+    println!(
+        "{label}: copied {}, already present {}, corrupt {}, failed {}, missing {} in {:?}",
+        stats.copied,
+        stats.skipped,
+        stats.corrupt,
+        stats.failed,
+        stats.missing,
+        start.elapsed()
+    );
+
+    Ok(stats)
+}
+
+async fn migrate(
+    meta_spool: &dyn Spool,
+    data_spool: &dyn Spool,
+    cmd: MigrateCommand,
+) -> anyhow::Result<()> {
+    // The destination is opened with flush enabled, so that each
+    // entry is durable before the source can be discarded
+    let compression = Compression::new(
+        cmd.dest_compression_level,
+        cmd.dest_compression_dictionary.as_deref(),
+        &[],
+    )?;
+    let dest_data =
+        cmd.dest_kind
+            .open(&cmd.dest_data, true, &cmd.dest_encryption_key, &compression)?;
+    let dest_meta = cmd.dest_kind.open(
+        &cmd.dest_meta,
+        true,
+        &cmd.dest_encryption_key,
+        &Compression::default(),
+    )?;
+
+    let data_stats = migrate_spool("data", data_spool, dest_data.clone(), &cmd).await?;
+    let meta_stats = migrate_spool("meta", meta_spool, dest_meta.clone(), &cmd).await?;
+
+    dest_data.shutdown().await?;
+    dest_meta.shutdown().await?;
+    drop(dest_data);
+    drop(dest_meta);
+
+    if cmd.dest_kind == SpoolKind::RocksDB && !cmd.no_compact {
+        for path in [&cmd.dest_data, &cmd.dest_meta] {
+            eprintln!("compacting {}...", path.display());
+            let start = std::time::Instant::now();
+            let spool = RocksSpool::new(path, false, None, Handle::current())?;
+            spool.compact().await?;
+            println!("compacted {} in {:?}", path.display(), start.elapsed());
+        }
+    }
+
+    let errors = [data_stats, meta_stats]
+        .iter()
+        .map(|stats| stats.corrupt + stats.failed + stats.missing)
+        .sum::<usize>();
+    anyhow::ensure!(
+        errors == 0,
+        "migration completed with {errors} entries that could not be copied"
+    );
+
+    Ok(())
+}
+
+/// Train a zstd dictionary from a sample of the message bodies
+/// in the data spool, for use with the spool compression option
+#[derive(Debug, Parser)]
+struct TrainDictionaryCommand {
+    /// Where to write the dictionary
+    #[arg(long)]
+    output: PathBuf,
+
+    /// The maximum size of the dictionary, in bytes
+    #[arg(long, default_value = "112640")]
+    max_size: usize,
+
+    /// The maximum number of message bodies to sample
+    #[arg(long, default_value = "10000")]
+    samples: usize,
+
+    /// Only the first this-many bytes of each message
+    /// body are used for training
+    #[arg(long, default_value = "131072")]
+    max_sample_size: usize,
+}
+
+impl TrainDictionaryCommand {
+    async fn run(&self, data_spool: &dyn Spool) -> anyhow::Result<()> {
+        let (tx, rx) = flume::bounded(1024);
+        data_spool.enumerate(tx, Utc::now())?;
+
+        let mut samples = vec![];
+        while let Ok(info) = rx.recv_async().await {
+            match info {
+                SpoolEntry::Item { id: _, mut data } => {
+                    data.truncate(self.max_sample_size);
+                    samples.push(data);
+                    if samples.len() >= self.samples {
+                        break;
+                    }
+                }
+                SpoolEntry::Corrupt { id, error } => {
+                    eprintln!("ERROR: entry {id} is corrupt: {error}");
+                }
+            }
+        }
+        anyhow::ensure!(
+            !samples.is_empty(),
+            "the data spool has no messages to sample"
+        );
+
+        eprintln!("training from {} samples...", samples.len());
+        let dictionary =
+            zstd::dict::from_samples(&samples, self.max_size).context("training dictionary")?;
+        std::fs::write(&self.output, &dictionary)
+            .with_context(|| format!("writing dictionary to {:?}", self.output))?;
+        println!(
+            "wrote {} dictionary to {}",
+            human_bytes(dictionary.len() as f64),
+            self.output.display()
+        );
+        Ok(())
+    }
 }
 
 async fn show_size_stats(label: &str, spool: &dyn Spool) -> anyhow::Result<()> {
     let start = std::time::Instant::now();
     let (tx, rx) = flume::bounded(1024);
-    spool.enumerate(tx, Utc::now())?;
+    let enumeration = spool.enumerate(tx, Utc::now())?;
     let mut stats = incr_stats::incr::Stats::new();
     let mut hist = hdrhistogram::Histogram::<u64>::new(3)?;
     eprintln!("enumerating...");
@@ -46,6 +473,7 @@ async fn show_size_stats(label: &str, spool: &dyn Spool) -> anyhow::Result<()> {
             }
         }
     }
+    join_enumerate(enumeration).await?;
 
     println!("{label} size stats computed in {:?}", start.elapsed());
     println!("count = {}", stats.count());
@@ -82,17 +510,104 @@ async fn show_size_stats(label: &str, spool: &dyn Spool) -> anyhow::Result<()> {
 async fn main() -> anyhow::Result<()> {
     let opts = Opt::parse();
 
-    let meta_spool = RocksSpool::new(&opts.meta, false, None, Handle::current())?;
-    let data_spool = RocksSpool::new(&opts.data, false, None, Handle::current())?;
+    let compression = Compression::new(None, None, &opts.compression_dictionary)?;
+    let meta_spool = opts
+        .kind
+        .open(&opts.meta, false, &opts.encryption_key, &compression)?;
+    let data_spool = opts
+        .kind
+        .open(&opts.data, false, &opts.encryption_key, &compression)?;
 
     match opts.cmd {
         SubCommand::MetaSize => {
-            show_size_stats("meta", &meta_spool).await?;
+            show_size_stats("meta", &*meta_spool).await?;
         }
         SubCommand::DataSize => {
-            show_size_stats("data", &data_spool).await?;
+            show_size_stats("data", &*data_spool).await?;
+        }
+        SubCommand::Migrate(cmd) => {
+            migrate(&*meta_spool, &*data_spool, cmd).await?;
+        }
+        SubCommand::List(cmd) => {
+            cmd.run(&*meta_spool).await?;
+        }
+        SubCommand::Dump(cmd) => {
+            cmd.run(&*meta_spool, &*data_spool).await?;
+        }
+        SubCommand::Delete(cmd) => {
+            cmd.run(&*meta_spool, &*data_spool).await?;
+        }
+        SubCommand::Rewrite(cmd) => {
+            cmd.run(&*meta_spool).await?;
+        }
+        SubCommand::Quarantine(cmd) => {
+            cmd.run(&*meta_spool, &*data_spool).await?;
+        }
+        SubCommand::TrainDictionary(cmd) => {
+            cmd.run(&*data_spool).await?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn migrate_command(dest: &Path) -> MigrateCommand {
+        MigrateCommand {
+            dest_kind: SpoolKind::RocksDB,
+            dest_meta: dest.join("meta"),
+            dest_data: dest.join("data"),
+            verify: true,
+            dest_encryption_key: vec![],
+            dest_compression_level: Some(3),
+            dest_compression_dictionary: None,
+            concurrency: 4,
+            no_compact: false,
+        }
+    }
+
+    fn record(text: String) -> Arc<Box<[u8]>> {
+        Arc::new(text.into_bytes().into_boxed_slice())
+    }
+
+    #[tokio::test]
+    async fn migrate_local_disk_to_rocks() -> anyhow::Result<()> {
+        let source = tempfile::tempdir()?;
+        let dest = tempfile::tempdir()?;
+
+        let no_compression = Compression::default();
+        let meta =
+            SpoolKind::LocalDisk.open(&source.path().join("meta"), false, &[], &no_compression)?;
+        let data =
+            SpoolKind::LocalDisk.open(&source.path().join("data"), false, &[], &no_compression)?;
+
+        let mut ids = vec![];
+        for i in 0..50 {
+            let id = SpoolId::new();
+            meta.store(id, record(format!("meta {i}")), false, None)
+                .await?;
+            data.store(id, record(format!("data {i}")), false, None)
+                .await?;
+            ids.push(id);
+        }
+
+        migrate(&*meta, &*data, migrate_command(dest.path())).await?;
+        // A second run finds everything already present, and
+        // must still succeed
+        migrate(&*meta, &*data, migrate_command(dest.path())).await?;
+
+        let dest_meta =
+            SpoolKind::RocksDB.open(&dest.path().join("meta"), false, &[], &no_compression)?;
+        let dest_data =
+            SpoolKind::RocksDB.open(&dest.path().join("data"), false, &[], &no_compression)?;
+        for (i, &id) in ids.iter().enumerate() {
+            assert_eq!(dest_meta.load(id).await?, format!("meta {i}").into_bytes());
+            assert_eq!(dest_data.load(id).await?, format!("data {i}").into_bytes());
+        }
+
+        Ok(())
+    }
+}
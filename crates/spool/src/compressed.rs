@@ -0,0 +1,458 @@
+//! Transparent zstd compression for the records held by a spool.
+//!
+//! Records are compressed on store, optionally using a trained
+//! dictionary, which can substantially improve the compression
+//! ratio for the many similar messages that make up a typical
+//! bulk mail campaign.
+//!
+//! Records that were written before compression was enabled are
+//! returned unchanged, as are records that did not get any smaller
+//! when compressed.
+//!
+//! Dictionaries can be rotated in the same way as encryption keys:
+//! new records are compressed using the current dictionary, while
+//! previous dictionaries are retained only to decompress records
+//! that were written using them.
+use crate::{enumerate_mapped, EnumerateHandle, Spool, SpoolEntry, SpoolId};
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flume::Sender;
+use kumo_prometheus::declare_metric;
+use kumo_prometheus::prometheus::IntCounter;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::Handle;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+/// Prefix that identifies a compressed record.
+/// Message content may legitimately start with these bytes
+/// (eg: BINARYMIME), so we never store such content as-is;
+/// it is stored with a header marking it as uncompressed,
+/// so that it cannot be mistaken for a compressed record.
+/// Records written before compression was enabled have no
+/// such protection.
+const MAGIC: &[u8; 4] = b"\0KSZ";
+/// The record is a zstd frame
+const VERSION: u8 = 1;
+/// The record holds uncompressed data that begins with MAGIC
+const VERSION_RAW: u8 = 2;
+/// MAGIC + VERSION + dictionary id
+const HEADER_LEN: usize = 4 + 1 + 4;
+
+declare_metric! {
+/// Total number of bytes, prior to compression, of the records
+/// written to a compressed spool.
+///
+/// Compare with `spool_compression_physical_bytes` to determine
+/// the effective compression ratio.
+static LOGICAL_BYTES: IntCounterVec(
+        "spool_compression_logical_bytes",
+        &["spool"]);
+}
+
+declare_metric! {
+/// Total number of bytes, after compression, of the records
+/// written to a compressed spool.
+///
+/// Compare with `spool_compression_logical_bytes` to determine
+/// the effective compression ratio.
+static PHYSICAL_BYTES: IntCounterVec(
+        "spool_compression_physical_bytes",
+        &["spool"]);
+}
+
+/// Recorded in each record so that we can select the dictionary
+/// with which to decompress it, or produce a helpful error if
+/// that dictionary is no longer configured
+fn dictionary_id(dict: &[u8]) -> u32 {
+    crc32fast::hash(dict).max(1)
+}
+
+struct Codec {
+    /// None means that new records are not compressed
+    level: Option<i32>,
+    /// The dictionary used to compress new records
+    encoder: Option<(u32, EncoderDictionary<'static>)>,
+    /// All of the dictionaries that can be used for decompression,
+    /// including the one used for compression
+    decoders: HashMap<u32, DecoderDictionary<'static>>,
+}
+
+impl Codec {
+    fn frame(version: u8, dict_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+        record.extend_from_slice(MAGIC);
+        record.push(version);
+        record.extend_from_slice(&dict_id.to_le_bytes());
+        record.extend_from_slice(payload);
+        record
+    }
+
+    /// Returns the record to be stored, or None if `data`
+    /// should be stored as-is
+    fn compress(&self, id: SpoolId, data: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let compressed = match self.level {
+            Some(level) => {
+                let (dict_id, compressed) = match &self.encoder {
+                    Some((dict_id, encoder)) => (
+                        *dict_id,
+                        zstd::bulk::Compressor::with_prepared_dictionary(encoder)
+                            .and_then(|mut c| c.compress(data)),
+                    ),
+                    None => (0, zstd::bulk::compress(data, level)),
+                };
+                let compressed = compressed.with_context(|| format!("failed to compress {id}"))?;
+                if HEADER_LEN + compressed.len() < data.len() {
+                    Some(Self::frame(VERSION, dict_id, &compressed))
+                } else {
+                    None
+                }
+            }
+            None => None,
+        };
+
+        Ok(match compressed {
+            Some(record) => Some(record),
+            None if data.starts_with(MAGIC) => Some(Self::frame(VERSION_RAW, 0, data)),
+            None => None,
+        })
+    }
+
+    fn decompress(&self, id: SpoolId, record: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if !record.starts_with(MAGIC) {
+            // Written uncompressed
+            return Ok(record);
+        }
+        anyhow::ensure!(
+            record.len() >= HEADER_LEN,
+            "compressed record for {id} is truncated"
+        );
+        let version = record[MAGIC.len()];
+        let dict_id = u32::from_le_bytes(record[5..HEADER_LEN].try_into()?);
+        let frame = &record[HEADER_LEN..];
+
+        match version {
+            VERSION => {}
+            VERSION_RAW => return Ok(frame.to_vec()),
+            _ => anyhow::bail!("compressed record for {id} has unsupported version {version}"),
+        }
+
+        let mut data = vec![];
+        let result = if dict_id == 0 {
+            zstd::stream::read::Decoder::new(frame).and_then(|mut d| d.read_to_end(&mut data))
+        } else {
+            let decoder = self.decoders.get(&dict_id).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{id} was compressed with dictionary {dict_id:08x}, \
+                     which is not configured"
+                )
+            })?;
+            zstd::stream::read::Decoder::with_prepared_dictionary(frame, decoder)
+                .and_then(|mut d| d.read_to_end(&mut data))
+        };
+        result.with_context(|| format!("failed to decompress {id}"))?;
+        Ok(data)
+    }
+}
+
+pub struct CompressedSpool {
+    inner: Arc<dyn Spool + Send + Sync>,
+    codec: Arc<Codec>,
+    logical_bytes: IntCounter,
+    physical_bytes: IntCounter,
+    runtime: Handle,
+}
+
+impl CompressedSpool {
+    /// Wrap `inner` so that new records are compressed using zstd at
+    /// the specified `level`, and optionally using `dictionary`.
+    /// `previous_dictionaries` are used only to decompress records
+    /// that were written prior to a change of dictionary.
+    ///
+    /// When `level` is None, new records are stored uncompressed,
+    /// but previously compressed records can still be loaded.
+    /// `name` is used to label the compression metrics.
+    /// Compression is performed in blocking threads of `runtime`.
+    pub fn new(
+        inner: Arc<dyn Spool + Send + Sync>,
+        name: &str,
+        level: Option<i32>,
+        dictionary: Option<&[u8]>,
+        previous_dictionaries: &[Vec<u8>],
+        runtime: Handle,
+    ) -> anyhow::Result<Self> {
+        let encoder = dictionary.map(|dict| {
+            (
+                dictionary_id(dict),
+                EncoderDictionary::copy(dict, level.unwrap_or(0)),
+            )
+        });
+        let decoders = dictionary
+            .into_iter()
+            .chain(previous_dictionaries.iter().map(|dict| dict.as_slice()))
+            .map(|dict| (dictionary_id(dict), DecoderDictionary::copy(dict)))
+            .collect();
+        Ok(Self {
+            inner,
+            codec: Arc::new(Codec {
+                level,
+                encoder,
+                decoders,
+            }),
+            logical_bytes: LOGICAL_BYTES.get_metric_with_label_values(&[name])?,
+            physical_bytes: PHYSICAL_BYTES.get_metric_with_label_values(&[name])?,
+            runtime,
+        })
+    }
+}
+
+#[async_trait]
+impl Spool for CompressedSpool {
+    async fn load(&self, id: SpoolId) -> anyhow::Result<Vec<u8>> {
+        let record = self.inner.load(id).await?;
+        if !record.starts_with(MAGIC) {
+            return Ok(record);
+        }
+        let codec = self.codec.clone();
+        tokio::task::Builder::new()
+            .name("CompressedSpool load")
+            .spawn_blocking_on(move || codec.decompress(id, record), &self.runtime)?
+            .await?
+    }
+
+    async fn remove(&self, id: SpoolId) -> anyhow::Result<()> {
+        self.inner.remove(id).await
+    }
+
+    async fn store(
+        &self,
+        id: SpoolId,
+        data: Arc<Box<[u8]>>,
+        force_sync: bool,
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<()> {
+        let logical_len = data.len();
+        let compressed = if self.codec.level.is_some() {
+            let codec = self.codec.clone();
+            let data = data.clone();
+            tokio::task::Builder::new()
+                .name("CompressedSpool store")
+                .spawn_blocking_on(move || codec.compress(id, &data), &self.runtime)?
+                .await??
+        } else {
+            self.codec.compress(id, &data)?
+        };
+        let data = match compressed {
+            Some(record) => Arc::new(record.into_boxed_slice()),
+            None => data,
+        };
+        if self.codec.level.is_some() {
+            self.logical_bytes.inc_by(logical_len as u64);
+            self.physical_bytes.inc_by(data.len() as u64);
+        }
+        self.inner.store(id, data, force_sync, deadline).await
+    }
+
+    fn enumerate(
+        &self,
+        sender: Sender<SpoolEntry>,
+        start_time: DateTime<Utc>,
+    ) -> anyhow::Result<EnumerateHandle> {
+        let codec = self.codec.clone();
+        enumerate_mapped(
+            &*self.inner,
+            sender,
+            start_time,
+            "CompressedSpool enumerate",
+            move |entry| match entry {
+                SpoolEntry::Item { id, data } => match codec.decompress(id, data) {
+                    Ok(data) => SpoolEntry::Item { id, data },
+                    Err(err) => SpoolEntry::Corrupt {
+                        id,
+                        error: format!("{err:#}"),
+                    },
+                },
+                corrupt @ SpoolEntry::Corrupt { .. } => corrupt,
+            },
+        )
+    }
+
+    async fn cleanup(&self) -> anyhow::Result<()> {
+        self.inner.cleanup().await
+    }
+
+    async fn shutdown(&self) -> anyhow::Result<()> {
+        self.inner.shutdown().await
+    }
+
+    async fn advise_low_memory(&self) -> anyhow::Result<isize> {
+        self.inner.advise_low_memory().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local_disk::LocalDiskSpool;
+    use crate::test::store_data;
+
+    fn body(i: usize) -> String {
+        let mut body = format!("Subject: Our big sale, customer {i}\r\n\r\n");
+        for line in 0..50 {
+            body.push_str(&format!(
+                "Dear customer {i}, item {line} is now {}% off! Use code {:x}\r\n",
+                (i * line) % 90,
+                (i + 1) * (line + 7919)
+            ));
+        }
+        body
+    }
+
+    #[tokio::test]
+    async fn compressed_spool() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let inner: Arc<dyn Spool + Send + Sync> = Arc::new(LocalDiskSpool::new(
+            location.path(),
+            false,
+            Handle::current(),
+        )?);
+
+        // A record written before compression was enabled
+        let plain_id = SpoolId::new();
+        inner
+            .store(plain_id, store_data(&body(0)), false, None)
+            .await?;
+
+        let spool =
+            CompressedSpool::new(inner.clone(), "test", Some(3), None, &[], Handle::current())?;
+        let id1 = SpoolId::new();
+        spool.store(id1, store_data(&body(1)), false, None).await?;
+        let raw = inner.load(id1).await?;
+        assert!(raw.starts_with(MAGIC));
+        assert!(raw.len() < body(1).len() / 5);
+
+        // Something that doesn't compress is stored as-is
+        let tiny_id = SpoolId::new();
+        spool.store(tiny_id, store_data("hi"), false, None).await?;
+        assert_eq!(inner.load(tiny_id).await?, b"hi");
+
+        assert_eq!(spool.load(id1).await?, body(1).as_bytes());
+        assert_eq!(spool.load(plain_id).await?, body(0).as_bytes());
+        assert_eq!(spool.load(tiny_id).await?, b"hi");
+
+        // Switch to a dictionary
+        let samples: Vec<String> = (0..100).map(body).collect();
+        let dictionary = zstd::dict::from_samples(&samples, 4096)?;
+        let spool = CompressedSpool::new(
+            inner.clone(),
+            "test",
+            Some(3),
+            Some(&dictionary),
+            &[],
+            Handle::current(),
+        )?;
+        let id2 = SpoolId::new();
+        spool.store(id2, store_data(&body(2)), false, None).await?;
+        assert_eq!(spool.load(id1).await?, body(1).as_bytes());
+        assert_eq!(spool.load(id2).await?, body(2).as_bytes());
+
+        // Rotate to another dictionary, retaining the previous one
+        // for decompression
+        let samples: Vec<String> = (100..200).map(body).collect();
+        let new_dictionary = zstd::dict::from_samples(&samples, 4096)?;
+        let spool = CompressedSpool::new(
+            inner.clone(),
+            "test",
+            Some(3),
+            Some(&new_dictionary),
+            &[dictionary.clone()],
+            Handle::current(),
+        )?;
+        let rotated_id = SpoolId::new();
+        spool
+            .store(rotated_id, store_data(&body(4)), false, None)
+            .await?;
+        assert_eq!(spool.load(id2).await?, body(2).as_bytes());
+        assert_eq!(spool.load(rotated_id).await?, body(4).as_bytes());
+        let spool = CompressedSpool::new(
+            inner.clone(),
+            "test",
+            None,
+            None,
+            &[new_dictionary],
+            Handle::current(),
+        )?;
+        assert_eq!(spool.load(rotated_id).await?, body(4).as_bytes());
+        assert!(
+            format!("{:#}", spool.load(id2).await.unwrap_err()).contains("which is not configured")
+        );
+        inner.remove(rotated_id).await?;
+
+        // Compression disabled; existing records remain readable
+        // only if they don't depend upon an absent dictionary
+        let spool =
+            CompressedSpool::new(inner.clone(), "test", None, None, &[], Handle::current())?;
+        let id3 = SpoolId::new();
+        spool.store(id3, store_data(&body(3)), false, None).await?;
+        assert_eq!(inner.load(id3).await?, body(3).as_bytes());
+        assert_eq!(spool.load(id1).await?, body(1).as_bytes());
+        assert!(
+            format!("{:#}", spool.load(id2).await.unwrap_err()).contains("which is not configured")
+        );
+
+        // Enumeration decompresses
+        let (tx, rx) = flume::bounded(32);
+        spool.enumerate(tx, Utc::now())?;
+        let mut items = vec![];
+        let mut corrupt = vec![];
+        while let Ok(entry) = rx.recv_async().await {
+            match entry {
+                SpoolEntry::Item { id, data } => items.push((id, data)),
+                SpoolEntry::Corrupt { id, .. } => corrupt.push(id),
+            }
+        }
+        assert_eq!(items.len(), 4);
+        for (id, data) in items {
+            assert_eq!(data, spool.load(id).await?);
+        }
+        assert_eq!(corrupt, vec![id2]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn content_resembling_magic() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let inner: Arc<dyn Spool + Send + Sync> = Arc::new(LocalDiskSpool::new(
+            location.path(),
+            false,
+            Handle::current(),
+        )?);
+
+        // Binary content that happens to start with MAGIC, and
+        // that is too short to benefit from compression
+        let mut content = MAGIC.to_vec();
+        content.extend_from_slice(b"\x01\0\0\0\0binary");
+
+        for level in [Some(3), None] {
+            let spool =
+                CompressedSpool::new(inner.clone(), "test", level, None, &[], Handle::current())?;
+            let id = SpoolId::new();
+            spool
+                .store(
+                    id,
+                    Arc::new(content.clone().into_boxed_slice()),
+                    false,
+                    None,
+                )
+                .await?;
+            assert_ne!(inner.load(id).await?, content);
+            assert_eq!(spool.load(id).await?, content);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+//! Transparent encryption at rest for the records held by a spool.
+//!
+//! Each record is encrypted using AES-256-GCM with a random nonce,
+//! and the SpoolId is used as the additional authenticated data so
+//! that a record cannot be substituted for the record of another id.
+//! The record carries the id of the key that was used to encrypt it,
+//! which allows keys to be rotated while retaining the ability to
+//! read records that were written using an older key.
+//!
+//! Records that were written before encryption was enabled are
+//! returned unchanged, so that encryption can be enabled on an
+//! existing spool, unless the spool was configured to require
+//! encryption, in which case they are reported as corrupt.
+use crate::{enumerate_mapped, EnumerateHandle, Spool, SpoolEntry, SpoolId};
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flume::Sender;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Prefix that identifies an encrypted record.
+/// This is not unambiguous: json metadata never starts with NUL,
+/// but message content can (eg: BINARYMIME), so a plaintext record
+/// that happens to start with these bytes will be treated as an
+/// encrypted record and fail to decrypt. Spools that have been
+/// fully migrated to encryption should set `require_encrypted` so
+/// that the plaintext fallback is never used.
+const MAGIC: &[u8; 4] = b"\0KSE";
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+pub struct SpoolEncryptionKey {
+    id: String,
+    key: [u8; 32],
+}
+
+impl SpoolEncryptionKey {
+    /// Create a key from some high-entropy key material.
+    /// The material is hashed with SHA-256 to produce the
+    /// AES-256 key.
+    pub fn new(id: &str, material: &[u8]) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            !id.is_empty() && id.len() <= u8::MAX as usize,
+            "spool encryption key id must be between 1 and 255 bytes long"
+        );
+        anyhow::ensure!(
+            material.len() >= 32,
+            "spool encryption key {id} must have at least 32 bytes of key material"
+        );
+        Ok(Self {
+            id: id.to_string(),
+            key: openssl::sha::sha256(material),
+        })
+    }
+}
+
+struct KeyRing {
+    /// The first key is used to encrypt; all of them
+    /// are candidates for decryption
+    keys: Vec<SpoolEncryptionKey>,
+    /// When true, records without the MAGIC prefix are
+    /// rejected rather than returned as plaintext
+    require_encrypted: bool,
+}
+
+impl KeyRing {
+    fn encrypt(&self, id: SpoolId, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let key = &self.keys[0];
+        let mut nonce = [0u8; NONCE_LEN];
+        openssl::rand::rand_bytes(&mut nonce)?;
+        let mut tag = [0u8; TAG_LEN];
+
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key.key,
+            Some(&nonce),
+            id.as_bytes(),
+            data,
+            &mut tag,
+        )
+        .with_context(|| format!("failed to encrypt {id}"))?;
+
+        let mut record = Vec::with_capacity(
+            MAGIC.len() + 2 + key.id.len() + NONCE_LEN + TAG_LEN + ciphertext.len(),
+        );
+        record.extend_from_slice(MAGIC);
+        record.push(VERSION);
+        record.push(key.id.len() as u8);
+        record.extend_from_slice(key.id.as_bytes());
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(&tag);
+        record.extend_from_slice(&ciphertext);
+        Ok(record)
+    }
+
+    fn decrypt(&self, id: SpoolId, record: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if !record.starts_with(MAGIC) {
+            anyhow::ensure!(
+                !self.require_encrypted,
+                "{id} is not encrypted, but encryption is required"
+            );
+            // Written before encryption was enabled
+            return Ok(record);
+        }
+        let remain = &record[MAGIC.len()..];
+
+        let (&version, remain) = remain
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("encrypted record for {id} is truncated"))?;
+        anyhow::ensure!(
+            version == VERSION,
+            "encrypted record for {id} has unsupported version {version}"
+        );
+        let (&key_id_len, remain) = remain
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("encrypted record for {id} is truncated"))?;
+        let key_id_len = key_id_len as usize;
+        anyhow::ensure!(
+            remain.len() >= key_id_len + NONCE_LEN + TAG_LEN,
+            "encrypted record for {id} is truncated"
+        );
+        let (key_id, remain) = remain.split_at(key_id_len);
+        let (nonce, remain) = remain.split_at(NONCE_LEN);
+        let (tag, ciphertext) = remain.split_at(TAG_LEN);
+
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.id.as_bytes() == key_id)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{id} was encrypted with key {}, which is not configured",
+                    String::from_utf8_lossy(key_id)
+                )
+            })?;
+
+        decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key.key,
+            Some(nonce),
+            id.as_bytes(),
+            ciphertext,
+            tag,
+        )
+        .with_context(|| format!("failed to decrypt {id} with key {}", key.id))
+    }
+}
+
+pub struct EncryptedSpool {
+    inner: Arc<dyn Spool + Send + Sync>,
+    keys: Arc<KeyRing>,
+}
+
+impl EncryptedSpool {
+    /// Wrap `inner` so that records are encrypted using the first
+    /// of the provided keys. The remaining keys are used only to
+    /// decrypt records that were written prior to a key rotation.
+    /// If `require_encrypted` is true, records that were not
+    /// encrypted are treated as corrupt.
+    pub fn new(
+        inner: Arc<dyn Spool + Send + Sync>,
+        keys: Vec<SpoolEncryptionKey>,
+        require_encrypted: bool,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            !keys.is_empty(),
+            "at least one spool encryption key is required"
+        );
+        Ok(Self {
+            inner,
+            keys: Arc::new(KeyRing {
+                keys,
+                require_encrypted,
+            }),
+        })
+    }
+}
+
+#[async_trait]
+impl Spool for EncryptedSpool {
+    async fn load(&self, id: SpoolId) -> anyhow::Result<Vec<u8>> {
+        let record = self.inner.load(id).await?;
+        self.keys.decrypt(id, record)
+    }
+
+    async fn remove(&self, id: SpoolId) -> anyhow::Result<()> {
+        self.inner.remove(id).await
+    }
+
+    async fn store(
+        &self,
+        id: SpoolId,
+        data: Arc<Box<[u8]>>,
+        force_sync: bool,
+        deadline: Option<Instant>,
+    ) -> anyhow::Result<()> {
+        let record = self.keys.encrypt(id, &data)?;
+        self.inner
+            .store(
+                id,
+                Arc::new(record.into_boxed_slice()),
+                force_sync,
+                deadline,
+            )
+            .await
+    }
+
+    fn enumerate(
+        &self,
+        sender: Sender<SpoolEntry>,
+        start_time: DateTime<Utc>,
+    ) -> anyhow::Result<EnumerateHandle> {
+        let keys = self.keys.clone();
+        enumerate_mapped(
+            &*self.inner,
+            sender,
+            start_time,
+            "EncryptedSpool enumerate",
+            move |entry| match entry {
+                SpoolEntry::Item { id, data } => match keys.decrypt(id, data) {
+                    Ok(data) => SpoolEntry::Item { id, data },
+                    Err(err) => SpoolEntry::Corrupt {
+                        id,
+                        error: format!("{err:#}"),
+                    },
+                },
+                corrupt @ SpoolEntry::Corrupt { .. } => corrupt,
+            },
+        )
+    }
+
+    async fn cleanup(&self) -> anyhow::Result<()> {
+        self.inner.cleanup().await
+    }
+
+    async fn shutdown(&self) -> anyhow::Result<()> {
+        self.inner.shutdown().await
+    }
+
+    async fn advise_low_memory(&self) -> anyhow::Result<isize> {
+        self.inner.advise_low_memory().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local_disk::LocalDiskSpool;
+    use crate::test::store_data;
+    use tokio::runtime::Handle;
+
+    fn make_key(id: &str) -> anyhow::Result<SpoolEncryptionKey> {
+        SpoolEncryptionKey::new(
+            id,
+            format!("{id} key material that is long enough").as_bytes(),
+        )
+    }
+
+    #[tokio::test]
+    async fn encrypted_spool() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let inner: Arc<dyn Spool + Send + Sync> = Arc::new(LocalDiskSpool::new(
+            location.path(),
+            false,
+            Handle::current(),
+        )?);
+
+        // A record written before encryption was enabled
+        let plain_id = SpoolId::new();
+        inner
+            .store(plain_id, store_data("plaintext"), false, None)
+            .await?;
+
+        let spool = EncryptedSpool::new(inner.clone(), vec![make_key("k1")?], false)?;
+        let id1 = SpoolId::new();
+        spool.store(id1, store_data("first"), false, None).await?;
+
+        // The stored record is not the plaintext
+        let raw = inner.load(id1).await?;
+        assert!(raw.starts_with(MAGIC));
+        assert!(!raw.windows(5).any(|w| w == b"first"));
+
+        assert_eq!(spool.load(id1).await?, b"first");
+        assert_eq!(spool.load(plain_id).await?, b"plaintext");
+
+        // Rotate to a new key; the old record remains readable
+        let spool =
+            EncryptedSpool::new(inner.clone(), vec![make_key("k2")?, make_key("k1")?], false)?;
+        let id2 = SpoolId::new();
+        spool.store(id2, store_data("second"), false, None).await?;
+        assert_eq!(spool.load(id1).await?, b"first");
+        assert_eq!(spool.load(id2).await?, b"second");
+
+        // Once the old key is removed, its records cannot be read
+        let spool = EncryptedSpool::new(inner.clone(), vec![make_key("k2")?], false)?;
+        assert_eq!(
+            format!("{:#}", spool.load(id1).await.unwrap_err()),
+            format!("{id1} was encrypted with key k1, which is not configured")
+        );
+
+        // A record cannot be presented as though it belonged to another id
+        let swapped = SpoolId::new();
+        inner
+            .store(
+                swapped,
+                Arc::new(inner.load(id2).await?.into_boxed_slice()),
+                false,
+                None,
+            )
+            .await?;
+        assert!(spool.load(swapped).await.is_err());
+
+        // Enumeration decrypts, and reports undecryptable records as corrupt
+        let (tx, rx) = flume::bounded(32);
+        spool.enumerate(tx, Utc::now())?;
+        let mut items = vec![];
+        let mut corrupt = vec![];
+        while let Ok(entry) = rx.recv_async().await {
+            match entry {
+                SpoolEntry::Item { id, data } => items.push((id, String::from_utf8(data)?)),
+                SpoolEntry::Corrupt { id, .. } => corrupt.push(id),
+            }
+        }
+        items.sort_by_key(|(_, text)| text.clone());
+        assert_eq!(
+            items,
+            vec![
+                (plain_id, "plaintext".to_string()),
+                (id2, "second".to_string())
+            ]
+        );
+        corrupt.sort_by_key(|id| id.to_string());
+        let mut expect_corrupt = vec![id1, swapped];
+        expect_corrupt.sort_by_key(|id| id.to_string());
+        assert_eq!(corrupt, expect_corrupt);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn require_encrypted() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let inner: Arc<dyn Spool + Send + Sync> = Arc::new(LocalDiskSpool::new(
+            location.path(),
+            false,
+            Handle::current(),
+        )?);
+
+        let plain_id = SpoolId::new();
+        inner
+            .store(plain_id, store_data("plaintext"), false, None)
+            .await?;
+
+        let spool = EncryptedSpool::new(inner.clone(), vec![make_key("k1")?], true)?;
+        let id = SpoolId::new();
+        spool
+            .store(id, store_data("encrypted"), false, None)
+            .await?;
+        assert_eq!(spool.load(id).await?, b"encrypted");
+        assert_eq!(
+            format!("{:#}", spool.load(plain_id).await.unwrap_err()),
+            format!("{plain_id} is not encrypted, but encryption is required")
+        );
+
+        let (tx, rx) = flume::bounded(32);
+        let enumeration = spool.enumerate(tx, Utc::now())?;
+        let mut items = vec![];
+        let mut corrupt = vec![];
+        while let Ok(entry) = rx.recv_async().await {
+            match entry {
+                SpoolEntry::Item { id, .. } => items.push(id),
+                SpoolEntry::Corrupt { id, .. } => corrupt.push(id),
+            }
+        }
+        crate::join_enumerate(enumeration).await?;
+        assert_eq!(items, vec![id]);
+        assert_eq!(corrupt, vec![plain_id]);
+
+        Ok(())
+    }
+}
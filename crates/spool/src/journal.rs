@@ -0,0 +1,707 @@
+//! A group-commit write-ahead journal for LocalDiskSpool.
+//!
+//! Without the journal, LocalDiskSpool must choose between calling
+//! fsync for every file that it writes (`flush: true`), or risking
+//! the loss of acknowledged messages on power failure (`flush: false`).
+//!
+//! When the journal is enabled, each store is first appended to a
+//! single journal file. Concurrent stores are collected into a batch
+//! for up to `max_latency`, the batch is written with a single fsync,
+//! and only then are the individual spool files written, without
+//! syncing them. Once the journal grows beyond `checkpoint_size`,
+//! the filesystem is synced and the journal is truncated.
+//!
+//! If a store cannot be applied to its spool file, an abort record
+//! is appended to the journal before the failure is reported, so
+//! that the store is not resurrected by a later replay.
+//!
+//! On startup, any records remaining in the journal are replayed
+//! into the spool, recovering files whose contents had not reached
+//! the disk at the time of a crash.
+use crate::local_disk::LocalDiskSpool;
+use crate::SpoolId;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+const OP_STORE: u8 = 1;
+const OP_REMOVE: u8 = 2;
+/// Cancels the most recent store of the same id
+const OP_ABORT: u8 = 3;
+/// op + id + length
+const HEADER_LEN: usize = 1 + 16 + 4;
+const CRC_LEN: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JournalParams {
+    /// How long the first store in a batch may wait for other
+    /// stores to join it before the batch is committed
+    #[serde(
+        with = "duration_serde",
+        default = "JournalParams::default_max_latency"
+    )]
+    pub max_latency: Duration,
+
+    /// A batch is committed without waiting for `max_latency`
+    /// once it holds at least this many bytes
+    #[serde(default = "JournalParams::default_max_batch_size")]
+    pub max_batch_size: usize,
+
+    /// Once the journal reaches this size, the filesystem is
+    /// synced and the journal is truncated
+    #[serde(default = "JournalParams::default_checkpoint_size")]
+    pub checkpoint_size: u64,
+
+    /// How many threads may be used to write the spool files
+    /// for a committed batch
+    #[serde(default = "JournalParams::default_apply_concurrency")]
+    pub apply_concurrency: usize,
+}
+
+impl Default for JournalParams {
+    fn default() -> Self {
+        Self {
+            max_latency: Self::default_max_latency(),
+            max_batch_size: Self::default_max_batch_size(),
+            checkpoint_size: Self::default_checkpoint_size(),
+            apply_concurrency: Self::default_apply_concurrency(),
+        }
+    }
+}
+
+impl JournalParams {
+    fn default_max_latency() -> Duration {
+        Duration::from_millis(2)
+    }
+
+    fn default_max_batch_size() -> usize {
+        4 * 1024 * 1024
+    }
+
+    fn default_checkpoint_size() -> u64 {
+        256 * 1024 * 1024
+    }
+
+    fn default_apply_concurrency() -> usize {
+        4
+    }
+}
+
+enum Op {
+    Store { id: SpoolId, data: Arc<Box<[u8]>> },
+    Remove { id: SpoolId },
+    Abort { id: SpoolId },
+}
+
+impl Op {
+    fn id(&self) -> SpoolId {
+        match self {
+            Self::Store { id, .. } | Self::Remove { id } | Self::Abort { id } => *id,
+        }
+    }
+
+    fn encoded_len(&self) -> usize {
+        match self {
+            Self::Store { data, .. } => HEADER_LEN + data.len() + CRC_LEN,
+            Self::Remove { .. } | Self::Abort { .. } => HEADER_LEN + CRC_LEN,
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        let (op, id, data): (u8, SpoolId, &[u8]) = match self {
+            Self::Store { id, data } => (OP_STORE, *id, data),
+            Self::Remove { id } => (OP_REMOVE, *id, &[]),
+            Self::Abort { id } => (OP_ABORT, *id, &[]),
+        };
+        buf.push(op);
+        buf.extend_from_slice(id.as_bytes());
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(data);
+        let crc = crc32fast::hash(&buf[start..]);
+        buf.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    /// Decode the next record from `buf`, returning it and the number
+    /// of bytes that it occupied. Returns None if the record is
+    /// incomplete or fails its checksum, which is what we expect
+    /// to find at the end of the journal after a crash.
+    fn decode(buf: &[u8]) -> Option<(Self, usize)> {
+        if buf.len() < HEADER_LEN + CRC_LEN {
+            return None;
+        }
+        let op = buf[0];
+        let id = SpoolId::from_slice(&buf[1..17])?;
+        let len = u32::from_le_bytes(buf[17..21].try_into().ok()?) as usize;
+        let end = HEADER_LEN.checked_add(len)?;
+        if buf.len() < end + CRC_LEN {
+            return None;
+        }
+        let crc = u32::from_le_bytes(buf[end..end + CRC_LEN].try_into().ok()?);
+        if crc32fast::hash(&buf[..end]) != crc {
+            return None;
+        }
+        let op = match op {
+            OP_STORE => Self::Store {
+                id,
+                data: Arc::new(buf[HEADER_LEN..end].to_vec().into_boxed_slice()),
+            },
+            OP_REMOVE => Self::Remove { id },
+            OP_ABORT => Self::Abort { id },
+            _ => return None,
+        };
+        Some((op, end + CRC_LEN))
+    }
+
+    /// Read the next record from `reader`, which has `remain` bytes
+    /// left in it. As with `decode`, returns None if the record is
+    /// incomplete or fails its checksum.
+    fn read(reader: &mut impl Read, remain: u64) -> anyhow::Result<Option<(Self, usize)>> {
+        if remain < (HEADER_LEN + CRC_LEN) as u64 {
+            return Ok(None);
+        }
+        let mut record = vec![0u8; HEADER_LEN];
+        reader.read_exact(&mut record)?;
+        let len = u32::from_le_bytes(record[17..21].try_into()?) as usize;
+        let total = HEADER_LEN + len + CRC_LEN;
+        if total as u64 > remain {
+            return Ok(None);
+        }
+        record.resize(total, 0);
+        reader.read_exact(&mut record[HEADER_LEN..])?;
+        Ok(Self::decode(&record))
+    }
+
+    /// Apply the operation to the spool files
+    fn apply(&self, root: &Path, flush: bool) -> anyhow::Result<()> {
+        match self {
+            Self::Store { id, data } => LocalDiskSpool::write_entry(root, *id, data, flush),
+            Self::Remove { id } => {
+                let path = LocalDiskSpool::entry_path(root, *id);
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {id} from {path:?}"))
+            }
+            Self::Abort { .. } => Ok(()),
+        }
+    }
+}
+
+struct Request {
+    op: Op,
+    reply: oneshot::Sender<anyhow::Result<()>>,
+}
+
+pub(crate) struct Journal {
+    tx: Mutex<Option<flume::Sender<Request>>>,
+    committer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Journal {
+    /// Open the journal in the spool directory `root`, replaying
+    /// any records that it holds, and start the committer thread
+    pub fn open(root: &Path, flush: bool, params: JournalParams) -> anyhow::Result<Self> {
+        let journal_path = root.join("journal");
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&journal_path)
+            .with_context(|| format!("opening spool journal {journal_path:?}"))?;
+
+        let replayed = replay(root, &mut file)
+            .with_context(|| format!("replaying spool journal {journal_path:?}"))?;
+        if replayed > 0 {
+            tracing::info!("replayed {replayed} records from spool journal {journal_path:?}");
+        }
+        checkpoint(root, &file)?;
+
+        let (tx, rx) = flume::bounded(4096);
+        let committer = Committer {
+            root: root.to_path_buf(),
+            file,
+            len: 0,
+            flush,
+            params,
+        };
+        let committer = std::thread::Builder::new()
+            .name("LocalDiskSpool journal".to_string())
+            .spawn(move || committer.run(rx))?;
+
+        Ok(Self {
+            tx: Mutex::new(Some(tx)),
+            committer: Mutex::new(Some(committer)),
+        })
+    }
+
+    async fn submit(&self, op: Op) -> anyhow::Result<()> {
+        let tx = self
+            .tx
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("spool journal has been shut down"))?;
+        let (reply, rx) = oneshot::channel();
+        tx.send_async(Request { op, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("spool journal committer has terminated"))?;
+        rx.await
+            .map_err(|_| anyhow::anyhow!("spool journal committer has terminated"))?
+    }
+
+    pub async fn store(&self, id: SpoolId, data: Arc<Box<[u8]>>) -> anyhow::Result<()> {
+        self.submit(Op::Store { id, data }).await
+    }
+
+    pub async fn remove(&self, id: SpoolId) -> anyhow::Result<()> {
+        self.submit(Op::Remove { id }).await
+    }
+
+    /// Stop accepting new records, wait for the committer to finish
+    /// the records that are in flight, and checkpoint the journal.
+    /// This blocks and should be called from a blocking thread.
+    pub fn shutdown(&self) {
+        self.tx.lock().unwrap().take();
+        if let Some(committer) = self.committer.lock().unwrap().take() {
+            committer.join().ok();
+        }
+    }
+}
+
+/// Read the complete records from the journal, passing each of
+/// them and its offset to `func`. Returns the number of bytes
+/// occupied by the complete records.
+fn scan(
+    file: &File,
+    journal_len: u64,
+    mut func: impl FnMut(u64, Op) -> anyhow::Result<()>,
+) -> anyhow::Result<u64> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0))?;
+    let mut offset = 0;
+    while let Some((op, len)) = Op::read(&mut reader, journal_len - offset)? {
+        func(offset, op)?;
+        offset += len as u64;
+    }
+    Ok(offset)
+}
+
+/// Apply the complete records from the journal to the spool files.
+/// Returns the number of records that were applied.
+fn replay(root: &Path, file: &mut File) -> anyhow::Result<usize> {
+    let journal_len = file.metadata()?.len();
+
+    // Find the stores that were aborted because they could not be
+    // applied; their failure was reported to the caller, so they
+    // must not be resurrected here
+    let mut last_store = HashMap::new();
+    let mut aborted = HashSet::new();
+    scan(file, journal_len, |offset, op| {
+        match op {
+            Op::Store { id, .. } => {
+                last_store.insert(id, offset);
+            }
+            Op::Abort { id } => {
+                if let Some(offset) = last_store.remove(&id) {
+                    aborted.insert(offset);
+                }
+            }
+            Op::Remove { .. } => {}
+        }
+        Ok(())
+    })?;
+
+    let mut count = 0;
+    let valid_len = scan(file, journal_len, |offset, op| {
+        if aborted.contains(&offset) {
+            return Ok(());
+        }
+        match op.apply(root, false) {
+            Ok(()) => {}
+            Err(err) if matches!(op, Op::Remove { .. }) => {
+                // The file was removed before the crash
+                tracing::trace!("{err:#}");
+            }
+            Err(err) => return Err(err),
+        }
+        count += 1;
+        Ok(())
+    })?;
+    if valid_len < journal_len {
+        tracing::warn!(
+            "discarding {} bytes of incomplete records from the end of the spool journal",
+            journal_len - valid_len
+        );
+    }
+    Ok(count)
+}
+
+/// Make the spool files durable, then empty the journal
+fn checkpoint(root: &Path, file: &File) -> anyhow::Result<()> {
+    sync_filesystem(root)?;
+    file.set_len(0).context("truncating spool journal")?;
+    file.sync_all().context("syncing spool journal")?;
+    Ok(())
+}
+
+fn sync_filesystem(root: &Path) -> anyhow::Result<()> {
+    let dir = File::open(root).with_context(|| format!("opening {root:?}"))?;
+    #[cfg(target_os = "linux")]
+    let res = unsafe { libc::syncfs(std::os::fd::AsRawFd::as_raw_fd(&dir)) };
+    #[cfg(not(target_os = "linux"))]
+    let res = {
+        drop(dir);
+        unsafe { libc::sync() };
+        0
+    };
+    if res != 0 {
+        let err = std::io::Error::last_os_error();
+        anyhow::bail!("failed to sync filesystem containing {root:?}: {err:#}");
+    }
+    Ok(())
+}
+
+struct Committer {
+    root: PathBuf,
+    file: File,
+    len: u64,
+    flush: bool,
+    params: JournalParams,
+}
+
+impl Committer {
+    fn run(mut self, rx: flume::Receiver<Request>) {
+        let mut buf = vec![];
+        while let Ok(first) = rx.recv() {
+            let mut size = first.op.encoded_len();
+            let mut batch = vec![first];
+            let deadline = Instant::now() + self.params.max_latency;
+            while size < self.params.max_batch_size {
+                match rx.recv_deadline(deadline) {
+                    Ok(request) => {
+                        size += request.op.encoded_len();
+                        batch.push(request);
+                    }
+                    Err(_) => break,
+                }
+            }
+            self.commit(batch, &mut buf);
+        }
+
+        if let Err(err) = checkpoint(&self.root, &self.file) {
+            tracing::error!("failed to checkpoint spool journal during shutdown: {err:#}");
+        }
+    }
+
+    fn commit(&mut self, batch: Vec<Request>, buf: &mut Vec<u8>) {
+        buf.clear();
+        for request in &batch {
+            request.op.encode(buf);
+        }
+        // Removals don't need to be durable any sooner than they
+        // would have been without the journal, so we only pay for
+        // a sync when the batch contains stores
+        let need_sync = batch
+            .iter()
+            .any(|request| matches!(request.op, Op::Store { .. }));
+
+        if let Err(err) = self.append(buf, need_sync) {
+            let error = format!("{err:#}");
+            tracing::error!("{error}");
+            for request in batch {
+                request.reply.send(Err(anyhow::anyhow!("{error}"))).ok();
+            }
+            return;
+        }
+
+        let results = self.apply_batch(&batch);
+
+        // Make sure that a replay won't resurrect a store whose
+        // failure we are about to report
+        buf.clear();
+        for (request, result) in batch.iter().zip(&results) {
+            if let (Op::Store { id, .. }, Err(_)) = (&request.op, result) {
+                Op::Abort { id: *id }.encode(buf);
+            }
+        }
+        if !buf.is_empty() {
+            if let Err(err) = self.append(buf, true) {
+                tracing::error!(
+                    "failed to record aborted stores in the spool journal, \
+                     they may be restored if the journal is replayed: {err:#}"
+                );
+            }
+        }
+
+        for (request, result) in batch.into_iter().zip(results) {
+            request.reply.send(result).ok();
+        }
+
+        if self.len >= self.params.checkpoint_size {
+            match checkpoint(&self.root, &self.file) {
+                Ok(()) => {
+                    self.len = 0;
+                }
+                Err(err) => {
+                    // We'll try again after the next batch
+                    tracing::error!("failed to checkpoint spool journal: {err:#}");
+                }
+            }
+        }
+    }
+
+    /// Apply the operations in the batch to the spool files, returning
+    /// their results in the same order. Operations on different ids
+    /// are applied in parallel, while those on the same id are applied
+    /// in the order in which they were submitted.
+    fn apply_batch(&self, batch: &[Request]) -> Vec<anyhow::Result<()>> {
+        let mut groups: HashMap<SpoolId, Vec<usize>> = HashMap::new();
+        for (idx, request) in batch.iter().enumerate() {
+            groups.entry(request.op.id()).or_default().push(idx);
+        }
+
+        let workers = self.params.apply_concurrency.clamp(1, groups.len().max(1));
+        let mut buckets = vec![vec![]; workers];
+        for (n, group) in groups.into_values().enumerate() {
+            buckets[n % workers].extend(group);
+        }
+
+        let apply = |bucket: Vec<usize>| -> Vec<(usize, anyhow::Result<()>)> {
+            bucket
+                .into_iter()
+                .map(|idx| (idx, batch[idx].op.apply(&self.root, self.flush)))
+                .collect()
+        };
+        let applied = if workers == 1 {
+            apply(buckets.pop().unwrap_or_default())
+        } else {
+            let apply = &apply;
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = buckets
+                    .into_iter()
+                    .map(|bucket| scope.spawn(move || apply(bucket)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().expect("spool journal apply panicked"))
+                    .collect()
+            })
+        };
+
+        let mut results: Vec<Option<anyhow::Result<()>>> = batch.iter().map(|_| None).collect();
+        for (idx, result) in applied {
+            results[idx] = Some(result);
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every op in the batch was applied"))
+            .collect()
+    }
+
+    fn append(&mut self, buf: &[u8], sync: bool) -> anyhow::Result<()> {
+        let result = self
+            .file
+            .write_all(buf)
+            .context("failed to append to spool journal")
+            .and_then(|_| {
+                if sync {
+                    self.file
+                        .sync_data()
+                        .context("failed to sync spool journal")
+                } else {
+                    Ok(())
+                }
+            });
+        match result {
+            Ok(()) => {
+                self.len += buf.len() as u64;
+                Ok(())
+            }
+            Err(err) => {
+                // Don't leave a partial batch for replay to trip over
+                self.file.set_len(self.len).ok();
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::store_data;
+    use crate::{Spool, SpoolEntry};
+    use chrono::Utc;
+    use tokio::runtime::Handle;
+
+    #[tokio::test]
+    async fn journaled_spool() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let spool = Arc::new(LocalDiskSpool::with_journal(
+            location.path(),
+            false,
+            Some(JournalParams::default()),
+            Handle::current(),
+        )?);
+
+        // Concurrent stores are committed together
+        let mut set = tokio::task::JoinSet::new();
+        let mut ids = vec![];
+        for i in 0..100 {
+            let id = SpoolId::new();
+            ids.push(id);
+            let spool = spool.clone();
+            set.spawn(async move {
+                spool
+                    .store(id, store_data(&format!("I am {i}")), false, None)
+                    .await
+            });
+        }
+        while let Some(result) = set.join_next().await {
+            result??;
+        }
+
+        for (i, &id) in ids.iter().enumerate() {
+            assert_eq!(spool.load(id).await?, format!("I am {i}").as_bytes());
+        }
+
+        spool.remove(ids[0]).await?;
+        assert!(spool.load(ids[0]).await.is_err());
+        assert!(spool.remove(ids[0]).await.is_err());
+
+        spool.shutdown().await?;
+        drop(spool);
+        // A clean shutdown leaves nothing to replay
+        assert_eq!(std::fs::metadata(location.path().join("journal"))?.len(), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replay_after_crash() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        // Prepare the directory structure, as though we crashed
+        // after committing to the journal but before any of the
+        // spool files were written
+        drop(LocalDiskSpool::new(
+            location.path(),
+            false,
+            Handle::current(),
+        )?);
+
+        let kept = SpoolId::new();
+        let removed = SpoolId::new();
+        let torn = SpoolId::new();
+
+        let mut buf = vec![];
+        Op::Store {
+            id: kept,
+            data: store_data("first version"),
+        }
+        .encode(&mut buf);
+        Op::Store {
+            id: removed,
+            data: store_data("removed"),
+        }
+        .encode(&mut buf);
+        Op::Store {
+            id: kept,
+            data: store_data("second version"),
+        }
+        .encode(&mut buf);
+        Op::Remove { id: removed }.encode(&mut buf);
+        // A batch that was only partially written
+        let mut partial = vec![];
+        Op::Store {
+            id: torn,
+            data: store_data("torn"),
+        }
+        .encode(&mut partial);
+        buf.extend_from_slice(&partial[..partial.len() - 3]);
+        std::fs::write(location.path().join("journal"), &buf)?;
+
+        let spool = LocalDiskSpool::with_journal(
+            location.path(),
+            false,
+            Some(JournalParams::default()),
+            Handle::current(),
+        )?;
+        assert_eq!(std::fs::metadata(location.path().join("journal"))?.len(), 0);
+
+        let (tx, rx) = flume::bounded(32);
+        spool.enumerate(tx, Utc::now())?;
+        let mut items = vec![];
+        while let Ok(entry) = rx.recv_async().await {
+            match entry {
+                SpoolEntry::Item { id, data } => items.push((id, String::from_utf8(data)?)),
+                SpoolEntry::Corrupt { id, error } => anyhow::bail!("{id} corrupt: {error}"),
+            }
+        }
+        assert_eq!(items, vec![(kept, "second version".to_string())]);
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn failed_store_is_not_replayed() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let spool = LocalDiskSpool::with_journal(
+            location.path(),
+            false,
+            Some(JournalParams::default()),
+            Handle::current(),
+        )?;
+
+        let kept = SpoolId::new();
+        spool.store(kept, store_data("kept"), false, None).await?;
+
+        // A directory in the way of the spool file causes the store
+        // to fail after it was committed to the journal
+        let failed = SpoolId::new();
+        let failed_path = LocalDiskSpool::entry_path(location.path(), failed);
+        std::fs::create_dir_all(&failed_path)?;
+        assert!(spool
+            .store(failed, store_data("failed"), false, None)
+            .await
+            .is_err());
+
+        // Capture the journal as it would be found after a crash
+        let journal_path = location.path().join("journal");
+        let journal = std::fs::read(&journal_path)?;
+        let mut ops = vec![];
+        let mut offset = 0;
+        while let Some((op, len)) = Op::decode(&journal[offset..]) {
+            ops.push(op);
+            offset += len;
+        }
+        assert_eq!(offset, journal.len());
+        assert!(matches!(
+            ops.as_slice(),
+            [Op::Store { id: a, .. }, Op::Store { id: b, .. }, Op::Abort { id: c }]
+                if *a == kept && *b == failed && *c == failed
+        ));
+
+        spool.shutdown().await?;
+        drop(spool);
+        std::fs::remove_dir(&failed_path)?;
+        std::fs::remove_file(LocalDiskSpool::entry_path(location.path(), kept))?;
+        std::fs::write(&journal_path, &journal)?;
+
+        let spool = LocalDiskSpool::with_journal(
+            location.path(),
+            false,
+            Some(JournalParams::default()),
+            Handle::current(),
+        )?;
+        assert_eq!(spool.load(kept).await?, b"kept");
+        assert!(spool.load(failed).await.is_err());
+
+        Ok(())
+    }
+}
@@ -1,10 +1,18 @@
+use anyhow::Context;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use flume::Sender;
 use std::sync::{Arc, OnceLock};
 use std::time::Instant;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
 
+pub mod compressed;
+pub mod encrypted;
+pub mod journal;
 pub mod local_disk;
+#[cfg(feature = "object-store")]
+pub mod object_store;
 #[cfg(feature = "rocksdb")]
 pub mod rocks;
 pub mod spool_id;
@@ -17,6 +25,54 @@ pub enum SpoolEntry {
     Corrupt { id: SpoolId, error: String },
 }
 
+/// The task performing a spool enumeration; see [Spool::enumerate]
+pub type EnumerateHandle = JoinHandle<anyhow::Result<()>>;
+
+/// Wait for an enumeration to complete, returning any error
+/// that caused it to stop early
+pub async fn join_enumerate(handle: EnumerateHandle) -> anyhow::Result<()> {
+    handle.await.context("spool enumeration task failed")?
+}
+
+/// Enumerate `inner`, passing each of its entries through `map`
+/// before sending them on to `sender`.  This is intended for use
+/// by spools that transform the records of another spool.
+/// `map` runs in a blocking task, so it may perform CPU intensive
+/// work such as decryption or decompression.
+pub fn enumerate_mapped<F>(
+    inner: &dyn Spool,
+    sender: Sender<SpoolEntry>,
+    start_time: DateTime<Utc>,
+    name: &str,
+    map: F,
+) -> anyhow::Result<EnumerateHandle>
+where
+    F: Fn(SpoolEntry) -> SpoolEntry + Send + 'static,
+{
+    let runtime = Handle::try_current().context("enumerate requires a tokio runtime")?;
+    let (tx, rx) = flume::bounded(1024);
+    let inner_enumeration = inner.enumerate(tx, start_time)?;
+
+    let mapper = tokio::task::Builder::new().name(name).spawn_blocking_on(
+        move || {
+            while let Ok(entry) = rx.recv() {
+                if sender.send(map(entry)).is_err() {
+                    break;
+                }
+            }
+        },
+        &runtime,
+    )?;
+
+    Ok(tokio::task::Builder::new().name(name).spawn_on(
+        async move {
+            mapper.await.context("spool enumeration mapper failed")?;
+            join_enumerate(inner_enumeration).await
+        },
+        &runtime,
+    )?)
+}
+
 #[async_trait]
 pub trait Spool: Send + Sync {
     /// Load the data corresponding to the provided Id
@@ -41,11 +97,15 @@ pub trait Spool: Send + Sync {
     ///
     /// The results are undefined if you enumerate concurrently with
     /// load/remove/store operations.
+    ///
+    /// Returns a handle to the task that performs the enumeration.
+    /// It resolves once all of the entries have been sent, or to the
+    /// error that prevented the enumeration from completing.
     fn enumerate(
         &self,
         sender: Sender<SpoolEntry>,
         start_time: DateTime<Utc>,
-    ) -> anyhow::Result<()>;
+    ) -> anyhow::Result<EnumerateHandle>;
 
     /// Perform some periodic cleanup/maintenance
     async fn cleanup(&self) -> anyhow::Result<()>;
@@ -83,3 +143,13 @@ pub fn set_data_spool(data: Arc<dyn Spool + Send + Sync>) {
         .map_err(|_| "set_data_spool has already been called")
         .unwrap();
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::Arc;
+
+    /// Produce the data for a store operation from `text`
+    pub fn store_data(text: &str) -> Arc<Box<[u8]>> {
+        Arc::new(text.as_bytes().to_vec().into_boxed_slice())
+    }
+}
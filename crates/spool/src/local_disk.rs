@@ -1,4 +1,5 @@
-use crate::{Spool, SpoolEntry, SpoolId};
+use crate::journal::{Journal, JournalParams};
+use crate::{EnumerateHandle, Spool, SpoolEntry, SpoolId};
 use anyhow::Context;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -15,20 +16,38 @@ use tokio::runtime::Handle;
 pub struct LocalDiskSpool {
     path: PathBuf,
     flush: bool,
+    journal: Option<Arc<Journal>>,
     _pid_file: File,
     runtime: Handle,
 }
 
 impl LocalDiskSpool {
     pub fn new(path: &Path, flush: bool, runtime: Handle) -> anyhow::Result<Self> {
+        Self::with_journal(path, flush, None, runtime)
+    }
+
+    /// Open the spool, optionally using a group-commit journal
+    /// to make stores durable. See the `journal` module.
+    pub fn with_journal(
+        path: &Path,
+        flush: bool,
+        journal: Option<JournalParams>,
+        runtime: Handle,
+    ) -> anyhow::Result<Self> {
         let pid_file_path = path.join("lock");
         let _pid_file = lock_pid_file(pid_file_path)?;
 
         Self::create_dir_structure(path)?;
 
+        let journal = match journal {
+            Some(params) => Some(Arc::new(Journal::open(path, flush, params)?)),
+            None => None,
+        };
+
         Ok(Self {
             path: path.to_path_buf(),
             flush,
+            journal,
             _pid_file,
             runtime,
         })
@@ -41,7 +60,40 @@ impl LocalDiskSpool {
     }
 
     fn compute_path(&self, id: SpoolId) -> PathBuf {
-        id.compute_path(&self.path.join("data"))
+        Self::entry_path(&self.path, id)
+    }
+
+    pub(crate) fn entry_path(root: &Path, id: SpoolId) -> PathBuf {
+        id.compute_path(&root.join("data"))
+    }
+
+    /// Write the data for id via a temporary file in the `new`
+    /// directory, so that readers never observe a partial file
+    pub(crate) fn write_entry(
+        root: &Path,
+        id: SpoolId,
+        data: &[u8],
+        flush: bool,
+    ) -> anyhow::Result<()> {
+        let path = Self::entry_path(root, id);
+        let mut temp = NamedTempFile::new_in(root.join("new"))
+            .with_context(|| format!("failed to create a temporary file to store {id}"))?;
+
+        temp.write_all(data)
+            .with_context(|| format!("failed to write data for {id}"))?;
+
+        if flush {
+            temp.as_file_mut()
+                .sync_data()
+                .with_context(|| format!("failed to sync data for {id}"))?;
+        }
+
+        std::fs::create_dir_all(path.parent().unwrap())
+            .with_context(|| format!("failed to create dir structure for {id} {path:?}"))?;
+
+        temp.persist(&path)
+            .with_context(|| format!("failed to move temp file for {id} to {path:?}"))?;
+        Ok(())
     }
 
     fn cleanup_dirs(path: &Path) {
@@ -88,6 +140,9 @@ impl Spool for LocalDiskSpool {
     }
 
     async fn remove(&self, id: SpoolId) -> anyhow::Result<()> {
+        if let Some(journal) = &self.journal {
+            return journal.remove(id).await;
+        }
         let path = self.compute_path(id);
         tokio::fs::remove_file(&path)
             .await
@@ -101,35 +156,17 @@ impl Spool for LocalDiskSpool {
         force_sync: bool,
         _deadline: Option<Instant>,
     ) -> anyhow::Result<()> {
-        let path = self.compute_path(id);
-        let new_dir = self.path.join("new");
+        if let Some(journal) = &self.journal {
+            // The journal is always synced before the store completes,
+            // so force_sync is implicitly satisfied
+            return journal.store(id, data).await;
+        }
+        let root = self.path.clone();
         let flush = force_sync || self.flush;
         tokio::task::Builder::new()
             .name("LocalDiskSpool store")
             .spawn_blocking_on(
-                move || {
-                    let mut temp = NamedTempFile::new_in(new_dir).with_context(|| {
-                        format!("failed to create a temporary file to store {id}")
-                    })?;
-
-                    temp.write_all(&data)
-                        .with_context(|| format!("failed to write data for {id}"))?;
-
-                    if flush {
-                        temp.as_file_mut()
-                            .sync_data()
-                            .with_context(|| format!("failed to sync data for {id}"))?;
-                    }
-
-                    std::fs::create_dir_all(path.parent().unwrap()).with_context(|| {
-                        format!("failed to create dir structure for {id} {path:?}")
-                    })?;
-
-                    temp.persist(&path).with_context(|| {
-                        format!("failed to move temp file for {id} to {path:?}")
-                    })?;
-                    Ok(())
-                },
+                move || Self::write_entry(&root, id, &data, flush),
                 &self.runtime,
             )?
             .await?
@@ -139,9 +176,9 @@ impl Spool for LocalDiskSpool {
         &self,
         sender: Sender<SpoolEntry>,
         start_time: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<EnumerateHandle> {
         let path = self.path.clone();
-        tokio::task::Builder::new()
+        Ok(tokio::task::Builder::new()
             .name("LocalDiskSpool enumerate")
             .spawn_blocking_on(
                 move || -> anyhow::Result<()> {
@@ -185,8 +222,7 @@ impl Spool for LocalDiskSpool {
                     anyhow::Result::Ok(())
                 },
                 &self.runtime,
-            )?;
-        Ok(())
+            )?)
     }
 
     async fn cleanup(&self) -> anyhow::Result<()> {
@@ -203,6 +239,12 @@ impl Spool for LocalDiskSpool {
     }
 
     async fn shutdown(&self) -> anyhow::Result<()> {
+        if let Some(journal) = self.journal.clone() {
+            tokio::task::Builder::new()
+                .name("LocalDiskSpool journal shutdown")
+                .spawn_blocking_on(move || journal.shutdown(), &self.runtime)?
+                .await?;
+        }
         Ok(())
     }
 
@@ -1,4 +1,4 @@
-use crate::{Spool, SpoolEntry, SpoolId};
+use crate::{EnumerateHandle, Spool, SpoolEntry, SpoolId};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use flume::Sender;
@@ -229,6 +229,20 @@ impl RocksSpool {
             limit_concurrent_removes,
         })
     }
+
+    /// Perform a full manual compaction of the database,
+    /// reclaiming the space used by removed entries.
+    pub async fn compact(&self) -> anyhow::Result<()> {
+        let db = self.db.clone();
+        tokio::task::Builder::new()
+            .name("rocksdb compact")
+            .spawn_blocking_on(
+                move || db.compact_range(None::<&[u8]>, None::<&[u8]>),
+                &self.runtime,
+            )?
+            .await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -380,9 +394,9 @@ impl Spool for RocksSpool {
         &self,
         sender: Sender<SpoolEntry>,
         start_time: DateTime<Utc>,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<EnumerateHandle> {
         let db = Arc::clone(&self.db);
-        tokio::task::Builder::new()
+        Ok(tokio::task::Builder::new()
             .name("rocksdb enumerate")
             .spawn_blocking_on(
                 move || {
@@ -411,8 +425,7 @@ impl Spool for RocksSpool {
                     Ok::<(), anyhow::Error>(())
                 },
                 &self.runtime,
-            )?;
-        Ok(())
+            )?)
     }
 }
 
@@ -515,6 +528,46 @@ mod test {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn compact() -> anyhow::Result<()> {
+        let location = tempfile::tempdir()?;
+        let spool = RocksSpool::new(location.path(), false, None, Handle::current())?;
+
+        let mut ids = vec![];
+        for i in 0..100 {
+            let id = SpoolId::new();
+            spool
+                .store(
+                    id,
+                    Arc::new(format!("I am {i}").as_bytes().to_vec().into_boxed_slice()),
+                    false,
+                    None,
+                )
+                .await?;
+            ids.push(id);
+        }
+        for &id in ids.iter().step_by(2) {
+            spool.remove(id).await?;
+        }
+
+        spool.compact().await?;
+
+        // Compaction must preserve the live entries and must
+        // not resurrect those that were removed
+        for (i, &id) in ids.iter().enumerate() {
+            if i % 2 == 0 {
+                assert!(spool.load(id).await.is_err(), "{id} was removed");
+            } else {
+                assert_eq!(
+                    String::from_utf8(spool.load(id).await?)?,
+                    format!("I am {i}")
+                );
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// The rocksdb type doesn't impl Debug, so we get to do it
@@ -0,0 +1,274 @@
+//! Replication of TSA state between a set of peer tsa-daemon instances.
+//!
+//! Each node forwards the log records that were published to it directly
+//! by kumod to all of its configured peers, so that every node in the
+//! cluster observes the same event stream and computes the same thresholds
+//! and actions. Records received from a peer are processed locally but are
+//! never forwarded again, which prevents loops in a fully meshed cluster.
+//!
+//! In addition to the event stream, nodes periodically pull the current
+//! set of suspensions, bounces and configuration overrides from each other.
+//! That allows a node that was restarted, or that missed some replicated
+//! records, to converge on the same set of active actions.
+use crate::http_server::submit_subscription_item;
+use crate::state::{SerializableState, TSA_STATE};
+use anyhow::Context;
+use kumo_log_types::JsonLogRecord;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::Duration;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+const BATCH_SIZE: usize = 1024;
+const BATCH_DURATION: Duration = Duration::from_millis(100);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+pub static CLUSTER_CONFIG: LazyLock<Mutex<Option<ClusterConfig>>> =
+    LazyLock::new(|| Mutex::new(None));
+static PEERS: OnceLock<Vec<Peer>> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClusterConfig {
+    /// The base http(s) urls of the other tsa-daemon instances
+    /// in the cluster. This node should not be listed.
+    pub peers: Vec<String>,
+
+    /// How long to wait for a peer to respond to a request
+    #[serde(default = "ClusterConfig::default_timeout", with = "duration_serde")]
+    pub timeout: Duration,
+
+    /// How often to pull the set of active actions from each peer
+    #[serde(
+        default = "ClusterConfig::default_sync_interval",
+        with = "duration_serde"
+    )]
+    pub sync_interval: Duration,
+
+    /// How many records may be waiting to be sent to an individual
+    /// peer before we start discarding them
+    #[serde(default = "ClusterConfig::default_max_backlog")]
+    pub max_backlog: usize,
+}
+
+impl ClusterConfig {
+    fn default_timeout() -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn default_sync_interval() -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn default_max_backlog() -> usize {
+        128 * 1024
+    }
+}
+
+struct Peer {
+    url: String,
+    tx: Sender<JsonLogRecord>,
+    /// The number of records discarded because the backlog was full,
+    /// since we last reported on them
+    dropped: Arc<AtomicUsize>,
+}
+
+fn peer_url(base: &str, path: &str) -> String {
+    format!("{}/{path}", base.trim_end_matches('/'))
+}
+
+/// Queue a record that was published directly to this node
+/// so that it will be sent to each of the peers.
+pub fn replicate_record(record: &JsonLogRecord) {
+    let Some(peers) = PEERS.get() else {
+        return;
+    };
+    for peer in peers {
+        match peer.tx.try_send(record.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                // Only warn on the first record that we drop; the
+                // total is reported by the replicator for the peer
+                if peer.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                    tracing::warn!(
+                        "replication backlog to {} is full, discarding records. \
+                         Actions will be reconciled by the next state sync",
+                        peer.url
+                    );
+                }
+            }
+            Err(TrySendError::Closed(_)) => {
+                tracing::error!("replication task for {} has terminated", peer.url);
+            }
+        }
+    }
+}
+
+async fn send_batch(
+    client: &reqwest::Client,
+    url: &str,
+    batch: &[JsonLogRecord],
+) -> anyhow::Result<()> {
+    let response = client.post(url).json(batch).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!(
+            "request status {url}: {}: {}. Response body: {body}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+/// Log and reset the count of records that were discarded
+/// because the backlog for the peer was full
+fn report_dropped(url: &str, dropped: &AtomicUsize) {
+    let count = dropped.swap(0, Ordering::Relaxed);
+    if count > 0 {
+        tracing::warn!(
+            "discarded {count} records because the replication backlog \
+             to {url} was full"
+        );
+    }
+}
+
+async fn run_replicator(
+    client: reqwest::Client,
+    base_url: String,
+    mut rx: Receiver<JsonLogRecord>,
+    dropped: Arc<AtomicUsize>,
+) {
+    let url = peer_url(&base_url, "replicate_log_v1");
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    loop {
+        if rx.recv_many(&mut batch, BATCH_SIZE).await == 0 {
+            return;
+        }
+        // Give the batch a brief opportunity to fill up
+        let deadline = tokio::time::Instant::now() + BATCH_DURATION;
+        while batch.len() < BATCH_SIZE {
+            let limit = BATCH_SIZE - batch.len();
+            match tokio::time::timeout_at(deadline, rx.recv_many(&mut batch, limit)).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+
+        while let Err(err) = send_batch(&client, &url, &batch).await {
+            tracing::error!(
+                "failed to replicate {} records to {url}: {err:#}. \
+                 Will retry in {RETRY_INTERVAL:?}",
+                batch.len()
+            );
+            report_dropped(&url, &dropped);
+            tokio::time::sleep(RETRY_INTERVAL).await;
+        }
+        batch.clear();
+        report_dropped(&url, &dropped);
+    }
+}
+
+async fn fetch_peer_state(
+    client: &reqwest::Client,
+    base_url: &str,
+    include_events: bool,
+) -> anyhow::Result<SerializableState> {
+    let url = peer_url(base_url, "get_state_v1");
+    let url = if include_events {
+        format!("{url}?events=true")
+    } else {
+        url
+    };
+    let response = client.get(&url).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        anyhow::bail!(
+            "request status {url}: {}: {}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        );
+    }
+    let data = response.bytes().await.context("read response body")?;
+    rmp_serde::from_slice(&data).with_context(|| format!("decoding state from {url}"))
+}
+
+/// Pull the state from each peer and merge it into our own.
+/// When `include_events` is true, the threshold event history
+/// is also fetched and merged; that is only done on startup, as
+/// during normal operation the replicated event stream keeps it
+/// in sync, and the history can be large.
+async fn sync_from_peers(client: &reqwest::Client, peers: &[String], include_events: bool) {
+    let state = TSA_STATE.get().expect("tsa_state missing");
+    for peer in peers {
+        match fetch_peer_state(client, peer, include_events).await {
+            Ok(peer_state) => {
+                let items = state.merge_peer_state(peer_state, include_events);
+                if !items.is_empty() {
+                    tracing::info!("learned of {} new actions from peer {peer}", items.len());
+                }
+                for item in items {
+                    submit_subscription_item(item);
+                }
+            }
+            Err(err) => {
+                tracing::warn!("failed to sync state from peer {peer}: {err:#}");
+            }
+        }
+    }
+}
+
+async fn state_syncer(client: reqwest::Client, peers: Vec<String>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        sync_from_peers(&client, &peers, false).await;
+    }
+}
+
+/// If a cluster was configured via `tsa.configure_cluster`, bootstrap
+/// our state from the peers and start the replication tasks.
+pub async fn start_cluster() -> anyhow::Result<()> {
+    let Some(config) = CLUSTER_CONFIG.lock().clone() else {
+        return Ok(());
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .build()
+        .context("building http client for cluster peers")?;
+
+    sync_from_peers(&client, &config.peers, true).await;
+
+    let mut peers = vec![];
+    for url in &config.peers {
+        let (tx, rx) = channel(config.max_backlog);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(run_replicator(
+            client.clone(),
+            url.clone(),
+            rx,
+            dropped.clone(),
+        ));
+        peers.push(Peer {
+            url: url.clone(),
+            tx,
+            dropped,
+        });
+    }
+    PEERS.set(peers).ok();
+
+    tokio::spawn(state_syncer(
+        client,
+        config.peers.clone(),
+        config.sync_interval,
+    ));
+
+    tracing::info!("replicating with {} peers", config.peers.len());
+
+    Ok(())
+}
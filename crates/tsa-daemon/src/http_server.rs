@@ -7,6 +7,7 @@ use crate::state::{
 };
 use anyhow::anyhow;
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Query;
 use axum::response::IntoResponse;
 use axum::Json;
 use chrono::{DateTime, Utc};
@@ -23,6 +24,7 @@ use kumo_server_common::router_with_docs;
 use message::message::QueueNameComponents;
 use parking_lot::Mutex;
 use rfc5321::ForwardPath;
+use serde::Deserialize;
 use serde_json::Value as JsonValue;
 use sha2::{Digest, Sha256};
 use std::hash::Hash;
@@ -46,8 +48,10 @@ pub fn make_router() -> RouterAndDocs {
         handlers = [
             get_bounce_v1,
             get_config_v1,
+            get_state_v1,
             get_suspension_v1,
             publish_log_v1,
+            replicate_log_v1,
             subscribe_event_v1,
             subscribe_suspension_v1,
             tsa_status,
@@ -499,6 +503,7 @@ async fn publish_log_v1(
     // Note: Json<> must be last in the param list
     Json(record): Json<JsonLogRecord>,
 ) -> Result<(), AppError> {
+    crate::cluster::replicate_record(&record);
     submit_record(record).await.map_err(|err| {
         tracing::error!("while processing /publish_log_v1: {err:#}");
         let app_err: AppError = err.into();
@@ -506,6 +511,40 @@ async fn publish_log_v1(
     })
 }
 
+/// Accepts a batch of log records that were published to a cluster peer.
+/// The records are processed locally but are not forwarded to any
+/// other peers.
+#[utoipa::path(post, path="/replicate_log_v1", request_body=Object)]
+async fn replicate_log_v1(
+    // Note: Json<> must be last in the param list
+    Json(records): Json<Vec<JsonLogRecord>>,
+) -> Result<(), AppError> {
+    for record in records {
+        submit_record(record).await.map_err(|err| {
+            tracing::error!("while processing /replicate_log_v1: {err:#}");
+            let app_err: AppError = err.into();
+            app_err
+        })?;
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct GetStateV1Params {
+    /// Whether to include the threshold event history
+    #[serde(default)]
+    events: bool,
+}
+
+/// Returns the msgpack encoded state of this node, so that a cluster
+/// peer can bootstrap or reconcile its own state from it.
+/// The threshold event history is included only when the `events`
+/// query parameter is set to `true`.
+#[utoipa::path(get, path = "/get_state_v1")]
+async fn get_state_v1(Query(params): Query<GetStateV1Params>) -> Result<Vec<u8>, AppError> {
+    Ok(crate::state::serialize_state(params.events)?)
+}
+
 fn json_to_toml_value(item_value: &JsonValue) -> anyhow::Result<toml::Value> {
     Ok(match item_value {
         JsonValue::Bool(b) => toml::Value::Boolean(*b),
@@ -743,6 +782,11 @@ impl SubscriberMgr {
     }
 }
 
+/// Pass an item learned from a cluster peer on to our subscribers
+pub fn submit_subscription_item(entry: SubscriptionItem) {
+    SubscriberMgr::submit(entry);
+}
+
 /// This is a legacy endpoint that can only report on the old SuspensionEntry
 /// enum variants
 async fn process_suspension_subscription_inner(mut socket: WebSocket) -> anyhow::Result<()> {
@@ -8,6 +8,7 @@ use kumo_server_common::start::StartConfig;
 use nix::sys::resource::{getrlimit, setrlimit, Resource};
 use std::path::PathBuf;
 
+mod cluster;
 mod database;
 mod http_server;
 mod mod_auto;
@@ -85,6 +86,7 @@ async fn perform_init() -> anyhow::Result<()> {
     config.put();
 
     crate::state::load_state().await?;
+    crate::cluster::start_cluster().await?;
 
     spawn_shaping_updater()?;
     tokio::spawn(state_pruner());
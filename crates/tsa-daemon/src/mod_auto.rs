@@ -18,6 +18,15 @@ pub fn register(lua: &Lua) -> anyhow::Result<()> {
         })?,
     )?;
 
+    tsa_mod.set(
+        "configure_cluster",
+        lua.create_function(|lua, params: Value| {
+            let config: crate::cluster::ClusterConfig = from_lua_value(&lua, params)?;
+            *crate::cluster::CLUSTER_CONFIG.lock() = Some(config);
+            Ok(())
+        })?,
+    )?;
+
     tsa_mod.set(
         "configure_tsa_db_path",
         lua.create_function(|_lua, file_name: String| {
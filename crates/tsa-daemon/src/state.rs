@@ -5,11 +5,12 @@ use crate::http_server::{
 };
 use anyhow::Context;
 use chrono::{DateTime, Utc};
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use kumo_api_types::shaping::{
     Action, EgressPathConfigValue, EgressPathConfigValueUnchecked, Rule,
 };
-use kumo_api_types::tsa::{ReadyQSuspension, SchedQBounce, SchedQSuspension};
+use kumo_api_types::tsa::{ReadyQSuspension, SchedQBounce, SchedQSuspension, SubscriptionItem};
 use kumo_log_types::JsonLogRecord;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -151,6 +152,36 @@ impl EventData {
             .filter(|&&ts| ts >= report_thresh)
             .count()
     }
+
+    /// Merge the series observed by a cluster peer into our own.
+    /// The peers observe the same replicated stream, so an event
+    /// that both of us saw will be present in both series.  For
+    /// each timestamp we therefore keep the larger of the two
+    /// occurrence counts, which yields the union of the events
+    /// without double counting those that we have in common.
+    fn merge(&mut self, mut other: EventData) {
+        self.duration = self.duration.max(other.duration);
+        other.series.sort_unstable();
+
+        let ours = std::mem::take(&mut self.series);
+        let theirs = other.series;
+        let mut merged = Vec::with_capacity(ours.len().max(theirs.len()));
+        let (mut i, mut j) = (0, 0);
+
+        while let Some(ts) = match (ours.get(i), theirs.get(j)) {
+            (Some(&x), Some(&y)) => Some(x.min(y)),
+            (Some(&x), None) | (None, Some(&x)) => Some(x),
+            (None, None) => None,
+        } {
+            let a = ours[i..].iter().take_while(|&&t| t == ts).count();
+            let b = theirs[j..].iter().take_while(|&&t| t == ts).count();
+            merged.extend(std::iter::repeat_n(ts, a.max(b)));
+            i += a;
+            j += b;
+        }
+
+        self.series = merged;
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -200,6 +231,69 @@ pub struct SchedQSuspensionEntry {
     pub expires: DateTime<Utc>,
 }
 
+fn schedq_suspension_item(
+    key: &SchedQSuspensionKey,
+    value: &SchedQSuspensionEntry,
+) -> SchedQSuspension {
+    SchedQSuspension {
+        rule_hash: key.action_hash.to_string(),
+        domain: key.domain.clone(),
+        campaign: key.campaign.clone(),
+        tenant: key.tenant.clone(),
+        reason: value.reason.clone(),
+        expires: value.expires,
+    }
+}
+
+fn readyq_suspension_item(key: &ActionHash, value: &ReadyQSuspensionEntry) -> ReadyQSuspension {
+    ReadyQSuspension {
+        rule_hash: key.hash_portion(),
+        site_name: key.site_name().to_string(),
+        source: value.source.clone(),
+        reason: value.reason.clone(),
+        expires: value.expires,
+    }
+}
+
+fn schedq_bounce_item(key: &SchedQBounceKey, value: &SchedQBounceEntry) -> SchedQBounce {
+    SchedQBounce {
+        rule_hash: key.action_hash.to_string(),
+        domain: key.domain.clone(),
+        tenant: key.tenant.clone(),
+        campaign: key.campaign.clone(),
+        reason: value.reason.clone(),
+        expires: value.expires,
+    }
+}
+
+/// Insert `value` into `map` if there is no entry for `key`, or if
+/// `value` expires later than the existing entry.
+/// Returns true if the map was updated.
+fn merge_entry<K: Eq + Hash + Clone, V>(
+    map: &DashMap<K, V>,
+    key: K,
+    value: V,
+    expires: impl Fn(&V) -> DateTime<Utc>,
+) -> bool {
+    if Utc::now() >= expires(&value) {
+        return false;
+    }
+    match map.entry(key) {
+        Entry::Occupied(mut entry) => {
+            if expires(&value) > expires(entry.get()) {
+                entry.insert(value);
+                true
+            } else {
+                false
+            }
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(value);
+            true
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct TsaState {
     event_history: DashMap<MatchingScope, EventData>,
@@ -210,7 +304,7 @@ pub struct TsaState {
 }
 
 #[derive(Serialize, Deserialize)]
-struct SerializableState {
+pub struct SerializableState {
     #[serde(default)]
     event_history: HashMap<MatchingScope, EventData>,
     #[serde(default)]
@@ -313,15 +407,7 @@ impl TsaState {
             if now >= value.expires {
                 continue;
             }
-            let key = entry.key();
-            entries.push(SchedQSuspension {
-                rule_hash: key.action_hash.to_string(),
-                domain: key.domain.clone(),
-                campaign: key.campaign.clone(),
-                tenant: key.tenant.clone(),
-                reason: value.reason.clone(),
-                expires: value.expires.clone(),
-            });
+            entries.push(schedq_suspension_item(entry.key(), value));
         }
 
         entries.sort_by_key(|over| {
@@ -344,14 +430,7 @@ impl TsaState {
             if now >= value.expires {
                 continue;
             }
-            let key = entry.key();
-            entries.push(ReadyQSuspension {
-                rule_hash: key.hash_portion(),
-                site_name: key.site_name().to_string(),
-                source: value.source.clone(),
-                reason: value.reason.clone(),
-                expires: value.expires.clone(),
-            });
+            entries.push(readyq_suspension_item(entry.key(), value));
         }
 
         entries.sort_by_key(|over| (over.expires, over.source.clone()));
@@ -367,15 +446,7 @@ impl TsaState {
             if now >= value.expires {
                 continue;
             }
-            let key = entry.key();
-            entries.push(SchedQBounce {
-                rule_hash: key.action_hash.to_string(),
-                domain: key.domain.clone(),
-                tenant: key.tenant.clone(),
-                campaign: key.campaign.clone(),
-                reason: value.reason.clone(),
-                expires: value.expires.clone(),
-            });
+            entries.push(schedq_bounce_item(entry.key(), value));
         }
 
         entries.sort_by_key(|over| {
@@ -461,14 +532,75 @@ impl TsaState {
         )
     }
 
+    /// Merge state obtained from a cluster peer into our own state.
+    /// Entries that we don't have, or that the peer has extended,
+    /// are adopted. Returns the suspensions and bounces that were
+    /// added or changed so that they can be passed on to subscribers.
+    pub fn merge_peer_state(
+        &self,
+        peer: SerializableState,
+        include_events: bool,
+    ) -> Vec<SubscriptionItem> {
+        let mut items = vec![];
+
+        if include_events {
+            for (key, value) in peer.event_history {
+                match self.event_history.entry(key) {
+                    Entry::Occupied(mut entry) => {
+                        entry.get_mut().merge(value);
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(value);
+                    }
+                }
+            }
+        }
+
+        for (key, value) in peer.config_overrides {
+            merge_entry(&self.config_overrides, key, value, |v| v.expires);
+        }
+
+        for (key, value) in peer.schedq_bounces {
+            let item = schedq_bounce_item(&key, &value);
+            if merge_entry(&self.schedq_bounces, key, value, |v| v.expires) {
+                items.push(SubscriptionItem::SchedQBounce(item));
+            }
+        }
+
+        for (key, value) in peer.readyq_suspensions {
+            let item = readyq_suspension_item(&key, &value);
+            if merge_entry(&self.readyq_suspensions, key, value, |v| v.expires) {
+                items.push(SubscriptionItem::ReadyQSuspension(item));
+            }
+        }
+
+        for (key, value) in peer.schedq_suspensions {
+            let item = schedq_suspension_item(&key, &value);
+            if merge_entry(&self.schedq_suspensions, key, value, |v| v.expires) {
+                items.push(SubscriptionItem::SchedQSuspension(item));
+            }
+        }
+
+        items
+    }
+
     /// Return a serializable version of the state
     fn serializable(&self) -> SerializableState {
+        self.serializable_impl(true)
+    }
+
+    /// Return a serializable version of the state, optionally
+    /// omitting the threshold event history, which can be large
+    fn serializable_impl(&self, include_events: bool) -> SerializableState {
         SerializableState {
-            event_history: self
-                .event_history
-                .iter()
-                .map(|entry| (entry.key().clone(), entry.value().clone()))
-                .collect(),
+            event_history: if include_events {
+                self.event_history
+                    .iter()
+                    .map(|entry| (entry.key().clone(), entry.value().clone()))
+                    .collect()
+            } else {
+                HashMap::new()
+            },
             config_overrides: self
                 .config_overrides
                 .iter()
@@ -847,6 +979,19 @@ pub async fn load_state() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Produce the msgpack encoded form of the current state,
+/// for consumption by a cluster peer
+/// Serialize the state for a cluster peer. The event history is
+/// only included when `include_events` is true, as it is only
+/// needed when the peer is bootstrapping.
+pub fn serialize_state(include_events: bool) -> anyhow::Result<Vec<u8>> {
+    let state = TSA_STATE
+        .get()
+        .expect("state not initialized")
+        .serializable_impl(include_events);
+    rmp_serde::to_vec_named(&state).context("failed to serialize state")
+}
+
 pub async fn save_state(background: bool) -> anyhow::Result<()> {
     let start = Instant::now();
     let state = TSA_STATE
@@ -904,3 +1049,148 @@ pub async fn state_pruner() -> anyhow::Result<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    fn scope() -> MatchingScope {
+        MatchingScope(RuleHash([0; 32]), SiteKey("site".to_string()))
+    }
+
+    fn action_hash(site: &str) -> ActionHash {
+        ActionHash::from_legacy_hash_and_site(&"00".repeat(32), site)
+    }
+
+    fn suspension(expires: DateTime<Utc>) -> ReadyQSuspensionEntry {
+        ReadyQSuspensionEntry {
+            reason: "testing".to_string(),
+            source: "source".to_string(),
+            expires,
+        }
+    }
+
+    #[test]
+    fn merge_event_data() {
+        let mut ours = EventData {
+            duration: 60,
+            series: vec![10, 20, 20, 30],
+        };
+        ours.merge(EventData {
+            duration: 120,
+            series: vec![40, 5, 30, 20, 30],
+        });
+        assert_eq!(
+            ours,
+            EventData {
+                duration: 120,
+                series: vec![5, 10, 20, 20, 30, 30, 40],
+            }
+        );
+
+        let mut empty = EventData {
+            duration: 60,
+            series: vec![],
+        };
+        empty.merge(EventData {
+            duration: 60,
+            series: vec![1, 1],
+        });
+        assert_eq!(empty.series, vec![1, 1]);
+    }
+
+    #[test]
+    fn merge_peer_state() {
+        let state = TsaState::default();
+        let now = Utc::now();
+
+        state.event_history.insert(
+            scope(),
+            EventData {
+                duration: 60,
+                series: vec![1, 2],
+            },
+        );
+        state.readyq_suspensions.insert(
+            action_hash("extended"),
+            suspension(now + Duration::hours(1)),
+        );
+        state
+            .readyq_suspensions
+            .insert(action_hash("shorter"), suspension(now + Duration::hours(2)));
+
+        let peer_state = || SerializableState {
+            event_history: [(
+                scope(),
+                EventData {
+                    duration: 60,
+                    series: vec![2, 3],
+                },
+            )]
+            .into_iter()
+            .collect(),
+            config_overrides: HashMap::new(),
+            schedq_bounces: HashMap::new(),
+            readyq_suspensions: [
+                (
+                    action_hash("extended"),
+                    suspension(now + Duration::hours(2)),
+                ),
+                (action_hash("shorter"), suspension(now + Duration::hours(1))),
+                (action_hash("expired"), suspension(now - Duration::hours(1))),
+                (action_hash("new"), suspension(now + Duration::hours(1))),
+            ]
+            .into_iter()
+            .collect(),
+            schedq_suspensions: HashMap::new(),
+        };
+
+        // The event history is left alone unless requested
+        let mut items = state.merge_peer_state(peer_state(), false);
+        assert_eq!(
+            state.event_history.get(&scope()).unwrap().series,
+            vec![1, 2]
+        );
+
+        let mut sites: Vec<String> = items
+            .drain(..)
+            .map(|item| match item {
+                SubscriptionItem::ReadyQSuspension(s) => s.site_name,
+                _ => panic!("unexpected subscription item"),
+            })
+            .collect();
+        sites.sort();
+        assert_eq!(sites, vec!["extended".to_string(), "new".to_string()]);
+
+        assert_eq!(
+            state
+                .readyq_suspensions
+                .get(&action_hash("extended"))
+                .unwrap()
+                .expires,
+            now + Duration::hours(2)
+        );
+        assert_eq!(
+            state
+                .readyq_suspensions
+                .get(&action_hash("shorter"))
+                .unwrap()
+                .expires,
+            now + Duration::hours(2)
+        );
+        assert!(state
+            .readyq_suspensions
+            .get(&action_hash("expired"))
+            .is_none());
+
+        // Merging the same state again is a no-op for the actions,
+        // but the event history is now merged by timestamp
+        let items = state.merge_peer_state(peer_state(), true);
+        assert!(items.is_empty());
+        assert_eq!(
+            state.event_history.get(&scope()).unwrap().series,
+            vec![1, 2, 3]
+        );
+    }
+}
@@ -439,6 +439,10 @@ TOC = [
                 "module: kumo.dkim",
                 "reference/kumo.dkim",
             ),
+            Gen(
+                "module: kumo.dmarc",
+                "reference/kumo.dmarc",
+            ),
             Gen(
                 "module: kumo.dns",
                 "reference/kumo.dns",
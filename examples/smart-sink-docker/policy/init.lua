@@ -17,6 +17,7 @@ end
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(os.tmpname())
+  kumo.configure_admin_db_path(os.tmpname())
   kumo.set_config_monitor_globs { SINK_DATA_FILE }
   local DOCKER_NETWORK = resolve_docker_network()
   local SINK_PORT = os.getenv 'SINK_PORT' or '25'
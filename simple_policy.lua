@@ -104,6 +104,7 @@ kumo.on('init', function()
 
   kumo.set_lua_gc_on_put(1)
   kumo.configure_accounting_db_path(os.tmpname())
+  kumo.configure_admin_db_path(os.tmpname())
   kumo.configure_bounce_classifier {
     files = {
       '/home/wez/kumocorp/kumomta/assets/community/bounces.toml',
@@ -3,6 +3,7 @@ local kumo = require 'kumo'
 
 kumo.on('init', function()
   kumo.configure_accounting_db_path(os.tmpname())
+  kumo.configure_admin_db_path(os.tmpname())
   -- Define a listener.
   -- Can be used multiple times with different parameters to
   -- define multiple listeners!